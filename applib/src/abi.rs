@@ -0,0 +1,477 @@
+use alloc::vec::Vec;
+
+use crate::input::{
+    InputEvent, InputState, KeyboardLayout, Keycode, Modifiers, PointerState, TimedInputEvent,
+};
+use crate::{Color, Rect, StyleSheet, StyleSheetColors, StyleSheetText, TextSizes};
+
+//
+// Host ABI
+//
+// Data exchanged between the kernel and WASM apps is encoded field by field, in little endian,
+// so that it does not depend on how the compiler lays out the Rust types on either side.
+// Apps export the ABI version they were built against (see guestlib), which the kernel checks
+// when loading them, and encodes data for. Changing an encoding means bumping ABI_VERSION and
+// keeping the previous encoder, so that apps built against it keep running.
+//
+// Version 1 is what apps received before the ABI was versioned: the in-memory layout of the
+// types, which were copied as raw bytes. It is reproduced here byte for byte (padding included),
+// and used for apps which do not export a version.
+//   InputState (#[repr(C)], 216 bytes): PointerState (position, deltas, left/right clicked and
+//   click triggers), shift, 10 Option<InputEvent> (key press, key release or scroll), and the
+//   number of events. It was written by host_get_input_state(addr), without a buffer length.
+//
+// Version 2:
+//   Rect and StyleSheet are unchanged
+//   InputState: pointer, modifiers, layout, event count, then the events (see below), without
+//   any padding
+
+pub const ABI_VERSION: u32 = 2;
+
+// Oldest version the kernel still supports
+pub const MIN_ABI_VERSION: u32 = 1;
+
+// Name of the function (() -> i32) exported by apps, returning their ABI version
+pub const ABI_VERSION_EXPORT: &str = "munal_abi_version";
+
+// Apps built before the version export
+pub const LEGACY_ABI_VERSION: u32 = 1;
+
+pub const RECT_SIZE: usize = 24;
+pub const STYLESHEET_SIZE: usize = 140;
+
+const N_STYLESHEET_COLORS: usize = 14;
+const FONT_FAMILY_BYTES: usize = 64;
+
+// Kinds of InputEvent in version 2
+const EVENT_KEY_PRESS: u8 = 0;
+const EVENT_KEY_RELEASE: u8 = 1;
+const EVENT_KEY_REPEAT: u8 = 2;
+const EVENT_SCROLL: u8 = 3;
+const EVENT_HSCROLL: u8 = 4;
+const EVENT_CHAR: u8 = 5;
+
+// Version 1 InputState
+const V1_INPUT_STATE_SIZE: usize = 216;
+const V1_MAX_EVENTS: usize = 10;
+
+// Tags of the #[repr(C)] InputEvent enum of version 1. None is stored as the first unused tag.
+const V1_EVENT_KEY_PRESS: u32 = 0;
+const V1_EVENT_KEY_RELEASE: u32 = 1;
+const V1_EVENT_SCROLL: u32 = 2;
+const V1_EVENT_NONE: u32 = 3;
+
+//
+// Rect
+
+pub fn encode_rect(rect: &Rect) -> Vec<u8> {
+    let mut out = Vec::with_capacity(RECT_SIZE);
+    put_i64(&mut out, rect.x0);
+    put_i64(&mut out, rect.y0);
+    put_u32(&mut out, rect.w);
+    put_u32(&mut out, rect.h);
+    out
+}
+
+pub fn decode_rect(buf: &[u8]) -> Rect {
+    let mut reader = Reader::new(buf);
+    Rect {
+        x0: reader.i64(),
+        y0: reader.i64(),
+        w: reader.u32(),
+        h: reader.u32(),
+    }
+}
+
+//
+// StyleSheet
+
+fn stylesheet_colors(colors: &StyleSheetColors) -> [&Color; N_STYLESHEET_COLORS] {
+    [
+        &colors.background,
+        &colors.hover_overlay,
+        &colors.selected_overlay,
+        &colors.red,
+        &colors.yellow,
+        &colors.green,
+        &colors.blue,
+        &colors.purple,
+        &colors.element,
+        &colors.frame,
+        &colors.text,
+        &colors.accent,
+        &colors.editable,
+        &colors.outline,
+    ]
+}
+
+pub fn encode_stylesheet(stylesheet: &StyleSheet) -> Vec<u8> {
+    let mut out = Vec::with_capacity(STYLESHEET_SIZE);
+
+    for color in stylesheet_colors(&stylesheet.colors) {
+        out.extend_from_slice(&color.0);
+    }
+
+    put_u32(&mut out, stylesheet.margin);
+
+    let font_family = stylesheet.text.font_family().as_bytes();
+    let mut font_family_bytes = [0u8; FONT_FAMILY_BYTES];
+    font_family_bytes[..font_family.len()].copy_from_slice(font_family);
+    out.extend_from_slice(&font_family_bytes);
+    put_u32(&mut out, font_family.len() as u32);
+
+    let sizes = &stylesheet.text.sizes;
+    put_u32(&mut out, sizes.small);
+    put_u32(&mut out, sizes.medium);
+    put_u32(&mut out, sizes.large);
+
+    out
+}
+
+pub fn decode_stylesheet(buf: &[u8]) -> StyleSheet {
+    let mut reader = Reader::new(buf);
+
+    let mut color = || Color(reader.bytes(4).try_into().unwrap());
+    let colors = StyleSheetColors {
+        background: color(),
+        hover_overlay: color(),
+        selected_overlay: color(),
+        red: color(),
+        yellow: color(),
+        green: color(),
+        blue: color(),
+        purple: color(),
+        element: color(),
+        frame: color(),
+        text: color(),
+        accent: color(),
+        editable: color(),
+        outline: color(),
+    };
+
+    let margin = reader.u32();
+
+    let font_family_bytes = reader.bytes(FONT_FAMILY_BYTES);
+    let font_family_len = reader.u32() as usize;
+    let font_family = core::str::from_utf8(&font_family_bytes[..font_family_len])
+        .expect("Invalid font family in stylesheet");
+
+    let sizes = TextSizes {
+        small: reader.u32(),
+        medium: reader.u32(),
+        large: reader.u32(),
+    };
+
+    StyleSheet {
+        colors,
+        margin,
+        text: StyleSheetText::new(font_family, sizes),
+    }
+}
+
+//
+// InputState
+
+pub fn encode_input_state(input_state: &InputState, abi_version: u32) -> Vec<u8> {
+    match abi_version {
+        1 => encode_input_state_v1(input_state),
+        2 => encode_input_state_v2(input_state),
+        _ => panic!("Unsupported ABI version {}", abi_version),
+    }
+}
+
+// Event layout: kind (u8), value (u64: keycode, scroll delta or char), time (f64), x, y (i64)
+fn encode_input_state_v2(input_state: &InputState) -> Vec<u8> {
+    let InputState {
+        pointer,
+        modifiers,
+        layout,
+        events,
+        ..
+    } = input_state;
+
+    let mut out = Vec::new();
+
+    for val in [pointer.x, pointer.y, pointer.delta_x, pointer.delta_y] {
+        put_i64(&mut out, val);
+    }
+    for flag in pointer_triggers(pointer) {
+        out.push(flag as u8);
+    }
+    put_u32(&mut out, pointer.click_count);
+    for flag in [
+        pointer.dragging,
+        pointer.drag_start_trigger,
+        pointer.drag_end_trigger,
+    ] {
+        out.push(flag as u8);
+    }
+    put_i64(&mut out, pointer.drag_origin_x);
+    put_i64(&mut out, pointer.drag_origin_y);
+
+    for flag in modifier_flags(modifiers) {
+        out.push(flag as u8);
+    }
+    out.push(*layout as u8);
+
+    put_u32(&mut out, events.len() as u32);
+    for timed in events.iter() {
+        let (kind, value) = event_kind_and_value(&timed.event);
+        out.push(kind);
+        put_u64(&mut out, value);
+        put_f64(&mut out, timed.time);
+        put_i64(&mut out, timed.x);
+        put_i64(&mut out, timed.y);
+    }
+
+    out
+}
+
+fn encode_input_state_v1(input_state: &InputState) -> Vec<u8> {
+    let InputState {
+        pointer,
+        modifiers,
+        events,
+        ..
+    } = input_state;
+
+    let mut out = Vec::with_capacity(V1_INPUT_STATE_SIZE);
+
+    // PointerState (40 bytes)
+    for val in [pointer.x, pointer.y, pointer.delta_x, pointer.delta_y] {
+        put_i64(&mut out, val);
+    }
+    for flag in [
+        pointer.left_clicked,
+        pointer.right_clicked,
+        pointer.left_click_trigger,
+        pointer.right_click_trigger,
+    ] {
+        out.push(flag as u8);
+    }
+    pad_to(&mut out, 40);
+
+    out.push(modifiers.shift as u8);
+    pad_to(&mut out, 48);
+
+    // Events which version 1 apps cannot represent are left out, as the kernel used to drop them
+    let v1_events: Vec<(u32, u64)> = events
+        .iter()
+        .filter_map(|timed| match timed.event {
+            InputEvent::KeyPress { keycode } if is_v1_keycode(keycode) => {
+                Some((V1_EVENT_KEY_PRESS, keycode as u64))
+            }
+            InputEvent::KeyRelease { keycode } if is_v1_keycode(keycode) => {
+                Some((V1_EVENT_KEY_RELEASE, keycode as u64))
+            }
+            InputEvent::Scroll { delta } => Some((V1_EVENT_SCROLL, delta as u64)),
+            _ => None,
+        })
+        .take(V1_MAX_EVENTS)
+        .collect();
+
+    // Option<InputEvent> (16 bytes): tag, then the keycode or scroll delta at offset 8
+    for i in 0..V1_MAX_EVENTS {
+        let (tag, value) = v1_events.get(i).copied().unwrap_or((V1_EVENT_NONE, 0));
+        put_u32(&mut out, tag);
+        put_u32(&mut out, 0);
+        put_u64(&mut out, value);
+    }
+
+    // Index of the next free event slot (usize, padded to 8 bytes on wasm32)
+    put_u64(&mut out, v1_events.len() as u64);
+
+    out
+}
+
+// Keycodes which existed in version 1, others being invalid values for the apps' Keycode enum
+fn is_v1_keycode(keycode: Keycode) -> bool {
+    matches!(
+        keycode as u16,
+        2..=14 | 16..=28 | 30..=40 | 42..=54 | 57 | 103 | 105 | 106 | 108 | 272 | 273 | 336 | 337
+    )
+}
+
+pub fn decode_input_state(buf: &[u8]) -> InputState {
+    let mut reader = Reader::new(buf);
+
+    let mut pointer = PointerState {
+        x: reader.i64(),
+        y: reader.i64(),
+        delta_x: reader.i64(),
+        delta_y: reader.i64(),
+        ..Default::default()
+    };
+    pointer.left_clicked = reader.bool();
+    pointer.right_clicked = reader.bool();
+    pointer.middle_clicked = reader.bool();
+    pointer.left_click_trigger = reader.bool();
+    pointer.right_click_trigger = reader.bool();
+    pointer.middle_click_trigger = reader.bool();
+    pointer.left_release_trigger = reader.bool();
+    pointer.right_release_trigger = reader.bool();
+    pointer.middle_release_trigger = reader.bool();
+    pointer.click_count = reader.u32();
+    pointer.dragging = reader.bool();
+    pointer.drag_start_trigger = reader.bool();
+    pointer.drag_end_trigger = reader.bool();
+    pointer.drag_origin_x = reader.i64();
+    pointer.drag_origin_y = reader.i64();
+
+    let modifiers = Modifiers {
+        shift: reader.bool(),
+        ctrl: reader.bool(),
+        alt: reader.bool(),
+        altgr: reader.bool(),
+        meta: reader.bool(),
+        caps_lock: reader.bool(),
+        num_lock: reader.bool(),
+    };
+
+    let layout = reader.u8();
+    let layout = *KeyboardLayout::ALL
+        .iter()
+        .find(|other| **other as u8 == layout)
+        .expect("Invalid keyboard layout");
+
+    let n_events = reader.u32();
+    let events = (0..n_events)
+        .map(|_| {
+            let kind = reader.u8();
+            let value = reader.u64();
+            let keycode = || Keycode::n(value as u16).expect("Invalid keycode");
+            let event = match kind {
+                EVENT_KEY_PRESS => InputEvent::KeyPress { keycode: keycode() },
+                EVENT_KEY_RELEASE => InputEvent::KeyRelease { keycode: keycode() },
+                EVENT_KEY_REPEAT => InputEvent::KeyRepeat { keycode: keycode() },
+                EVENT_SCROLL => InputEvent::Scroll {
+                    delta: value as i64,
+                },
+                EVENT_HSCROLL => InputEvent::HScroll {
+                    delta: value as i64,
+                },
+                EVENT_CHAR => InputEvent::Char {
+                    c: char::from_u32(value as u32).expect("Invalid char"),
+                },
+                _ => panic!("Invalid input event kind {}", kind),
+            };
+            TimedInputEvent {
+                event,
+                time: reader.f64(),
+                x: reader.i64(),
+                y: reader.i64(),
+            }
+        })
+        .collect();
+
+    let mut input_state = InputState::new(0, 0, layout);
+    input_state.pointer = pointer;
+    input_state.modifiers = modifiers;
+    input_state.events = events;
+    input_state
+}
+
+fn pointer_triggers(pointer: &PointerState) -> [bool; 9] {
+    [
+        pointer.left_clicked,
+        pointer.right_clicked,
+        pointer.middle_clicked,
+        pointer.left_click_trigger,
+        pointer.right_click_trigger,
+        pointer.middle_click_trigger,
+        pointer.left_release_trigger,
+        pointer.right_release_trigger,
+        pointer.middle_release_trigger,
+    ]
+}
+
+fn modifier_flags(modifiers: &Modifiers) -> [bool; 7] {
+    [
+        modifiers.shift,
+        modifiers.ctrl,
+        modifiers.alt,
+        modifiers.altgr,
+        modifiers.meta,
+        modifiers.caps_lock,
+        modifiers.num_lock,
+    ]
+}
+
+fn event_kind_and_value(event: &InputEvent) -> (u8, u64) {
+    match *event {
+        InputEvent::KeyPress { keycode } => (EVENT_KEY_PRESS, keycode as u64),
+        InputEvent::KeyRelease { keycode } => (EVENT_KEY_RELEASE, keycode as u64),
+        InputEvent::KeyRepeat { keycode } => (EVENT_KEY_REPEAT, keycode as u64),
+        InputEvent::Scroll { delta } => (EVENT_SCROLL, delta as u64),
+        InputEvent::HScroll { delta } => (EVENT_HSCROLL, delta as u64),
+        InputEvent::Char { c } => (EVENT_CHAR, c as u64),
+    }
+}
+
+//
+// Helpers
+
+fn put_u32(out: &mut Vec<u8>, val: u32) {
+    out.extend_from_slice(&val.to_le_bytes());
+}
+
+fn put_u64(out: &mut Vec<u8>, val: u64) {
+    out.extend_from_slice(&val.to_le_bytes());
+}
+
+fn put_i64(out: &mut Vec<u8>, val: i64) {
+    out.extend_from_slice(&val.to_le_bytes());
+}
+
+fn put_f64(out: &mut Vec<u8>, val: f64) {
+    out.extend_from_slice(&val.to_le_bytes());
+}
+
+fn pad_to(out: &mut Vec<u8>, len: usize) {
+    out.resize(len, 0);
+}
+
+// Malformed data can only come from a bug on the other side of the ABI, hence the panics
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> &'a [u8] {
+        let bytes = self
+            .buf
+            .get(self.pos..self.pos + len)
+            .expect("Truncated host ABI data");
+        self.pos += len;
+        bytes
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.bytes(1)[0]
+    }
+
+    fn bool(&mut self) -> bool {
+        self.u8() != 0
+    }
+
+    fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.bytes(4).try_into().unwrap())
+    }
+
+    fn u64(&mut self) -> u64 {
+        u64::from_le_bytes(self.bytes(8).try_into().unwrap())
+    }
+
+    fn i64(&mut self) -> i64 {
+        self.u64() as i64
+    }
+
+    fn f64(&mut self) -> f64 {
+        f64::from_bits(self.u64())
+    }
+}
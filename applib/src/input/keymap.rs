@@ -1,6 +1,3 @@
-use alloc::collections::BTreeMap;
-use lazy_static::lazy_static;
-
 #[derive(PartialEq, Eq, Debug, Clone, Copy, enumn::N)]
 #[repr(u16)]
 #[allow(non_camel_case_types)]
@@ -8,6 +5,7 @@ pub enum EventType {
     EV_SYN = 0x0,
     EV_KEY = 0x1,
     EV_REL = 0x2,
+    EV_LED = 0x11,
 }
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, enumn::N)]
@@ -16,6 +14,7 @@ pub enum EventType {
 pub enum Keycode {
     BTN_MOUSE_LEFT = 272,
     BTN_MOUSE_RIGHT = 273,
+    BTN_MOUSE_MIDDLE = 274,
     BTN_GEAR_DOWN = 336,
     BTN_GEAR_UP = 337,
 
@@ -68,67 +67,51 @@ pub enum Keycode {
     KEY_N = 49,
     KEY_M = 50,
 
+    KEY_GRAVE = 41,
+    KEY_102ND = 86,
+
+    KEY_ESC = 1,
     KEY_BACKSPACE = 14,
+    KEY_TAB = 15,
     KEY_ENTER = 28,
+    KEY_SPACE = 57,
+    KEY_DELETE = 111,
+    KEY_HOME = 102,
+    KEY_END = 107,
+    KEY_PAGEUP = 104,
+    KEY_PAGEDOWN = 109,
+    KEY_SYSRQ = 99, // PrintScreen
+
     KEY_LEFTSHIFT = 42,
     KEY_RIGHTSHIFT = 54,
-    KEY_SPACE = 57,
+    KEY_LEFTCTRL = 29,
+    KEY_RIGHTCTRL = 97,
+    KEY_LEFTALT = 56,
+    KEY_RIGHTALT = 100,
+    KEY_LEFTMETA = 125,
+    KEY_RIGHTMETA = 126,
+    KEY_CAPSLOCK = 58,
+    KEY_NUMLOCK = 69,
+
+    KEY_KP0 = 82,
+    KEY_KP1 = 79,
+    KEY_KP2 = 80,
+    KEY_KP3 = 81,
+    KEY_KP4 = 75,
+    KEY_KP5 = 76,
+    KEY_KP6 = 77,
+    KEY_KP7 = 71,
+    KEY_KP8 = 72,
+    KEY_KP9 = 73,
+    KEY_KPDOT = 83,
+    KEY_KPPLUS = 78,
+    KEY_KPMINUS = 74,
+    KEY_KPASTERISK = 55,
+    KEY_KPSLASH = 98,
+    KEY_KPENTER = 96,
 
     KEY_LEFT = 105,
     KEY_RIGHT = 106,
     KEY_UP = 103,
     KEY_DOWN = 108,
 }
-
-lazy_static! {
-    pub static ref CHARMAP: BTreeMap<Keycode, (Option<char>, Option<char>)> = [
-        (Keycode::KEY_1, (Some('1'), Some('!'))),
-        (Keycode::KEY_2, (Some('2'), Some('@'))),
-        (Keycode::KEY_3, (Some('3'), Some('#'))),
-        (Keycode::KEY_4, (Some('4'), Some('$'))),
-        (Keycode::KEY_5, (Some('5'), Some('%'))),
-        (Keycode::KEY_6, (Some('6'), Some('^'))),
-        (Keycode::KEY_7, (Some('7'), Some('&'))),
-        (Keycode::KEY_8, (Some('8'), Some('*'))),
-        (Keycode::KEY_9, (Some('9'), Some('('))),
-        (Keycode::KEY_0, (Some('0'), Some(')'))),
-        (Keycode::KEY_MINUS, (Some('-'), Some('_'))),
-        (Keycode::KEY_EQUAL, (Some('='), Some('+'))),
-        (Keycode::KEY_LEFTBRACE, (Some('['), Some('{'))),
-        (Keycode::KEY_RIGHTBRACE, (Some(']'), Some('}'))),
-        (Keycode::KEY_BACKSLASH, (Some('\\'), Some('|'))),
-        (Keycode::KEY_SEMICOLON, (Some(';'), Some(':'))),
-        (Keycode::KEY_APOSTROPHE, (Some('\''), Some('"'))),
-        (Keycode::KEY_COMMA, (Some(','), Some('<'))),
-        (Keycode::KEY_DOT, (Some('.'), Some('>'))),
-        (Keycode::KEY_SLASH, (Some('/'), Some('?'))),
-        (Keycode::KEY_Q, (Some('q'), Some('Q'))),
-        (Keycode::KEY_W, (Some('w'), Some('W'))),
-        (Keycode::KEY_E, (Some('e'), Some('E'))),
-        (Keycode::KEY_R, (Some('r'), Some('R'))),
-        (Keycode::KEY_T, (Some('t'), Some('T'))),
-        (Keycode::KEY_Y, (Some('y'), Some('Y'))),
-        (Keycode::KEY_U, (Some('u'), Some('U'))),
-        (Keycode::KEY_I, (Some('i'), Some('I'))),
-        (Keycode::KEY_O, (Some('o'), Some('O'))),
-        (Keycode::KEY_P, (Some('p'), Some('P'))),
-        (Keycode::KEY_A, (Some('a'), Some('A'))),
-        (Keycode::KEY_S, (Some('s'), Some('S'))),
-        (Keycode::KEY_D, (Some('d'), Some('D'))),
-        (Keycode::KEY_F, (Some('f'), Some('F'))),
-        (Keycode::KEY_G, (Some('g'), Some('G'))),
-        (Keycode::KEY_H, (Some('h'), Some('H'))),
-        (Keycode::KEY_J, (Some('j'), Some('J'))),
-        (Keycode::KEY_K, (Some('k'), Some('K'))),
-        (Keycode::KEY_L, (Some('l'), Some('L'))),
-        (Keycode::KEY_Z, (Some('z'), Some('Z'))),
-        (Keycode::KEY_X, (Some('x'), Some('X'))),
-        (Keycode::KEY_C, (Some('c'), Some('C'))),
-        (Keycode::KEY_V, (Some('v'), Some('V'))),
-        (Keycode::KEY_B, (Some('b'), Some('B'))),
-        (Keycode::KEY_N, (Some('n'), Some('N'))),
-        (Keycode::KEY_M, (Some('m'), Some('M'))),
-        (Keycode::KEY_SPACE, (Some(' '), Some(' '))),
-    ]
-    .into();
-}
@@ -0,0 +1,398 @@
+use super::keymap::Keycode;
+use super::Modifiers;
+
+use KeySym::{Char as C, Dead as D};
+use Keycode::*;
+
+const N: KeySym = KeySym::None;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(C)]
+pub enum KeySym {
+    None,
+    Char(char),
+
+    // Dead keys do not produce a character on their own,
+    // they modify the next one (e.g ^ + e = ê)
+    Dead(char),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum KeyboardLayout {
+    US,
+    UK,
+    FR,
+    DE,
+}
+
+// [base, shift, altgr, shift + altgr]
+type KeyLevels = [KeySym; 4];
+
+impl KeyboardLayout {
+    pub const ALL: [KeyboardLayout; 4] = [
+        KeyboardLayout::US,
+        KeyboardLayout::UK,
+        KeyboardLayout::FR,
+        KeyboardLayout::DE,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeyboardLayout::US => "us",
+            KeyboardLayout::UK => "uk",
+            KeyboardLayout::FR => "fr",
+            KeyboardLayout::DE => "de",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|layout| layout.name() == name)
+    }
+
+    fn table(&self) -> &'static [(Keycode, KeyLevels)] {
+        match self {
+            KeyboardLayout::US => &LAYOUT_US,
+            KeyboardLayout::UK => &LAYOUT_UK,
+            KeyboardLayout::FR => &LAYOUT_FR,
+            KeyboardLayout::DE => &LAYOUT_DE,
+        }
+    }
+
+    pub fn resolve(&self, keycode: Keycode, modifiers: &Modifiers) -> KeySym {
+        if let Some(sym) = resolve_keypad(keycode, modifiers) {
+            return sym;
+        }
+
+        let levels = self
+            .table()
+            .iter()
+            .chain(COMMON_KEYS.iter())
+            .find(|(kc, _)| *kc == keycode)
+            .map(|(_, levels)| levels);
+
+        let levels = match levels {
+            Some(levels) => levels,
+            None => return KeySym::None,
+        };
+
+        let level = match (modifiers.altgr, modifiers.shift) {
+            (false, false) => 0,
+            (false, true) => 1,
+            (true, false) => 2,
+            (true, true) => 3,
+        };
+
+        match levels[level] {
+            // Caps Lock only affects letters, and is reversed by Shift
+            KeySym::Char(c) if modifiers.caps_lock && !modifiers.altgr && c.is_alphabetic() => {
+                let flip = |c: char| match c.is_lowercase() {
+                    true => c.to_uppercase().next().unwrap_or(c),
+                    false => c.to_lowercase().next().unwrap_or(c),
+                };
+                KeySym::Char(flip(c))
+            }
+            sym => sym,
+        }
+    }
+}
+
+pub fn compose_dead_key(dead: char, c: char) -> Option<char> {
+    DEAD_KEY_COMPOSITIONS
+        .iter()
+        .find(|(d, base, _)| *d == dead && *base == c)
+        .map(|(_, _, composed)| *composed)
+}
+
+fn resolve_keypad(keycode: Keycode, modifiers: &Modifiers) -> Option<KeySym> {
+    let digit = match keycode {
+        KEY_KP0 => '0',
+        KEY_KP1 => '1',
+        KEY_KP2 => '2',
+        KEY_KP3 => '3',
+        KEY_KP4 => '4',
+        KEY_KP5 => '5',
+        KEY_KP6 => '6',
+        KEY_KP7 => '7',
+        KEY_KP8 => '8',
+        KEY_KP9 => '9',
+        KEY_KPDOT => '.',
+        _ => return None,
+    };
+
+    match modifiers.num_lock {
+        true => Some(KeySym::Char(digit)),
+        false => Some(KeySym::None),
+    }
+}
+
+//
+// Layout tables
+
+const COMMON_KEYS: [(Keycode, KeyLevels); 5] = [
+    (KEY_SPACE, [C(' '), C(' '), C(' '), C(' ')]),
+    (KEY_KPPLUS, [C('+'), C('+'), N, N]),
+    (KEY_KPMINUS, [C('-'), C('-'), N, N]),
+    (KEY_KPASTERISK, [C('*'), C('*'), N, N]),
+    (KEY_KPSLASH, [C('/'), C('/'), N, N]),
+];
+
+const LAYOUT_US: [(Keycode, KeyLevels); 47] = [
+    (KEY_GRAVE, [C('`'), C('~'), N, N]),
+    (KEY_1, [C('1'), C('!'), N, N]),
+    (KEY_2, [C('2'), C('@'), N, N]),
+    (KEY_3, [C('3'), C('#'), N, N]),
+    (KEY_4, [C('4'), C('$'), N, N]),
+    (KEY_5, [C('5'), C('%'), N, N]),
+    (KEY_6, [C('6'), C('^'), N, N]),
+    (KEY_7, [C('7'), C('&'), N, N]),
+    (KEY_8, [C('8'), C('*'), N, N]),
+    (KEY_9, [C('9'), C('('), N, N]),
+    (KEY_0, [C('0'), C(')'), N, N]),
+    (KEY_MINUS, [C('-'), C('_'), N, N]),
+    (KEY_EQUAL, [C('='), C('+'), N, N]),
+    (KEY_Q, [C('q'), C('Q'), N, N]),
+    (KEY_W, [C('w'), C('W'), N, N]),
+    (KEY_E, [C('e'), C('E'), N, N]),
+    (KEY_R, [C('r'), C('R'), N, N]),
+    (KEY_T, [C('t'), C('T'), N, N]),
+    (KEY_Y, [C('y'), C('Y'), N, N]),
+    (KEY_U, [C('u'), C('U'), N, N]),
+    (KEY_I, [C('i'), C('I'), N, N]),
+    (KEY_O, [C('o'), C('O'), N, N]),
+    (KEY_P, [C('p'), C('P'), N, N]),
+    (KEY_LEFTBRACE, [C('['), C('{'), N, N]),
+    (KEY_RIGHTBRACE, [C(']'), C('}'), N, N]),
+    (KEY_A, [C('a'), C('A'), N, N]),
+    (KEY_S, [C('s'), C('S'), N, N]),
+    (KEY_D, [C('d'), C('D'), N, N]),
+    (KEY_F, [C('f'), C('F'), N, N]),
+    (KEY_G, [C('g'), C('G'), N, N]),
+    (KEY_H, [C('h'), C('H'), N, N]),
+    (KEY_J, [C('j'), C('J'), N, N]),
+    (KEY_K, [C('k'), C('K'), N, N]),
+    (KEY_L, [C('l'), C('L'), N, N]),
+    (KEY_SEMICOLON, [C(';'), C(':'), N, N]),
+    (KEY_APOSTROPHE, [C('\''), C('"'), N, N]),
+    (KEY_BACKSLASH, [C('\\'), C('|'), N, N]),
+    (KEY_Z, [C('z'), C('Z'), N, N]),
+    (KEY_X, [C('x'), C('X'), N, N]),
+    (KEY_C, [C('c'), C('C'), N, N]),
+    (KEY_V, [C('v'), C('V'), N, N]),
+    (KEY_B, [C('b'), C('B'), N, N]),
+    (KEY_N, [C('n'), C('N'), N, N]),
+    (KEY_M, [C('m'), C('M'), N, N]),
+    (KEY_COMMA, [C(','), C('<'), N, N]),
+    (KEY_DOT, [C('.'), C('>'), N, N]),
+    (KEY_SLASH, [C('/'), C('?'), N, N]),
+];
+
+const LAYOUT_UK: [(Keycode, KeyLevels); 48] = [
+    (KEY_GRAVE, [C('`'), C('¬'), C('¦'), N]),
+    (KEY_1, [C('1'), C('!'), N, N]),
+    (KEY_2, [C('2'), C('"'), N, N]),
+    (KEY_3, [C('3'), C('£'), N, N]),
+    (KEY_4, [C('4'), C('$'), C('€'), N]),
+    (KEY_5, [C('5'), C('%'), N, N]),
+    (KEY_6, [C('6'), C('^'), N, N]),
+    (KEY_7, [C('7'), C('&'), N, N]),
+    (KEY_8, [C('8'), C('*'), N, N]),
+    (KEY_9, [C('9'), C('('), N, N]),
+    (KEY_0, [C('0'), C(')'), N, N]),
+    (KEY_MINUS, [C('-'), C('_'), N, N]),
+    (KEY_EQUAL, [C('='), C('+'), N, N]),
+    (KEY_Q, [C('q'), C('Q'), N, N]),
+    (KEY_W, [C('w'), C('W'), N, N]),
+    (KEY_E, [C('e'), C('E'), C('é'), C('É')]),
+    (KEY_R, [C('r'), C('R'), N, N]),
+    (KEY_T, [C('t'), C('T'), N, N]),
+    (KEY_Y, [C('y'), C('Y'), N, N]),
+    (KEY_U, [C('u'), C('U'), C('ú'), C('Ú')]),
+    (KEY_I, [C('i'), C('I'), C('í'), C('Í')]),
+    (KEY_O, [C('o'), C('O'), C('ó'), C('Ó')]),
+    (KEY_P, [C('p'), C('P'), N, N]),
+    (KEY_LEFTBRACE, [C('['), C('{'), N, N]),
+    (KEY_RIGHTBRACE, [C(']'), C('}'), N, N]),
+    (KEY_A, [C('a'), C('A'), C('á'), C('Á')]),
+    (KEY_S, [C('s'), C('S'), N, N]),
+    (KEY_D, [C('d'), C('D'), N, N]),
+    (KEY_F, [C('f'), C('F'), N, N]),
+    (KEY_G, [C('g'), C('G'), N, N]),
+    (KEY_H, [C('h'), C('H'), N, N]),
+    (KEY_J, [C('j'), C('J'), N, N]),
+    (KEY_K, [C('k'), C('K'), N, N]),
+    (KEY_L, [C('l'), C('L'), N, N]),
+    (KEY_SEMICOLON, [C(';'), C(':'), N, N]),
+    (KEY_APOSTROPHE, [C('\''), C('@'), N, N]),
+    (KEY_BACKSLASH, [C('#'), C('~'), N, N]),
+    (KEY_102ND, [C('\\'), C('|'), N, N]),
+    (KEY_Z, [C('z'), C('Z'), N, N]),
+    (KEY_X, [C('x'), C('X'), N, N]),
+    (KEY_C, [C('c'), C('C'), N, N]),
+    (KEY_V, [C('v'), C('V'), N, N]),
+    (KEY_B, [C('b'), C('B'), N, N]),
+    (KEY_N, [C('n'), C('N'), N, N]),
+    (KEY_M, [C('m'), C('M'), N, N]),
+    (KEY_COMMA, [C(','), C('<'), N, N]),
+    (KEY_DOT, [C('.'), C('>'), N, N]),
+    (KEY_SLASH, [C('/'), C('?'), N, N]),
+];
+
+const LAYOUT_FR: [(Keycode, KeyLevels); 48] = [
+    (KEY_GRAVE, [C('²'), N, N, N]),
+    (KEY_1, [C('&'), C('1'), N, N]),
+    (KEY_2, [C('é'), C('2'), D('~'), N]),
+    (KEY_3, [C('"'), C('3'), C('#'), N]),
+    (KEY_4, [C('\''), C('4'), C('{'), N]),
+    (KEY_5, [C('('), C('5'), C('['), N]),
+    (KEY_6, [C('-'), C('6'), C('|'), N]),
+    (KEY_7, [C('è'), C('7'), D('`'), N]),
+    (KEY_8, [C('_'), C('8'), C('\\'), N]),
+    (KEY_9, [C('ç'), C('9'), C('^'), N]),
+    (KEY_0, [C('à'), C('0'), C('@'), N]),
+    (KEY_MINUS, [C(')'), C('°'), C(']'), N]),
+    (KEY_EQUAL, [C('='), C('+'), C('}'), N]),
+    (KEY_Q, [C('a'), C('A'), N, N]),
+    (KEY_W, [C('z'), C('Z'), N, N]),
+    (KEY_E, [C('e'), C('E'), C('€'), N]),
+    (KEY_R, [C('r'), C('R'), N, N]),
+    (KEY_T, [C('t'), C('T'), N, N]),
+    (KEY_Y, [C('y'), C('Y'), N, N]),
+    (KEY_U, [C('u'), C('U'), N, N]),
+    (KEY_I, [C('i'), C('I'), N, N]),
+    (KEY_O, [C('o'), C('O'), N, N]),
+    (KEY_P, [C('p'), C('P'), N, N]),
+    (KEY_LEFTBRACE, [D('^'), D('¨'), N, N]),
+    (KEY_RIGHTBRACE, [C('$'), C('£'), C('¤'), N]),
+    (KEY_A, [C('q'), C('Q'), N, N]),
+    (KEY_S, [C('s'), C('S'), N, N]),
+    (KEY_D, [C('d'), C('D'), N, N]),
+    (KEY_F, [C('f'), C('F'), N, N]),
+    (KEY_G, [C('g'), C('G'), N, N]),
+    (KEY_H, [C('h'), C('H'), N, N]),
+    (KEY_J, [C('j'), C('J'), N, N]),
+    (KEY_K, [C('k'), C('K'), N, N]),
+    (KEY_L, [C('l'), C('L'), N, N]),
+    (KEY_SEMICOLON, [C('m'), C('M'), N, N]),
+    (KEY_APOSTROPHE, [C('ù'), C('%'), N, N]),
+    (KEY_BACKSLASH, [C('*'), C('µ'), N, N]),
+    (KEY_102ND, [C('<'), C('>'), N, N]),
+    (KEY_Z, [C('w'), C('W'), N, N]),
+    (KEY_X, [C('x'), C('X'), N, N]),
+    (KEY_C, [C('c'), C('C'), N, N]),
+    (KEY_V, [C('v'), C('V'), N, N]),
+    (KEY_B, [C('b'), C('B'), N, N]),
+    (KEY_N, [C('n'), C('N'), N, N]),
+    (KEY_M, [C(','), C('?'), N, N]),
+    (KEY_COMMA, [C(';'), C('.'), N, N]),
+    (KEY_DOT, [C(':'), C('/'), N, N]),
+    (KEY_SLASH, [C('!'), C('§'), N, N]),
+];
+
+const LAYOUT_DE: [(Keycode, KeyLevels); 48] = [
+    (KEY_GRAVE, [D('^'), C('°'), N, N]),
+    (KEY_1, [C('1'), C('!'), N, N]),
+    (KEY_2, [C('2'), C('"'), C('²'), N]),
+    (KEY_3, [C('3'), C('§'), C('³'), N]),
+    (KEY_4, [C('4'), C('$'), N, N]),
+    (KEY_5, [C('5'), C('%'), N, N]),
+    (KEY_6, [C('6'), C('&'), N, N]),
+    (KEY_7, [C('7'), C('/'), C('{'), N]),
+    (KEY_8, [C('8'), C('('), C('['), N]),
+    (KEY_9, [C('9'), C(')'), C(']'), N]),
+    (KEY_0, [C('0'), C('='), C('}'), N]),
+    (KEY_MINUS, [C('ß'), C('?'), C('\\'), N]),
+    (KEY_EQUAL, [D('´'), D('`'), N, N]),
+    (KEY_Q, [C('q'), C('Q'), C('@'), N]),
+    (KEY_W, [C('w'), C('W'), N, N]),
+    (KEY_E, [C('e'), C('E'), C('€'), N]),
+    (KEY_R, [C('r'), C('R'), N, N]),
+    (KEY_T, [C('t'), C('T'), N, N]),
+    (KEY_Y, [C('z'), C('Z'), N, N]),
+    (KEY_U, [C('u'), C('U'), N, N]),
+    (KEY_I, [C('i'), C('I'), N, N]),
+    (KEY_O, [C('o'), C('O'), N, N]),
+    (KEY_P, [C('p'), C('P'), N, N]),
+    (KEY_LEFTBRACE, [C('ü'), C('Ü'), N, N]),
+    (KEY_RIGHTBRACE, [C('+'), C('*'), C('~'), N]),
+    (KEY_A, [C('a'), C('A'), N, N]),
+    (KEY_S, [C('s'), C('S'), N, N]),
+    (KEY_D, [C('d'), C('D'), N, N]),
+    (KEY_F, [C('f'), C('F'), N, N]),
+    (KEY_G, [C('g'), C('G'), N, N]),
+    (KEY_H, [C('h'), C('H'), N, N]),
+    (KEY_J, [C('j'), C('J'), N, N]),
+    (KEY_K, [C('k'), C('K'), N, N]),
+    (KEY_L, [C('l'), C('L'), N, N]),
+    (KEY_SEMICOLON, [C('ö'), C('Ö'), N, N]),
+    (KEY_APOSTROPHE, [C('ä'), C('Ä'), N, N]),
+    (KEY_BACKSLASH, [C('#'), C('\''), N, N]),
+    (KEY_102ND, [C('<'), C('>'), C('|'), N]),
+    (KEY_Z, [C('y'), C('Y'), N, N]),
+    (KEY_X, [C('x'), C('X'), N, N]),
+    (KEY_C, [C('c'), C('C'), N, N]),
+    (KEY_V, [C('v'), C('V'), N, N]),
+    (KEY_B, [C('b'), C('B'), N, N]),
+    (KEY_N, [C('n'), C('N'), N, N]),
+    (KEY_M, [C('m'), C('M'), C('µ'), N]),
+    (KEY_COMMA, [C(','), C(';'), N, N]),
+    (KEY_DOT, [C('.'), C(':'), N, N]),
+    (KEY_SLASH, [C('-'), C('_'), N, N]),
+];
+
+// (dead key, base character, composed character)
+const DEAD_KEY_COMPOSITIONS: [(char, char, char); 53] = [
+    ('^', 'a', 'â'),
+    ('^', 'e', 'ê'),
+    ('^', 'i', 'î'),
+    ('^', 'o', 'ô'),
+    ('^', 'u', 'û'),
+    ('^', 'A', 'Â'),
+    ('^', 'E', 'Ê'),
+    ('^', 'I', 'Î'),
+    ('^', 'O', 'Ô'),
+    ('^', 'U', 'Û'),
+    ('¨', 'a', 'ä'),
+    ('¨', 'e', 'ë'),
+    ('¨', 'i', 'ï'),
+    ('¨', 'o', 'ö'),
+    ('¨', 'u', 'ü'),
+    ('¨', 'y', 'ÿ'),
+    ('¨', 'A', 'Ä'),
+    ('¨', 'E', 'Ë'),
+    ('¨', 'I', 'Ï'),
+    ('¨', 'O', 'Ö'),
+    ('¨', 'U', 'Ü'),
+    ('´', 'a', 'á'),
+    ('´', 'e', 'é'),
+    ('´', 'i', 'í'),
+    ('´', 'o', 'ó'),
+    ('´', 'u', 'ú'),
+    ('´', 'y', 'ý'),
+    ('´', 'A', 'Á'),
+    ('´', 'E', 'É'),
+    ('´', 'I', 'Í'),
+    ('´', 'O', 'Ó'),
+    ('´', 'U', 'Ú'),
+    ('`', 'a', 'à'),
+    ('`', 'e', 'è'),
+    ('`', 'i', 'ì'),
+    ('`', 'o', 'ò'),
+    ('`', 'u', 'ù'),
+    ('`', 'A', 'À'),
+    ('`', 'E', 'È'),
+    ('`', 'I', 'Ì'),
+    ('`', 'O', 'Ò'),
+    ('`', 'U', 'Ù'),
+    ('~', 'a', 'ã'),
+    ('~', 'n', 'ñ'),
+    ('~', 'o', 'õ'),
+    ('~', 'A', 'Ã'),
+    ('~', 'N', 'Ñ'),
+    ('~', 'O', 'Õ'),
+    ('^', ' ', '^'),
+    ('¨', ' ', '¨'),
+    ('´', ' ', '´'),
+    ('`', ' ', '`'),
+    ('~', ' ', '~'),
+];
@@ -1,83 +1,196 @@
 pub mod keymap;
+pub mod layouts;
 
-use crate::geometry::Point2D;
-pub use keymap::{Keycode, CHARMAP};
+use alloc::vec::Vec;
 
-pub const MAX_EVENTS: usize = 10;
+use crate::geometry::Point2D;
+pub use keymap::Keycode;
+pub use layouts::{KeySym, KeyboardLayout};
 
 #[derive(Debug, Clone)]
-#[repr(C)]
 pub struct InputState {
     pub pointer: PointerState,
-    pub shift: bool,
-    pub events: [Option<InputEvent>; MAX_EVENTS],
-    next_event_index: usize,
+    pub modifiers: Modifiers,
+    pub layout: KeyboardLayout,
+    pub events: Vec<TimedInputEvent>,
+
+    // Pending dead key, waiting for the next character to compose with
+    dead_key: Option<char>,
 }
 
 impl InputState {
-    pub fn new(w: u32, h: u32) -> Self {
+    pub fn new(w: u32, h: u32, layout: KeyboardLayout) -> Self {
         Self {
             pointer: PointerState {
                 x: (w / 2).into(),
                 y: (h / 2).into(),
-                delta_x: 0,
-                delta_y: 0,
-                left_clicked: false,
-                right_clicked: false,
-                left_click_trigger: false,
-                right_click_trigger: false,
+                ..Default::default()
             },
-            shift: false,
-            events: [None; MAX_EVENTS],
-            next_event_index: 0,
+            modifiers: Modifiers::default(),
+            layout,
+            events: Vec::new(),
+            dead_key: None,
         }
     }
 
     pub fn clear_events(&mut self) {
-        self.next_event_index = 0;
-        self.events.fill(None);
+        self.events.clear();
     }
 
-    pub fn add_event(&mut self, event: InputEvent) {
-        self.update_shift_key_state(&event);
-
-        if self.next_event_index < self.events.len() {
-            self.events[self.next_event_index] = Some(event);
-            self.next_event_index += 1;
-        } else {
-            log::warn!(
-                "Max input events {} reached, dropping event {:?}",
-                MAX_EVENTS,
-                event
-            );
+    // Time is in milliseconds, as returned by the system clock
+    pub fn add_event(&mut self, event: InputEvent, time: f64) {
+        self.update_modifiers_state(&event);
+        self.push_event(event, time);
+
+        if let InputEvent::KeyPress { keycode } = event {
+            self.compose_text(keycode, time);
         }
     }
 
+    fn push_event(&mut self, event: InputEvent, time: f64) {
+        self.events.push(TimedInputEvent {
+            event,
+            time,
+            x: self.pointer.x,
+            y: self.pointer.y,
+        });
+    }
+
+    pub fn iter_events(&self) -> impl Iterator<Item = InputEvent> + '_ {
+        self.events.iter().map(|timed| timed.event)
+    }
+
     pub fn change_origin(&mut self, origin: Point2D<i64>) {
         self.pointer.x -= origin.x;
         self.pointer.y -= origin.y;
+        self.pointer.drag_origin_x -= origin.x;
+        self.pointer.drag_origin_y -= origin.y;
+
+        for timed in self.events.iter_mut() {
+            timed.x -= origin.x;
+            timed.y -= origin.y;
+        }
     }
 
     pub fn check_key_pressed(&self, kc: Keycode) -> bool {
-        self.events.iter().any(|event| match event {
-            Some(InputEvent::KeyPress { keycode }) if *keycode == kc => true,
+        self.iter_events().any(|event| match event {
+            InputEvent::KeyPress { keycode } if keycode == kc => true,
             _ => false,
         })
     }
 
-    fn update_shift_key_state(&mut self, event: &InputEvent) {
-        let check_is_shift =
-            |&keycode| keycode == Keycode::KEY_LEFTSHIFT || keycode == Keycode::KEY_RIGHTSHIFT;
+    fn update_modifiers_state(&mut self, event: &InputEvent) {
+        let (keycode, pressed) = match *event {
+            InputEvent::KeyPress { keycode } => (keycode, true),
+            InputEvent::KeyRelease { keycode } => (keycode, false),
+            _ => return,
+        };
+
+        let m = &mut self.modifiers;
+
+        match keycode {
+            Keycode::KEY_LEFTSHIFT | Keycode::KEY_RIGHTSHIFT => m.shift = pressed,
+            Keycode::KEY_LEFTCTRL | Keycode::KEY_RIGHTCTRL => m.ctrl = pressed,
+            Keycode::KEY_LEFTALT => m.alt = pressed,
+            Keycode::KEY_RIGHTALT => m.altgr = pressed,
+            Keycode::KEY_LEFTMETA | Keycode::KEY_RIGHTMETA => m.meta = pressed,
+
+            // Lock keys toggle on press
+            Keycode::KEY_CAPSLOCK if pressed => m.caps_lock = !m.caps_lock,
+            Keycode::KEY_NUMLOCK if pressed => m.num_lock = !m.num_lock,
 
-        match event {
-            InputEvent::KeyPress { keycode } if check_is_shift(keycode) => self.shift = true,
-            InputEvent::KeyRelease { keycode } if check_is_shift(keycode) => self.shift = false,
             _ => (),
         }
     }
+
+    // Character produced by a key, without dead key composition (used for key repeats)
+    pub fn key_char(&self, keycode: Keycode) -> Option<char> {
+        match self.resolve_key(keycode) {
+            KeySym::Char(c) => Some(c),
+            _ => None,
+        }
+    }
+
+    fn resolve_key(&self, keycode: Keycode) -> KeySym {
+        // Keyboard shortcuts should not produce text
+        if self.modifiers.ctrl || self.modifiers.alt || self.modifiers.meta {
+            return KeySym::None;
+        }
+
+        self.layout.resolve(keycode, &self.modifiers)
+    }
+
+    fn compose_text(&mut self, keycode: Keycode, time: f64) {
+        match self.resolve_key(keycode) {
+            // Other keys (Enter, Backspace, arrows, shortcuts...) cancel a pending dead key,
+            // but modifiers do not, since they may be needed for the next character
+            KeySym::None if is_modifier(keycode) => (),
+            KeySym::None => self.dead_key = None,
+
+            KeySym::Char(c) => match self.dead_key.take() {
+                None => self.push_event(InputEvent::Char { c }, time),
+                Some(dead) => match layouts::compose_dead_key(dead, c) {
+                    Some(composed) => self.push_event(InputEvent::Char { c: composed }, time),
+                    None => {
+                        self.push_event(InputEvent::Char { c: dead }, time);
+                        self.push_event(InputEvent::Char { c }, time);
+                    }
+                },
+            },
+
+            // Pressing a dead key twice outputs it as-is
+            KeySym::Dead(dead) => match self.dead_key.take() {
+                Some(prev) if prev == dead => self.push_event(InputEvent::Char { c: dead }, time),
+                Some(prev) => {
+                    self.push_event(InputEvent::Char { c: prev }, time);
+                    self.dead_key = Some(dead);
+                }
+                None => self.dead_key = Some(dead),
+            },
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+fn is_modifier(keycode: Keycode) -> bool {
+    matches!(
+        keycode,
+        Keycode::KEY_LEFTSHIFT
+            | Keycode::KEY_RIGHTSHIFT
+            | Keycode::KEY_LEFTCTRL
+            | Keycode::KEY_RIGHTCTRL
+            | Keycode::KEY_LEFTALT
+            | Keycode::KEY_RIGHTALT
+            | Keycode::KEY_LEFTMETA
+            | Keycode::KEY_RIGHTMETA
+            | Keycode::KEY_CAPSLOCK
+            | Keycode::KEY_NUMLOCK
+    )
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct TimedInputEvent {
+    pub event: InputEvent,
+    pub time: f64,
+
+    // Pointer position when the event happened
+    pub x: i64,
+    pub y: i64,
+}
+
+#[derive(Debug, Clone, Default)]
+#[repr(C)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub altgr: bool,
+    pub meta: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+}
+
+#[derive(Debug, Clone, Default)]
 #[repr(C)]
 
 pub struct PointerState {
@@ -87,8 +200,34 @@ pub struct PointerState {
     pub delta_y: i64,
     pub left_clicked: bool,
     pub right_clicked: bool,
+    pub middle_clicked: bool,
     pub left_click_trigger: bool,
     pub right_click_trigger: bool,
+    pub middle_click_trigger: bool,
+    pub left_release_trigger: bool,
+    pub right_release_trigger: bool,
+    pub middle_release_trigger: bool,
+
+    // Number of successive left clicks (2 for a double click, 3 for a triple click...),
+    // only meaningful when left_click_trigger is set
+    pub click_count: u32,
+
+    // Left button held while the pointer moved away from where it was pressed
+    pub dragging: bool,
+    pub drag_start_trigger: bool,
+    pub drag_end_trigger: bool,
+    pub drag_origin_x: i64,
+    pub drag_origin_y: i64,
+}
+
+impl PointerState {
+    pub fn double_click_trigger(&self) -> bool {
+        self.left_click_trigger && self.click_count == 2
+    }
+
+    pub fn triple_click_trigger(&self) -> bool {
+        self.left_click_trigger && self.click_count == 3
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -96,5 +235,12 @@ pub struct PointerState {
 pub enum InputEvent {
     KeyPress { keycode: Keycode },
     KeyRelease { keycode: Keycode },
+
+    // Generated by the kernel while a key is held down
+    KeyRepeat { keycode: Keycode },
     Scroll { delta: i64 },
+    HScroll { delta: i64 },
+
+    // Text input, resolved from key presses according to the keyboard layout
+    Char { c: char },
 }
@@ -4,11 +4,13 @@ extern crate alloc;
 
 use zune_png::PngDecoder;
 
+pub mod abi;
 pub mod content;
 pub mod drawing;
 pub mod geometry;
 pub mod hash;
 pub mod input;
+pub mod png;
 mod stylesheet;
 pub mod uitk;
 
@@ -388,6 +390,19 @@ impl<'a> Framebuffer<BorrowedPixels<'a>> {
 
         Self::new(data, w, h)
     }
+
+    // Same as subregion(), but keeps the lifetime of the underlying data
+    pub fn into_subregion(self, rect: &Rect) -> Framebuffer<BorrowedPixels<'a>> {
+        let Rect { x0, y0, w, h } = *rect;
+        let (x0, y0) = self.to_data_coords(x0, y0);
+
+        Framebuffer {
+            data: self.data,
+            data_w: self.data_w,
+            data_h: self.data_h,
+            rect: Rect { x0, y0, w, h },
+        }
+    }
 }
 
 impl Framebuffer<OwnedPixels> {
@@ -434,6 +449,65 @@ impl Framebuffer<OwnedPixels> {
         }
     }
 
+    // Box filter: each destination pixel averages the source pixels it covers
+    pub fn downscaled_from<F: FbView>(src: &F, w: u32, h: u32) -> Self {
+        let (src_w, src_h) = src.shape();
+        let src_data = src.get_data();
+
+        // Source range covered by destination index i, never empty (for upscaling)
+        let src_range = |i: u32, dst_len: u32, src_len: u32| {
+            let start = (i as u64 * src_len as u64 / dst_len as u64) as u32;
+            let end = ((i + 1) as u64 * src_len as u64 / dst_len as u64) as u32;
+            (start, u32::max(end, start + 1))
+        };
+
+        let mut data = Vec::with_capacity((w * h) as usize);
+
+        for y in 0..h {
+            let (sy0, sy1) = src_range(y, h, src_h);
+            for x in 0..w {
+                let (sx0, sx1) = src_range(x, w, src_w);
+
+                let mut acc = [0u32; 4];
+                for sy in sy0..sy1 {
+                    for sx in sx0..sx1 {
+                        let offset = src
+                            .get_offset_region_coords(sx as i64, sy as i64)
+                            .expect("Pixel out of framebuffer");
+                        let Color(rgba) = src_data[offset];
+                        for i in 0..4 {
+                            acc[i] += rgba[i] as u32;
+                        }
+                    }
+                }
+
+                let n = (sx1 - sx0) * (sy1 - sy0);
+                data.push(Color(acc.map(|v| (v / n) as u8)));
+            }
+        }
+
+        let rect = Rect { x0: 0, y0: 0, w, h };
+
+        Framebuffer {
+            data: OwnedPixels(data),
+            data_w: w,
+            data_h: h,
+            rect,
+        }
+    }
+
+    // Downscales to fit within (max_w, max_h), preserving the aspect ratio
+    pub fn thumbnail_from<F: FbView>(src: &F, max_w: u32, max_h: u32) -> Self {
+        let (src_w, src_h) = src.shape();
+        let scale = f32::min(max_w as f32 / src_w as f32, max_h as f32 / src_h as f32);
+        let scale = f32::min(scale, 1.0);
+
+        let w = u32::max(1, (src_w as f32 * scale) as u32);
+        let h = u32::max(1, (src_h as f32 * scale) as u32);
+
+        Self::downscaled_from(src, w, h)
+    }
+
     pub fn size_bytes(&self) -> usize {
         self.data.as_slice().len() * 4
     }
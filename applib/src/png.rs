@@ -0,0 +1,258 @@
+use alloc::vec::Vec;
+
+use crate::FbView;
+
+//
+// Minimal PNG encoder (decoding is done with zune-png)
+//
+// Writes 8-bit RGB images (alpha is dropped), with the "Sub" filter on every row
+// and a deflate stream using the fixed Huffman codes. Only runs of identical bytes
+// are matched (distance 1), which is enough for flat UI regions to compress well.
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+const COLOR_TYPE_RGB: u8 = 2;
+const FILTER_SUB: u8 = 1;
+const BYTES_PER_PIXEL: usize = 3;
+
+// Max size of IDAT chunks
+const MAX_CHUNK_SIZE: usize = 1 << 16;
+
+pub fn encode_png<F: FbView>(fb: &F) -> Vec<u8> {
+    let (w, h) = fb.shape();
+
+    let filtered = filter_rows(fb);
+    let compressed = zlib_compress(&filtered);
+
+    let mut out = Vec::with_capacity(compressed.len() + 128);
+    out.extend_from_slice(&PNG_SIGNATURE);
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&w.to_be_bytes());
+    ihdr.extend_from_slice(&h.to_be_bytes());
+    ihdr.extend_from_slice(&[8, COLOR_TYPE_RGB, 0, 0, 0]); // Bit depth, color type, compression, filter, interlace
+    write_chunk(&mut out, b"IHDR", &ihdr);
+
+    for data in compressed.chunks(MAX_CHUNK_SIZE) {
+        write_chunk(&mut out, b"IDAT", data);
+    }
+
+    write_chunk(&mut out, b"IEND", &[]);
+
+    out
+}
+
+fn filter_rows<F: FbView>(fb: &F) -> Vec<u8> {
+    let (w, h) = fb.shape();
+    let (w, h) = (w as usize, h as usize);
+
+    let row_len = 1 + w * BYTES_PER_PIXEL;
+    let mut out = Vec::with_capacity(row_len * h);
+
+    let data = fb.get_data();
+
+    for y in 0..h {
+        out.push(FILTER_SUB);
+
+        let mut prev = [0u8; BYTES_PER_PIXEL];
+        for x in 0..w {
+            let offset = fb
+                .get_offset_region_coords(x as i64, y as i64)
+                .expect("Pixel out of framebuffer");
+            let (r, g, b, _a) = data[offset].as_rgba();
+            let rgb = [r, g, b];
+            for i in 0..BYTES_PER_PIXEL {
+                out.push(rgb[i].wrapping_sub(prev[i]));
+            }
+            prev = rgb;
+        }
+    }
+
+    out
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+
+    let crc_start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[crc_start..]);
+
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+//
+// Deflate (fixed Huffman codes, RFC 1951)
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+
+// (base length, extra bits) for length codes 257..=285
+const LENGTH_CODES: [(u16, u8); 29] = [
+    (3, 0),
+    (4, 0),
+    (5, 0),
+    (6, 0),
+    (7, 0),
+    (8, 0),
+    (9, 0),
+    (10, 0),
+    (11, 1),
+    (13, 1),
+    (15, 1),
+    (17, 1),
+    (19, 2),
+    (23, 2),
+    (27, 2),
+    (31, 2),
+    (35, 3),
+    (43, 3),
+    (51, 3),
+    (59, 3),
+    (67, 4),
+    (83, 4),
+    (99, 4),
+    (115, 4),
+    (131, 5),
+    (163, 5),
+    (195, 5),
+    (227, 5),
+    (258, 0),
+];
+
+fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+
+    // CMF / FLG: deflate with a 32K window, no dictionary, default level
+    writer.out.extend_from_slice(&[0x78, 0x9c]);
+
+    writer.write_bits(1, 1); // BFINAL
+    writer.write_bits(0b01, 2); // BTYPE = fixed Huffman
+
+    let mut i = 0;
+    while i < data.len() {
+        let run_len = match i > 0 {
+            true => data[i..]
+                .iter()
+                .take(MAX_MATCH)
+                .take_while(|&&b| b == data[i - 1])
+                .count(),
+            false => 0,
+        };
+
+        if run_len >= MIN_MATCH {
+            writer.write_length(run_len);
+            writer.write_fixed_code(0, 5); // Distance code 0 (distance 1)
+            i += run_len;
+        } else {
+            writer.write_literal(data[i]);
+            i += 1;
+        }
+    }
+
+    writer.write_literal_code(256); // End of block
+    writer.flush();
+
+    writer.out.extend_from_slice(&adler32(data).to_be_bytes());
+
+    writer.out
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u32,
+    n_bits: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter {
+            out: Vec::new(),
+            acc: 0,
+            n_bits: 0,
+        }
+    }
+
+    // Data elements are packed LSB first
+    fn write_bits(&mut self, val: u32, n: u32) {
+        self.acc |= val << self.n_bits;
+        self.n_bits += n;
+        while self.n_bits >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.n_bits -= 8;
+        }
+    }
+
+    // Huffman codes are packed MSB first
+    fn write_fixed_code(&mut self, code: u32, n: u32) {
+        let reversed = code.reverse_bits() >> (32 - n);
+        self.write_bits(reversed, n);
+    }
+
+    fn write_literal(&mut self, byte: u8) {
+        self.write_literal_code(byte as u16);
+    }
+
+    fn write_literal_code(&mut self, val: u16) {
+        let val = val as u32;
+        match val {
+            0..=143 => self.write_fixed_code(0x30 + val, 8),
+            144..=255 => self.write_fixed_code(0x190 + val - 144, 9),
+            256..=279 => self.write_fixed_code(val - 256, 7),
+            _ => self.write_fixed_code(0xc0 + val - 280, 8),
+        }
+    }
+
+    fn write_length(&mut self, len: usize) {
+        let index = LENGTH_CODES
+            .iter()
+            .rposition(|&(base, _)| base as usize <= len)
+            .unwrap();
+        let (base, extra_bits) = LENGTH_CODES[index];
+
+        self.write_literal_code(257 + index as u16);
+        self.write_bits((len - base as usize) as u32, extra_bits as u32);
+    }
+
+    fn flush(&mut self) {
+        if self.n_bits > 0 {
+            self.out.push(self.acc as u8);
+            self.acc = 0;
+            self.n_bits = 0;
+        }
+    }
+}
+
+//
+// Checksums
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    // Max number of bytes before the sums have to be reduced to avoid overflowing
+    const NMAX: usize = 5552;
+
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(NMAX) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
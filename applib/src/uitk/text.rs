@@ -3,8 +3,8 @@ use alloc::vec::Vec;
 
 use crate::content::TrackedContent;
 use crate::drawing::text::{draw_rich_slice, FormattedRichText};
+use crate::input::Keycode;
 use crate::input::{InputEvent, InputState};
-use crate::input::{Keycode, CHARMAP};
 use crate::Rect;
 use crate::{FbView, FbViewMut};
 
@@ -14,6 +14,7 @@ pub fn string_input<T: EditableText>(
     buffer: &mut T,
     input_state: &InputState,
     allow_newline: bool,
+    key_repeat: bool,
     cursor: &mut usize,
     uuid_provider: &mut UuidProvider,
 ) {
@@ -28,46 +29,45 @@ pub fn string_input<T: EditableText>(
 
     let mut updates = Vec::new();
 
-    for event in input_state.events {
-        match event {
+    for event in input_state.iter_events() {
+        let keycode = match event {
+            InputEvent::KeyPress { keycode } => keycode,
+
+            InputEvent::KeyRepeat { keycode } if key_repeat => {
+                if let Some(c) = input_state.key_char(keycode) {
+                    updates.push(TextUpdate::Char(c));
+                }
+                keycode
+            }
+
+            // Character input
+            InputEvent::Char { c } => {
+                updates.push(TextUpdate::Char(c));
+                continue;
+            }
+
+            _ => continue,
+        };
+
+        match keycode {
             // Enter
-            Some(InputEvent::KeyPress {
-                keycode: Keycode::KEY_ENTER,
-            }) if allow_newline => {
+            Keycode::KEY_ENTER if allow_newline => {
                 updates.push(TextUpdate::Newline);
             }
 
             // Backspace
-            Some(InputEvent::KeyPress {
-                keycode: Keycode::KEY_BACKSPACE,
-            }) => {
+            Keycode::KEY_BACKSPACE => {
                 updates.push(TextUpdate::Backspace);
             }
 
             // Cursor movement
-            Some(InputEvent::KeyPress {
-                keycode: Keycode::KEY_LEFT,
-            }) if *cursor > 0 => {
+            Keycode::KEY_LEFT if *cursor > 0 => {
                 *cursor -= 1;
             }
-            Some(InputEvent::KeyPress {
-                keycode: Keycode::KEY_RIGHT,
-            }) if *cursor < buf_len => {
+            Keycode::KEY_RIGHT if *cursor < buf_len => {
                 *cursor += 1;
             }
 
-            // Character input
-            Some(InputEvent::KeyPress { keycode }) => {
-                let new_char = CHARMAP
-                    .get(&keycode)
-                    .map(|(low_c, up_c)| if input_state.shift { *up_c } else { *low_c })
-                    .flatten();
-
-                if let Some(new_char) = new_char {
-                    updates.push(TextUpdate::Char(new_char))
-                }
-            }
-
             _ => (),
         };
     }
@@ -102,18 +102,25 @@ pub trait EditableText {
 
 impl EditableText for TrackedContent<String> {
     fn len(&self) -> usize {
-        self.as_ref().len()
+        self.as_ref().chars().count()
     }
 
     fn insert(&mut self, uuid_provider: &mut UuidProvider, pos: usize, c: char) {
-        self.mutate(uuid_provider).insert(pos, c);
+        let byte_pos = char_to_byte_index(self.as_ref(), pos);
+        self.mutate(uuid_provider).insert(byte_pos, c);
     }
 
     fn remove(&mut self, uuid_provider: &mut UuidProvider, pos: usize) {
-        self.mutate(uuid_provider).remove(pos);
+        let byte_pos = char_to_byte_index(self.as_ref(), pos);
+        self.mutate(uuid_provider).remove(byte_pos);
     }
 }
 
+// Cursor positions are in chars, but String operations expect byte indices
+fn char_to_byte_index(s: &str, pos: usize) -> usize {
+    s.char_indices().nth(pos).map(|(i, _)| i).unwrap_or(s.len())
+}
+
 pub fn render_rich_text<F: FbViewMut>(
     dst_fb: &mut F,
     origin: (i64, i64),
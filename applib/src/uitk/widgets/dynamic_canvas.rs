@@ -88,11 +88,9 @@ impl<'a, F: FbViewMut> UiContext<'a, F> {
                 *scroll_y0 +=
                     (src_max_h as i64) * input_state.pointer.delta_y / (dst_rect.h as i64);
             } else {
-                for event in input_state.events {
-                    if let Some(InputEvent::Scroll { delta }) = event {
-                        let x = input_state.pointer.x;
-                        let y = input_state.pointer.y;
-                        if dst_rect.check_contains_point(x, y) {
+                for timed in input_state.events.iter() {
+                    if let InputEvent::Scroll { delta } = timed.event {
+                        if dst_rect.check_contains_point(timed.x, timed.y) {
                             *scroll_y0 -= delta * (SCROLL_SPEED as i64);
                         }
                     }
@@ -139,6 +137,14 @@ impl<'a, F: FbViewMut> UiContext<'a, F> {
             if *x_dragging {
                 *scroll_x0 +=
                     (src_max_w as i64) * input_state.pointer.delta_x / (dst_rect.w as i64);
+            } else {
+                for timed in input_state.events.iter() {
+                    if let InputEvent::HScroll { delta } = timed.event {
+                        if dst_rect.check_contains_point(timed.x, timed.y) {
+                            *scroll_x0 += delta * (SCROLL_SPEED as i64);
+                        }
+                    }
+                }
             }
 
             *scroll_x0 = i64::max(0, *scroll_x0);
@@ -55,6 +55,7 @@ impl<'a, F: FbViewMut> UiContext<'a, F> {
             text,
             input_state,
             allow_newline,
+            state.key_repeat,
             &mut state.cursor,
             *uuid_provider,
         );
@@ -192,6 +193,9 @@ pub struct TextBoxState {
     pub cursor: usize,
     pub justif: TextJustification,
 
+    // Whether held keys keep typing (KeyRepeat events)
+    pub key_repeat: bool,
+
     cursor_visible: bool,
     last_blink_t: u64,
 }
@@ -204,6 +208,7 @@ impl TextBoxState {
             scroll_dragging: (false, false),
             cursor: 0,
             justif: TextJustification::Left,
+            key_repeat: true,
             cursor_visible: true,
             last_blink_t: 0,
         }
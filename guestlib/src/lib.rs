@@ -4,7 +4,7 @@ extern crate alloc;
 use alloc::format;
 use alloc::vec;
 use alloc::vec::Vec;
-use applib::StyleSheet;
+use applib::{abi, StyleSheet};
 use applib::{input::InputState, BorrowedMutPixels, Color, Framebuffer, Rect};
 use core::fmt::Debug;
 use core::mem::size_of;
@@ -16,7 +16,7 @@ static ALLOC: dlmalloc::GlobalDlmalloc = dlmalloc::GlobalDlmalloc;
 extern "C" {
 
     fn host_log(addr: i32, len: i32, level: i32);
-    fn host_get_input_state(addr: i32);
+    fn host_get_input_state(addr: i32, max_len: i32) -> i32;
     fn host_get_win_rect(addr: i32);
     fn host_set_framebuffer(addr: i32, w: i32, h: i32);
 
@@ -32,9 +32,18 @@ extern "C" {
     fn host_get_consumed_fuel(addr: i32);
     fn host_save_timing(key_addr: i32, key_len: i32, consumed_addr: i32);
 
+    fn host_take_screenshot();
+    fn host_take_window_screenshot();
+
     fn host_qemu_dump(addr: i32, len: i32);
 }
 
+// Checked by the kernel when loading the app, and used to encode the data it passes to it
+#[no_mangle]
+pub extern "C" fn munal_abi_version() -> i32 {
+    abi::ABI_VERSION as i32
+}
+
 #[derive(Debug)]
 struct FramebufferHandle {
     ptr: *mut Color,
@@ -68,21 +77,34 @@ impl FramebufferHandle {
 }
 
 pub fn get_input_state() -> InputState {
-    let mut buf = [0u8; size_of::<InputState>()];
-    let addr = buf.as_mut_ptr() as i32;
-    unsafe {
-        host_get_input_state(addr);
-        core::mem::transmute(buf)
+    const INIT_BUF_SIZE: usize = 1024;
+
+    let mut buf = vec![0u8; INIT_BUF_SIZE];
+
+    // The host returns the required size and only writes if the buffer is large enough
+    loop {
+        let addr = buf.as_mut_ptr() as i32;
+        let max_len = buf.len() as i32;
+        let required_len = unsafe { host_get_input_state(addr, max_len) } as usize;
+
+        if required_len <= buf.len() {
+            break;
+        }
+
+        buf.resize(required_len, 0);
     }
+
+    let (len_prefix, payload) = buf.split_at(size_of::<u32>());
+    let payload_len = u32::from_le_bytes(len_prefix.try_into().unwrap()) as usize;
+
+    abi::decode_input_state(&payload[..payload_len])
 }
 
 pub fn get_win_rect() -> Rect {
-    let mut buf = [0u8; size_of::<Rect>()];
+    let mut buf = [0u8; abi::RECT_SIZE];
     let addr = buf.as_mut_ptr() as i32;
-    unsafe {
-        host_get_win_rect(addr);
-        core::mem::transmute(buf)
-    }
+    unsafe { host_get_win_rect(addr) };
+    abi::decode_rect(&buf)
 }
 
 pub struct PixelData {
@@ -188,12 +210,10 @@ pub fn get_time() -> f64 {
 }
 
 pub fn get_stylesheet() -> StyleSheet {
-    let mut buf = [0u8; size_of::<StyleSheet>()];
+    let mut buf = [0u8; abi::STYLESHEET_SIZE];
     let addr = buf.as_mut_ptr() as i32;
-    unsafe {
-        host_get_stylesheet(addr);
-        core::mem::transmute(buf)
-    }
+    unsafe { host_get_stylesheet(addr) };
+    abi::decode_stylesheet(&buf)
 }
 
 pub fn get_consumed_fuel() -> u64 {
@@ -243,6 +263,16 @@ pub fn save_timing(key: &str, consumed: u64) {
     }
 }
 
+// The screenshot is taken at the end of the next frame, and streamed over the kernel console
+pub fn take_screenshot() {
+    unsafe { host_take_screenshot() };
+}
+
+// Same as take_screenshot(), but only captures the content of the calling app's window
+pub fn take_window_screenshot() {
+    unsafe { host_take_window_screenshot() };
+}
+
 pub fn qemu_dump(buf: &[u8]) {
     let addr = buf.as_ptr() as i32;
     let len = buf.len() as i32;
@@ -0,0 +1,449 @@
+use alloc::vec::Vec;
+use uefi::prelude::{Boot, SystemTable};
+use uefi::table::cfg::{ACPI2_GUID, ACPI_GUID};
+use x86_64::instructions::port::Port;
+
+use crate::memory;
+
+//
+// ACPI table parsing (RSDP, XSDT/RSDT, MADT, FADT, MCFG) and power management.
+// Only the fields the kernel actually uses are extracted; AML is not interpreted,
+// apart from a pattern search for the \_S5 sleep type in the DSDT.
+
+const SDT_HEADER_LEN: usize = 36;
+
+// PM1 control register fields
+const SLP_EN: u16 = 1 << 13;
+const SLP_TYP_SHIFT: u16 = 10;
+const SCI_EN: u16 = 1;
+
+// FADT flags
+const RESET_REG_SUP: u32 = 1 << 10;
+
+// Generic Address Structure address spaces
+const GAS_SYSTEM_MEMORY: u8 = 0;
+const GAS_SYSTEM_IO: u8 = 1;
+
+pub struct Acpi {
+    pub madt: Option<Madt>,
+    pub fadt: Option<Fadt>,
+    pub mcfg: Vec<McfgEntry>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Madt {
+    pub local_apic_addr: u64,
+    pub processors: Vec<LocalApic>,
+    // Interrupts are routed through the local APICs only, so I/O APICs are just counted
+    pub n_io_apics: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LocalApic {
+    pub apic_id: u32,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Fadt {
+    pub smi_cmd: u32,
+    pub acpi_enable: u8,
+    pub pm1a_cnt: u16,
+    pub pm1b_cnt: u16,
+    pub reset_reg: Option<(GenericAddress, u8)>,
+
+    // From the \_S5 object in the DSDT
+    pub s5_sleep_types: Option<(u16, u16)>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GenericAddress {
+    pub space_id: u8,
+    pub addr: u64,
+}
+
+// PCIe enhanced configuration space (ECAM) region
+#[derive(Debug, Clone, Copy)]
+pub struct McfgEntry {
+    pub base_addr: u64,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+// Must be called before exiting UEFI boot services
+pub fn find_rsdp(system_table: &SystemTable<Boot>) -> Option<u64> {
+    let entries = system_table.config_table();
+
+    // Preferring the ACPI 2.0 RSDP, which points to the XSDT
+    entries
+        .iter()
+        .find(|entry| entry.guid == ACPI2_GUID)
+        .or_else(|| entries.iter().find(|entry| entry.guid == ACPI_GUID))
+        .map(|entry| entry.address as u64)
+}
+
+impl Acpi {
+    pub fn parse(rsdp_addr: Option<u64>) -> Self {
+        let mut acpi = Acpi {
+            madt: None,
+            fadt: None,
+            mcfg: Vec::new(),
+        };
+
+        let Some(rsdp_addr) = rsdp_addr else {
+            log::warn!("No ACPI RSDP found");
+            return acpi;
+        };
+
+        for table in root_tables(rsdp_addr) {
+            match &table[..4] {
+                b"APIC" => acpi.madt = Some(parse_madt(table)),
+                b"FACP" => acpi.fadt = Some(parse_fadt(table)),
+                b"MCFG" => acpi.mcfg = parse_mcfg(table),
+                _ => (),
+            }
+        }
+
+        if let Some(madt) = &acpi.madt {
+            log::info!(
+                "ACPI: {} processors, {} I/O APICs, local APIC at {:#x}",
+                madt.processors.len(),
+                madt.n_io_apics,
+                madt.local_apic_addr
+            );
+        }
+        if let Some(fadt) = &acpi.fadt {
+            log::info!(
+                "ACPI: PM1a control at {:#x}, reset register: {}, S5: {:?}",
+                fadt.pm1a_cnt,
+                fadt.reset_reg.is_some(),
+                fadt.s5_sleep_types
+            );
+        }
+        for entry in acpi.mcfg.iter() {
+            log::info!(
+                "ACPI: ECAM region at {:#x} (segment {}, buses {}-{})",
+                entry.base_addr,
+                entry.segment,
+                entry.start_bus,
+                entry.end_bus
+            );
+        }
+
+        acpi
+    }
+
+    // Only returns if shutting down is not possible, in which case the system keeps running
+    pub fn shutdown(&self) -> anyhow::Result<()> {
+        let fadt = self
+            .fadt
+            .as_ref()
+            .ok_or_else(|| anyhow::format_err!("Cannot shut down: no FADT"))?;
+        let (slp_typ_a, slp_typ_b) = fadt
+            .s5_sleep_types
+            .ok_or_else(|| anyhow::format_err!("Cannot shut down: no \\_S5 object in the DSDT"))?;
+
+        log::info!("Shutting down");
+
+        unsafe {
+            fadt.enable_acpi_mode();
+            write_pm1_cnt(fadt.pm1a_cnt, slp_typ_a);
+            if fadt.pm1b_cnt != 0 {
+                write_pm1_cnt(fadt.pm1b_cnt, slp_typ_b);
+            }
+        }
+
+        // Powering off may take a moment
+        spin_wait();
+
+        Err(anyhow::format_err!("Shutdown failed"))
+    }
+
+    // Same as shutdown()
+    pub fn reboot(&self) -> anyhow::Result<()> {
+        log::info!("Rebooting");
+
+        if let Some((reset_reg, reset_value)) = self.fadt.as_ref().and_then(|f| f.reset_reg) {
+            unsafe { reset_reg.write_u8(reset_value) };
+            spin_wait();
+        }
+
+        // Fallback: pulsing the CPU reset line through the 8042 keyboard controller
+        log::warn!("ACPI reset failed, trying the keyboard controller");
+        unsafe { Port::<u8>::new(0x64).write(0xfe) };
+        spin_wait();
+
+        Err(anyhow::format_err!("Reboot failed"))
+    }
+}
+
+impl Fadt {
+    // Firmware may leave the system in legacy mode, in which case PM1 writes are ignored
+    unsafe fn enable_acpi_mode(&self) {
+        let sci_enabled = Port::<u16>::new(self.pm1a_cnt).read() & SCI_EN != 0;
+        if sci_enabled || self.smi_cmd == 0 || self.acpi_enable == 0 {
+            return;
+        }
+
+        Port::<u8>::new(self.smi_cmd as u16).write(self.acpi_enable);
+
+        let mut port = Port::<u16>::new(self.pm1a_cnt);
+        for _ in 0..1_000_000 {
+            if port.read() & SCI_EN != 0 {
+                return;
+            }
+        }
+        log::warn!("Timed out waiting for ACPI mode");
+    }
+}
+
+impl GenericAddress {
+    fn parse(bytes: &[u8]) -> Self {
+        GenericAddress {
+            space_id: bytes[0],
+            addr: read_u64(bytes, 4),
+        }
+    }
+
+    unsafe fn write_u8(&self, value: u8) {
+        match self.space_id {
+            GAS_SYSTEM_IO => Port::<u8>::new(self.addr as u16).write(value),
+            GAS_SYSTEM_MEMORY => {
+                let mapper = memory::get_mapper();
+                let ptr: *mut u8 = mapper
+                    .phys_to_virt(x86_64::PhysAddr::new(self.addr))
+                    .as_mut_ptr();
+                core::ptr::write_volatile(ptr, value);
+            }
+            space_id => log::error!("Unsupported ACPI address space {}", space_id),
+        }
+    }
+}
+
+unsafe fn write_pm1_cnt(port: u16, slp_typ: u16) {
+    let mut port = Port::<u16>::new(port);
+    let val = port.read() & !(0b111 << SLP_TYP_SHIFT);
+    port.write(val | (slp_typ << SLP_TYP_SHIFT) | SLP_EN);
+}
+
+// Writes to the POST code port take about a microsecond (the usual I/O delay), so this waits
+// for about a second
+fn spin_wait() {
+    let mut port = Port::<u8>::new(0x80);
+    for _ in 0..1_000_000 {
+        unsafe { port.write(0) };
+    }
+}
+
+pub fn halt_forever() -> ! {
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+//
+// Table discovery
+
+fn root_tables(rsdp_addr: u64) -> Vec<&'static [u8]> {
+    let rsdp = unsafe { phys_slice(rsdp_addr, 36) };
+
+    if &rsdp[..8] != b"RSD PTR " || checksum(&rsdp[..20]) != 0 {
+        log::error!("Invalid ACPI RSDP at {:#x}", rsdp_addr);
+        return Vec::new();
+    }
+
+    let revision = rsdp[15];
+    let (root_addr, entry_size) = match revision >= 2 {
+        true => (read_u64(rsdp, 24), 8),
+        false => (read_u32(rsdp, 16) as u64, 4),
+    };
+
+    let Some(root) = load_table(root_addr) else {
+        log::error!("Invalid ACPI root table at {:#x}", root_addr);
+        return Vec::new();
+    };
+
+    root[SDT_HEADER_LEN..]
+        .chunks_exact(entry_size)
+        .filter_map(|entry| {
+            let addr = match entry_size {
+                8 => read_u64(entry, 0),
+                _ => read_u32(entry, 0) as u64,
+            };
+            let table = load_table(addr);
+            if table.is_none() {
+                log::warn!("Skipping invalid ACPI table at {:#x}", addr);
+            }
+            table
+        })
+        .collect()
+}
+
+fn load_table(addr: u64) -> Option<&'static [u8]> {
+    if addr == 0 {
+        return None;
+    }
+
+    let header = unsafe { phys_slice(addr, SDT_HEADER_LEN) };
+    let len = read_u32(header, 4) as usize;
+    if len < SDT_HEADER_LEN {
+        return None;
+    }
+
+    let table = unsafe { phys_slice(addr, len) };
+    match checksum(table) {
+        0 => Some(table),
+        _ => None,
+    }
+}
+
+// ACPI tables live in memory reserved by the firmware, which stays mapped and untouched
+unsafe fn phys_slice(addr: u64, len: usize) -> &'static [u8] {
+    let mapper = memory::get_mapper();
+    let ptr: *const u8 = mapper.phys_to_virt(x86_64::PhysAddr::new(addr)).as_ptr();
+    core::slice::from_raw_parts(ptr, len)
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |acc, b| acc.wrapping_add(*b))
+}
+
+//
+// Individual tables
+
+fn parse_madt(table: &[u8]) -> Madt {
+    let mut madt = Madt {
+        local_apic_addr: read_u32(table, SDT_HEADER_LEN) as u64,
+        processors: Vec::new(),
+        n_io_apics: 0,
+    };
+
+    let mut offset = SDT_HEADER_LEN + 8;
+    while offset + 2 <= table.len() {
+        let entry_type = table[offset];
+        let entry_len = table[offset + 1] as usize;
+        if entry_len < 2 || offset + entry_len > table.len() {
+            log::warn!("Malformed MADT entry at offset {}", offset);
+            break;
+        }
+        let entry = &table[offset..offset + entry_len];
+
+        match entry_type {
+            0 => madt.processors.push(LocalApic {
+                apic_id: entry[3] as u32,
+                enabled: read_u32(entry, 4) & 1 != 0,
+            }),
+            1 => madt.n_io_apics += 1,
+            5 => madt.local_apic_addr = read_u64(entry, 4),
+            9 => madt.processors.push(LocalApic {
+                apic_id: read_u32(entry, 4),
+                enabled: read_u32(entry, 8) & 1 != 0,
+            }),
+            _ => (),
+        }
+
+        offset += entry_len;
+    }
+
+    madt
+}
+
+fn parse_fadt(table: &[u8]) -> Fadt {
+    let field_u32 = |offset: usize| match offset + 4 <= table.len() {
+        true => read_u32(table, offset),
+        false => 0,
+    };
+
+    let flags = field_u32(112);
+    let reset_reg = match flags & RESET_REG_SUP != 0 && table.len() >= 129 {
+        true => Some((GenericAddress::parse(&table[116..128]), table[128])),
+        false => None,
+    };
+
+    // The 64-bit DSDT pointer takes precedence, when present
+    let x_dsdt = match table.len() >= 148 {
+        true => read_u64(table, 140),
+        false => 0,
+    };
+    let dsdt_addr = match x_dsdt {
+        0 => field_u32(40) as u64,
+        addr => addr,
+    };
+
+    let s5_sleep_types = load_table(dsdt_addr).and_then(find_s5_sleep_types);
+
+    Fadt {
+        smi_cmd: field_u32(48),
+        acpi_enable: table[52],
+        pm1a_cnt: field_u32(64) as u16,
+        pm1b_cnt: field_u32(68) as u16,
+        reset_reg,
+        s5_sleep_types,
+    }
+}
+
+fn parse_mcfg(table: &[u8]) -> Vec<McfgEntry> {
+    table[SDT_HEADER_LEN + 8..]
+        .chunks_exact(16)
+        .map(|entry| McfgEntry {
+            base_addr: read_u64(entry, 0),
+            segment: read_u16(entry, 8),
+            start_bus: entry[10],
+            end_bus: entry[11],
+        })
+        .collect()
+}
+
+// Looks for the AML encoding of: Name (\_S5, Package () { SLP_TYPa, SLP_TYPb, ... })
+fn find_s5_sleep_types(dsdt: &[u8]) -> Option<(u16, u16)> {
+    const NAME_OP: u8 = 0x08;
+    const PACKAGE_OP: u8 = 0x12;
+    const BYTE_PREFIX: u8 = 0x0a;
+
+    let aml = &dsdt[SDT_HEADER_LEN..];
+
+    let pos = aml.windows(4).position(|w| w == b"_S5_")?;
+    let is_name = (pos >= 1 && aml[pos - 1] == NAME_OP)
+        || (pos >= 2 && aml[pos - 2] == NAME_OP && aml[pos - 1] == b'\\');
+    if !is_name {
+        return None;
+    }
+
+    let mut i = pos + 4;
+    if *aml.get(i)? != PACKAGE_OP {
+        return None;
+    }
+    i += 1;
+
+    // PkgLength (1 to 4 bytes), then NumElements
+    let pkg_len_bytes = (*aml.get(i)? >> 6) as usize + 1;
+    i += pkg_len_bytes + 1;
+
+    // Elements are either ZeroOp / OneOp (which encode 0 and 1) or BytePrefix + value
+    let mut read_element = || -> Option<u16> {
+        if *aml.get(i)? == BYTE_PREFIX {
+            i += 1;
+        }
+        let val = *aml.get(i)? as u16;
+        i += 1;
+        Some(val)
+    };
+
+    let slp_typ_a = read_element()?;
+    let slp_typ_b = read_element()?;
+
+    Some((slp_typ_a, slp_typ_b))
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
@@ -1,5 +1,6 @@
 use core::alloc::{GlobalAlloc, Layout};
 use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use x86_64::VirtAddr;
 
@@ -7,6 +8,9 @@ const NB_BLOCK_SIZES: usize = 28;
 
 pub struct SimpleAllocator {
     pub heap: UnsafeCell<Option<SimpleHeap>>,
+
+    // Application processors allocate too (see smp.rs)
+    locked: AtomicBool,
 }
 
 pub struct SimpleHeap {
@@ -35,6 +39,7 @@ impl SimpleAllocator {
     pub const fn new() -> Self {
         Self {
             heap: UnsafeCell::new(None),
+            locked: AtomicBool::new(false),
         }
     }
 
@@ -60,14 +65,32 @@ impl SimpleAllocator {
         }
     }
 
+    pub fn is_initialized(&self) -> bool {
+        unsafe { (*self.heap.get()).is_some() }
+    }
+
     pub fn size(&self) -> usize {
         self.get_heap().stats.total
     }
 
     pub fn get_stats(&self) -> AllocStats {
+        let _guard = self.lock();
         self.get_heap().stats.clone()
     }
 
+    fn lock(&self) -> HeapLockGuard {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        HeapLockGuard {
+            locked: &self.locked,
+        }
+    }
+
     fn get_heap(&self) -> &SimpleHeap {
         unsafe {
             self.heap
@@ -93,6 +116,16 @@ impl SimpleAllocator {
 
 unsafe impl Sync for SimpleAllocator {}
 
+struct HeapLockGuard<'a> {
+    locked: &'a AtomicBool,
+}
+
+impl Drop for HeapLockGuard<'_> {
+    fn drop(&mut self) {
+        self.locked.store(false, Ordering::Release);
+    }
+}
+
 unsafe impl GlobalAlloc for SimpleAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let size = layout.size();
@@ -100,6 +133,7 @@ unsafe impl GlobalAlloc for SimpleAllocator {
 
         let (tracker_index, block_size) = get_tracker(size, align);
 
+        let _guard = self.lock();
         let heap = self.get_heap_mut();
 
         heap.stats.allocated += size;
@@ -157,6 +191,7 @@ unsafe impl GlobalAlloc for SimpleAllocator {
 
         let (tracker_index, block_size) = get_tracker(size, align);
 
+        let _guard = self.lock();
         let heap = self.get_heap_mut();
 
         let prev_addr = match heap.trackers[tracker_index] {
@@ -1,23 +1,52 @@
 use alloc::borrow::ToOwned;
+use alloc::collections::BTreeMap;
 use alloc::format;
 use alloc::string::{String, ToString};
+use alloc::vec;
 use alloc::vec::Vec;
-use applib::input::PointerState;
+use applib::input::{InputEvent, Keycode, PointerState};
 use applib::{FbView, StyleSheet};
 
+use crate::pci::PciBus;
 use crate::shell::{pie_menu, PieDrawCalls, PieMenuEntry};
 use crate::stats::SystemStats;
-use applib::content::TrackedContent;
+use applib::content::{TrackedContent, UuidProvider};
 use applib::drawing::primitives::{draw_rect, draw_rect_outline};
 use applib::drawing::text::{compute_text_bbox, draw_line_in_rect, draw_str, get_font, Font, TextJustification};
 use applib::geometry::{Point2D, Vec2D};
-use applib::uitk::{self, GraphSeries, TextBoxState};
-use applib::{input::InputState, Color, FbViewMut, Framebuffer, OwnedPixels, Rect};
+use applib::uitk::layout::{make_horizontal_layout, LayoutItem};
+use applib::uitk::{self, ButtonConfig, GraphSeries, TextBoxState};
+use applib::{input::InputState, BorrowedPixels, Color, FbViewMut, Framebuffer, OwnedPixels, Rect};
 
+use crate::smp;
 use crate::system::System;
-use crate::wasm::{WasmApp, WasmEngine};
+use crate::wasm::{
+    console_rich_text, ConsoleOutput, HostState, LaunchArgs, SharedHostState, WasmApp, WasmEngine,
+};
 use crate::{resources, TOPBAR_H};
 
+// Max size of window thumbnails (window switcher and desktop pie menu)
+const THUMBNAIL_W: u32 = 240;
+const THUMBNAIL_H: u32 = 160;
+
+// Distance between the desktop pie menu center and the thumbnail of the hovered app
+const PIE_PREVIEW_OFFSET: i64 = 220;
+
+// Delay before restarting a crashed app (RestartPolicy::OnFailure), in milliseconds.
+// Doubles with each consecutive crash.
+const RESTART_BACKOFF_BASE: f64 = 1000.0;
+const RESTART_BACKOFF_MAX: f64 = 30_000.0;
+
+// Same for RestartPolicy::Always, which keeps restarting apps quickly, but not on every frame
+const ALWAYS_RESTART_BACKOFF_BASE: f64 = 100.0;
+const ALWAYS_RESTART_BACKOFF_MAX: f64 = 1000.0;
+
+// Apps which ran for that long before crashing get their consecutive crash count reset
+const STABLE_RUN_TIME: f64 = 60_000.0;
+
+// Steps per second of minimized and fully occluded windows
+const THROTTLED_STEP_RATE: f64 = 5.0;
+
 #[derive(Clone)]
 pub struct AppDescriptor {
     pub data: &'static [u8],
@@ -25,6 +54,61 @@ pub struct AppDescriptor {
     pub init_win_rect: Rect,
     pub min_size: (u32, u32),
     pub icon: &'static Framebuffer<OwnedPixels>,
+    pub restart_policy: RestartPolicy,
+    pub mode: AppMode,
+    // Default command-line arguments and environment variables (KEY=VALUE)
+    pub args: &'static [&'static str],
+    pub env: &'static [&'static str],
+}
+
+impl AppDescriptor {
+    fn launch_args(&self) -> LaunchArgs {
+        LaunchArgs {
+            args: self.args.iter().map(|arg| arg.to_string()).collect(),
+            env: self.env.iter().map(|var| var.to_string()).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum AppMode {
+    Windowed,
+    // No window, stepped at a fixed rate (steps per second) like a daemon.
+    // Only used by test apps for now.
+    #[cfg_attr(not(feature = "test-apps"), allow(dead_code))]
+    Background {
+        step_rate: f64,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum RestartPolicy {
+    // Stays crashed until restarted from the crash panel
+    Never,
+    // Restarted after a growing delay, unless it crashed more than max_retries times in a row.
+    // Otherwise, it stays crashed until restarted from the crash panel.
+    OnFailure { max_retries: u32 },
+    // Restarted after a short delay, however many times it crashes
+    Always,
+}
+
+impl RestartPolicy {
+    // None if the app should not be restarted
+    fn restart_delay(&self, consecutive_crashes: u32) -> Option<f64> {
+        let factor = 1u32 << u32::min(consecutive_crashes.saturating_sub(1), 16);
+        match *self {
+            RestartPolicy::Never => None,
+            RestartPolicy::OnFailure { max_retries } if consecutive_crashes > max_retries => None,
+            RestartPolicy::OnFailure { .. } => Some(f64::min(
+                RESTART_BACKOFF_BASE * factor as f64,
+                RESTART_BACKOFF_MAX,
+            )),
+            RestartPolicy::Always => Some(f64::min(
+                ALWAYS_RESTART_BACKOFF_BASE * factor as f64,
+                ALWAYS_RESTART_BACKOFF_MAX,
+            )),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -38,51 +122,178 @@ pub enum HoverKind {
 pub enum AppsInteractionState {
     Idle,
     AppHover {
-        app_name: &'static str,
+        app_id: AppId,
         hover_kind: HoverKind,
     },
     TitlebarHold {
-        app_name: &'static str,
+        app_id: AppId,
         anchor: Point2D<i64>,
 
         // In "toggle" mode, another click is required to get out of the TitlebarHold state
         toggle: bool,
     },
     ResizeHold {
-        app_name: &'static str,
+        app_id: AppId,
     },
     PieDesktopMenu {
         anchor: Point2D<i64>,
     },
     PieAppMenu {
-        app_name: &'static str,
+        app_id: AppId,
         anchor: Point2D<i64>,
     },
+    WindowSwitcher {
+        app_id: AppId,
+    },
+    DevicesView {
+        // Index of the first line shown
+        scroll: usize,
+    },
+    // Editing the arguments of an app before opening it (the text is in AppsManager)
+    LaunchDialog {
+        app_name: &'static str,
+    },
+}
+
+impl AppsInteractionState {
+    fn app_id(&self) -> Option<AppId> {
+        match *self {
+            AppsInteractionState::AppHover { app_id, .. }
+            | AppsInteractionState::TitlebarHold { app_id, .. }
+            | AppsInteractionState::ResizeHold { app_id }
+            | AppsInteractionState::PieAppMenu { app_id, .. }
+            | AppsInteractionState::WindowSwitcher { app_id } => Some(app_id),
+            _ => None,
+        }
+    }
 }
 
 pub struct AppsManager {
     z_ordered: Vec<App>,
+    next_id: u32,
+    // By app instance name
+    snapshots: BTreeMap<String, Vec<u8>>,
+    launch_dialog: Option<LaunchDialog>,
 }
 
-impl AppsManager {
-    fn get_by_name(&mut self, app_name: &str) -> &mut App {
-        self.z_ordered
-            .iter_mut()
-            .find(|app| app.descriptor.name == app_name)
-            .expect("Unknown app")
-    }
+struct LaunchDialog {
+    command_line: TrackedContent<String>,
+    text_state: TextBoxState,
 }
 
+// Identifies an app instance (there may be several windows of the same app)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AppId(u32);
+
 pub struct App {
+    pub id: AppId,
+    // Instance number, starting at 1
+    pub instance: u32,
+    // Descriptor name, followed by the instance number for all instances but the first one
+    pub name: String,
     pub app_state: AppState,
     pub descriptor: AppDescriptor,
     pub is_open: bool,
     pub rect: Rect,
     pub time_used: f64,
+    pub started_at: f64,
+    // Crashes since the app last ran for at least STABLE_RUN_TIME
+    pub consecutive_crashes: u32,
+    pub minimized: bool,
+    // Used when the app is (re)started
+    pub launch_args: LaunchArgs,
+    // Name of the app whose stdin is fed with the stdout of this one
+    pub stdout_pipe: Option<String>,
+    // For apps which are not stepped on every frame
+    next_step_at: f64,
+}
+
+impl App {
+    fn new(id: AppId, instance: u32, descriptor: &AppDescriptor) -> Self {
+        let name = match instance {
+            1 => descriptor.name.to_string(),
+            _ => format!("{} {}", descriptor.name, instance),
+        };
+
+        App {
+            id,
+            instance,
+            name,
+            app_state: AppState::Init,
+            descriptor: descriptor.clone(),
+            is_open: false,
+            rect: descriptor.init_win_rect.clone(),
+            time_used: 0.0,
+            started_at: 0.0,
+            consecutive_crashes: 0,
+            minimized: false,
+            launch_args: descriptor.launch_args(),
+            stdout_pipe: None,
+            next_step_at: 0.0,
+        }
+    }
+
+    pub fn is_windowed(&self) -> bool {
+        matches!(self.descriptor.mode, AppMode::Windowed)
+    }
+
+    // Whether the app window is drawn
+    fn is_shown(&self) -> bool {
+        self.is_open && self.is_windowed() && !self.minimized
+    }
+
+    // Time between steps in milliseconds, None to step on every frame
+    fn step_period(&self, occluded: bool) -> Option<f64> {
+        match self.descriptor.mode {
+            AppMode::Background { step_rate } => Some(1000.0 / step_rate),
+            AppMode::Windowed if self.minimized || occluded => Some(1000.0 / THROTTLED_STEP_RATE),
+            AppMode::Windowed => None,
+        }
+    }
+
+    // Switches to the crashed state, scheduling a restart if the restart policy allows one
+    fn crash(
+        &mut self,
+        system: &mut System,
+        error: anyhow::Error,
+        log: TrackedContent<ConsoleOutput>,
+    ) {
+        let app_name = self.name.as_str();
+        let now = system.clock.time();
+
+        self.consecutive_crashes = match now - self.started_at >= STABLE_RUN_TIME {
+            true => 1,
+            false => self.consecutive_crashes + 1,
+        };
+        system.stats.record_crash(app_name);
+
+        let restart_at = self
+            .descriptor
+            .restart_policy
+            .restart_delay(self.consecutive_crashes)
+            .map(|delay| now + delay);
+
+        match restart_at {
+            Some(t) => log::info!("Restarting {} in {:.1}s", app_name, (t - now) / 1000.0),
+            None => log::warn!("Not restarting {}", app_name),
+        }
+
+        self.app_state = AppState::Crashed {
+            error,
+            log,
+            show_log: false,
+            log_state: TextBoxState::new(),
+            restart_at,
+        };
+    }
 }
 
 pub enum AppState {
     Init,
+    // Restored on the next frame
+    Restore {
+        snapshot: Vec<u8>,
+    },
     Active {
         paused: bool,
         wasm_app: WasmApp,
@@ -90,64 +301,344 @@ pub enum AppState {
     },
     Crashed {
         error: anyhow::Error,
+        // Console output of the app when it crashed
+        log: TrackedContent<ConsoleOutput>,
+        show_log: bool,
+        log_state: TextBoxState,
+        // Time of the automatic restart, if the restart policy allows one
+        restart_at: Option<f64>,
     },
 }
 
 pub enum AppAuditMode {
     Disabled,
-    Enabled { scrollable_text_state: TextBoxState },
+    Enabled {
+        scrollable_text_state: TextBoxState,
+        stdin_line: TrackedContent<String>,
+        stdin_state: TextBoxState,
+        // Apps get no keyboard input while an audit window input line is focused
+        stdin_focused: bool,
+    },
 }
 
 impl AppAuditMode {
+    fn enabled(uuid_provider: &mut UuidProvider) -> Self {
+        AppAuditMode::Enabled {
+            scrollable_text_state: TextBoxState::new(),
+            stdin_line: TrackedContent::new(String::new(), uuid_provider),
+            stdin_state: TextBoxState::new(),
+            stdin_focused: false,
+        }
+    }
+
+    fn is_stdin_focused(&self) -> bool {
+        matches!(
+            self,
+            AppAuditMode::Enabled {
+                stdin_focused: true,
+                ..
+            }
+        )
+    }
+
+    // Returns the line entered in the input line, if any, to be written to the app stdin
     fn audit_window<F: FbViewMut>(
         &mut self,
         uitk_context: &mut uitk::UiContext<F>,
         app_name: &str,
         deco: &AppDecorations,
         stats: &SystemStats,
-        console_log: &TrackedContent<String>,
-    ) {
+        console_log: &TrackedContent<ConsoleOutput>,
+    ) -> Option<String> {
         match self {
-            AppAuditMode::Disabled => return,
+            AppAuditMode::Disabled => None,
             AppAuditMode::Enabled {
                 scrollable_text_state,
-            } => {
-                app_audit_window(
-                    uitk_context,
-                    app_name,
-                    deco,
-                    stats,
-                    console_log,
-                    scrollable_text_state,
-                );
-            }
+                stdin_line,
+                stdin_state,
+                stdin_focused,
+            } => app_audit_window(
+                uitk_context,
+                app_name,
+                deco,
+                stats,
+                console_log,
+                scrollable_text_state,
+                stdin_line,
+                stdin_state,
+                stdin_focused,
+            ),
         }
     }
 }
 
 impl AppsManager {
-    pub fn new(apps: Vec<App>) -> Self {
-        Self { z_ordered: apps }
+    // One instance of each app, initially closed
+    pub fn new(descriptors: &[AppDescriptor]) -> Self {
+        let z_ordered = descriptors
+            .iter()
+            .enumerate()
+            .map(|(i, descriptor)| App::new(AppId(i as u32), 1, descriptor))
+            .collect();
+
+        Self {
+            z_ordered,
+            next_id: descriptors.len() as u32,
+            snapshots: BTreeMap::new(),
+            launch_dialog: None,
+        }
     }
 
-    fn get_mut(&mut self, app_name: &'static str) -> &mut App {
-        self.z_ordered
-            .iter_mut()
-            .find(|app| app.descriptor.name == app_name)
-            .unwrap()
+    // None if the instance was dropped
+    fn get_mut(&mut self, app_id: AppId) -> Option<&mut App> {
+        self.z_ordered.iter_mut().find(|app| app.id == app_id)
     }
 
-    fn set_on_top(&mut self, app_name: &'static str) {
-        let index = self
+    fn set_on_top(&mut self, app_id: AppId) {
+        if let Some(index) = self.z_ordered.iter().position(|app| app.id == app_id) {
+            let app = self.z_ordered.remove(index);
+            self.z_ordered.push(app);
+        }
+    }
+
+    // Opens a closed instance of the app if there is one, otherwise creates a new instance.
+    // With launch arguments (on top of the default ones of the app), a closed instance which
+    // is still running is restarted.
+    fn open_instance(
+        &mut self,
+        stats: &mut SystemStats,
+        app_name: &str,
+        launch_args: Option<&LaunchArgs>,
+    ) -> anyhow::Result<AppId> {
+        let closed = self
             .z_ordered
             .iter()
-            .position(|app| app.descriptor.name == app_name)
-            .unwrap();
-        let app = self.z_ordered.remove(index);
-        self.z_ordered.push(app);
+            .find(|app| app.descriptor.name == app_name && !app.is_open);
+
+        let app_id = match closed {
+            Some(app) => app.id,
+            None => {
+                let descriptor = self
+                    .z_ordered
+                    .iter()
+                    .find(|app| app.descriptor.name == app_name)
+                    .map(|app| app.descriptor.clone())
+                    .ok_or_else(|| anyhow::format_err!("Unknown app {}", app_name))?;
+
+                let instance = (1..)
+                    .find(|instance| {
+                        !self
+                            .z_ordered
+                            .iter()
+                            .any(|app| app.descriptor.name == app_name && app.instance == *instance)
+                    })
+                    .unwrap();
+
+                let app = App::new(AppId(self.next_id), instance, &descriptor);
+                self.next_id += 1;
+
+                log::info!("New instance of {}: {}", app_name, app.name);
+                stats.add_app(&app.name);
+
+                let app_id = app.id;
+                self.z_ordered.push(app);
+                app_id
+            }
+        };
+
+        let app = self
+            .get_mut(app_id)
+            .ok_or_else(|| anyhow::format_err!("Unknown app {}", app_name))?;
+        app.is_open = true;
+        app.minimized = false;
+        if let Some(launch_args) = launch_args {
+            app.launch_args = app.descriptor.launch_args().overridden_by(launch_args);
+            if !matches!(app.app_state, AppState::Init) {
+                log::info!("Restarting {} with new arguments", app.name);
+                app.app_state = AppState::Init;
+            }
+        }
+        self.set_on_top(app_id);
+
+        Ok(app_id)
+    }
+
+    // The first instance of an app is only hidden, the other ones are dropped along with
+    // their state
+    // Any interaction state referring to a dropped instance is reset by run_apps()
+    fn close_instance(&mut self, stats: &mut SystemStats, app_id: AppId) {
+        let Some(app) = self.get_mut(app_id) else {
+            return;
+        };
+        app.is_open = false;
+
+        if app.instance > 1 {
+            log::info!("Dropping {}", app.name);
+            stats.remove_app(&app.name);
+            self.z_ordered.retain(|app| app.id != app_id);
+        }
+    }
+
+    //
+    // Used by the kernel console
+
+    pub fn iter(&self) -> impl Iterator<Item = &App> {
+        self.z_ordered.iter()
+    }
+
+    pub fn open_app(
+        &mut self,
+        stats: &mut SystemStats,
+        app_name: &str,
+        launch_args: Option<&LaunchArgs>,
+    ) -> anyhow::Result<()> {
+        self.open_instance(stats, app_name, launch_args).map(|_| ())
+    }
+
+    pub fn close_app(&mut self, stats: &mut SystemStats, app_name: &str) -> anyhow::Result<()> {
+        let app_id = self.find_mut(app_name)?.id;
+        self.close_instance(stats, app_id);
+        Ok(())
+    }
+
+    // Feeds the stdout of an app to the stdin of another one, or stops doing it (target None)
+    pub fn pipe_app(&mut self, app_name: &str, target: Option<&str>) -> anyhow::Result<()> {
+        if let Some(target) = target {
+            if target == app_name {
+                return Err(anyhow::format_err!("Cannot pipe {} to itself", app_name));
+            }
+            self.find_mut(target)?;
+        }
+
+        let app = self.find_mut(app_name)?;
+        app.stdout_pipe = target.map(|target| target.to_string());
+
+        match target {
+            Some(target) => log::info!("Piping {} to {}", app_name, target),
+            None => log::info!("Stopped piping {}", app_name),
+        }
+
+        Ok(())
+    }
+
+    // Pauses the app, and keeps its snapshot in memory (replacing the previous one). Returns the
+    // size of the snapshot.
+    pub fn snapshot_app(&mut self, app_name: &str) -> anyhow::Result<usize> {
+        let app = self.find_mut(app_name)?;
+
+        let AppState::Active {
+            wasm_app, paused, ..
+        } = &mut app.app_state
+        else {
+            return Err(anyhow::format_err!("{} is not running", app_name));
+        };
+
+        // Not paused if it cannot be snapshotted (e.g. while it waits in a host call)
+        let snapshot = wasm_app.snapshot()?;
+        *paused = true;
+        let size = snapshot.len();
+
+        log::info!("Snapshot of {}: {} bytes", app.name, size);
+        let app_name = app.name.clone();
+        self.snapshots.insert(app_name, snapshot);
+
+        Ok(size)
+    }
+
+    pub fn restore_app(&mut self, app_name: &str) -> anyhow::Result<()> {
+        let snapshot = self
+            .snapshots
+            .get(app_name)
+            .ok_or_else(|| anyhow::format_err!("No snapshot of {}", app_name))?
+            .clone();
+
+        let app = self.find_mut(app_name)?;
+        app.app_state = AppState::Restore { snapshot };
+        app.is_open = true;
+        app.minimized = false;
+        let app_id = app.id;
+        self.set_on_top(app_id);
+
+        Ok(())
+    }
+
+    // Names and sizes
+    pub fn snapshots(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.snapshots
+            .iter()
+            .map(|(app_name, snapshot)| (app_name.as_str(), snapshot.len()))
+    }
+
+    // Only available for open windows. The app framebuffer may be larger than the window,
+    // if it was shrunk since the last step
+    pub fn window_framebuffer(&self, app_name: &str) -> Option<Framebuffer<BorrowedPixels>> {
+        let app = self
+            .z_ordered
+            .iter()
+            .find(|app| app.name == app_name && app.is_open)?;
+
+        let AppState::Active { wasm_app, .. } = &app.app_state else {
+            return None;
+        };
+
+        let app_fb = wasm_app.get_framebuffer()?;
+        let (fb_w, fb_h) = app_fb.shape();
+        let rect = Rect {
+            x0: 0,
+            y0: 0,
+            w: u32::min(fb_w, app.rect.w),
+            h: u32::min(fb_h, app.rect.h),
+        };
+
+        Some(app_fb.into_subregion(&rect))
+    }
+
+    pub fn thumbnail(
+        &self,
+        app_name: &str,
+        max_w: u32,
+        max_h: u32,
+    ) -> Option<Framebuffer<OwnedPixels>> {
+        let app_fb = self.window_framebuffer(app_name)?;
+        let (w, h) = app_fb.shape();
+        if w == 0 || h == 0 {
+            return None;
+        }
+        Some(Framebuffer::thumbnail_from(&app_fb, max_w, max_h))
+    }
+
+    // By instance name
+    fn find_mut(&mut self, app_name: &str) -> anyhow::Result<&mut App> {
+        self.z_ordered
+            .iter_mut()
+            .find(|app| app.name == app_name)
+            .ok_or_else(|| anyhow::format_err!("Unknown app {}", app_name))
     }
 }
 
+#[derive(Clone, Copy, PartialEq)]
+enum AppMenuAction {
+    OpenAudit,
+    CloseAudit,
+    Move,
+    Reload,
+    Pause,
+    Resume,
+    Close,
+    Minimize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum DesktopMenuAction {
+    // By descriptor name
+    Launch(&'static str),
+    Screenshot,
+    ToggleRecording,
+    Devices,
+    Restart,
+    ShutDown,
+}
+
 pub fn run_apps<F: FbViewMut>(
     uitk_context: &mut uitk::UiContext<F>,
     system: &mut System,
@@ -159,6 +650,7 @@ pub fn run_apps<F: FbViewMut>(
     let stylesheet = system.stylesheet.clone();
     let pointer = &input_state.pointer;
     let mut pie_draw_calls: Option<PieDrawCalls> = None;
+    let mut pie_preview: Option<(Framebuffer<OwnedPixels>, (i64, i64))> = None;
 
     //
     // Hover
@@ -172,16 +664,16 @@ pub fn run_apps<F: FbViewMut>(
             (app, deco)
         })
         .find_map(|(app, deco)| {
-            let app_name = app.descriptor.name;
+            let app_id = app.id;
 
-            if !app.is_open {
+            if !app.is_shown() {
                 None
             } else if deco.titlebar_hover {
-                Some((app_name, HoverKind::Titlebar))
+                Some((app_id, HoverKind::Titlebar))
             } else if deco.resize_hover {
-                Some((app_name, HoverKind::Resize))
+                Some((app_id, HoverKind::Resize))
             } else if deco.window_hover {
-                Some((app_name, HoverKind::Window))
+                Some((app_id, HoverKind::Window))
             } else {
                 None
             }
@@ -192,7 +684,24 @@ pub fn run_apps<F: FbViewMut>(
 
     let is = interaction_state;
 
+    // The instance may have been dropped since the last frame (e.g. closed from the console)
+    if let Some(app_id) = is.app_id() {
+        if apps_manager.get_mut(app_id).is_none() {
+            *is = AppsInteractionState::Idle;
+        }
+    }
+
+    let switch_pressed =
+        input_state.modifiers.alt && input_state.check_key_pressed(Keycode::KEY_TAB);
+
     match *is {
+        AppsInteractionState::Idle | AppsInteractionState::AppHover { .. } if switch_pressed => {
+            let order = switcher_order(apps_manager);
+            if let Some(app_id) = order.get(1).or(order.first()) {
+                *is = AppsInteractionState::WindowSwitcher { app_id: *app_id };
+            }
+        }
+
         AppsInteractionState::Idle => match hover_state {
             None if pointer.right_click_trigger => {
                 let anchor = Point2D {
@@ -202,41 +711,36 @@ pub fn run_apps<F: FbViewMut>(
                 *is = AppsInteractionState::PieDesktopMenu { anchor };
             }
             None => (),
-            Some((app_name, hover_kind)) => {
-                *is = AppsInteractionState::AppHover {
-                    app_name,
-                    hover_kind,
-                }
+            Some((app_id, hover_kind)) => {
+                *is = AppsInteractionState::AppHover { app_id, hover_kind }
             }
         },
 
-        AppsInteractionState::AppHover { app_name, .. } if pointer.right_click_trigger => {
+        AppsInteractionState::AppHover { app_id, .. } if pointer.right_click_trigger => {
             let anchor = Point2D {
                 x: pointer.x,
                 y: pointer.y,
             };
-            apps_manager.set_on_top(app_name);
-            *is = AppsInteractionState::PieAppMenu { app_name, anchor };
+            apps_manager.set_on_top(app_id);
+            *is = AppsInteractionState::PieAppMenu { app_id, anchor };
         }
 
-        AppsInteractionState::AppHover {
-            app_name,
-            hover_kind,
-        } if pointer.left_click_trigger => {
-            apps_manager.set_on_top(app_name);
+        AppsInteractionState::AppHover { app_id, hover_kind } if pointer.left_click_trigger => {
+            apps_manager.set_on_top(app_id);
 
             match hover_kind {
                 HoverKind::Titlebar => {
-                    let app = apps_manager.get_mut(app_name);
-                    let anchor = get_hold_anchor(pointer, &app.rect);
-                    *is = AppsInteractionState::TitlebarHold {
-                        app_name,
-                        anchor,
-                        toggle: false,
-                    };
+                    if let Some(app) = apps_manager.get_mut(app_id) {
+                        let anchor = get_hold_anchor(pointer, &app.rect);
+                        *is = AppsInteractionState::TitlebarHold {
+                            app_id,
+                            anchor,
+                            toggle: false,
+                        };
+                    }
                 }
 
-                HoverKind::Resize => *is = AppsInteractionState::ResizeHold { app_name },
+                HoverKind::Resize => *is = AppsInteractionState::ResizeHold { app_id },
 
                 HoverKind::Window => (),
             }
@@ -244,11 +748,8 @@ pub fn run_apps<F: FbViewMut>(
 
         AppsInteractionState::AppHover { .. } => match hover_state {
             None => *is = AppsInteractionState::Idle,
-            Some((app_name, hover_kind)) => {
-                *is = AppsInteractionState::AppHover {
-                    app_name,
-                    hover_kind,
-                }
+            Some((app_id, hover_kind)) => {
+                *is = AppsInteractionState::AppHover { app_id, hover_kind }
             }
         },
 
@@ -262,155 +763,224 @@ pub fn run_apps<F: FbViewMut>(
             *is = AppsInteractionState::Idle;
         }
 
-        AppsInteractionState::TitlebarHold {
-            app_name, anchor, ..
-        } => {
-            let app = apps_manager.get_mut(app_name);
-            app.rect.x0 = pointer.x - anchor.x;
-            app.rect.y0 = pointer.y - anchor.y;
+        AppsInteractionState::TitlebarHold { app_id, anchor, .. } => {
+            if let Some(app) = apps_manager.get_mut(app_id) {
+                app.rect.x0 = pointer.x - anchor.x;
+                app.rect.y0 = pointer.y - anchor.y;
+            }
         }
 
         AppsInteractionState::ResizeHold { .. } if !pointer.left_clicked => {
             *is = AppsInteractionState::Idle;
         }
 
-        AppsInteractionState::ResizeHold { app_name } => {
-            let app = apps_manager.get_mut(app_name);
-            let (min_w, min_h) = app.descriptor.min_size;
-            let [x1, y1, _, _] = app.rect.as_xyxy();
-            let x2 = i64::max(x1 + min_w as i64, pointer.x);
-            let y2 = i64::max(y1 + min_h as i64, pointer.y);
-            app.rect = Rect::from_xyxy([x1, y1, x2, y2]);
+        AppsInteractionState::ResizeHold { app_id } => {
+            if let Some(app) = apps_manager.get_mut(app_id) {
+                let (min_w, min_h) = app.descriptor.min_size;
+                let [x1, y1, _, _] = app.rect.as_xyxy();
+                let x2 = i64::max(x1 + min_w as i64, pointer.x);
+                let y2 = i64::max(y1 + min_h as i64, pointer.y);
+                app.rect = Rect::from_xyxy([x1, y1, x2, y2]);
+            }
         }
 
-        AppsInteractionState::PieAppMenu { app_name, anchor } => {
-            let app = apps_manager.get_mut(app_name);
-
-            let entries = [
-                match &app.app_state {
-                    AppState::Active { audit_mode, .. } => match audit_mode {
-                        AppAuditMode::Disabled => PieMenuEntry::Button {
-                            icon: &resources::INSPECT_ICON,
-                            color: stylesheet.colors.purple,
-                            text: "Open audit".to_owned(),
+        AppsInteractionState::PieAppMenu { app_id, anchor } => match apps_manager.get_mut(app_id) {
+            None => *is = AppsInteractionState::Idle,
+            Some(app) => {
+                let entries = [
+                    match &app.app_state {
+                        AppState::Active { audit_mode, .. } => match audit_mode {
+                            AppAuditMode::Disabled => PieMenuEntry::Button {
+                                action: AppMenuAction::OpenAudit,
+                                icon: &resources::INSPECT_ICON,
+                                color: stylesheet.colors.purple,
+                                text: "Open audit".to_owned(),
+                                text_color: stylesheet.colors.text,
+                                weight: 1.0,
+                            },
+                            AppAuditMode::Enabled { .. } => PieMenuEntry::Button {
+                                action: AppMenuAction::CloseAudit,
+                                icon: &resources::INSPECT_ICON,
+                                color: stylesheet.colors.purple,
+                                text: "Close audit".to_owned(),
+                                text_color: stylesheet.colors.text,
+                                weight: 1.0,
+                            },
+                        },
+                        _ => PieMenuEntry::Spacer { weight: 1.0 },
+                    },
+                    PieMenuEntry::Button {
+                        action: AppMenuAction::Move,
+                        icon: &resources::MOVE_ICON,
+                        color: stylesheet.colors.blue,
+                        text: "Move".to_owned(),
+                        text_color: stylesheet.colors.text,
+                        weight: 1.0,
+                    },
+                    PieMenuEntry::Button {
+                        action: AppMenuAction::Reload,
+                        icon: &resources::RELOAD_ICON,
+                        color: stylesheet.colors.yellow,
+                        text: "Reload".to_owned(),
+                        text_color: stylesheet.colors.text,
+                        weight: 1.0,
+                    },
+                    match &app.app_state {
+                        AppState::Active { paused, .. } if !paused => PieMenuEntry::Button {
+                            action: AppMenuAction::Pause,
+                            icon: &resources::PAUSE_ICON,
+                            color: stylesheet.colors.green,
+                            text: "Pause".to_owned(),
                             text_color: stylesheet.colors.text,
                             weight: 1.0,
                         },
-                        AppAuditMode::Enabled { .. } => PieMenuEntry::Button {
-                            icon: &resources::INSPECT_ICON,
-                            color: stylesheet.colors.purple,
-                            text: "Close audit".to_owned(),
+                        AppState::Active { paused, .. } if *paused => PieMenuEntry::Button {
+                            action: AppMenuAction::Resume,
+                            icon: &resources::PLAY_ICON,
+                            color: stylesheet.colors.green,
+                            text: "Resume".to_owned(),
                             text_color: stylesheet.colors.text,
                             weight: 1.0,
                         },
+                        _ => PieMenuEntry::Spacer { weight: 1.0 },
                     },
-                    _ => PieMenuEntry::Spacer { weight: 1.0 },
-                },
-                PieMenuEntry::Button {
-                    icon: &resources::MOVE_ICON,
-                    color: stylesheet.colors.blue,
-                    text: "Move".to_owned(),
-                    text_color: stylesheet.colors.text,
-                    weight: 1.0,
-                },
-                PieMenuEntry::Button {
-                    icon: &resources::RELOAD_ICON,
-                    color: stylesheet.colors.yellow,
-                    text: "Reload".to_owned(),
-                    text_color: stylesheet.colors.text,
-                    weight: 1.0,
-                },
-                match &app.app_state {
-                    AppState::Active { paused, .. } if !paused => PieMenuEntry::Button {
-                        icon: &resources::PAUSE_ICON,
-                        color: stylesheet.colors.green,
-                        text: "Pause".to_owned(),
+                    PieMenuEntry::Button {
+                        action: AppMenuAction::Close,
+                        icon: &resources::CLOSE_ICON,
+                        color: stylesheet.colors.red,
+                        text: "Close".to_owned(),
                         text_color: stylesheet.colors.text,
                         weight: 1.0,
                     },
-                    AppState::Active { paused, .. } if *paused => PieMenuEntry::Button {
-                        icon: &resources::PLAY_ICON,
-                        color: stylesheet.colors.green,
-                        text: "Resume".to_owned(),
+                    PieMenuEntry::Button {
+                        action: AppMenuAction::Minimize,
+                        icon: &resources::HOME_ICON,
+                        color: stylesheet.colors.blue,
+                        text: "Minimize".to_owned(),
                         text_color: stylesheet.colors.text,
                         weight: 1.0,
                     },
-                    _ => PieMenuEntry::Spacer { weight: 1.0 },
-                },
-                PieMenuEntry::Button {
-                    icon: &resources::CLOSE_ICON,
-                    color: stylesheet.colors.red,
-                    text: "Close".to_owned(),
-                    text_color: stylesheet.colors.text,
-                    weight: 1.0,
-                },
-                PieMenuEntry::Spacer { weight: 3.0 },
-            ];
+                    PieMenuEntry::Spacer { weight: 2.0 },
+                ];
 
-            let (selected, draw_calls) = pie_menu(uitk_context, &entries, anchor);
+                let (selected, _hovered, draw_calls) = pie_menu(uitk_context, &entries, anchor);
 
-            pie_draw_calls.replace(draw_calls);
+                pie_draw_calls.replace(draw_calls);
 
-            match selected {
-                Some("Close") => {
-                    app.is_open = false;
-                    *is = AppsInteractionState::Idle;
-                }
-                Some("Move") => {
-                    let anchor = get_hold_anchor(pointer, &app.rect);
-                    *is = AppsInteractionState::TitlebarHold {
-                        app_name,
-                        anchor,
-                        toggle: true,
-                    };
-                }
-                Some("Reload") => {
-                    log::info!("De-loading app {}", app.descriptor.name);
-                    app.app_state = AppState::Init;
-                    *is = AppsInteractionState::Idle;
-                }
-                Some("Pause") => {
-                    if let AppState::Active { paused, .. } = &mut app.app_state {
-                        *paused = true;
+                match selected {
+                    Some(AppMenuAction::Close) => {
+                        apps_manager.close_instance(&mut system.stats, app_id);
                         *is = AppsInteractionState::Idle;
                     }
-                }
-                Some("Resume") => {
-                    if let AppState::Active { paused, .. } = &mut app.app_state {
-                        *paused = false;
+                    Some(AppMenuAction::Minimize) => {
+                        app.minimized = true;
                         *is = AppsInteractionState::Idle;
                     }
-                }
-                Some("Open audit") => {
-                    if let AppState::Active { audit_mode, .. } = &mut app.app_state {
-                        *audit_mode = AppAuditMode::Enabled {
-                            scrollable_text_state: TextBoxState::new(),
+                    Some(AppMenuAction::Move) => {
+                        let anchor = get_hold_anchor(pointer, &app.rect);
+                        *is = AppsInteractionState::TitlebarHold {
+                            app_id,
+                            anchor,
+                            toggle: true,
                         };
+                    }
+                    Some(AppMenuAction::Reload) => {
+                        log::info!("De-loading app {}", app.name);
+                        app.app_state = AppState::Init;
                         *is = AppsInteractionState::Idle;
                     }
-                }
-                Some("Close audit") => {
-                    if let AppState::Active { audit_mode, .. } = &mut app.app_state {
-                        *audit_mode = AppAuditMode::Disabled;
+                    Some(AppMenuAction::Pause) => {
+                        if let AppState::Active { paused, .. } = &mut app.app_state {
+                            *paused = true;
+                            *is = AppsInteractionState::Idle;
+                        }
+                    }
+                    Some(AppMenuAction::Resume) => {
+                        if let AppState::Active { paused, .. } = &mut app.app_state {
+                            *paused = false;
+                            *is = AppsInteractionState::Idle;
+                        }
+                    }
+                    Some(AppMenuAction::OpenAudit) => {
+                        if let AppState::Active { audit_mode, .. } = &mut app.app_state {
+                            *audit_mode = AppAuditMode::enabled(uitk_context.uuid_provider);
+                            *is = AppsInteractionState::Idle;
+                        }
+                    }
+                    Some(AppMenuAction::CloseAudit) => {
+                        if let AppState::Active { audit_mode, .. } = &mut app.app_state {
+                            *audit_mode = AppAuditMode::Disabled;
+                            *is = AppsInteractionState::Idle;
+                        }
+                    }
+
+                    _ if pointer.right_click_trigger || pointer.left_click_trigger => {
                         *is = AppsInteractionState::Idle;
                     }
+                    _ => (),
                 }
+            }
+        },
 
-                _ if pointer.right_click_trigger || pointer.left_click_trigger => {
-                    *is = AppsInteractionState::Idle;
+        AppsInteractionState::WindowSwitcher { app_id } if !input_state.modifiers.alt => {
+            if let Some(app) = apps_manager.get_mut(app_id) {
+                if app.is_open {
+                    app.minimized = false;
+                    apps_manager.set_on_top(app_id);
                 }
-                _ => (),
             }
+            *is = AppsInteractionState::Idle;
+        }
+
+        AppsInteractionState::WindowSwitcher { app_id } if switch_pressed => {
+            let order = switcher_order(apps_manager);
+            let next = match order.iter().position(|id| *id == app_id) {
+                Some(i) => order[(i + 1) % order.len()],
+                None => app_id,
+            };
+            *is = AppsInteractionState::WindowSwitcher { app_id: next };
+        }
+
+        AppsInteractionState::WindowSwitcher { .. } => (),
+
+        // Handled when drawing the dialog
+        AppsInteractionState::LaunchDialog { .. } => (),
+
+        AppsInteractionState::DevicesView { .. }
+            if pointer.left_click_trigger
+                || pointer.right_click_trigger
+                || input_state.check_key_pressed(Keycode::KEY_ESC) =>
+        {
+            *is = AppsInteractionState::Idle;
+        }
+
+        AppsInteractionState::DevicesView { scroll } => {
+            let delta: i64 = input_state
+                .iter_events()
+                .filter_map(|event| match event {
+                    InputEvent::Scroll { delta } => Some(delta),
+                    _ => None,
+                })
+                .sum();
+            let scroll = (scroll as i64 - delta).max(0) as usize;
+            *is = AppsInteractionState::DevicesView { scroll };
         }
 
         AppsInteractionState::PieDesktopMenu { anchor } => {
-            // Sorting apps by name to ensure consistent order
-            let mut sorted_apps: Vec<&mut App> = apps_manager.z_ordered.iter_mut().collect();
+            // Sorting apps by name to ensure consistent order (one entry per app, selecting it
+            // opens another instance if the app is already open). Background apps are launched
+            // from the console.
+            let mut sorted_apps: Vec<&mut App> = apps_manager
+                .z_ordered
+                .iter_mut()
+                .filter(|app| app.instance == 1 && app.is_windowed())
+                .collect();
             sorted_apps.sort_by_key(|app| app.descriptor.name);
 
-            let entries: Vec<PieMenuEntry> = sorted_apps
+            let mut entries: Vec<PieMenuEntry<DesktopMenuAction>> = sorted_apps
                 .iter()
                 .map(|app| PieMenuEntry::Button {
+                    action: DesktopMenuAction::Launch(app.descriptor.name),
                     icon: app.descriptor.icon,
                     color: stylesheet.colors.background,
                     text: app.descriptor.name.to_string(),
@@ -419,87 +989,359 @@ pub fn run_apps<F: FbViewMut>(
                 })
                 .collect();
 
-            let (selected, draw_calls) = pie_menu(uitk_context, &entries, anchor);
+            entries.push(PieMenuEntry::Button {
+                action: DesktopMenuAction::Screenshot,
+                icon: &resources::INSPECT_ICON,
+                color: stylesheet.colors.purple,
+                text: "Screenshot".to_owned(),
+                text_color: stylesheet.colors.text,
+                weight: 1.0,
+            });
+
+            entries.push(match system.capture.is_recording() {
+                false => PieMenuEntry::Button {
+                    action: DesktopMenuAction::ToggleRecording,
+                    icon: &resources::PLAY_ICON,
+                    color: stylesheet.colors.red,
+                    text: "Record".to_owned(),
+                    text_color: stylesheet.colors.text,
+                    weight: 1.0,
+                },
+                true => PieMenuEntry::Button {
+                    action: DesktopMenuAction::ToggleRecording,
+                    icon: &resources::STOP_ICON,
+                    color: stylesheet.colors.red,
+                    text: "Stop recording".to_owned(),
+                    text_color: stylesheet.colors.text,
+                    weight: 1.0,
+                },
+            });
+
+            entries.push(PieMenuEntry::Button {
+                action: DesktopMenuAction::Devices,
+                icon: &resources::CHIP_ICON,
+                color: stylesheet.colors.blue,
+                text: "Devices".to_owned(),
+                text_color: stylesheet.colors.text,
+                weight: 1.0,
+            });
+
+            entries.push(PieMenuEntry::Button {
+                action: DesktopMenuAction::Restart,
+                icon: &resources::RELOAD_ICON,
+                color: stylesheet.colors.yellow,
+                text: "Restart".to_owned(),
+                text_color: stylesheet.colors.text,
+                weight: 1.0,
+            });
+
+            entries.push(PieMenuEntry::Button {
+                action: DesktopMenuAction::ShutDown,
+                icon: &resources::CLOSE_ICON,
+                color: stylesheet.colors.red,
+                text: "Shut down".to_owned(),
+                text_color: stylesheet.colors.text,
+                weight: 1.0,
+            });
+
+            let (selected, hovered, draw_calls) = pie_menu(uitk_context, &entries, anchor);
 
             pie_draw_calls.replace(draw_calls);
 
+            if let Some(DesktopMenuAction::Launch(hovered_app_name)) = hovered {
+                if let Some(thumbnail) =
+                    apps_manager.thumbnail(hovered_app_name, THUMBNAIL_W, THUMBNAIL_H)
+                {
+                    let (w, _) = thumbnail.shape();
+                    let origin = (anchor.x - (w / 2) as i64, anchor.y + PIE_PREVIEW_OFFSET);
+                    pie_preview.replace((thumbnail, origin));
+                }
+            }
+
             match selected {
-                Some(selected_app_name) => {
-                    let app = apps_manager.get_by_name(selected_app_name);
+                Some(DesktopMenuAction::Screenshot) => system.capture.request_screenshot(),
+                Some(DesktopMenuAction::ToggleRecording) => system.capture.toggle_recording(),
+                Some(DesktopMenuAction::Devices) => {
+                    *is = AppsInteractionState::DevicesView { scroll: 0 }
+                }
+                Some(DesktopMenuAction::Restart) => {
+                    if let Err(error) = system.acpi.reboot() {
+                        log::error!("{}", error);
+                    }
+                }
+                Some(DesktopMenuAction::ShutDown) => {
+                    if let Err(error) = system.acpi.shutdown() {
+                        log::error!("{}", error);
+                    }
+                }
+                // Holding Shift lets the user edit the arguments of the app first
+                Some(DesktopMenuAction::Launch(selected_app_name))
+                    if input_state.modifiers.shift =>
+                {
+                    let descriptor = apps_manager
+                        .z_ordered
+                        .iter()
+                        .find(|app| app.descriptor.name == selected_app_name)
+                        .map(|app| app.descriptor.clone());
+
+                    if let Some(descriptor) = descriptor {
+                        let command_line = descriptor.launch_args().to_command_line();
+                        let mut text_state = TextBoxState::new();
+                        text_state.cursor = command_line.chars().count();
+                        apps_manager.launch_dialog = Some(LaunchDialog {
+                            command_line: TrackedContent::new(
+                                command_line,
+                                uitk_context.uuid_provider,
+                            ),
+                            text_state,
+                        });
+                        *is = AppsInteractionState::LaunchDialog {
+                            app_name: descriptor.name,
+                        };
+                    }
+                }
+                Some(DesktopMenuAction::Launch(selected_app_name)) => {
+                    match apps_manager.open_instance(&mut system.stats, selected_app_name, None) {
+                        Ok(app_id) => {
+                            if let Some(app) = apps_manager.get_mut(app_id) {
+                                let deco = compute_decorations(app, input_state);
+
+                                let preferred_rect =
+                                    Rect::from_center(pointer.x, pointer.y, app.rect.w, app.rect.h);
+
+                                app.rect = position_window(
+                                    &preferred_rect,
+                                    uitk_context.fb.shape(),
+                                    &deco,
+                                );
+                            }
+                        }
+                        Err(error) => log::error!("{}", error),
+                    }
+                }
+
+                _ => (),
+            }
+
+            let menu_closed = matches!(*is, AppsInteractionState::PieDesktopMenu { .. });
+            if menu_closed && (pointer.right_click_trigger || pointer.left_click_trigger) {
+                *is = AppsInteractionState::Idle
+            }
+        }
+    }
+
+    //
+    // Step apps
+    // (spread across all cores, the frame is only composited once every app has stepped)
+
+    let n = apps_manager.z_ordered.len();
+    let now = system.clock.time();
+
+    let screen_rect = {
+        let (w, h) = uitk_context.fb.shape();
+        Rect { x0: 0, y0: 0, w, h }
+    };
+    let window_rects: Vec<Option<Rect>> = apps_manager
+        .z_ordered
+        .iter()
+        .map(|app| match app.is_shown() {
+            true => Some(compute_decorations(app, input_state).window_rect),
+            false => None,
+        })
+        .collect();
+
+    // Background apps may be on top of the z-order, but they do not get input
+    let stdin_focused = apps_manager
+        .z_ordered
+        .iter()
+        .filter(|app| app.is_shown())
+        .any(|app| match &app.app_state {
+            AppState::Active { audit_mode, .. } => audit_mode.is_stdin_focused(),
+            _ => false,
+        });
+    let dialog_open = matches!(*is, AppsInteractionState::LaunchDialog { .. });
+    let foreground = match stdin_focused || dialog_open {
+        true => None,
+        false => window_rects.iter().rposition(|rect| rect.is_some()),
+    };
+
+    let mut step_jobs: Vec<StepJob> = Vec::new();
+    for (i, app) in apps_manager.z_ordered.iter_mut().enumerate() {
+        if !app.is_open || !matches!(app.app_state, AppState::Active { .. }) {
+            continue;
+        }
+
+        let occluded = match &window_rects[i] {
+            Some(rect) => is_occluded(rect, &screen_rect, window_rects[i + 1..].iter().flatten()),
+            None => false,
+        };
+
+        if let Some(step_period) = app.step_period(occluded) {
+            if now < app.next_step_at {
+                system.stats.record_idle_frame(&app.name);
+                continue;
+            }
+            app.next_step_at = now + step_period;
+        }
+
+        let AppState::Active {
+            wasm_app, paused, ..
+        } = &mut app.app_state
+        else {
+            continue;
+        };
+
+        step_jobs.push(StepJob {
+            app_index: i,
+            wasm_app,
+            rect: app.rect.clone(),
+            is_foreground: foreground == Some(i),
+            paused: *paused,
+            result: None,
+        });
+    }
 
-                    let deco = compute_decorations(app, input_state);
+    {
+        let shared = SharedHostState::new(HostState {
+            system: &mut *system,
+            uuid_provider: &mut *uitk_context.uuid_provider,
+        });
 
-                    let preferred_rect =
-                        Rect::from_center(pointer.x, pointer.y, app.rect.w, app.rect.h);
+        smp::for_each_parallel(&mut step_jobs, |job| {
+            let res = job.wasm_app.step(
+                &shared,
+                input_state,
+                &job.rect,
+                job.is_foreground,
+                job.paused,
+            );
+            job.result = Some(res);
+        });
+    }
 
-                    app.is_open = true;
-                    app.rect = position_window(&preferred_rect, uitk_context.fb.shape(), &deco);
-                    let app_name = app.descriptor.name;
-                    apps_manager.set_on_top(app_name);
-                }
+    let mut step_results: Vec<Option<anyhow::Result<()>>> = (0..n).map(|_| None).collect();
+    for job in step_jobs {
+        step_results[job.app_index] = job.result;
+    }
 
-                _ => (),
-            }
+    //
+    // Pipes (output which cannot be delivered, e.g. to a closed app, is dropped)
 
-            if pointer.right_click_trigger || pointer.left_click_trigger {
-                *is = AppsInteractionState::Idle
-            }
+    let piped: Vec<(String, Vec<u8>)> = apps_manager
+        .z_ordered
+        .iter_mut()
+        .filter_map(|app| {
+            let AppState::Active { wasm_app, .. } = &mut app.app_state else {
+                return None;
+            };
+            let data = wasm_app.take_piped_stdout(app.stdout_pipe.is_some());
+            Some((app.stdout_pipe.clone()?, data))
+        })
+        .collect();
+
+    for (target, data) in piped.into_iter().filter(|(_, data)| !data.is_empty()) {
+        let target = apps_manager.find_mut(&target);
+        if let Ok(App {
+            is_open: true,
+            app_state: AppState::Active { wasm_app, .. },
+            ..
+        }) = target
+        {
+            wasm_app.write_stdin(&data);
         }
     }
 
     //
-    // Step and draw apps
+    // Draw apps
 
     let font = get_font(&stylesheet.text.font_family(), stylesheet.text.sizes.medium);
 
-    let n = apps_manager.z_ordered.len();
-
     for (i, app) in apps_manager.z_ordered.iter_mut().enumerate() {
         if !app.is_open {
             continue;
         }
 
-        let app_name = &app.descriptor.name;
+        // Windowless apps still go through state changes (initialization, crashes...)
+        let shown = app.is_shown();
+
+        let app_id = app.id;
+        let app_name = app.name.as_str();
         let deco = compute_decorations(&app, input_state);
 
         let highlight = match *is {
             AppsInteractionState::AppHover {
-                app_name: hover_app_name,
+                app_id: hover_app_id,
                 hover_kind,
-            } => hover_app_name == *app_name && hover_kind == HoverKind::Titlebar,
+            } => hover_app_id == app_id && hover_kind == HoverKind::Titlebar,
             _ => false,
         };
 
-        let is_foreground = i == n - 1;
-
-        draw_decorations(
-            uitk_context.fb,
-            &stylesheet,
-            font,
-            &app.descriptor,
-            &deco,
-            highlight,
-        );
+        if shown {
+            draw_decorations(uitk_context.fb, &stylesheet, font, app, &deco, highlight);
+        }
 
         match &mut app.app_state {
             AppState::Init => {
                 let desc = &app.descriptor;
 
-                log::info!("Initializing app {}", desc.name);
-                let wasm_app = wasm_engine.instantiate_app(
+                log::info!("Initializing app {}", app_name);
+                app.started_at = system.clock.time();
+                let res = wasm_engine.instantiate_app(
                     system,
                     uitk_context.uuid_provider,
                     input_state,
                     desc.data,
-                    desc.name,
+                    app_name,
+                    &app.launch_args,
                     &app.rect,
                 );
 
-                app.app_state = AppState::Active {
-                    wasm_app,
-                    audit_mode: AppAuditMode::Disabled,
-                    paused: false,
-                };
+                match res {
+                    Ok(wasm_app) => {
+                        app.app_state = AppState::Active {
+                            wasm_app,
+                            audit_mode: AppAuditMode::Disabled,
+                            paused: false,
+                        };
+                    }
+                    Err(error) => {
+                        log::error!("Failed to initialize {}: {}", app_name, error);
+                        let log = TrackedContent::new(
+                            ConsoleOutput::from(format!("CRASH: {}\n", error)),
+                            uitk_context.uuid_provider,
+                        );
+                        app.crash(system, error, log);
+                    }
+                }
+            }
+
+            AppState::Restore { snapshot } => {
+                app.started_at = system.clock.time();
+                let res = wasm_engine.restore_app(
+                    uitk_context.uuid_provider,
+                    app.descriptor.data,
+                    app_name,
+                    &app.launch_args,
+                    snapshot,
+                );
+
+                match res {
+                    Ok(wasm_app) => {
+                        app.app_state = AppState::Active {
+                            wasm_app,
+                            audit_mode: AppAuditMode::Disabled,
+                            paused: false,
+                        };
+                    }
+                    Err(error) => {
+                        log::error!("Failed to restore {}: {}", app_name, error);
+                        let log = TrackedContent::new(
+                            ConsoleOutput::from(format!("CRASH: {}\n", error)),
+                            uitk_context.uuid_provider,
+                        );
+                        app.crash(system, error, log);
+                    }
+                }
             }
 
             AppState::Active {
@@ -507,7 +1349,7 @@ pub fn run_apps<F: FbViewMut>(
                 audit_mode,
                 paused,
             } => {
-                if *paused {
+                if *paused && shown {
                     draw_line_in_rect(
                         uitk_context.fb,
                         "PAUSED",
@@ -518,17 +1360,13 @@ pub fn run_apps<F: FbViewMut>(
                     );
                 }
 
-                let wasm_res = wasm_app.step(
-                    system,
-                    uitk_context.uuid_provider,
-                    input_state,
-                    &app.rect,
-                    is_foreground,
-                    *paused,
-                );
-
-                match wasm_res {
-                    Ok(()) => {
+                match step_results[i].take() {
+                    Some(Err(error)) => {
+                        let log = wasm_app.get_console_output().clone();
+                        app.crash(system, error, log);
+                    }
+                    _ if !shown => (),
+                    _ => {
                         if let Some(app_fb) = wasm_app.get_framebuffer() {
                             // To avoid visual glitches when resizing a paused app
                             let (src_w, src_h) = app_fb.shape();
@@ -555,30 +1393,75 @@ pub fn run_apps<F: FbViewMut>(
                                 .fb
                                 .copy_from_fb(&src, deco.content_rect.origin(), false);
 
-                            audit_mode.audit_window(
+                            let stdin_line = audit_mode.audit_window(
                                 uitk_context,
-                                &app.descriptor.name,
+                                &app.name,
                                 &deco,
                                 &system.stats,
                                 wasm_app.get_console_output(),
                             );
+
+                            if let Some(line) = stdin_line {
+                                wasm_app.write_stdin(line.as_bytes());
+                            }
                         }
                     }
-                    Err(error) => app.app_state = AppState::Crashed { error },
                 }
             }
 
-            AppState::Crashed { error } => {
-                let (x0, y0) = deco.content_rect.origin();
-                draw_str(
-                    uitk_context.fb,
-                    &format!("{:?}", error),
-                    x0,
-                    y0,
-                    font,
-                    Color::WHITE,
-                    None,
+            AppState::Crashed {
+                error,
+                log,
+                show_log,
+                log_state,
+                restart_at,
+            } => {
+                let now = system.clock.time();
+
+                let status = match *restart_at {
+                    Some(t) => format!("restarting in {:.0}s", f64::max(0.0, (t - now) / 1000.0)),
+                    None => "not restarting".to_string(),
+                };
+                let status = format!(
+                    "Crashed {} times, {}",
+                    system.stats.crash_count(app_name),
+                    status
                 );
+
+                // Ignoring clicks on the panel if another window is on top of it
+                let hovered = matches!(hover_state, Some((id, _)) if id == app_id);
+
+                let action = match shown {
+                    true => crash_panel(
+                        uitk_context,
+                        &deco.content_rect,
+                        font,
+                        &status,
+                        error,
+                        log,
+                        *show_log,
+                        log_state,
+                    ),
+                    false => None,
+                };
+
+                match action {
+                    Some(CrashPanelAction::Restart) if hovered => {
+                        log::info!("Restarting {}", app_name);
+                        app.consecutive_crashes = 0;
+                        app.app_state = AppState::Init;
+                    }
+                    Some(CrashPanelAction::ToggleLog) if hovered => *show_log = !*show_log,
+                    Some(CrashPanelAction::CopyError) if hovered => {
+                        system.clipboard = format!("{:?}", error);
+                        log::info!("Copied the {} crash report to the clipboard", app_name);
+                    }
+                    _ if restart_at.is_some_and(|t| now >= t) => {
+                        log::info!("Restarting {}", app_name);
+                        app.app_state = AppState::Init;
+                    }
+                    _ => (),
+                }
             }
         }
     }
@@ -586,8 +1469,532 @@ pub fn run_apps<F: FbViewMut>(
     if let Some(draw_calls) = pie_draw_calls {
         draw_calls.draw(uitk_context.fb);
     }
+
+    if let Some((thumbnail, origin)) = pie_preview {
+        let (w, h) = thumbnail.shape();
+        let (x0, y0) = origin;
+        uitk_context.fb.copy_from_fb(&thumbnail, origin, false);
+        draw_rect_outline(
+            uitk_context.fb,
+            &Rect { x0, y0, w, h },
+            stylesheet.colors.outline,
+            false,
+            1,
+        );
+    }
+
+    match *is {
+        AppsInteractionState::WindowSwitcher { app_id } => {
+            draw_window_switcher(uitk_context.fb, &stylesheet, font, apps_manager, app_id);
+        }
+        AppsInteractionState::DevicesView { scroll } => {
+            draw_devices_view(uitk_context.fb, &stylesheet, &system.pci_bus, scroll);
+        }
+        AppsInteractionState::LaunchDialog { app_name } => {
+            let action = match apps_manager.launch_dialog.as_mut() {
+                Some(dialog) => launch_dialog(uitk_context, app_name, dialog),
+                None => Some(LaunchDialogAction::Cancel),
+            };
+
+            if let Some(action) = action {
+                let dialog = apps_manager.launch_dialog.take();
+                if let (LaunchDialogAction::Launch, Some(dialog)) = (action, dialog) {
+                    let launch_args = LaunchArgs::parse(dialog.command_line.as_ref());
+                    let res =
+                        apps_manager.open_instance(&mut system.stats, app_name, Some(&launch_args));
+                    match res {
+                        Ok(app_id) => {
+                            if let Some(app) = apps_manager.get_mut(app_id) {
+                                let deco = compute_decorations(app, input_state);
+
+                                let (fb_w, fb_h) = uitk_context.fb.shape();
+                                let preferred_rect = Rect::from_center(
+                                    (fb_w / 2) as i64,
+                                    (fb_h / 2) as i64,
+                                    app.rect.w,
+                                    app.rect.h,
+                                );
+
+                                app.rect = position_window(
+                                    &preferred_rect,
+                                    uitk_context.fb.shape(),
+                                    &deco,
+                                );
+                            }
+                        }
+                        Err(error) => log::error!("{}", error),
+                    }
+                }
+                *is = AppsInteractionState::Idle;
+            }
+        }
+        _ => (),
+    }
+}
+
+fn draw_devices_view<F: FbViewMut>(
+    fb: &mut F,
+    stylesheet: &StyleSheet,
+    pci_bus: &PciBus,
+    scroll: usize,
+) {
+    const MARGIN: u32 = 20;
+
+    let title_font = get_font(&stylesheet.text.font_family(), stylesheet.text.sizes.large);
+    let font = get_font(&stylesheet.text.font_family(), stylesheet.text.sizes.small);
+
+    let (fb_w, fb_h) = fb.shape();
+    let panel_rect = Rect {
+        x0: MARGIN as i64,
+        y0: (TOPBAR_H + MARGIN) as i64,
+        w: fb_w - 2 * MARGIN,
+        h: fb_h - TOPBAR_H - 2 * MARGIN,
+    };
+
+    draw_rect(fb, &panel_rect, stylesheet.colors.background, false);
+    draw_rect_outline(fb, &panel_rect, stylesheet.colors.outline, false, 1);
+
+    let x0 = panel_rect.x0 + MARGIN as i64;
+    let mut y = panel_rect.y0 + MARGIN as i64;
+
+    draw_str(
+        fb,
+        "PCI devices (scroll to browse, click to close)",
+        x0,
+        y,
+        title_font,
+        stylesheet.colors.text,
+        None,
+    );
+    y += (title_font.char_h as u32 + MARGIN) as i64;
+
+    let lines: Vec<String> = pci_bus.iter().flat_map(|entry| entry.describe()).collect();
+    let y_max = panel_rect.y0 + (panel_rect.h - MARGIN) as i64 - font.char_h as i64;
+
+    let scroll = usize::min(scroll, lines.len().saturating_sub(1));
+
+    for line in lines.iter().skip(scroll) {
+        if y > y_max {
+            break;
+        }
+
+        // Device lines are not indented, unlike their BARs and capabilities
+        let color = match line.starts_with(' ') {
+            true => stylesheet.colors.text,
+            false => stylesheet.colors.accent,
+        };
+
+        draw_str(fb, line, x0, y, font, color, None);
+        y += font.char_h as i64;
+    }
+}
+
+enum LaunchDialogAction {
+    Launch,
+    Cancel,
+}
+
+// Shown when opening an app from the desktop menu with Shift held
+fn launch_dialog<F: FbViewMut>(
+    uitk_context: &mut uitk::UiContext<F>,
+    app_name: &str,
+    dialog: &mut LaunchDialog,
+) -> Option<LaunchDialogAction> {
+    const DIALOG_W: u32 = 500;
+    const MARGIN: u32 = 10;
+    const BUTTON_H: u32 = 25;
+
+    let stylesheet = uitk_context.stylesheet.clone();
+    let title_font = get_font(&stylesheet.text.font_family(), stylesheet.text.sizes.large);
+    let font = get_font(&stylesheet.text.font_family(), stylesheet.text.sizes.medium);
+
+    let line_h = font.char_h as u32;
+    let input_h = line_h + 2 * stylesheet.margin;
+    let dialog_h = title_font.char_h as u32 + 2 * line_h + input_h + BUTTON_H + 6 * MARGIN;
+
+    let (fb_w, fb_h) = uitk_context.fb.shape();
+    let dialog_rect = Rect::from_center(
+        (fb_w / 2) as i64,
+        (fb_h / 2) as i64,
+        u32::min(DIALOG_W, fb_w),
+        dialog_h,
+    );
+
+    draw_rect(
+        uitk_context.fb,
+        &dialog_rect,
+        stylesheet.colors.background,
+        false,
+    );
+    draw_rect_outline(
+        uitk_context.fb,
+        &dialog_rect,
+        stylesheet.colors.outline,
+        false,
+        1,
+    );
+
+    let x0 = dialog_rect.x0 + MARGIN as i64;
+    let inner_w = dialog_rect.w.saturating_sub(2 * MARGIN);
+    let mut y = dialog_rect.y0 + MARGIN as i64;
+
+    draw_str(
+        uitk_context.fb,
+        &format!("Launch {}", app_name),
+        x0,
+        y,
+        title_font,
+        stylesheet.colors.text,
+        None,
+    );
+    y += (title_font.char_h as u32 + MARGIN) as i64;
+
+    let help_lines = [
+        "Arguments, separated by spaces",
+        "Leading KEY=VALUE words set environment variables",
+    ];
+    for line in help_lines {
+        draw_str(
+            uitk_context.fb,
+            line,
+            x0,
+            y,
+            font,
+            stylesheet.colors.text,
+            None,
+        );
+        y += line_h as i64;
+    }
+    y += MARGIN as i64;
+
+    let input_rect = Rect {
+        x0,
+        y0: y,
+        w: inner_w,
+        h: input_h,
+    };
+    uitk_context.editable_text_box(
+        &input_rect,
+        &mut dialog.command_line,
+        &mut dialog.text_state,
+        false,
+        false,
+        None::<&TrackedContent<String>>,
+    );
+    y += (input_h + 2 * MARGIN) as i64;
+
+    let buttons_rect = Rect {
+        x0,
+        y0: y,
+        w: inner_w,
+        h: BUTTON_H,
+    };
+    let button_rects = make_horizontal_layout(&buttons_rect, MARGIN, &[LayoutItem::Float; 2]);
+
+    let buttons = [
+        (LaunchDialogAction::Launch, "Launch"),
+        (LaunchDialogAction::Cancel, "Cancel"),
+    ];
+
+    let mut action = None;
+    for ((button_action, text), button_rect) in buttons.into_iter().zip(button_rects) {
+        let clicked = uitk_context.button(&ButtonConfig {
+            rect: button_rect,
+            text: text.to_owned(),
+            ..Default::default()
+        });
+        if clicked {
+            action = Some(button_action);
+        }
+    }
+
+    let input_state = &uitk_context.input_state;
+    if input_state.check_key_pressed(Keycode::KEY_ENTER) {
+        action = Some(LaunchDialogAction::Launch);
+    } else if input_state.check_key_pressed(Keycode::KEY_ESC) {
+        action = Some(LaunchDialogAction::Cancel);
+    }
+
+    action
+}
+
+enum CrashPanelAction {
+    Restart,
+    ToggleLog,
+    CopyError,
+}
+
+// Shown in place of the content of a crashed app
+fn crash_panel<F: FbViewMut>(
+    uitk_context: &mut uitk::UiContext<F>,
+    rect: &Rect,
+    font: &'static Font,
+    status: &str,
+    error: &anyhow::Error,
+    log: &TrackedContent<ConsoleOutput>,
+    show_log: bool,
+    log_state: &mut TextBoxState,
+) -> Option<CrashPanelAction> {
+    const MARGIN: u32 = 10;
+    const BUTTON_H: u32 = 25;
+
+    let line_h = font.char_h as u32;
+    if rect.w <= 2 * MARGIN || rect.h <= 2 * line_h + BUTTON_H + 4 * MARGIN {
+        return None;
+    }
+
+    let inner_rect = Rect {
+        x0: rect.x0 + MARGIN as i64,
+        y0: rect.y0 + MARGIN as i64,
+        w: rect.w - 2 * MARGIN,
+        h: rect.h - 2 * MARGIN,
+    };
+    let (x0, y0) = inner_rect.origin();
+
+    let colors = &uitk_context.stylesheet.colors;
+    let (title_color, text_color) = (colors.red, colors.text);
+
+    draw_str(
+        uitk_context.fb,
+        "App crashed",
+        x0,
+        y0,
+        font,
+        title_color,
+        None,
+    );
+    draw_str(
+        uitk_context.fb,
+        status,
+        x0,
+        y0 + line_h as i64,
+        font,
+        text_color,
+        None,
+    );
+
+    let body_rect = Rect {
+        x0,
+        y0: y0 + (2 * line_h + MARGIN) as i64,
+        w: inner_rect.w,
+        h: inner_rect.h - 2 * line_h - BUTTON_H - 2 * MARGIN,
+    };
+
+    match show_log {
+        true => {
+            let log = console_rich_text(log, text_color, title_color, font);
+            uitk_context.text_box(&body_rect, &log, log_state, false)
+        }
+        false => {
+            let error = format!("{:?}", error);
+            let max_lines = (body_rect.h / line_h) as usize;
+            for (i, line) in error.lines().take(max_lines).enumerate() {
+                let y = body_rect.y0 + (i as u32 * line_h) as i64;
+                draw_str(uitk_context.fb, line, x0, y, font, text_color, None);
+            }
+        }
+    }
+
+    let buttons_rect = Rect {
+        x0,
+        y0: inner_rect.y0 + (inner_rect.h - BUTTON_H) as i64,
+        w: inner_rect.w,
+        h: BUTTON_H,
+    };
+    let button_rects = make_horizontal_layout(&buttons_rect, MARGIN, &[LayoutItem::Float; 3]);
+
+    let buttons = [
+        (CrashPanelAction::Restart, "Restart"),
+        (
+            CrashPanelAction::ToggleLog,
+            match show_log {
+                true => "View error",
+                false => "View log",
+            },
+        ),
+        (CrashPanelAction::CopyError, "Copy error"),
+    ];
+
+    let mut action = None;
+    for ((button_action, text), button_rect) in buttons.into_iter().zip(button_rects) {
+        let clicked = uitk_context.button(&ButtonConfig {
+            rect: button_rect,
+            text: text.to_owned(),
+            ..Default::default()
+        });
+        if clicked {
+            action = Some(button_action);
+        }
+    }
+
+    action
+}
+
+// Whether a window is entirely covered by the windows above it, or off-screen
+fn is_occluded<'a>(
+    rect: &Rect,
+    screen_rect: &Rect,
+    windows_above: impl Iterator<Item = &'a Rect>,
+) -> bool {
+    let Some(visible) = rect.intersection(screen_rect) else {
+        return true;
+    };
+
+    let mut uncovered = vec![visible];
+    for window_rect in windows_above {
+        uncovered = uncovered
+            .iter()
+            .flat_map(|part| subtract_rect(part, window_rect))
+            .collect();
+        if uncovered.is_empty() {
+            return true;
+        }
+    }
+
+    false
+}
+
+// Parts of a rectangle outside of another one (at most 4)
+fn subtract_rect(rect: &Rect, other: &Rect) -> Vec<Rect> {
+    let Some(inter) = rect.intersection(other) else {
+        return vec![rect.clone()];
+    };
+
+    let [x0, y0, x1, y1] = rect.as_xyxy();
+    let [ix0, iy0, ix1, iy1] = inter.as_xyxy();
+
+    let mut parts = Vec::new();
+    if y0 < iy0 {
+        parts.push(Rect::from_xyxy([x0, y0, x1, iy0 - 1]));
+    }
+    if iy1 < y1 {
+        parts.push(Rect::from_xyxy([x0, iy1 + 1, x1, y1]));
+    }
+    if x0 < ix0 {
+        parts.push(Rect::from_xyxy([x0, iy0, ix0 - 1, iy1]));
+    }
+    if ix1 < x1 {
+        parts.push(Rect::from_xyxy([ix1 + 1, iy0, x1, iy1]));
+    }
+
+    parts
+}
+
+// Open windows (including minimized ones), from the most recently focused one to the least
+fn switcher_order(apps_manager: &AppsManager) -> Vec<AppId> {
+    apps_manager
+        .z_ordered
+        .iter()
+        .rev()
+        .filter(|app| app.is_open && app.is_windowed())
+        .map(|app| app.id)
+        .collect()
+}
+
+fn draw_window_switcher<F: FbViewMut>(
+    fb: &mut F,
+    stylesheet: &StyleSheet,
+    font: &Font,
+    apps_manager: &AppsManager,
+    selected_app_id: AppId,
+) {
+    const SLOT_MARGIN: u32 = 10;
+    const OUTLINE_W: u32 = 3;
+
+    let order = switcher_order(apps_manager);
+    if order.is_empty() {
+        return;
+    }
+
+    let n = order.len() as u32;
+    let slot_w = THUMBNAIL_W + 2 * SLOT_MARGIN;
+    let slot_h = THUMBNAIL_H + 2 * SLOT_MARGIN + font.char_h as u32;
+
+    let (fb_w, fb_h) = fb.shape();
+    let panel_rect = Rect::from_center(
+        (fb_w / 2) as i64,
+        (fb_h / 2) as i64,
+        n * slot_w + 2 * SLOT_MARGIN,
+        slot_h + 2 * SLOT_MARGIN,
+    );
+
+    draw_rect(fb, &panel_rect, stylesheet.colors.background, false);
+    draw_rect_outline(fb, &panel_rect, stylesheet.colors.outline, false, 1);
+
+    for (i, app_id) in order.into_iter().enumerate() {
+        let Some(app) = apps_manager.iter().find(|app| app.id == app_id) else {
+            continue;
+        };
+
+        let slot_rect = Rect {
+            x0: panel_rect.x0 + (SLOT_MARGIN + i as u32 * slot_w) as i64,
+            y0: panel_rect.y0 + SLOT_MARGIN as i64,
+            w: slot_w,
+            h: slot_h,
+        };
+        let thumbnail_rect = Rect {
+            x0: slot_rect.x0 + SLOT_MARGIN as i64,
+            y0: slot_rect.y0 + SLOT_MARGIN as i64,
+            w: THUMBNAIL_W,
+            h: THUMBNAIL_H,
+        };
+
+        // Falling back to the app icon for apps which have not drawn anything yet
+        let thumbnail = apps_manager.thumbnail(&app.name, THUMBNAIL_W, THUMBNAIL_H);
+        let icon = app.descriptor.icon;
+
+        let (xc, yc) = (
+            thumbnail_rect.x0 + (THUMBNAIL_W / 2) as i64,
+            thumbnail_rect.y0 + (THUMBNAIL_H / 2) as i64,
+        );
+        match thumbnail {
+            Some(thumbnail) => {
+                let (w, h) = thumbnail.shape();
+                let dst = Rect::from_center(xc, yc, w, h);
+                fb.copy_from_fb(&thumbnail, dst.origin(), false);
+            }
+            None => {
+                let (w, h) = icon.shape();
+                let dst = Rect::from_center(xc, yc, w, h);
+                fb.copy_from_fb(icon, dst.origin(), true);
+            }
+        }
+
+        if app_id == selected_app_id {
+            draw_rect_outline(fb, &slot_rect, stylesheet.colors.accent, false, OUTLINE_W);
+        }
+
+        let text_rect = Rect {
+            x0: slot_rect.x0,
+            y0: thumbnail_rect.y0 + (THUMBNAIL_H + SLOT_MARGIN / 2) as i64,
+            w: slot_w,
+            h: font.char_h as u32,
+        };
+        let text = ellipsize_text(&app.name, font, slot_w - 2 * SLOT_MARGIN);
+        draw_line_in_rect(
+            fb,
+            &text,
+            &text_rect,
+            font,
+            stylesheet.colors.text,
+            TextJustification::Center,
+        );
+    }
+}
+
+struct StepJob<'a> {
+    app_index: usize,
+    wasm_app: &'a mut WasmApp,
+    rect: Rect,
+    is_foreground: bool,
+    paused: bool,
+    result: Option<anyhow::Result<()>>,
 }
 
+// Safety: each job is handed to a single core, and WasmApp only reaches kernel state
+// through the SharedHostState lock
+unsafe impl Send for StepJob<'_> {}
+
 struct AppDecorations {
     content_rect: Rect,
     window_rect: Rect,
@@ -607,9 +2014,12 @@ fn app_audit_window<F: FbViewMut>(
     app_name: &str,
     deco: &AppDecorations,
     stats: &SystemStats,
-    console_log: &TrackedContent<String>,
+    console_log: &TrackedContent<ConsoleOutput>,
     scrollable_text_state: &mut TextBoxState,
-) {
+    stdin_line: &mut TrackedContent<String>,
+    stdin_state: &mut TextBoxState,
+    stdin_focused: &mut bool,
+) -> Option<String> {
     const ROW_H: u32 = 100;
     const AUDIT_WIN_W: u32 = 300;
     const MIN_AUDIT_WIN_H: u32 = 100;
@@ -699,9 +2109,9 @@ fn app_audit_window<F: FbViewMut>(
         },
     ];
 
-    let font_family_name = &uitk_context.stylesheet.text.font_family();
-    let title_font = get_font(font_family_name, SECTION_TITLE_FONT_SIZE);
-    let subtitle_font = get_font(font_family_name, SECTION_SUBTITLE_FONT_SIZE);
+    let font_family_name = uitk_context.stylesheet.text.font_family().to_owned();
+    let title_font = get_font(&font_family_name, SECTION_TITLE_FONT_SIZE);
+    let subtitle_font = get_font(&font_family_name, SECTION_SUBTITLE_FONT_SIZE);
 
     let mut y = deco.window_rect.y0;
     let x = deco.window_rect.x0 + deco.window_rect.w as i64 + 10;
@@ -781,18 +2191,70 @@ fn app_audit_window<F: FbViewMut>(
 
     let [_, _, _, win_y] = deco.window_rect.as_xyxy();
 
+    let log_font = get_font(&font_family_name, LOG_FONT_SIZE);
+    let stdin_h = log_font.char_h as u32 + 2 * uitk_context.stylesheet.margin;
+
     let console_rect = Rect::from_xyxy([
         x,
         y,
         x + AUDIT_WIN_W as i64,
-        i64::max(y + MIN_AUDIT_WIN_H as i64, win_y - deco.handle_h as i64 - 1),
+        i64::max(
+            y + MIN_AUDIT_WIN_H as i64,
+            win_y - deco.handle_h as i64 - 1 - stdin_h as i64,
+        ),
     ]);
 
-    uitk_context
-        .style(|ss| ss.text.sizes.medium = LOG_FONT_SIZE)
-        .text_box(&console_rect, console_log, scrollable_text_state, true);
+    let colors = &uitk_context.stylesheet.colors;
+    let console_text = console_rich_text(console_log, colors.text, colors.red, log_font);
+    uitk_context.text_box(&console_rect, &console_text, scrollable_text_state, true);
+
+    //
+    // Stdin input line (focused by clicking on it, Enter sends the line to the app)
+
+    let stdin_rect = Rect {
+        x0: x,
+        y0: console_rect.y0 + console_rect.h as i64,
+        w: AUDIT_WIN_W,
+        h: stdin_h,
+    };
+
+    let pointer = &uitk_context.input_state.pointer;
+    if pointer.left_click_trigger {
+        *stdin_focused = stdin_rect.check_contains_point(pointer.x, pointer.y);
+    }
+
+    let prompt = TrackedContent::new_from_hash("> ".to_string());
+    let mut stdin_ui = uitk_context.style(|ss| ss.text.sizes.medium = LOG_FONT_SIZE);
+
+    let entered_line = match *stdin_focused {
+        true => {
+            stdin_ui.editable_text_box(
+                &stdin_rect,
+                stdin_line,
+                stdin_state,
+                false,
+                false,
+                Some(&prompt),
+            );
+
+            match stdin_ui.input_state.check_key_pressed(Keycode::KEY_ENTER) {
+                true => {
+                    let mut line = core::mem::take(stdin_line.mutate(stdin_ui.uuid_provider));
+                    line.push('\n');
+                    stdin_state.cursor = 0;
+                    Some(line)
+                }
+                false => None,
+            }
+        }
+        false => {
+            let text = TrackedContent::new_from_hash(format!("> {}", stdin_line.as_ref()));
+            stdin_ui.text_box(&stdin_rect, &text, stdin_state, false);
+            None
+        }
+    };
 
-    let outline_rect = title_rect.bounding_box(&console_rect);
+    let outline_rect = title_rect.bounding_box(&stdin_rect);
 
     draw_rect_outline(
         uitk_context.fb,
@@ -801,6 +2263,8 @@ fn app_audit_window<F: FbViewMut>(
         false,
         uitk_context.stylesheet.margin,
     );
+
+    entered_line
 }
 
 fn get_hold_anchor(pointer: &PointerState, rect: &Rect) -> Point2D<i64> {
@@ -943,7 +2407,7 @@ fn draw_decorations<F: FbViewMut>(
     fb: &mut F,
     stylesheet: &StyleSheet,
     font: &Font,
-    app_descriptor: &AppDescriptor,
+    app: &App,
     deco: &AppDecorations,
     highlight: bool,
 ) {
@@ -968,12 +2432,12 @@ fn draw_decorations<F: FbViewMut>(
     draw_rect_outline(fb, &deco.icon_rect, Color::BLACK, false, stylesheet.margin);
     let icon_fb_rect = {
         let (xc, yc) = deco.icon_rect.center();
-        let (w, h) = app_descriptor.icon.shape();
+        let (w, h) = app.descriptor.icon.shape();
         Rect::from_center(xc, yc, w, h)
     };
-    fb.copy_from_fb(app_descriptor.icon, icon_fb_rect.origin(), true);
+    fb.copy_from_fb(app.descriptor.icon, icon_fb_rect.origin(), true);
 
-    let ellipsized_title = ellipsize_text(app_descriptor.name, font, deco.titlebar_rect.w);
+    let ellipsized_title = ellipsize_text(&app.name, font, deco.titlebar_rect.w);
 
     draw_line_in_rect(
         fb,
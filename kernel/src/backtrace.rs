@@ -0,0 +1,148 @@
+use alloc::format;
+use alloc::string::String;
+use core::ptr::addr_of;
+use tinyvec::ArrayVec;
+
+const MAX_FRAMES: usize = 32;
+
+// Max distance between two consecutive frames, beyond which the frame chain is assumed broken
+const MAX_FRAME_SIZE: u64 = 1 << 20;
+
+//
+// Stack backtraces
+//
+// The kernel is built with frame pointers (see .cargo/config.toml), so the call stack can be
+// walked by following the chain of saved RBP values, each one sitting right below the return
+// address of its frame.
+
+extern "C" {
+    // Defined by the linker, at the start of the loaded PE image
+    static __ImageBase: u8;
+}
+
+pub type Backtrace = ArrayVec<[u64; MAX_FRAMES]>;
+
+// Return addresses of the callers of this function, innermost first
+#[inline(never)]
+pub fn capture() -> Backtrace {
+    let (image_start, image_end) = image_range();
+
+    let mut frames = Backtrace::new();
+
+    let mut rbp: u64;
+    unsafe { core::arch::asm!("mov {}, rbp", out(reg) rbp) };
+
+    while frames.len() < MAX_FRAMES && rbp != 0 && rbp % 8 == 0 {
+        let (next_rbp, ret_addr) = unsafe {
+            let frame = rbp as *const u64;
+            (frame.read(), frame.add(1).read())
+        };
+
+        if ret_addr < image_start || ret_addr >= image_end {
+            break;
+        }
+
+        frames.push(ret_addr);
+
+        if next_rbp <= rbp || next_rbp - rbp > MAX_FRAME_SIZE {
+            break;
+        }
+        rbp = next_rbp;
+    }
+
+    frames
+}
+
+pub fn format_frame(addr: u64) -> String {
+    let (image_start, _) = image_range();
+    let rva = addr - image_start;
+
+    match lookup_symbol(rva) {
+        Some((name, offset)) => format!("{:#010x} {}+{:#x}", rva, name, offset),
+        None => format!("{:#010x} ???", rva),
+    }
+}
+
+fn image_range() -> (u64, u64) {
+    let base = addr_of!(__ImageBase) as u64;
+
+    // SizeOfImage, in the PE32+ optional header
+    let size = unsafe {
+        let pe_offset = ((base + 0x3c) as *const u32).read_unaligned() as u64;
+        ((base + pe_offset + 24 + 56) as *const u32).read_unaligned() as u64
+    };
+
+    (base, base + size)
+}
+
+//
+// Kernel symbol table
+//
+// Space is reserved in the binary and filled in after linking by make.py, from the linker map.
+// Layout (little-endian):
+//   magic (8 bytes), symbol count (u32)
+//   (address relative to the image base: u32, name offset: u32, name length: u32), sorted by address
+//   symbol names (demangled, UTF-8)
+// If the table is left empty, backtraces only show raw addresses.
+
+const SYMBOL_TABLE_SIZE: usize = 2 << 20;
+const SYMBOL_TABLE_MAGIC: [u8; 8] = *b"KSYMTAB!";
+const SYMBOL_ENTRY_SIZE: usize = 12;
+
+// Only read through an opaque reference (see symbol_table()), so that the compiler does not
+// assume the placeholder content
+#[used]
+static SYMBOL_TABLE_DATA: [u8; SYMBOL_TABLE_SIZE] = symbol_table_placeholder();
+
+static SYMBOL_TABLE: spin::Once<&'static [u8]> = spin::Once::new();
+
+fn symbol_table() -> &'static [u8] {
+    SYMBOL_TABLE.call_once(|| core::hint::black_box(&SYMBOL_TABLE_DATA))
+}
+
+const fn symbol_table_placeholder() -> [u8; SYMBOL_TABLE_SIZE] {
+    let mut table = [0u8; SYMBOL_TABLE_SIZE];
+    let mut i = 0;
+    while i < SYMBOL_TABLE_MAGIC.len() {
+        table[i] = SYMBOL_TABLE_MAGIC[i];
+        i += 1;
+    }
+    table
+}
+
+fn lookup_symbol(rva: u64) -> Option<(&'static str, u64)> {
+    let table = symbol_table();
+
+    let read_u32 = |offset: usize| -> Option<u32> {
+        let bytes = table.get(offset..offset + 4)?;
+        Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+    };
+
+    let header_size = SYMBOL_TABLE_MAGIC.len() + 4;
+    let count = read_u32(SYMBOL_TABLE_MAGIC.len())? as usize;
+    let entry = |i: usize| -> Option<(u64, usize, usize)> {
+        let offset = header_size + i * SYMBOL_ENTRY_SIZE;
+        Some((
+            read_u32(offset)? as u64,
+            read_u32(offset + 4)? as usize,
+            read_u32(offset + 8)? as usize,
+        ))
+    };
+
+    // Last symbol starting at or before the address
+    let (mut lo, mut hi) = (0, count);
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        match entry(mid)?.0 <= rva {
+            true => lo = mid + 1,
+            false => hi = mid,
+        }
+    }
+    let (sym_rva, name_offset, name_len) = entry(lo.checked_sub(1)?)?;
+
+    let names_start = header_size + count * SYMBOL_ENTRY_SIZE;
+    let name_bytes = table.get(names_start + name_offset..names_start + name_offset + name_len)?;
+    let name = core::str::from_utf8(name_bytes).ok()?;
+
+    Some((name, rva - sym_rva))
+}
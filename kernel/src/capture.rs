@@ -0,0 +1,156 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use applib::png::encode_png;
+use applib::FbView;
+
+use crate::app::AppsManager;
+use crate::console_println;
+
+// Frames per second written in recording mode
+const RECORD_FPS: f64 = 10.0;
+
+// Base64 characters per line, must be a multiple of 4
+const LINE_LEN: usize = 76;
+
+//
+// Screenshots and screen recording
+//
+// Captured frames are PNG-encoded and streamed over the kernel console (COM2) as:
+//   BEGIN PNG <label>
+//   | <base64 data>
+//   ...
+//   END PNG
+
+pub struct ScreenCapture {
+    // Number of frames to wait before capturing, so that the pie menu
+    // used to request the screenshot is gone
+    screenshot_countdown: Option<u32>,
+    // Apps which requested a screenshot of their own window
+    pending_windows: Vec<String>,
+    recording: Option<Recording>,
+    screenshot_index: u32,
+}
+
+struct Recording {
+    frame_index: u32,
+    last_frame_t: f64,
+}
+
+impl ScreenCapture {
+    pub fn new() -> Self {
+        ScreenCapture {
+            screenshot_countdown: None,
+            pending_windows: Vec::new(),
+            recording: None,
+            screenshot_index: 0,
+        }
+    }
+
+    pub fn request_screenshot(&mut self) {
+        self.screenshot_countdown = Some(1);
+    }
+
+    pub fn request_window_screenshot(&mut self, app_name: &str) {
+        if !self.pending_windows.iter().any(|name| name == app_name) {
+            self.pending_windows.push(app_name.into());
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    pub fn toggle_recording(&mut self) {
+        match self.recording.take() {
+            Some(recording) => {
+                log::info!("Stopped recording ({} frames)", recording.frame_index);
+            }
+            None => {
+                log::info!("Started recording");
+                self.recording = Some(Recording {
+                    frame_index: 0,
+                    last_frame_t: f64::NEG_INFINITY,
+                });
+            }
+        }
+    }
+
+    // Should be called once the frame has been fully composited
+    pub fn update<F: FbView>(&mut self, fb: &F, apps_manager: &AppsManager, time: f64) {
+        match self.screenshot_countdown {
+            Some(0) => {
+                let label = format!("screenshot_{:04}", self.screenshot_index);
+                stream_png(&label, fb);
+                log::info!("Screenshot saved ({})", label);
+                self.screenshot_index += 1;
+                self.screenshot_countdown = None;
+            }
+            Some(n) => self.screenshot_countdown = Some(n - 1),
+            None => (),
+        }
+
+        for app_name in core::mem::take(&mut self.pending_windows) {
+            match apps_manager.window_framebuffer(&app_name) {
+                Some(window_fb) => {
+                    let label = format!("{}_{:04}", window_label(&app_name), self.screenshot_index);
+                    stream_png(&label, &window_fb);
+                    log::info!("Window screenshot saved ({})", label);
+                    self.screenshot_index += 1;
+                }
+                None => log::warn!("Cannot take a screenshot of {}: window not open", app_name),
+            }
+        }
+
+        if let Some(recording) = self.recording.as_mut() {
+            if time - recording.last_frame_t >= 1000.0 / RECORD_FPS {
+                let label = format!("frame_{:06}", recording.frame_index);
+                stream_png(&label, fb);
+                recording.frame_index += 1;
+                recording.last_frame_t = time;
+            }
+        }
+    }
+}
+
+pub fn stream_png<F: FbView>(label: &str, fb: &F) {
+    let png = encode_png(fb);
+    let encoded = base64_encode(&png);
+
+    console_println!("BEGIN PNG {}", label);
+    for line in encoded.as_bytes().chunks(LINE_LEN) {
+        console_println!("| {}", core::str::from_utf8(line).unwrap());
+    }
+    console_println!("END PNG");
+}
+
+pub fn window_label(app_name: &str) -> String {
+    format!("window_{}", app_name.replace(' ', "_"))
+}
+
+fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let index = (n >> (18 - 6 * i)) & 0x3f;
+                out.push(ALPHABET[index as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+
+    out
+}
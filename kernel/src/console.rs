@@ -0,0 +1,341 @@
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+use applib::input::KeyboardLayout;
+use applib::FbView;
+use smoltcp::wire::IpEndpoint;
+
+use crate::app::{AppState, AppsManager};
+use crate::capture::{stream_png, window_label};
+use crate::serial::try_receive_console;
+use crate::system::System;
+use crate::wasm::LaunchArgs;
+use crate::{console_print, console_println, memory};
+
+// Max number of bytes read from the serial port per frame
+const MAX_READ_PER_FRAME: usize = 256;
+const MAX_LINE_LEN: usize = 256;
+
+//
+// Kernel command console on COM2, meant to drive and inspect the OS from a host script.
+// Every response ends with a line starting with either "OK" or "ERR".
+
+pub struct KernelConsole {
+    line_buf: String,
+}
+
+impl KernelConsole {
+    pub fn new() -> Self {
+        console_println!("Munal OS kernel console");
+        console_print!("> ");
+        KernelConsole {
+            line_buf: String::new(),
+        }
+    }
+
+    // Should be called at the end of the frame, once everything has been composited
+    pub fn update<F: FbView>(
+        &mut self,
+        system: &mut System,
+        apps_manager: &mut AppsManager,
+        fb: &F,
+    ) {
+        for _ in 0..MAX_READ_PER_FRAME {
+            let Some(byte) = try_receive_console() else {
+                break;
+            };
+
+            match byte {
+                b'\r' | b'\n' => {
+                    let line = core::mem::take(&mut self.line_buf);
+                    if !line.trim().is_empty() {
+                        run_command(line.trim(), system, apps_manager, fb);
+                        console_print!("> ");
+                    }
+                }
+
+                // Backspace / DEL
+                0x08 | 0x7f => {
+                    self.line_buf.pop();
+                }
+
+                b if b.is_ascii() && !b.is_ascii_control() => {
+                    if self.line_buf.len() < MAX_LINE_LEN {
+                        self.line_buf.push(b as char);
+                    }
+                }
+
+                _ => (),
+            }
+        }
+    }
+}
+
+fn run_command<F: FbView>(line: &str, system: &mut System, apps_manager: &mut AppsManager, fb: &F) {
+    // Arguments are not split further, since app names may contain spaces
+    let (cmd, arg) = match line.split_once(' ') {
+        Some((cmd, arg)) => (cmd, arg.trim()),
+        None => (line, ""),
+    };
+
+    log::debug!("Console command: {}", line);
+
+    let res = match (cmd, arg) {
+        ("help", "") => cmd_help(),
+        ("apps", "") => cmd_apps(apps_manager),
+        ("launch", arg) if !arg.is_empty() => cmd_launch(system, apps_manager, arg),
+        ("close", app_name) if !app_name.is_empty() => {
+            apps_manager.close_app(&mut system.stats, app_name)
+        }
+        ("pipe", arg) if !arg.is_empty() => cmd_pipe(apps_manager, arg),
+        ("snapshot", app_name) => cmd_snapshot(apps_manager, app_name),
+        ("restore", app_name) => cmd_restore(apps_manager, app_name),
+        ("snapshots", "") => cmd_snapshots(apps_manager),
+        ("stats", "") => cmd_stats(system),
+        ("loglevel", level) => cmd_loglevel(level),
+        ("layout", layout_name) => cmd_layout(system, layout_name),
+        ("screenshot", "") => cmd_screenshot(fb),
+        ("screenshot", app_name) => cmd_screenshot_window(apps_manager, app_name),
+        ("record", "") => cmd_record(system),
+        ("net", "sockets") => cmd_net_sockets(system),
+        ("devices", "") => cmd_devices(system),
+        ("mem", "") => cmd_mem(),
+        ("clipboard", "") => cmd_clipboard(system),
+        ("shutdown", "") => system.acpi.shutdown(),
+        ("reboot", "") => system.acpi.reboot(),
+        _ => Err(anyhow::format_err!("Unknown command (type 'help')")),
+    };
+
+    match res {
+        Ok(()) => console_println!("OK"),
+        Err(error) => console_println!("ERR {}", error),
+    }
+}
+
+//
+// Commands
+
+fn cmd_help() -> anyhow::Result<()> {
+    console_println!("apps              List applications");
+    console_println!("launch <app>      Open an application window (a new one if already open)");
+    console_println!("  [-- <args>]     ...with arguments (leading KEY=VALUE words set env vars)");
+    console_println!("close <app>       Close an application window (e.g. \"Terminal 2\")");
+    console_println!("pipe <a> | <b>    Feed the stdout of app a to the stdin of app b");
+    console_println!("pipe <app>        Stop piping the stdout of an app");
+    console_println!("snapshot [app]    Pause an app (or all running ones) and snapshot its state");
+    console_println!("restore [app]     Restore an app (or all snapshotted ones) from a snapshot");
+    console_println!("snapshots         List snapshots");
+    console_println!("stats             Frame time and per-app usage");
+    console_println!("loglevel <lvl>    Set log level (off/error/warn/info/debug/trace)");
+    console_println!("layout [name]     Show or set the keyboard layout (us/uk/fr/de)");
+    console_println!("screenshot        Dump the screen (base64-encoded PNG)");
+    console_println!("screenshot <app>  Dump the content of an application window");
+    console_println!("record            Start/stop streaming frames (base64-encoded PNGs)");
+    console_println!("net sockets       List TCP sockets");
+    console_println!("devices           List PCI devices and their drivers");
+    console_println!("mem               Kernel heap usage");
+    console_println!("clipboard         Print the clipboard content (e.g. a copied crash report)");
+    console_println!("shutdown          Power off the machine (ACPI)");
+    console_println!("reboot            Restart the machine");
+    Ok(())
+}
+
+fn cmd_apps(apps_manager: &AppsManager) -> anyhow::Result<()> {
+    for app in apps_manager.iter() {
+        let state = match &app.app_state {
+            AppState::Init => "init",
+            AppState::Restore { .. } => "restoring",
+            AppState::Active { paused: true, .. } => "paused",
+            AppState::Active { wasm_app, .. } if wasm_app.is_suspended() => "waiting",
+            AppState::Active { paused: false, .. } => "running",
+            AppState::Crashed { .. } => "crashed",
+        };
+        let open = match (app.is_open, app.is_windowed(), app.minimized) {
+            (false, _, _) => "closed",
+            (true, false, _) => "background",
+            (true, true, true) => "minimized",
+            (true, true, false) => "open",
+        };
+        console_println!("{:<16} {:<10} {}", app.name, open, state);
+    }
+    Ok(())
+}
+
+// Arguments replace the default ones of the app, environment variables are added to its own
+fn cmd_launch(
+    system: &mut System,
+    apps_manager: &mut AppsManager,
+    arg: &str,
+) -> anyhow::Result<()> {
+    match arg.split_once(" --") {
+        Some((app_name, command_line)) => {
+            let launch_args = LaunchArgs::parse(command_line);
+            apps_manager.open_app(&mut system.stats, app_name.trim(), Some(&launch_args))
+        }
+        None => apps_manager.open_app(&mut system.stats, arg, None),
+    }
+}
+
+fn cmd_pipe(apps_manager: &mut AppsManager, arg: &str) -> anyhow::Result<()> {
+    match arg.split_once('|') {
+        Some((app_name, target)) => apps_manager.pipe_app(app_name.trim(), Some(target.trim())),
+        None => apps_manager.pipe_app(arg, None),
+    }
+}
+
+// Without an app name, applies to all running apps (e.g. to save the whole desktop session)
+fn cmd_snapshot(apps_manager: &mut AppsManager, app_name: &str) -> anyhow::Result<()> {
+    let app_names: Vec<String> = match app_name {
+        "" => apps_manager
+            .iter()
+            .filter(|app| matches!(app.app_state, AppState::Active { paused: false, .. }))
+            .map(|app| app.name.clone())
+            .collect(),
+        _ => vec![app_name.to_string()],
+    };
+
+    for app_name in app_names {
+        let size = apps_manager.snapshot_app(&app_name)?;
+        console_println!("{:<16} {} bytes", app_name, size);
+    }
+
+    Ok(())
+}
+
+fn cmd_restore(apps_manager: &mut AppsManager, app_name: &str) -> anyhow::Result<()> {
+    let app_names: Vec<String> = match app_name {
+        "" => apps_manager
+            .snapshots()
+            .map(|(app_name, _)| app_name.to_string())
+            .collect(),
+        _ => vec![app_name.to_string()],
+    };
+
+    for app_name in app_names {
+        apps_manager.restore_app(&app_name)?;
+    }
+
+    Ok(())
+}
+
+fn cmd_snapshots(apps_manager: &AppsManager) -> anyhow::Result<()> {
+    for (app_name, size) in apps_manager.snapshots() {
+        console_println!("{:<16} {} bytes", app_name, size);
+    }
+    Ok(())
+}
+
+fn cmd_stats(system: &System) -> anyhow::Result<()> {
+    let stats = &system.stats;
+
+    let avg = |data: &[f64]| data.iter().sum::<f64>() / data.len() as f64;
+
+    let frametime = stats.get_system_history(|dp| dp.frametime_used);
+    let net_recv = stats.get_system_history(|dp| dp.net_recv as f64);
+    let net_sent = stats.get_system_history(|dp| dp.net_sent as f64);
+
+    console_println!(
+        "system           frametime {:.2}ms net recv {:.0}B/frame sent {:.0}B/frame",
+        avg(&frametime),
+        avg(&net_recv),
+        avg(&net_sent),
+    );
+
+    for app_name in stats.app_names() {
+        let frametime = stats.get_app_history(app_name, |dp| dp.frametime_used);
+        let mem_used = stats.get_app_history(app_name, |dp| dp.mem_used as f64);
+        console_println!(
+            "{:<16} frametime {:.2}ms mem {:.0}B crashes {}",
+            app_name,
+            avg(&frametime),
+            avg(&mem_used),
+            stats.crash_count(app_name),
+        );
+    }
+
+    Ok(())
+}
+
+fn cmd_loglevel(level: &str) -> anyhow::Result<()> {
+    let level: log::LevelFilter = level
+        .parse()
+        .map_err(|_| anyhow::format_err!("Invalid log level {}", level))?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+// Without a layout name, shows the current one
+fn cmd_layout(system: &mut System, layout_name: &str) -> anyhow::Result<()> {
+    if !layout_name.is_empty() {
+        system.keyboard_layout = KeyboardLayout::from_name(layout_name)
+            .ok_or_else(|| anyhow::format_err!("Unknown keyboard layout {}", layout_name))?;
+    }
+    console_println!("{}", system.keyboard_layout.name());
+    Ok(())
+}
+
+fn cmd_screenshot<F: FbView>(fb: &F) -> anyhow::Result<()> {
+    stream_png("screenshot", fb);
+    Ok(())
+}
+
+fn cmd_screenshot_window(apps_manager: &AppsManager, app_name: &str) -> anyhow::Result<()> {
+    let window_fb = apps_manager
+        .window_framebuffer(app_name)
+        .ok_or_else(|| anyhow::format_err!("No open window for {}", app_name))?;
+    stream_png(&window_label(app_name), &window_fb);
+    Ok(())
+}
+
+fn cmd_record(system: &mut System) -> anyhow::Result<()> {
+    system.capture.toggle_recording();
+    match system.capture.is_recording() {
+        true => console_println!("Recording started"),
+        false => console_println!("Recording stopped"),
+    }
+    Ok(())
+}
+
+fn cmd_net_sockets(system: &System) -> anyhow::Result<()> {
+    for info in system.tcp_stack.list_sockets() {
+        let fmt_endpoint = |endpoint: Option<IpEndpoint>| match endpoint {
+            Some(endpoint) => alloc::format!("{}", endpoint),
+            None => "-".into(),
+        };
+        console_println!(
+            "{:?} {} -> {} {}",
+            info.handle,
+            fmt_endpoint(info.local),
+            fmt_endpoint(info.remote),
+            info.state,
+        );
+    }
+    Ok(())
+}
+
+fn cmd_devices(system: &System) -> anyhow::Result<()> {
+    for entry in system.pci_bus.iter() {
+        for line in entry.describe() {
+            console_println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+fn cmd_mem() -> anyhow::Result<()> {
+    let stats = memory::ALLOCATOR.get_stats();
+    console_println!("total       {}B", stats.total);
+    console_println!("allocated   {}B", stats.allocated);
+    console_println!("explored    {}B", stats.explored);
+    console_println!("reclaimable {}B", stats.reclaimable);
+    console_println!("lost        {}B", stats.lost);
+    Ok(())
+}
+
+fn cmd_clipboard(system: &System) -> anyhow::Result<()> {
+    for line in system.clipboard.lines() {
+        console_println!("{}", line);
+    }
+    Ok(())
+}
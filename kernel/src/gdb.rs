@@ -0,0 +1,618 @@
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::lazy_static;
+use spin::Mutex;
+use tinyvec::ArrayVec;
+use x86_64::instructions::segmentation::{Segment, CS, DS, ES, FS, GS};
+use x86_64::instructions::tables::{lidt, sidt};
+use x86_64::registers::control::{Cr0, Cr0Flags};
+use x86_64::structures::DescriptorTablePointer;
+use x86_64::VirtAddr;
+
+use crate::memory;
+use crate::serial::{gdb_data_ready, gdb_port_present, send_gdb, try_receive_gdb};
+
+// Max size of a packet payload (advertised to GDB)
+const PACKET_SIZE: usize = 0x1000;
+const MAX_BREAKPOINTS: usize = 32;
+
+const VECTOR_DEBUG: u64 = 1;
+const VECTOR_BREAKPOINT: u64 = 3;
+
+const INT3: u8 = 0xcc;
+const RFLAGS_TF: u64 = 1 << 8;
+
+// Signals reported to GDB
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+//
+// GDB remote serial protocol stub, on COM3
+//
+// Software breakpoints and single-stepping go through the breakpoint (#BP) and debug (#DB)
+// exceptions, whose handlers save all registers and hand them over to the stub, which then
+// talks to GDB until told to resume. The main loop polls the serial port every frame, so that
+// GDB can interrupt the kernel at any time. Only the core which stopped is halted, other cores
+// keep running.
+
+core::arch::global_asm!(
+    r#"
+.global gdb_debug_entry
+.global gdb_breakpoint_entry
+
+gdb_debug_entry:
+    pushq $1
+    jmp .Lgdb_common_entry
+
+gdb_breakpoint_entry:
+    pushq $3
+    jmp .Lgdb_common_entry
+
+# Saves registers in the layout of ExceptionFrame, and the SSE state (including MXCSR) below it,
+# as the handler is compiled code which may use SSE registers
+.Lgdb_common_entry:
+    pushq %r15
+    pushq %r14
+    pushq %r13
+    pushq %r12
+    pushq %r11
+    pushq %r10
+    pushq %r9
+    pushq %r8
+    pushq %rbp
+    pushq %rdi
+    pushq %rsi
+    pushq %rdx
+    pushq %rcx
+    pushq %rbx
+    pushq %rax
+
+    movq %rsp, %rdi
+    movq %rsp, %rbx
+    andq $-16, %rsp
+    subq $512, %rsp
+    fxsave64 (%rsp)
+    callq {handler}
+    fxrstor64 (%rsp)
+    movq %rbx, %rsp
+
+    popq %rax
+    popq %rbx
+    popq %rcx
+    popq %rdx
+    popq %rsi
+    popq %rdi
+    popq %rbp
+    popq %r8
+    popq %r9
+    popq %r10
+    popq %r11
+    popq %r12
+    popq %r13
+    popq %r14
+    popq %r15
+
+    # Vector
+    addq $8, %rsp
+    iretq
+"#,
+    handler = sym handle_exception,
+    options(att_syntax)
+);
+
+extern "C" {
+    fn gdb_debug_entry();
+    fn gdb_breakpoint_entry();
+}
+
+#[repr(C)]
+struct ExceptionFrame {
+    rax: u64,
+    rbx: u64,
+    rcx: u64,
+    rdx: u64,
+    rsi: u64,
+    rdi: u64,
+    rbp: u64,
+    r8: u64,
+    r9: u64,
+    r10: u64,
+    r11: u64,
+    r12: u64,
+    r13: u64,
+    r14: u64,
+    r15: u64,
+    vector: u64,
+
+    // Pushed by the CPU
+    rip: u64,
+    cs: u64,
+    rflags: u64,
+    rsp: u64,
+    ss: u64,
+}
+
+struct StubState {
+    // Address and original byte of each software breakpoint
+    breakpoints: ArrayVec<[(u64, u8); MAX_BREAKPOINTS]>,
+}
+
+lazy_static! {
+    static ref STUB_STATE: Mutex<StubState> = Mutex::new(StubState {
+        breakpoints: ArrayVec::new(),
+    });
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// Set when the stub is entered because GDB sent something, rather than from a breakpoint
+static BREAK_IN: AtomicBool = AtomicBool::new(false);
+
+//
+// IDT
+
+#[repr(C, align(16))]
+struct Idt([[u64; 2]; 256]);
+
+static mut IDT: Idt = Idt([[0; 2]; 256]);
+
+// Copies the IDT set up by UEFI, with our own handlers for #DB and #BP.
+// Should be called before starting the other cores, which load the same IDT.
+pub fn init() {
+    if !gdb_port_present() {
+        log::warn!("No serial port for the GDB stub");
+        return;
+    }
+
+    let current = sidt();
+    let n_entries = usize::min((current.limit as usize + 1) / 16, 256);
+    let selector = CS::get_reg().0;
+
+    unsafe {
+        let idt = &mut *core::ptr::addr_of_mut!(IDT);
+        let src = current.base.as_ptr::<[u64; 2]>();
+        core::ptr::copy_nonoverlapping(src, idt.0.as_mut_ptr(), n_entries);
+
+        idt.0[VECTOR_DEBUG as usize] =
+            interrupt_gate(gdb_debug_entry as *const () as u64, selector);
+        idt.0[VECTOR_BREAKPOINT as usize] =
+            interrupt_gate(gdb_breakpoint_entry as *const () as u64, selector);
+
+        lidt(&DescriptorTablePointer {
+            limit: (core::mem::size_of::<Idt>() - 1) as u16,
+            base: VirtAddr::from_ptr(idt),
+        });
+    }
+
+    ENABLED.store(true, Ordering::SeqCst);
+
+    log::info!("GDB stub listening on COM3");
+}
+
+fn interrupt_gate(handler: u64, selector: u16) -> [u64; 2] {
+    const PRESENT_INTERRUPT_GATE: u64 = 0x8e;
+
+    let low = (handler & 0xffff)
+        | (selector as u64) << 16
+        | PRESENT_INTERRUPT_GATE << 40
+        | ((handler >> 16) & 0xffff) << 48;
+    let high = handler >> 32;
+
+    [low, high]
+}
+
+// Should be called every frame, stops the kernel if GDB is trying to talk to it
+pub fn poll() {
+    if ENABLED.load(Ordering::Relaxed) && gdb_data_ready() {
+        BREAK_IN.store(true, Ordering::SeqCst);
+        // The entry stub preserves all general purpose and SSE registers
+        unsafe { core::arch::asm!("int3") };
+    }
+}
+
+//
+// Exception handling
+
+extern "sysv64" fn handle_exception(frame: &mut ExceptionFrame) {
+    let mut state = STUB_STATE.lock();
+
+    let break_in = BREAK_IN.swap(false, Ordering::SeqCst);
+
+    match frame.vector {
+        VECTOR_BREAKPOINT if !break_in => {
+            // Reporting the address of the breakpoint, rather than the instruction after it
+            let bp_addr = frame.rip - 1;
+            if state.breakpoints.iter().any(|&(addr, _)| addr == bp_addr) {
+                frame.rip = bp_addr;
+            }
+            send_packet(&stop_reply(SIGTRAP));
+        }
+        VECTOR_DEBUG => {
+            frame.rflags &= !RFLAGS_TF;
+            send_packet(&stop_reply(SIGTRAP));
+        }
+        _ => (),
+    }
+
+    run_stub(&mut state, frame);
+}
+
+fn run_stub(state: &mut StubState, frame: &mut ExceptionFrame) {
+    loop {
+        let Some(packet) = receive_packet() else {
+            // GDB interrupted the kernel (Ctrl+C)
+            send_packet(&stop_reply(SIGINT));
+            continue;
+        };
+
+        let mut reply = Packet::new();
+
+        match packet.first().copied() {
+            Some(b'?') => reply = stop_reply(SIGTRAP),
+            Some(b'g') => write_registers(&mut reply, frame),
+            Some(b'G') => match read_registers(&packet[1..], frame) {
+                Some(()) => reply_str(&mut reply, "OK"),
+                None => reply_str(&mut reply, "E01"),
+            },
+            Some(b'm') => match parse_mem_range(&packet[1..]) {
+                Some((addr, len)) if len <= PACKET_SIZE / 2 => {
+                    if !read_memory(&mut reply, addr, len) {
+                        reply = Packet::new();
+                        reply_str(&mut reply, "E14");
+                    }
+                }
+                _ => reply_str(&mut reply, "E01"),
+            },
+            Some(b'M') => match write_memory(&packet[1..]) {
+                true => reply_str(&mut reply, "OK"),
+                false => reply_str(&mut reply, "E14"),
+            },
+            Some(b'Z') if packet.starts_with(b"Z0,") => {
+                match insert_breakpoint(state, &packet[3..]) {
+                    true => reply_str(&mut reply, "OK"),
+                    false => reply_str(&mut reply, "E01"),
+                }
+            }
+            Some(b'z') if packet.starts_with(b"z0,") => {
+                match remove_breakpoint(state, &packet[3..]) {
+                    true => reply_str(&mut reply, "OK"),
+                    false => reply_str(&mut reply, "E01"),
+                }
+            }
+            Some(b'c') => return,
+            Some(b's') => {
+                frame.rflags |= RFLAGS_TF;
+                return;
+            }
+            Some(b'D') => {
+                remove_all_breakpoints(state);
+                send_packet(b"OK");
+                return;
+            }
+            Some(b'k') => {
+                remove_all_breakpoints(state);
+                return;
+            }
+            Some(b'H') => reply_str(&mut reply, "OK"),
+            _ if packet.starts_with(b"qSupported") => {
+                let _ = write!(PacketWriter(&mut reply), "PacketSize={:x}", PACKET_SIZE);
+            }
+            _ if packet.as_slice() == b"qAttached" => reply_str(&mut reply, "1"),
+
+            // Unsupported, GDB falls back to something else
+            _ => (),
+        }
+
+        send_packet(&reply);
+    }
+}
+
+//
+// Packets
+
+type Packet = ArrayVec<[u8; PACKET_SIZE]>;
+
+// Wrapper to write formatted text into a packet
+struct PacketWriter<'a>(&'a mut Packet);
+
+impl Write for PacketWriter<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for &byte in s.as_bytes() {
+            if self.0.try_push(byte).is_some() {
+                return Err(core::fmt::Error);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn reply_str(reply: &mut Packet, s: &str) {
+    reply.extend_from_slice(s.as_bytes());
+}
+
+fn stop_reply(signal: u8) -> Packet {
+    let mut reply = Packet::new();
+    let _ = write!(PacketWriter(&mut reply), "S{:02x}", signal);
+    reply
+}
+
+// Returns None if GDB sent an interrupt request instead of a packet
+fn receive_packet() -> Option<Packet> {
+    loop {
+        // Waiting for the start of a packet
+        match receive_byte() {
+            b'$' => (),
+            0x03 => return None,
+            _ => continue,
+        }
+
+        let mut packet = Packet::new();
+        let mut checksum: u8 = 0;
+        let mut overflow = false;
+
+        loop {
+            match receive_byte() {
+                b'#' => break,
+                byte => {
+                    checksum = checksum.wrapping_add(byte);
+                    overflow |= packet.try_push(byte).is_some();
+                }
+            }
+        }
+
+        let expected = hex_value(receive_byte()).zip(hex_value(receive_byte()));
+        match expected {
+            Some((hi, lo)) if hi << 4 | lo == checksum && !overflow => {
+                send_gdb(b"+");
+                return Some(packet);
+            }
+            _ => send_gdb(b"-"),
+        }
+    }
+}
+
+fn send_packet(data: &[u8]) {
+    let checksum = data.iter().fold(0u8, |acc, &byte| acc.wrapping_add(byte));
+
+    loop {
+        send_gdb(b"$");
+        send_gdb(data);
+        send_gdb(b"#");
+        send_gdb(&[hex_digit(checksum >> 4), hex_digit(checksum & 0xf)]);
+
+        // Resending until acknowledged
+        match receive_byte() {
+            b'-' => continue,
+            _ => break,
+        }
+    }
+}
+
+fn receive_byte() -> u8 {
+    loop {
+        if let Some(byte) = try_receive_gdb() {
+            return byte;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+//
+// Registers
+//
+// Order and sizes expected by GDB for x86_64: 16 general purpose registers, RIP (64-bit),
+// then EFLAGS, CS, SS, DS, ES, FS and GS (32-bit). FPU and SSE registers are not reported.
+
+fn write_registers(reply: &mut Packet, frame: &ExceptionFrame) {
+    let gprs = [
+        frame.rax, frame.rbx, frame.rcx, frame.rdx, frame.rsi, frame.rdi, frame.rbp, frame.rsp,
+        frame.r8, frame.r9, frame.r10, frame.r11, frame.r12, frame.r13, frame.r14, frame.r15,
+        frame.rip,
+    ];
+    for val in gprs {
+        write_hex_bytes(reply, &val.to_le_bytes());
+    }
+
+    let segments = [
+        frame.rflags as u32,
+        frame.cs as u32,
+        frame.ss as u32,
+        DS::get_reg().0 as u32,
+        ES::get_reg().0 as u32,
+        FS::get_reg().0 as u32,
+        GS::get_reg().0 as u32,
+    ];
+    for val in segments {
+        write_hex_bytes(reply, &val.to_le_bytes());
+    }
+}
+
+// Segment registers are left alone
+fn read_registers(data: &[u8], frame: &mut ExceptionFrame) -> Option<()> {
+    let mut values = data.chunks_exact(16).map(parse_le_hex);
+
+    let regs = [
+        &mut frame.rax,
+        &mut frame.rbx,
+        &mut frame.rcx,
+        &mut frame.rdx,
+        &mut frame.rsi,
+        &mut frame.rdi,
+        &mut frame.rbp,
+        &mut frame.rsp,
+        &mut frame.r8,
+        &mut frame.r9,
+        &mut frame.r10,
+        &mut frame.r11,
+        &mut frame.r12,
+        &mut frame.r13,
+        &mut frame.r14,
+        &mut frame.r15,
+        &mut frame.rip,
+    ];
+    for reg in regs {
+        *reg = values.next()??;
+    }
+
+    let rflags_hex = data.get(17 * 16..17 * 16 + 8)?;
+    frame.rflags = parse_le_hex(rflags_hex)?;
+
+    Some(())
+}
+
+//
+// Memory
+
+fn is_accessible(addr: u64, len: usize) -> bool {
+    let mapper = memory::get_mapper();
+    let end = addr.saturating_add(len as u64);
+    (addr & !0xfff..end)
+        .step_by(4096)
+        .all(|page| VirtAddr::try_new(page).is_ok_and(|page| mapper.is_mapped(page)))
+}
+
+fn read_memory(reply: &mut Packet, addr: u64, len: usize) -> bool {
+    if !is_accessible(addr, len) {
+        return false;
+    }
+    let bytes = unsafe { core::slice::from_raw_parts(addr as *const u8, len) };
+    write_hex_bytes(reply, bytes);
+    true
+}
+
+// "addr,len:XX..."
+fn write_memory(data: &[u8]) -> bool {
+    let Some(colon) = data.iter().position(|&c| c == b':') else {
+        return false;
+    };
+    let Some((addr, len)) = parse_mem_range(&data[..colon]) else {
+        return false;
+    };
+    let hex = &data[colon + 1..];
+    if len.checked_mul(2) != Some(hex.len()) || !is_accessible(addr, len) {
+        return false;
+    }
+
+    for (i, pair) in hex.chunks_exact(2).enumerate() {
+        let Some(byte) = parse_hex_byte(pair) else {
+            return false;
+        };
+        write_code_byte(addr + i as u64, byte);
+    }
+    true
+}
+
+// Code pages may have been mapped read-only by UEFI
+fn write_code_byte(addr: u64, byte: u8) {
+    unsafe {
+        let cr0 = Cr0::read();
+        Cr0::write(cr0 - Cr0Flags::WRITE_PROTECT);
+        (addr as *mut u8).write_volatile(byte);
+        Cr0::write(cr0);
+    }
+}
+
+//
+// Breakpoints
+
+// "addr,kind"
+fn insert_breakpoint(state: &mut StubState, data: &[u8]) -> bool {
+    let Some((addr, _)) = parse_mem_range(data) else {
+        return false;
+    };
+
+    if state
+        .breakpoints
+        .iter()
+        .any(|&(bp_addr, _)| bp_addr == addr)
+    {
+        return true;
+    }
+
+    if state.breakpoints.len() >= MAX_BREAKPOINTS || !is_accessible(addr, 1) {
+        return false;
+    }
+
+    let orig_byte = unsafe { (addr as *const u8).read_volatile() };
+    write_code_byte(addr, INT3);
+    state.breakpoints.push((addr, orig_byte));
+
+    true
+}
+
+fn remove_breakpoint(state: &mut StubState, data: &[u8]) -> bool {
+    let Some((addr, _)) = parse_mem_range(data) else {
+        return false;
+    };
+
+    let Some(index) = state
+        .breakpoints
+        .iter()
+        .position(|&(bp_addr, _)| bp_addr == addr)
+    else {
+        return false;
+    };
+
+    let (addr, orig_byte) = state.breakpoints.remove(index);
+    write_code_byte(addr, orig_byte);
+
+    true
+}
+
+fn remove_all_breakpoints(state: &mut StubState) {
+    for (addr, orig_byte) in state.breakpoints.drain(..) {
+        write_code_byte(addr, orig_byte);
+    }
+}
+
+//
+// Hex encoding
+
+fn hex_digit(val: u8) -> u8 {
+    b"0123456789abcdef"[val as usize & 0xf]
+}
+
+fn hex_value(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+fn parse_hex_byte(pair: &[u8]) -> Option<u8> {
+    Some(hex_value(pair[0])? << 4 | hex_value(pair[1])?)
+}
+
+fn write_hex_bytes(reply: &mut Packet, bytes: &[u8]) {
+    for &byte in bytes {
+        reply.push(hex_digit(byte >> 4));
+        reply.push(hex_digit(byte & 0xf));
+    }
+}
+
+// Little-endian value, as used for registers
+fn parse_le_hex(hex: &[u8]) -> Option<u64> {
+    hex.chunks_exact(2)
+        .enumerate()
+        .try_fold(0u64, |acc, (i, pair)| {
+            Some(acc | (parse_hex_byte(pair)? as u64) << (8 * i))
+        })
+}
+
+fn parse_hex_u64(hex: &[u8]) -> Option<u64> {
+    if hex.is_empty() {
+        return None;
+    }
+    hex.iter()
+        .try_fold(0u64, |acc, &c| Some(acc << 4 | hex_value(c)? as u64))
+}
+
+// "addr,len" (both big-endian hex)
+fn parse_mem_range(data: &[u8]) -> Option<(u64, usize)> {
+    let comma = data.iter().position(|&c| c == b',')?;
+    let addr = parse_hex_u64(&data[..comma])?;
+    let len = parse_hex_u64(&data[comma + 1..])?;
+    Some((addr, len as usize))
+}
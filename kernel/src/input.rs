@@ -0,0 +1,325 @@
+use alloc::collections::BTreeSet;
+use applib::input::keymap::{EventType, Keycode};
+use applib::input::{InputEvent, InputState, PointerState};
+
+use crate::virtio::input::{LedCode, VirtioInput};
+
+#[derive(Debug, Clone)]
+pub struct KeyRepeatConfig {
+    pub delay: f64, // in milliseconds
+    pub rate: f64,  // in repeats per second
+}
+
+pub struct KeyRepeater {
+    config: KeyRepeatConfig,
+    pressed: BTreeSet<Keycode>,
+
+    // Last pressed key and the time of its next repeat
+    repeating: Option<(Keycode, f64)>,
+}
+
+impl KeyRepeater {
+    pub fn new(config: KeyRepeatConfig) -> Self {
+        Self {
+            config,
+            pressed: BTreeSet::new(),
+            repeating: None,
+        }
+    }
+
+    // Returns false if the key was already held down
+    fn on_press(&mut self, keycode: Keycode, time: f64) -> bool {
+        if !self.pressed.insert(keycode) {
+            return false;
+        }
+
+        if is_repeatable(keycode) {
+            self.repeating = Some((keycode, time + self.config.delay));
+        }
+
+        true
+    }
+
+    fn on_release(&mut self, keycode: Keycode) {
+        self.pressed.remove(&keycode);
+
+        if let Some((repeat_keycode, _)) = self.repeating {
+            if repeat_keycode == keycode {
+                self.repeating = None;
+            }
+        }
+    }
+
+    fn generate_repeats(&mut self, input_state: &mut InputState, time: f64) {
+        // Repeats which could not be delivered in time (e.g during a long frame) are dropped,
+        // rather than flushed all at once
+        const MAX_REPEATS_PER_FRAME: usize = 2;
+
+        let (keycode, next_t) = match self.repeating.as_mut() {
+            Some((keycode, next_t)) => (*keycode, next_t),
+            None => return,
+        };
+
+        let period = 1000.0 / self.config.rate;
+
+        let mut n = 0;
+        while *next_t <= time {
+            if n < MAX_REPEATS_PER_FRAME {
+                input_state.add_event(InputEvent::KeyRepeat { keycode }, *next_t);
+                n += 1;
+            }
+            *next_t += period;
+        }
+    }
+}
+
+pub struct PointerGestures {
+    last_click: Option<(f64, i64, i64)>,
+    click_count: u32,
+}
+
+impl PointerGestures {
+    pub fn new() -> Self {
+        Self {
+            last_click: None,
+            click_count: 0,
+        }
+    }
+
+    fn update(&mut self, pointer: &mut PointerState, time: f64) {
+        const MULTI_CLICK_DELAY: f64 = 400.0; // In milliseconds
+        const MULTI_CLICK_MAX_DIST: i64 = 4;
+        const MAX_CLICK_COUNT: u32 = 3;
+        const DRAG_THRESHOLD: i64 = 4;
+
+        let (x, y) = (pointer.x, pointer.y);
+
+        //
+        // Multiple clicks
+
+        if pointer.left_click_trigger {
+            let is_successive = match self.last_click {
+                Some((t, x0, y0)) => {
+                    time - t < MULTI_CLICK_DELAY
+                        && i64::abs(x - x0) <= MULTI_CLICK_MAX_DIST
+                        && i64::abs(y - y0) <= MULTI_CLICK_MAX_DIST
+                }
+                None => false,
+            };
+
+            self.click_count = match is_successive && self.click_count < MAX_CLICK_COUNT {
+                true => self.click_count + 1,
+                false => 1,
+            };
+
+            self.last_click = Some((time, x, y));
+            pointer.click_count = self.click_count;
+
+            pointer.drag_origin_x = x;
+            pointer.drag_origin_y = y;
+        }
+
+        //
+        // Dragging
+
+        if pointer.left_clicked && !pointer.dragging {
+            let dist_x = i64::abs(x - pointer.drag_origin_x);
+            let dist_y = i64::abs(y - pointer.drag_origin_y);
+            if dist_x > DRAG_THRESHOLD || dist_y > DRAG_THRESHOLD {
+                pointer.dragging = true;
+                pointer.drag_start_trigger = true;
+            }
+        } else if !pointer.left_clicked && pointer.dragging {
+            pointer.dragging = false;
+            pointer.drag_end_trigger = true;
+        }
+    }
+}
+
+fn update_button(
+    value: u32,
+    clicked: &mut bool,
+    click_trigger: &mut bool,
+    release_trigger: &mut bool,
+) {
+    match value {
+        1 => {
+            if !*clicked {
+                *click_trigger = true;
+            }
+            *clicked = true;
+        }
+        _ => {
+            if *clicked {
+                *release_trigger = true;
+            }
+            *clicked = false;
+        }
+    }
+}
+
+fn is_repeatable(keycode: Keycode) -> bool {
+    !matches!(
+        keycode,
+        Keycode::KEY_LEFTSHIFT
+            | Keycode::KEY_RIGHTSHIFT
+            | Keycode::KEY_LEFTCTRL
+            | Keycode::KEY_RIGHTCTRL
+            | Keycode::KEY_LEFTALT
+            | Keycode::KEY_RIGHTALT
+            | Keycode::KEY_LEFTMETA
+            | Keycode::KEY_RIGHTMETA
+            | Keycode::KEY_CAPSLOCK
+            | Keycode::KEY_NUMLOCK
+            | Keycode::KEY_ESC
+    )
+}
+
+pub fn update_input_state(
+    input_state: &mut InputState,
+    dims: (u32, u32),
+    virtio_inputs: &mut [VirtioInput],
+    key_repeater: &mut KeyRepeater,
+    pointer_gestures: &mut PointerGestures,
+    time: f64,
+) {
+    let (w, h) = dims;
+    let (w, h) = (w as i32, h as i32);
+
+    input_state.clear_events();
+
+    let pointer = &mut input_state.pointer;
+    pointer.left_click_trigger = false;
+    pointer.right_click_trigger = false;
+    pointer.middle_click_trigger = false;
+    pointer.left_release_trigger = false;
+    pointer.right_release_trigger = false;
+    pointer.middle_release_trigger = false;
+    pointer.drag_start_trigger = false;
+    pointer.drag_end_trigger = false;
+    pointer.delta_x = 0;
+    pointer.delta_y = 0;
+
+    let prev_modifiers = input_state.modifiers.clone();
+
+    for virtio_inp in virtio_inputs.iter_mut() {
+        for event in virtio_inp.poll() {
+            //log::debug!("{:?}", event);
+
+            match EventType::n(event._type) {
+                Some(EventType::EV_SYN) => {}
+
+                Some(EventType::EV_KEY) => match Keycode::n(event.code) {
+                    // Mouse click
+                    Some(Keycode::BTN_MOUSE_LEFT) => {
+                        let p = &mut input_state.pointer;
+                        update_button(
+                            event.value,
+                            &mut p.left_clicked,
+                            &mut p.left_click_trigger,
+                            &mut p.left_release_trigger,
+                        );
+                    }
+                    Some(Keycode::BTN_MOUSE_RIGHT) => {
+                        let p = &mut input_state.pointer;
+                        update_button(
+                            event.value,
+                            &mut p.right_clicked,
+                            &mut p.right_click_trigger,
+                            &mut p.right_release_trigger,
+                        );
+                    }
+                    Some(Keycode::BTN_MOUSE_MIDDLE) => {
+                        let p = &mut input_state.pointer;
+                        update_button(
+                            event.value,
+                            &mut p.middle_clicked,
+                            &mut p.middle_click_trigger,
+                            &mut p.middle_release_trigger,
+                        );
+                    }
+
+                    // Keyboard
+                    Some(keycode) => match event.value {
+                        0 => {
+                            key_repeater.on_release(keycode);
+                            input_state.add_event(InputEvent::KeyRelease { keycode }, time);
+                        }
+                        1 => {
+                            // The host may send its own repeated presses, but we generate ours
+                            if key_repeater.on_press(keycode, time) {
+                                input_state.add_event(InputEvent::KeyPress { keycode }, time);
+                            }
+                        }
+                        2 => (), // Host-side auto-repeat
+                        val => log::warn!("Unknown key state {}", val),
+                    },
+                    None => log::warn!("Unknown keycode {} for keyboard event", event.code),
+                },
+
+                // Mouse movement
+                Some(EventType::EV_REL) => match event.code {
+                    0 => {
+                        // X axis
+                        let dx = (event.value as i32) as i64;
+                        let pointer_state = &mut input_state.pointer;
+                        let new_x =
+                            i64::max(0, i64::min(w as i64 - 1, pointer_state.x as i64 + dx));
+                        pointer_state.delta_x += dx;
+                        pointer_state.x = new_x;
+                    }
+                    1 => {
+                        // Y axis
+                        let dy = (event.value as i32) as i64;
+                        let pointer_state = &mut input_state.pointer;
+                        let new_y =
+                            i64::max(0, i64::min(h as i64 - 1, pointer_state.y as i64 + dy));
+                        pointer_state.delta_y += dy;
+                        pointer_state.y = new_y;
+                    }
+                    6 => {
+                        // Horizontal scroll wheel
+                        let delta = (event.value as i32) as i64;
+                        input_state.add_event(InputEvent::HScroll { delta }, time);
+                    }
+                    8 => {
+                        // Scroll wheel
+                        let delta = (event.value as i32) as i64;
+                        input_state.add_event(InputEvent::Scroll { delta }, time);
+                    }
+                    _ => log::warn!("Unknown event code {} for pointer event", event.code),
+                },
+
+                _ => log::warn!("Unknown event type {}", event._type),
+            };
+        }
+    }
+
+    key_repeater.generate_repeats(input_state, time);
+    pointer_gestures.update(&mut input_state.pointer, time);
+
+    //
+    // Keyboard LEDs
+
+    let modifiers = &input_state.modifiers;
+    let led_updates = [
+        (
+            LedCode::LED_CAPSL,
+            prev_modifiers.caps_lock,
+            modifiers.caps_lock,
+        ),
+        (
+            LedCode::LED_NUML,
+            prev_modifiers.num_lock,
+            modifiers.num_lock,
+        ),
+    ];
+
+    for (led, prev_on, on) in led_updates {
+        if prev_on != on {
+            for virtio_inp in virtio_inputs.iter_mut() {
+                virtio_inp.set_led(led, on);
+            }
+        }
+    }
+}
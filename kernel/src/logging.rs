@@ -1,7 +1,21 @@
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
 use log::{Log, Metadata, Record};
+use spin::Mutex;
 
+use crate::memory;
 use crate::serial_println;
 
+// Number of log lines kept in memory for the panic screen
+const RECENT_LINES_LEN: usize = 16;
+
+lazy_static! {
+    static ref RECENT_LINES: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+}
+
 pub struct SerialLogger;
 
 impl Log for SerialLogger {
@@ -21,7 +35,26 @@ impl Log for SerialLogger {
             record.module_path().unwrap(), // Not sure why this can fail?
             record.args(),
         );
+
+        // The first log lines are printed before the heap is set up
+        if !memory::ALLOCATOR.is_initialized() {
+            return;
+        }
+
+        let mut recent_lines = RECENT_LINES.lock();
+        if recent_lines.len() >= RECENT_LINES_LEN {
+            recent_lines.pop_front();
+        }
+        recent_lines.push_back(format!("{}: {}", record.level(), record.args()));
     }
 
     fn flush(&self) {}
 }
+
+// Does not wait for the lock, since this is meant to be called from the panic handler
+pub fn recent_lines() -> Vec<String> {
+    match RECENT_LINES.try_lock() {
+        Some(recent_lines) => recent_lines.iter().cloned().collect(),
+        None => Vec::new(),
+    }
+}
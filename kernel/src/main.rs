@@ -3,30 +3,38 @@
 #![feature(alloc_error_handler)]
 #![feature(abi_x86_interrupt)]
 
+use alloc::string::String;
 use alloc::vec::Vec;
 use core::panic::PanicInfo;
 use num_traits::Float;
-use rand::rngs::SmallRng;
-use rand::SeedableRng;
 use uefi::prelude::{entry, Boot, Handle, Status, SystemTable};
 use uefi::table::boot::MemoryType;
 
 use applib::drawing::primitives::draw_rect;
-use applib::input::{InputEvent, InputState};
+use applib::input::{InputState, KeyboardLayout, Keycode};
 use applib::uitk::{self};
 use applib::{BorrowedMutPixels, Color, FbViewMut, Framebuffer, OwnedPixels, Rect};
 
 extern crate alloc;
 
+mod acpi;
 mod allocator;
 mod app;
+mod backtrace;
+mod capture;
+mod console;
+mod gdb;
+mod input;
 mod logging;
 mod memory;
 mod network;
+mod panic;
 mod pci;
+mod random;
 mod resources;
 mod serial;
 mod shell;
+mod smp;
 mod stats;
 mod system;
 mod time;
@@ -34,14 +42,20 @@ mod topbar;
 mod virtio;
 mod wasm;
 
+use acpi::Acpi;
+use capture::ScreenCapture;
+use console::KernelConsole;
+use input::{update_input_state, KeyRepeatConfig, KeyRepeater, PointerGestures};
+use pci::PciBus;
+use random::SystemRng;
 use time::SystemClock;
 
 use virtio::gpu::VirtioGPU;
 use virtio::input::VirtioInput;
 use virtio::network::VirtioNetwork;
+use virtio::rng::VirtioRng;
 
-use app::{run_apps, App, AppState, AppsInteractionState, AppsManager};
-use applib::input::keymap::{EventType, Keycode};
+use app::{run_apps, AppsInteractionState, AppsManager};
 use resources::{APPLICATIONS, STYLESHEET, WALLPAPER};
 use system::System;
 use wasm::WasmEngine;
@@ -52,6 +66,13 @@ const LIMIT_FPS: bool = true;
 static LOGGER: logging::SerialLogger = logging::SerialLogger;
 const LOGGING_LEVEL: log::LevelFilter = log::LevelFilter::Debug;
 
+// Default layout, which can be changed with the "layout" console command
+const KEYBOARD_LAYOUT: KeyboardLayout = KeyboardLayout::US;
+const KEY_REPEAT: KeyRepeatConfig = KeyRepeatConfig {
+    delay: 500.0,
+    rate: 30.0,
+};
+
 pub const TOPBAR_H: u32 = 40;
 
 #[entry]
@@ -61,6 +82,8 @@ fn main(image: Handle, system_table: SystemTable<Boot>) -> Status {
 
     log::info!("Booting kernel");
 
+    let rsdp_addr = acpi::find_rsdp(&system_table);
+
     let (system_table, memory_map) = system_table.exit_boot_services(MemoryType::LOADER_DATA);
 
     log::info!("Exited UEFI boot services");
@@ -68,14 +91,24 @@ fn main(image: Handle, system_table: SystemTable<Boot>) -> Status {
     memory::init_mapper();
     memory::init_allocator(&memory_map);
 
-    let mut pci_devices = pci::enumerate();
+    let acpi = Acpi::parse(rsdp_addr);
+
+    log::info!("ACPI tables parsed");
+
+    pci::init_ecam(&acpi.mcfg);
+    let mut pci_bus = PciBus::enumerate();
 
-    let mut virtio_gpu = VirtioGPU::new(&mut pci_devices);
+    let mut virtio_gpu = VirtioGPU::new(&mut pci_bus);
     let mut virtio_inputs = [
-        VirtioInput::new(&mut pci_devices),
-        VirtioInput::new(&mut pci_devices),
+        VirtioInput::new(&mut pci_bus),
+        VirtioInput::new(&mut pci_bus),
     ];
-    let virtio_net = VirtioNetwork::new(&mut pci_devices);
+    let virtio_net = VirtioNetwork::new(&mut pci_bus);
+    let virtio_rng = VirtioRng::new(&mut pci_bus);
+
+    if virtio_rng.is_none() {
+        log::warn!("No VirtIO entropy device, falling back to CPU and timing sources");
+    }
 
     log::info!("All VirtIO devices created");
 
@@ -84,8 +117,18 @@ fn main(image: Handle, system_table: SystemTable<Boot>) -> Status {
 
     log::info!("System clock initialized");
 
+    // Before starting the other cores, which share the same IDT
+    gdb::init();
+
+    smp::init(acpi.madt.as_ref(), &memory_map, &clock);
+
+    let rng = SystemRng::new(virtio_rng, &clock);
+
+    log::info!("RNG initialized");
+
     virtio_gpu.init_framebuffer();
     virtio_gpu.flush();
+    panic::set_display(&mut virtio_gpu);
 
     log::info!("Display initialized");
 
@@ -99,7 +142,9 @@ fn main(image: Handle, system_table: SystemTable<Boot>) -> Status {
     let (w, h) = (w as u32, h as u32);
     let wasm_engine = WasmEngine::new();
 
-    let mut input_state = InputState::new(w, h);
+    let mut input_state = InputState::new(w, h, KEYBOARD_LAYOUT);
+    let mut key_repeater = KeyRepeater::new(KEY_REPEAT);
+    let mut pointer_gestures = PointerGestures::new();
 
     let app_names: Vec<&str> = APPLICATIONS.iter().map(|desc| desc.name).collect();
 
@@ -110,23 +155,17 @@ fn main(image: Handle, system_table: SystemTable<Boot>) -> Status {
     let mut system = System {
         clock,
         tcp_stack,
-        rng: SmallRng::seed_from_u64(0),
+        rng,
+        acpi,
+        pci_bus,
         stylesheet: &STYLESHEET,
         stats: system_stats,
+        capture: ScreenCapture::new(),
+        keyboard_layout: KEYBOARD_LAYOUT,
+        clipboard: String::new(),
     };
 
-    let apps: Vec<App> = APPLICATIONS
-        .iter()
-        .map(|app_desc| App {
-            descriptor: app_desc.clone(),
-            app_state: AppState::Init,
-            is_open: false,
-            rect: app_desc.init_win_rect.clone(),
-            time_used: 0.0,
-        })
-        .collect();
-
-    let mut apps_manager = AppsManager::new(apps);
+    let mut apps_manager = AppsManager::new(&*APPLICATIONS);
 
     log::info!("Applications loaded");
 
@@ -137,24 +176,48 @@ fn main(image: Handle, system_table: SystemTable<Boot>) -> Status {
 
     let mut apps_interaction_state = AppsInteractionState::Idle;
 
+    let mut console = KernelConsole::new();
+
     log::info!("Entering main loop");
 
     loop {
+        gdb::poll();
+
         let t0 = system.clock.time();
 
         {
             let System {
-                clock, tcp_stack, ..
+                clock,
+                tcp_stack,
+                rng,
+                ..
             } = &mut system;
             fps_manager.start_frame(clock);
             tcp_stack.poll_interface(clock);
+            rng.maybe_reseed(clock);
         }
 
         let time = system.clock.time();
 
         let datetime = SystemClock::utc_datetime(runtime_services);
 
-        update_input_state(&mut input_state, (w, h), &mut virtio_inputs);
+        input_state.layout = system.keyboard_layout;
+        update_input_state(
+            &mut input_state,
+            (w, h),
+            &mut virtio_inputs,
+            &mut key_repeater,
+            &mut pointer_gestures,
+            time,
+        );
+
+        // PrintScreen takes a screenshot, Shift+PrintScreen starts/stops recording
+        if input_state.check_key_pressed(Keycode::KEY_SYSRQ) {
+            match input_state.modifiers.shift {
+                false => system.capture.request_screenshot(),
+                true => system.capture.toggle_recording(),
+            }
+        }
 
         let mut framebuffer =
             Framebuffer::<BorrowedMutPixels>::from_bytes(&mut virtio_gpu.framebuffer, w, h);
@@ -181,17 +244,26 @@ fn main(image: Handle, system_table: SystemTable<Boot>) -> Status {
 
         topbar::topbar(&mut uitk_context, &system.stats, datetime);
 
+        system.capture.update(uitk_context.fb, &apps_manager, time);
+
         draw_cursor(uitk_context.fb, &input_state);
 
+        console.update(&mut system, &mut apps_manager, uitk_context.fb);
+
         let (net_recv, net_sent) = system.tcp_stack.pop_counters();
 
         let t1 = system.clock.time();
 
         let heap_stats = memory::ALLOCATOR.get_stats();
 
+        // The bootstrap processor is busy for the whole frame
+        let mut core_busy = smp::take_busy_times(&system.clock);
+        core_busy[0] = t1 - t0;
+
         *system.stats.get_system_point_mut() = stats::SystemDataPoint {
             alloc: heap_stats,
             frametime_used: t1 - t0,
+            core_busy,
             net_recv,
             net_sent,
         };
@@ -229,91 +301,6 @@ fn draw_cursor<F: FbViewMut>(fb: &mut F, input_state: &InputState) {
     draw_rect(fb, &rect_inner, Color::WHITE, false);
 }
 
-fn update_input_state(
-    input_state: &mut InputState,
-    dims: (u32, u32),
-    virtio_inputs: &mut [VirtioInput],
-) {
-    let (w, h) = dims;
-    let (w, h) = (w as i32, h as i32);
-
-    input_state.clear_events();
-    input_state.pointer.left_click_trigger = false;
-    input_state.pointer.right_click_trigger = false;
-    input_state.pointer.delta_x = 0;
-    input_state.pointer.delta_y = 0;
-
-    for virtio_inp in virtio_inputs.iter_mut() {
-        for event in virtio_inp.poll() {
-            //log::debug!("{:?}", event);
-
-            match EventType::n(event._type) {
-                Some(EventType::EV_SYN) => {}
-
-                Some(EventType::EV_KEY) => match Keycode::n(event.code) {
-                    // Mouse click
-                    Some(Keycode::BTN_MOUSE_LEFT) => match event.value {
-                        1 => {
-                            if !input_state.pointer.left_clicked {
-                                input_state.pointer.left_click_trigger = true;
-                            }
-                            input_state.pointer.left_clicked = true;
-                        }
-                        _ => input_state.pointer.left_clicked = false,
-                    },
-                    Some(Keycode::BTN_MOUSE_RIGHT) => match event.value {
-                        1 => {
-                            if !input_state.pointer.right_clicked {
-                                input_state.pointer.right_click_trigger = true;
-                            }
-                            input_state.pointer.right_clicked = true;
-                        }
-                        _ => input_state.pointer.right_clicked = false,
-                    },
-
-                    // Keyboard
-                    Some(keycode) => match event.value {
-                        0 => input_state.add_event(InputEvent::KeyRelease { keycode }),
-                        1 => input_state.add_event(InputEvent::KeyPress { keycode }),
-                        val => log::warn!("Unknown key state {}", val),
-                    },
-                    None => log::warn!("Unknown keycode {} for keyboard event", event.code),
-                },
-
-                // Mouse movement
-                Some(EventType::EV_REL) => match event.code {
-                    0 => {
-                        // X axis
-                        let dx = (event.value as i32) as i64;
-                        let pointer_state = &mut input_state.pointer;
-                        let new_x =
-                            i64::max(0, i64::min(w as i64 - 1, pointer_state.x as i64 + dx));
-                        pointer_state.delta_x += dx;
-                        pointer_state.x = new_x;
-                    }
-                    1 => {
-                        // Y axis
-                        let dy = (event.value as i32) as i64;
-                        let pointer_state = &mut input_state.pointer;
-                        let new_y =
-                            i64::max(0, i64::min(h as i64 - 1, pointer_state.y as i64 + dy));
-                        pointer_state.delta_y += dy;
-                        pointer_state.y = new_y;
-                    }
-                    8 => {
-                        // Scroll wheel
-                        let delta = (event.value as i32) as i64;
-                        input_state.add_event(InputEvent::Scroll { delta });
-                    }
-                    _ => log::warn!("Unknown event code {} for pointer event", event.code),
-                },
-
-                _ => log::warn!("Unknown event type {}", event._type),
-            };
-        }
-    }
-}
-
 struct FpsManager {
     fps_target: f64,
     frame_start_t: f64,
@@ -358,6 +345,5 @@ impl FpsManager {
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    log::error!("{}", info);
-    loop {}
+    panic::handle_panic(info)
 }
@@ -52,6 +52,13 @@ impl MemoryMapper {
         frame.start_address() + offset
     }
 
+    pub fn is_mapped(&self, virt: VirtAddr) -> bool {
+        matches!(
+            self.page_table.translate(virt),
+            TranslateResult::Mapped { .. }
+        )
+    }
+
     // Note: technically there can be more than one VirtAddr mapped to
     // a given PhysAddr, but we only care about the one that has been
     // offset-mapped by UEFI
@@ -1,6 +1,7 @@
 mod device;
 
 use alloc::vec;
+use alloc::vec::Vec;
 
 use crate::time::SystemClock;
 use crate::virtio::network::VirtioNetwork;
@@ -9,9 +10,9 @@ use device::SmolTcpVirtio;
 use lazy_static::lazy_static;
 use smoltcp::iface::{Config, Interface, SocketHandle, SocketSet};
 use smoltcp::phy::{Device, Medium};
-use smoltcp::socket::tcp;
+use smoltcp::socket::{tcp, AnySocket};
 use smoltcp::time::Instant;
-use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr, Ipv4Address};
+use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr, IpEndpoint, Ipv4Address};
 
 lazy_static! {
     static ref IFACE_ADDR: IpCidr = IpCidr::new(IpAddress::v4(10, 0, 2, 15), 24);
@@ -134,7 +135,30 @@ impl TcpStack {
             .poll(elapsed, &mut self.device, &mut self.sockets);
     }
 
+    pub fn list_sockets(&self) -> Vec<SocketInfo> {
+        self.sockets
+            .iter()
+            .filter_map(|(handle, socket)| {
+                let socket = tcp::Socket::downcast(socket)?;
+                Some(SocketInfo {
+                    handle,
+                    state: socket.state(),
+                    local: socket.local_endpoint(),
+                    remote: socket.remote_endpoint(),
+                })
+            })
+            .collect()
+    }
+
     pub fn pop_counters(&mut self) -> (usize, usize) {
         self.device.virtio_dev.get_counters()
     }
 }
+
+#[derive(Debug, Clone)]
+pub struct SocketInfo {
+    pub handle: SocketHandle,
+    pub state: tcp::State,
+    pub local: Option<IpEndpoint>,
+    pub remote: Option<IpEndpoint>,
+}
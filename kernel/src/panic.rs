@@ -0,0 +1,265 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+use applib::drawing::primitives::draw_rect;
+use applib::drawing::text::{draw_str, get_font};
+use applib::{BorrowedMutPixels, Color, Framebuffer, Rect};
+
+use crate::acpi::halt_forever;
+use crate::backtrace::{self, Backtrace};
+use crate::logging;
+use crate::resources::STYLESHEET;
+use crate::smp::{self, MAX_CORES};
+use crate::virtio::gpu::VirtioGPU;
+
+const PANIC_SCREEN_BG: Color = Color::rgb(120, 0, 0);
+const PANIC_SCREEN_MARGIN: i64 = 20;
+const PANIC_SCREEN_LINE_SPACING: i64 = 4;
+
+//
+// Panic handling
+//
+// Panics happening within catch_panic() (which wraps app steps, and so all host calls) are
+// turned into an error: the panic handler jumps straight back to catch_panic(), skipping the
+// frames in between without unwinding them, so anything they owned is leaked.
+// Any other panic is fatal, and a panic screen is drawn directly to the GPU framebuffer.
+
+core::arch::global_asm!(
+    r#"
+.global recovery_call
+.global recovery_jump
+
+# rdi: recovery point, rsi: function, rdx: function argument
+recovery_call:
+    movq %rbx, 0(%rdi)
+    movq %rbp, 8(%rdi)
+    movq %r12, 16(%rdi)
+    movq %r13, 24(%rdi)
+    movq %r14, 32(%rdi)
+    movq %r15, 40(%rdi)
+    leaq 8(%rsp), %rax
+    movq %rax, 48(%rdi)
+    movq (%rsp), %rax
+    movq %rax, 56(%rdi)
+
+    movq %rdx, %rdi
+    subq $8, %rsp
+    callq *%rsi
+    addq $8, %rsp
+
+    xorl %eax, %eax
+    retq
+
+# rdi: recovery point
+# Returns from the matching recovery_call() a second time, with 1 as the return value
+recovery_jump:
+    movq 0(%rdi), %rbx
+    movq 8(%rdi), %rbp
+    movq 16(%rdi), %r12
+    movq 24(%rdi), %r13
+    movq 32(%rdi), %r14
+    movq 40(%rdi), %r15
+    movq 48(%rdi), %rsp
+    movl $1, %eax
+    jmpq *56(%rdi)
+"#,
+    options(att_syntax)
+);
+
+extern "sysv64" {
+    fn recovery_call(
+        regs: *mut SavedRegisters,
+        func: extern "sysv64" fn(*mut u8),
+        data: *mut u8,
+    ) -> u64;
+    fn recovery_jump(regs: *const SavedRegisters) -> !;
+}
+
+// RBX, RBP, R12-R15, RSP and RIP, saved by recovery_call()
+#[repr(C)]
+struct SavedRegisters([u64; 8]);
+
+struct RecoveryPoint {
+    regs: SavedRegisters,
+    report: Option<Box<PanicReport>>,
+}
+
+#[derive(Debug)]
+pub struct PanicReport {
+    pub message: String,
+    pub backtrace: Backtrace,
+}
+
+impl fmt::Display for PanicReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Kernel panic: {}", self.message)?;
+        for &addr in self.backtrace.iter() {
+            writeln!(f, "  {}", backtrace::format_frame(addr))?;
+        }
+        Ok(())
+    }
+}
+
+static RECOVERY_POINTS: [AtomicPtr<RecoveryPoint>; MAX_CORES] =
+    [const { AtomicPtr::new(core::ptr::null_mut()) }; MAX_CORES];
+
+static PANICKING: AtomicBool = AtomicBool::new(false);
+static DISPLAY: AtomicPtr<VirtioGPU> = AtomicPtr::new(core::ptr::null_mut());
+
+lazy_static! {
+    // App running on each core, shown on the panic screen
+    static ref ACTIVE_APPS: [Mutex<Option<String>>; MAX_CORES] =
+        core::array::from_fn(|_| Mutex::new(None));
+}
+
+struct Payload<F, T> {
+    func: Option<F>,
+    result: Option<T>,
+}
+
+extern "sysv64" fn call_payload<F: FnOnce() -> T, T>(data: *mut u8) {
+    let payload = unsafe { &mut *(data as *mut Payload<F, T>) };
+    let func = payload.func.take().expect("Payload already called");
+    payload.result = Some(func());
+}
+
+pub fn catch_panic<F: FnOnce() -> T, T>(func: F) -> Result<T, Box<PanicReport>> {
+    let core = smp::current_core();
+
+    let mut point = RecoveryPoint {
+        regs: SavedRegisters([0; 8]),
+        report: None,
+    };
+    let mut payload = Payload {
+        func: Some(func),
+        result: None,
+    };
+
+    // Recovery points can be nested, the innermost one catches the panic
+    let prev_point = RECOVERY_POINTS[core].swap(&mut point, Ordering::SeqCst);
+    let panicked = unsafe {
+        recovery_call(
+            &mut point.regs,
+            call_payload::<F, T>,
+            &mut payload as *mut Payload<F, T> as *mut u8,
+        )
+    };
+    RECOVERY_POINTS[core].store(prev_point, Ordering::SeqCst);
+
+    match panicked {
+        0 => Ok(payload.result.take().expect("No result")),
+        _ => Err(point.report.take().expect("No panic report")),
+    }
+}
+
+pub fn set_active_app(app_name: Option<&str>) {
+    *ACTIVE_APPS[smp::current_core()].lock() = app_name.map(|name| name.to_string());
+}
+
+// Where the panic screen is drawn
+pub fn set_display(virtio_gpu: &mut VirtioGPU) {
+    DISPLAY.store(virtio_gpu, Ordering::SeqCst);
+}
+
+pub fn handle_panic(info: &PanicInfo) -> ! {
+    let core = smp::current_core();
+    let backtrace = backtrace::capture();
+
+    log::error!("{}", info);
+    for &addr in backtrace.iter() {
+        log::error!("  {}", backtrace::format_frame(addr));
+    }
+
+    let point = RECOVERY_POINTS[core].load(Ordering::SeqCst);
+    if !point.is_null() {
+        unsafe {
+            (*point).report = Some(Box::new(PanicReport {
+                message: format!("{}", info.message()),
+                backtrace,
+            }));
+            recovery_jump(&(*point).regs);
+        }
+    }
+
+    // Only the first core to panic gets to draw the panic screen
+    if PANICKING.swap(true, Ordering::SeqCst) {
+        halt_forever();
+    }
+
+    let mut lines: Vec<String> = Vec::new();
+
+    lines.push(format!("KERNEL PANIC (core {})", core));
+    lines.push(String::new());
+    lines.extend(format!("{}", info).lines().map(|line| line.to_string()));
+
+    if let Some(app_name) = ACTIVE_APPS[core].try_lock().and_then(|app| app.clone()) {
+        lines.push(format!("While running app: {}", app_name));
+    }
+
+    lines.push(String::new());
+    lines.push("Backtrace:".to_string());
+    lines.extend(
+        backtrace
+            .iter()
+            .map(|&addr| format!("  {}", backtrace::format_frame(addr))),
+    );
+
+    lines.push(String::new());
+    lines.push("Recent log:".to_string());
+    lines.extend(
+        logging::recent_lines()
+            .iter()
+            .map(|line| format!("  {}", line)),
+    );
+
+    draw_panic_screen(&lines);
+
+    halt_forever();
+}
+
+fn draw_panic_screen(lines: &[String]) {
+    let virtio_gpu = DISPLAY.load(Ordering::SeqCst);
+    if virtio_gpu.is_null() {
+        return;
+    }
+
+    // Safety: the main loop may still hold a reference to the GPU, but it is not
+    // going to use it anymore (it is either panicking or waiting on this core)
+    let virtio_gpu = unsafe { &mut *virtio_gpu };
+
+    let (w, h) = virtio_gpu.get_dims();
+    let (w, h) = (w as u32, h as u32);
+    let mut fb = Framebuffer::<BorrowedMutPixels>::from_bytes(&mut virtio_gpu.framebuffer, w, h);
+
+    draw_rect(
+        &mut fb,
+        &Rect { x0: 0, y0: 0, w, h },
+        PANIC_SCREEN_BG,
+        false,
+    );
+
+    let font = get_font(STYLESHEET.text.font_family(), STYLESHEET.text.sizes.medium);
+
+    let mut y = PANIC_SCREEN_MARGIN;
+    for line in lines {
+        draw_str(
+            &mut fb,
+            line,
+            PANIC_SCREEN_MARGIN,
+            y,
+            font,
+            Color::WHITE,
+            None,
+        );
+        y += font.char_h as i64 + PANIC_SCREEN_LINE_SPACING;
+    }
+
+    virtio_gpu.flush();
+}
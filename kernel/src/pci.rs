@@ -1,20 +1,56 @@
 use alloc::borrow::ToOwned;
 use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
 use alloc::vec::Vec;
 use bitvec::field::BitField;
 use bitvec::prelude::Lsb0;
 use bitvec::view::BitView;
+use core::fmt;
 use core::mem;
+use core::ptr::{read_volatile, write_volatile};
 use x86_64::instructions::port::{Port, PortWriteOnly};
+use x86_64::PhysAddr;
 
-#[derive(Debug)]
+use crate::acpi::McfgEntry;
+use crate::memory;
+
+// Capability IDs
+const CAP_POWER_MANAGEMENT: u8 = 0x01;
+const CAP_MSI: u8 = 0x05;
+const CAP_VENDOR_SPECIFIC: u8 = 0x09;
+const CAP_PCI_EXPRESS: u8 = 0x10;
+const CAP_MSIX: u8 = 0x11;
+
+// PCIe extended capability IDs
+const EXT_CAP_AER: u16 = 0x0001;
+const EXT_CAP_DEVICE_SERIAL_NUMBER: u16 = 0x0003;
+const EXT_CAP_VENDOR_SPECIFIC: u16 = 0x000b;
+const EXT_CAP_ACS: u16 = 0x000d;
+const EXT_CAP_ARI: u16 = 0x000e;
+const EXT_CAP_SRIOV: u16 = 0x0010;
+
+// Extended capabilities start right after the 256 bytes of legacy configuration space
+const EXT_CAP_START: u16 = 0x100;
+
+// Guards against malformed (looping) capability lists
+const MAX_CAPABILITIES: usize = 48;
+
+// ECAM regions from the ACPI MCFG table, when available
+static ECAM_REGIONS: spin::Once<Vec<McfgEntry>> = spin::Once::new();
+
+#[derive(Debug, Clone)]
 pub struct PciDevice {
     pub addr: PciAddress,
     pub vendor_id: u16,
     pub device_id: u16,
     pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub header_type: u8,
 
     pub capabilities: Vec<PciCapability>,
+    pub extended_capabilities: Vec<PciExtendedCapability>,
     pub bars: BTreeMap<u32, PciBar>,
 }
 
@@ -29,12 +65,67 @@ pub struct PciAddress {
 pub struct PciCapability {
     pub vendor: u8,
     pub offset: u8,
+    pub kind: PciCapabilityKind,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum PciCapabilityKind {
+    PowerManagement,
+    Msi {
+        enabled: bool,
+        max_vectors: u8,
+        addr_64bit: bool,
+        per_vector_masking: bool,
+    },
+    MsiX {
+        enabled: bool,
+        table_size: u16,
+        table_bar: u8,
+        table_offset: u32,
+        pba_bar: u8,
+        pba_offset: u32,
+    },
+    PciExpress {
+        port_type: u8,
+        link_speed: u8,
+        link_width: u8,
+    },
+    VendorSpecific,
+    Other,
+}
+
+// Only reachable through ECAM, the legacy I/O ports being limited to the first 256 bytes
+#[derive(Debug, Clone)]
+pub struct PciExtendedCapability {
+    pub id: u16,
+    pub version: u8,
+    pub offset: u16,
+}
+
+// All enumerated PCI functions, and the drivers which claimed them
+pub struct PciBus {
+    entries: Vec<PciBusEntry>,
+}
+
+pub struct PciBusEntry {
+    pub device: PciDevice,
+    pub driver: Option<&'static str>,
 }
 
 #[derive(Debug)]
 pub struct PciConfigSpace {
-    address_port: PortWriteOnly<u32>,
-    data_port: Port<u32>,
+    access: ConfigAccess,
+}
+
+#[derive(Debug)]
+enum ConfigAccess {
+    // Legacy mechanism #1, through I/O ports 0xCF8 / 0xCFC
+    PortIo {
+        address_port: PortWriteOnly<u32>,
+        data_port: Port<u32>,
+    },
+    // PCIe enhanced configuration access mechanism (memory-mapped)
+    Ecam(&'static [McfgEntry]),
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -87,87 +178,216 @@ impl PciDevice {
     pub fn disable_msix(&self) {
         let mut pci_config_space = PciConfigSpace::new();
 
-        let cap = self.capabilities.iter().find(|cap| cap.vendor == 0x11);
+        let cap = self
+            .capabilities
+            .iter()
+            .find(|cap| matches!(cap.kind, PciCapabilityKind::MsiX { .. }));
 
         let cap = if let Some(cap) = cap { cap } else { return };
 
-        let mut word = unsafe { pci_config_space.read(&self.addr, cap.offset) };
+        let mut word = unsafe { pci_config_space.read(&self.addr, cap.offset.into()) };
 
         let bits = word.view_bits_mut::<Lsb0>();
         bits.set(31, false);
 
-        unsafe { pci_config_space.write(&self.addr, cap.offset, bits.load()) };
+        unsafe { pci_config_space.write(&self.addr, cap.offset.into(), bits.load()) };
     }
 }
 
-pub fn enumerate() -> Vec<PciDevice> {
+impl PciBus {
+    pub fn enumerate() -> Self {
+        let entries = enumerate()
+            .into_iter()
+            .map(|device| PciBusEntry {
+                device,
+                driver: None,
+            })
+            .collect();
+
+        PciBus { entries }
+    }
+
+    // Hands out the first unclaimed device matching the predicate
+    pub fn claim<P: Fn(&PciDevice) -> bool>(
+        &mut self,
+        driver: &'static str,
+        predicate: P,
+    ) -> Option<PciDevice> {
+        let entry = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.driver.is_none() && predicate(&entry.device))?;
+
+        log::info!("PCI device {} claimed by {}", entry.device.addr, driver);
+        entry.driver = Some(driver);
+
+        Some(entry.device.clone())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &PciBusEntry> {
+        self.entries.iter()
+    }
+}
+
+impl PciBusEntry {
+    // One line for the function itself, then one indented line per BAR and capability
+    pub fn describe(&self) -> Vec<String> {
+        let dev = &self.device;
+
+        let mut lines = Vec::new();
+        lines.push(format!(
+            "{}  {:04x}:{:04x}  {} (prog IF {:02x})  [{}]",
+            dev.addr,
+            dev.vendor_id,
+            dev.device_id,
+            class_name(dev.class, dev.subclass),
+            dev.prog_if,
+            self.driver.unwrap_or("no driver"),
+        ));
+
+        for (i, bar) in dev.bars.iter() {
+            lines.push(format!("    BAR {}: {}", i, bar));
+        }
+
+        for cap in dev.capabilities.iter() {
+            lines.push(format!("    Cap {:#04x}: {}", cap.offset, cap.kind));
+        }
+
+        for cap in dev.extended_capabilities.iter() {
+            lines.push(format!(
+                "    Ext cap {:#05x}: {} (version {})",
+                cap.offset,
+                extended_capability_name(cap.id),
+                cap.version
+            ));
+        }
+
+        lines
+    }
+}
+
+// Should be called before enumerating devices
+pub fn init_ecam(mcfg: &[McfgEntry]) {
+    // Only segment 0 is supported
+    let regions: Vec<McfgEntry> = mcfg
+        .iter()
+        .filter(|entry| entry.segment == 0)
+        .copied()
+        .collect();
+
+    if regions.is_empty() {
+        log::info!("No PCIe ECAM region, using legacy PCI configuration access");
+        return;
+    }
+
+    log::info!("Using PCIe ECAM configuration access");
+
+    if ECAM_REGIONS.r#try().is_some() {
+        panic!("ECAM already initialized?");
+    }
+    ECAM_REGIONS.call_once(|| regions);
+}
+
+fn enumerate() -> Vec<PciDevice> {
     let mut pci_config_space = PciConfigSpace::new();
 
-    // Don't care about multi-function devices for now
-    let function = 0;
+    let mut devices = Vec::new();
 
-    let bus_iter = 0..=255u8;
-    let device_iter = 0..32u8;
+    for bus in 0..=255u8 {
+        for device in 0..32u8 {
+            for function in 0..8u8 {
+                let addr = PciAddress {
+                    bus,
+                    device,
+                    function,
+                };
 
-    bus_iter
-        .flat_map(move |bus| device_iter.clone().map(move |device| (bus, device)))
-        .filter_map(move |(bus, device)| {
-            let addr = PciAddress {
-                bus,
-                device,
-                function,
-            };
+                let word_0 = unsafe { pci_config_space.read(&addr, 0x0) };
+
+                // No function at this address
+                if word_0 == u32::MAX {
+                    match function {
+                        0 => break,
+                        _ => continue,
+                    }
+                }
+
+                let pci_device = read_device(&mut pci_config_space, addr, word_0);
+                let multi_function = pci_device.header_type & 0x80 != 0;
+
+                log::info!(
+                    "Found PCI device {}, vendor={:#x} device={:#x} ({})",
+                    pci_device.addr,
+                    pci_device.vendor_id,
+                    pci_device.device_id,
+                    class_name(pci_device.class, pci_device.subclass),
+                );
 
-            let word_0 = unsafe { pci_config_space.read(&addr, 0x0) };
+                devices.push(pci_device);
 
-            // No device at this address
-            if word_0 == u32::MAX {
-                return None;
+                if function == 0 && !multi_function {
+                    break;
+                }
             }
+        }
+    }
 
-            // Header type
-            let word_0c = unsafe { pci_config_space.read(&addr, 0x0c) };
-            let bits_0c = word_0c.view_bits::<Lsb0>();
-            let mut header_bits = bits_0c[16..24].to_owned();
-            header_bits.set(7, false);
-            let header_type = header_bits.load::<u8>();
-            assert_eq!(header_type, 0x00); // We don't support PCI bridges for now
-
-            // Device/Vendor IDs
-            let bits_0 = word_0.view_bits::<Lsb0>();
-            let device_id = bits_0[16..32].load();
-            let vendor_id = bits_0[0..16].load();
-
-            // Device class
-            let word_8 = unsafe { pci_config_space.read(&addr, 0x8) };
-            let bits_8 = word_8.view_bits::<Lsb0>();
-            let class = bits_8[24..32].load();
-
-            let capabilities = get_capabilities(&mut pci_config_space, &addr);
-            let bars = get_bars(&mut pci_config_space, &addr);
-
-            log::info!(
-                "Found PCI device, vendor={:#x} device={:#x}",
-                vendor_id,
-                device_id
-            );
-
-            Some(PciDevice {
-                addr,
-                vendor_id,
-                device_id,
-                class,
-                capabilities,
-                bars,
-            })
-        })
-        .collect()
+    devices
+}
+
+fn read_device(pci_config_space: &mut PciConfigSpace, addr: PciAddress, word_0: u32) -> PciDevice {
+    // Header type
+    let word_0c = unsafe { pci_config_space.read(&addr, 0x0c) };
+    let bits_0c = word_0c.view_bits::<Lsb0>();
+    let header_type = bits_0c[16..24].load::<u8>();
+
+    // Device/Vendor IDs
+    let bits_0 = word_0.view_bits::<Lsb0>();
+    let device_id = bits_0[16..32].load();
+    let vendor_id = bits_0[0..16].load();
+
+    // Device class
+    let word_8 = unsafe { pci_config_space.read(&addr, 0x8) };
+    let bits_8 = word_8.view_bits::<Lsb0>();
+    let prog_if = bits_8[8..16].load();
+    let subclass = bits_8[16..24].load();
+    let class = bits_8[24..32].load();
+
+    // PCI-to-PCI bridges only have 2 BARs, and we don't parse CardBus bridges
+    let n_bars = match header_type & 0x7f {
+        0x00 => 6,
+        0x01 => 2,
+        _ => 0,
+    };
+
+    let capabilities = get_capabilities(pci_config_space, &addr);
+    let extended_capabilities = get_extended_capabilities(pci_config_space, &addr);
+    let bars = get_bars(pci_config_space, &addr, n_bars);
+
+    PciDevice {
+        addr,
+        vendor_id,
+        device_id,
+        class,
+        subclass,
+        prog_if,
+        header_type,
+        capabilities,
+        extended_capabilities,
+        bars,
+    }
 }
 
 fn get_capabilities(
     pci_config_space: &mut PciConfigSpace,
     addr: &PciAddress,
 ) -> Vec<PciCapability> {
+    // Status register, "capabilities list" bit
+    let word_04 = unsafe { pci_config_space.read(&addr, 0x04) };
+    if !word_04.view_bits::<Lsb0>()[20] {
+        return Vec::new();
+    }
+
     let mut cap_ptr = {
         let mut word_34 = unsafe { pci_config_space.read(&addr, 0x34) };
         let bits_34 = word_34.view_bits_mut::<Lsb0>();
@@ -176,31 +396,118 @@ fn get_capabilities(
     };
 
     let mut capabilities = Vec::new();
-    while cap_ptr != 0x00 {
-        let mut word_0 = unsafe { pci_config_space.read(&addr, cap_ptr) };
+    while cap_ptr != 0x00 && capabilities.len() < MAX_CAPABILITIES {
+        let mut word_0 = unsafe { pci_config_space.read(&addr, cap_ptr.into()) };
         let bits_0 = word_0.view_bits_mut::<Lsb0>();
 
         let vendor = bits_0[..8].load();
+        let kind = parse_capability(pci_config_space, addr, vendor, cap_ptr);
 
         capabilities.push(PciCapability {
             vendor,
             offset: cap_ptr,
+            kind,
         });
 
-        cap_ptr = bits_0[8..16].load();
+        cap_ptr = bits_0[8..16].load::<u8>() & !0b11;
     }
 
     capabilities
 }
 
-fn get_bars(pci_config_space: &mut PciConfigSpace, addr: &PciAddress) -> BTreeMap<u32, PciBar> {
-    const MAX_BARS: u32 = 6;
+// Linked list in the extended configuration space, which reads as all ones without ECAM
+fn get_extended_capabilities(
+    pci_config_space: &mut PciConfigSpace,
+    addr: &PciAddress,
+) -> Vec<PciExtendedCapability> {
+    let mut capabilities = Vec::new();
+    let mut cap_ptr = EXT_CAP_START;
+
+    while cap_ptr >= EXT_CAP_START && capabilities.len() < MAX_CAPABILITIES {
+        let header = unsafe { pci_config_space.read(addr, cap_ptr) };
+        if header == 0 || header == u32::MAX {
+            break;
+        }
 
+        let bits = header.view_bits::<Lsb0>();
+        capabilities.push(PciExtendedCapability {
+            id: bits[..16].load(),
+            version: bits[16..20].load(),
+            offset: cap_ptr,
+        });
+
+        cap_ptr = bits[20..32].load::<u16>() & !0b11;
+    }
+
+    capabilities
+}
+
+fn parse_capability(
+    pci_config_space: &mut PciConfigSpace,
+    addr: &PciAddress,
+    cap_id: u8,
+    offset: u8,
+) -> PciCapabilityKind {
+    let mut read =
+        |rel_offset: u16| unsafe { pci_config_space.read(addr, offset as u16 + rel_offset) };
+
+    match cap_id {
+        CAP_POWER_MANAGEMENT => PciCapabilityKind::PowerManagement,
+
+        CAP_MSI => {
+            let word_0 = read(0x0);
+            let control = word_0.view_bits::<Lsb0>();
+            PciCapabilityKind::Msi {
+                enabled: control[16],
+                max_vectors: 1 << control[17..20].load::<u8>(),
+                addr_64bit: control[23],
+                per_vector_masking: control[24],
+            }
+        }
+
+        CAP_MSIX => {
+            let word_0 = read(0x0);
+            let word_4 = read(0x4);
+            let word_8 = read(0x8);
+            let control = word_0.view_bits::<Lsb0>();
+            PciCapabilityKind::MsiX {
+                enabled: control[31],
+                table_size: control[16..27].load::<u16>() + 1,
+                table_bar: (word_4 & 0b111) as u8,
+                table_offset: word_4 & !0b111,
+                pba_bar: (word_8 & 0b111) as u8,
+                pba_offset: word_8 & !0b111,
+            }
+        }
+
+        CAP_PCI_EXPRESS => {
+            let word_0 = read(0x0);
+            let word_10 = read(0x10);
+            let caps = word_0.view_bits::<Lsb0>();
+            let link_status = word_10.view_bits::<Lsb0>();
+            PciCapabilityKind::PciExpress {
+                port_type: caps[20..24].load(),
+                link_speed: link_status[16..20].load(),
+                link_width: link_status[20..26].load(),
+            }
+        }
+
+        CAP_VENDOR_SPECIFIC => PciCapabilityKind::VendorSpecific,
+
+        _ => PciCapabilityKind::Other,
+    }
+}
+
+fn get_bars(
+    pci_config_space: &mut PciConfigSpace,
+    addr: &PciAddress,
+    n_bars: u32,
+) -> BTreeMap<u32, PciBar> {
     let mut bars = BTreeMap::new();
-    let mut it = 0..MAX_BARS;
+    let mut it = 0..n_bars;
 
     while let Some(i) = it.next() {
-        let offset = 0x10 + 0x4 * (i as u8);
+        let offset = 0x10 + 0x4 * (i as u16);
         let word_bars = unsafe { pci_config_space.read(&addr, offset) };
 
         let bits_bar = word_bars.view_bits::<Lsb0>();
@@ -256,7 +563,7 @@ fn get_bars(pci_config_space: &mut PciConfigSpace, addr: &PciAddress) -> BTreeMa
                     BarAddrType::Bar64 => {
                         // Grabbing high bits of the address from next BAR
                         let next_i = it.next().expect("64-bit BAR but already in last BAR");
-                        let next_offset = 0x10 + 0x4 * (next_i as u8);
+                        let next_offset = 0x10 + 0x4 * (next_i as u16);
                         let next_word_bars = unsafe { pci_config_space.read(&addr, next_offset) };
                         let next_word_bars: u64 = next_word_bars.into();
                         let addr_high_bits = next_word_bars.view_bits::<Lsb0>();
@@ -298,20 +605,25 @@ fn get_bars(pci_config_space: &mut PciConfigSpace, addr: &PciAddress) -> BTreeMa
 
 impl PciConfigSpace {
     pub fn new() -> Self {
-        PciConfigSpace {
-            address_port: PortWriteOnly::<u32>::new(0xCF8),
-            data_port: Port::<u32>::new(0xCFC),
-        }
+        let access = match ECAM_REGIONS.r#try() {
+            Some(regions) => ConfigAccess::Ecam(regions.as_slice()),
+            None => ConfigAccess::PortIo {
+                address_port: PortWriteOnly::<u32>::new(0xCF8),
+                data_port: Port::<u32>::new(0xCFC),
+            },
+        };
+
+        PciConfigSpace { access }
     }
 
-    pub unsafe fn read_struct<T: Clone>(&mut self, addr: &PciAddress, offset: u8) -> T {
+    pub unsafe fn read_struct<T: Clone>(&mut self, addr: &PciAddress, offset: u16) -> T {
         let n = mem::size_of::<T>();
         assert_eq!(n % 4, 0);
         let num_words = n / 4;
 
         let buf: Vec<u32> = (0..num_words)
             .map(|i| {
-                let i: u8 = i.try_into().unwrap();
+                let i: u16 = i.try_into().unwrap();
                 self.read(addr, offset + 4 * i)
             })
             .collect();
@@ -320,20 +632,66 @@ impl PciConfigSpace {
         ptr.as_ref().unwrap().clone()
     }
 
-    // Unsafe because addr and offset have to point to valid data
-    pub unsafe fn read(&mut self, addr: &PciAddress, offset: u8) -> u32 {
-        let addr_word = Self::get_addr_word(addr, offset);
-
-        self.address_port.write(addr_word);
-        self.data_port.read()
+    // Unsafe because addr and offset have to point to valid data.
+    // Offsets past the first 256 bytes (PCIe extended configuration space) read as all ones
+    // without ECAM, like missing functions.
+    pub unsafe fn read(&mut self, addr: &PciAddress, offset: u16) -> u32 {
+        match &mut self.access {
+            ConfigAccess::PortIo {
+                address_port,
+                data_port,
+            } => {
+                let Ok(offset) = u8::try_from(offset) else {
+                    return u32::MAX;
+                };
+                let addr_word = Self::get_addr_word(addr, offset);
+                address_port.write(addr_word);
+                data_port.read()
+            }
+            ConfigAccess::Ecam(regions) => match Self::get_ecam_ptr(regions, addr, offset) {
+                Some(ptr) => read_volatile(ptr),
+                None => u32::MAX,
+            },
+        }
     }
 
-    // Same
-    pub unsafe fn write(&mut self, addr: &PciAddress, offset: u8, val: u32) {
-        let addr_word = Self::get_addr_word(addr, offset);
+    // Same, writes to the extended configuration space are dropped without ECAM
+    pub unsafe fn write(&mut self, addr: &PciAddress, offset: u16, val: u32) {
+        match &mut self.access {
+            ConfigAccess::PortIo {
+                address_port,
+                data_port,
+            } => {
+                let Ok(offset) = u8::try_from(offset) else {
+                    return;
+                };
+                let addr_word = Self::get_addr_word(addr, offset);
+                address_port.write(addr_word);
+                data_port.write(val);
+            }
+            ConfigAccess::Ecam(regions) => {
+                if let Some(ptr) = Self::get_ecam_ptr(regions, addr, offset) {
+                    write_volatile(ptr, val);
+                }
+            }
+        }
+    }
 
-        self.address_port.write(addr_word);
-        self.data_port.write(val);
+    // Buses which are not covered by any ECAM region read as empty
+    fn get_ecam_ptr(regions: &[McfgEntry], addr: &PciAddress, offset: u16) -> Option<*mut u32> {
+        let region = regions
+            .iter()
+            .find(|region| (region.start_bus..=region.end_bus).contains(&addr.bus))?;
+
+        let rel_bus = (addr.bus - region.start_bus) as u64;
+        let phys_addr = region.base_addr
+            + (rel_bus << 20
+                | (addr.device as u64) << 15
+                | (addr.function as u64) << 12
+                | (offset & 0xffc) as u64);
+
+        let virt_addr = memory::get_mapper().phys_to_virt(PhysAddr::new(phys_addr));
+        Some(virt_addr.as_mut_ptr())
     }
 
     fn get_addr_word(addr: &PciAddress, offset: u8) -> u32 {
@@ -360,3 +718,130 @@ impl PciConfigSpace {
         bits.load()
     }
 }
+
+impl fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+impl fmt::Display for PciBar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PciBar::Memory {
+                addr_type,
+                prefetchable,
+                base_addr,
+                size,
+            } => {
+                let bits = match addr_type {
+                    BarAddrType::Bar32 => 32,
+                    BarAddrType::Bar64 => 64,
+                };
+                let prefetch = match prefetchable {
+                    true => ", prefetchable",
+                    false => "",
+                };
+                write!(
+                    f,
+                    "mem {:#x} ({}B, {}-bit{})",
+                    base_addr, size, bits, prefetch
+                )
+            }
+            PciBar::IO { base_addr, size } => write!(f, "io {:#x} ({}B)", base_addr, size),
+        }
+    }
+}
+
+impl fmt::Display for PciCapabilityKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PciCapabilityKind::PowerManagement => write!(f, "Power management"),
+            PciCapabilityKind::Msi {
+                enabled,
+                max_vectors,
+                addr_64bit,
+                per_vector_masking,
+            } => write!(
+                f,
+                "MSI ({} vectors, {}-bit{}, {})",
+                max_vectors,
+                if *addr_64bit { 64 } else { 32 },
+                if *per_vector_masking {
+                    ", per-vector masking"
+                } else {
+                    ""
+                },
+                if *enabled { "enabled" } else { "disabled" }
+            ),
+            PciCapabilityKind::MsiX {
+                enabled,
+                table_size,
+                table_bar,
+                table_offset,
+                pba_bar,
+                pba_offset,
+            } => write!(
+                f,
+                "MSI-X ({} vectors, table at BAR {} + {:#x}, PBA at BAR {} + {:#x}, {})",
+                table_size,
+                table_bar,
+                table_offset,
+                pba_bar,
+                pba_offset,
+                if *enabled { "enabled" } else { "disabled" }
+            ),
+            PciCapabilityKind::PciExpress {
+                port_type,
+                link_speed,
+                link_width,
+            } => write!(
+                f,
+                "PCI Express (port type {}, link gen{} x{})",
+                port_type, link_speed, link_width
+            ),
+            PciCapabilityKind::VendorSpecific => write!(f, "Vendor-specific"),
+            PciCapabilityKind::Other => write!(f, "Other"),
+        }
+    }
+}
+
+pub fn extended_capability_name(id: u16) -> &'static str {
+    match id {
+        EXT_CAP_AER => "Advanced error reporting",
+        EXT_CAP_DEVICE_SERIAL_NUMBER => "Device serial number",
+        EXT_CAP_VENDOR_SPECIFIC => "Vendor-specific",
+        EXT_CAP_ACS => "Access control services",
+        EXT_CAP_ARI => "Alternative routing-ID interpretation",
+        EXT_CAP_SRIOV => "SR-IOV",
+        _ => "Other",
+    }
+}
+
+pub fn class_name(class: u8, subclass: u8) -> &'static str {
+    match (class, subclass) {
+        (0x00, _) => "Unclassified device",
+        (0x01, 0x01) => "IDE controller",
+        (0x01, 0x06) => "SATA controller",
+        (0x01, 0x08) => "NVMe controller",
+        (0x01, _) => "Mass storage controller",
+        (0x02, 0x00) => "Ethernet controller",
+        (0x02, _) => "Network controller",
+        (0x03, 0x00) => "VGA controller",
+        (0x03, _) => "Display controller",
+        (0x04, _) => "Multimedia controller",
+        (0x05, _) => "Memory controller",
+        (0x06, 0x00) => "Host bridge",
+        (0x06, 0x01) => "ISA bridge",
+        (0x06, 0x04) => "PCI-to-PCI bridge",
+        (0x06, _) => "Bridge",
+        (0x07, _) => "Communication controller",
+        (0x08, _) => "System peripheral",
+        (0x09, _) => "Input device controller",
+        (0x0c, 0x03) => "USB controller",
+        (0x0c, 0x05) => "SMBus controller",
+        (0x0c, _) => "Serial bus controller",
+        (0x0d, _) => "Wireless controller",
+        _ => "Unknown device",
+    }
+}
@@ -0,0 +1,186 @@
+use core::arch::x86_64::{__cpuid, __cpuid_count, _rdrand64_step, _rdseed64_step, _rdtsc};
+
+use rand::{CryptoRng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+
+use crate::time::SystemClock;
+use crate::virtio::rng::{VirtioRng, ENTROPY_BUF_SIZE};
+
+const SEED_SIZE: usize = 32;
+
+// In milliseconds
+const RESEED_PERIOD: f64 = 60_000.0;
+
+// Cryptographically secure RNG used by the kernel and exposed to WASM apps (random_get)
+pub struct SystemRng {
+    rng: ChaCha20Rng,
+    pool: [u8; SEED_SIZE],
+    virtio_rng: Option<VirtioRng>,
+    last_reseed_t: f64,
+}
+
+impl SystemRng {
+    pub fn new(mut virtio_rng: Option<VirtioRng>, clock: &SystemClock) -> Self {
+        let mut pool = [0u8; SEED_SIZE];
+        let n_sources = collect_entropy(&mut pool, virtio_rng.as_mut(), clock);
+
+        log::info!("RNG seeded from {} entropy source(s)", n_sources);
+
+        SystemRng {
+            rng: ChaCha20Rng::from_seed(pool),
+            pool,
+            virtio_rng,
+            last_reseed_t: clock.time(),
+        }
+    }
+
+    pub fn maybe_reseed(&mut self, clock: &SystemClock) {
+        if clock.time() - self.last_reseed_t < RESEED_PERIOD {
+            return;
+        }
+
+        let mut fresh = [0u8; SEED_SIZE];
+        self.rng.fill_bytes(&mut fresh);
+        mix_into_pool(&mut self.pool, &fresh);
+
+        collect_entropy(&mut self.pool, self.virtio_rng.as_mut(), clock);
+
+        self.rng = ChaCha20Rng::from_seed(self.pool);
+        self.last_reseed_t = clock.time();
+    }
+}
+
+impl RngCore for SystemRng {
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.rng.try_fill_bytes(dest)
+    }
+}
+
+impl CryptoRng for SystemRng {}
+
+// Mixes every available source into the pool, returns the number of sources used.
+// Timing jitter is always used, since the others may be absent or untrustworthy.
+fn collect_entropy(
+    pool: &mut [u8; SEED_SIZE],
+    virtio_rng: Option<&mut VirtioRng>,
+    clock: &SystemClock,
+) -> usize {
+    let mut n_sources = 0;
+
+    if let Some(virtio_rng) = virtio_rng {
+        // Only counted if the device provided the whole buffer
+        let mut buf = [0u8; SEED_SIZE];
+        let mut complete = true;
+        for chunk in buf.chunks_mut(ENTROPY_BUF_SIZE) {
+            match virtio_rng.read_entropy(clock) {
+                Some(entropy) => chunk.copy_from_slice(&entropy[..chunk.len()]),
+                None => complete = false,
+            }
+        }
+        mix_into_pool(pool, &buf);
+        if complete {
+            n_sources += 1;
+        }
+    }
+
+    if let Some(buf) = read_cpu_entropy(has_rdseed(), |val| unsafe { _rdseed64_step(val) }) {
+        mix_into_pool(pool, &buf);
+        n_sources += 1;
+    }
+
+    if let Some(buf) = read_cpu_entropy(has_rdrand(), |val| unsafe { _rdrand64_step(val) }) {
+        mix_into_pool(pool, &buf);
+        n_sources += 1;
+    }
+
+    mix_into_pool(pool, &read_jitter_entropy());
+    n_sources += 1;
+
+    n_sources
+}
+
+// XORs the input into the pool, then runs it through ChaCha20 so that a single
+// good source is enough even if the others are weak or adversarial
+fn mix_into_pool(pool: &mut [u8; SEED_SIZE], input: &[u8; SEED_SIZE]) {
+    for (p, x) in pool.iter_mut().zip(input.iter()) {
+        *p ^= x;
+    }
+    ChaCha20Rng::from_seed(*pool).fill_bytes(pool);
+}
+
+//
+// CPU instructions (RDSEED / RDRAND)
+
+fn has_rdrand() -> bool {
+    let ecx = unsafe { __cpuid(1) }.ecx;
+    ecx & (1 << 30) != 0
+}
+
+fn has_rdseed() -> bool {
+    let ebx = unsafe { __cpuid_count(7, 0) }.ebx;
+    ebx & (1 << 18) != 0
+}
+
+fn read_cpu_entropy<F: Fn(&mut u64) -> i32>(supported: bool, step: F) -> Option<[u8; SEED_SIZE]> {
+    // Both instructions can transiently fail when the hardware is drained
+    const MAX_RETRIES: usize = 100;
+
+    if !supported {
+        return None;
+    }
+
+    let mut buf = [0u8; SEED_SIZE];
+
+    for chunk in buf.chunks_mut(8) {
+        let mut val = 0u64;
+        let ok = (0..MAX_RETRIES).any(|_| step(&mut val) == 1);
+        if !ok {
+            log::warn!("CPU random number instruction keeps failing");
+            return None;
+        }
+        chunk.copy_from_slice(&val.to_le_bytes());
+    }
+
+    Some(buf)
+}
+
+//
+// Timing jitter
+
+fn read_jitter_entropy() -> [u8; SEED_SIZE] {
+    // Each output bit folds in many samples, since a single one carries very little entropy
+    const SAMPLES_PER_BYTE: usize = 64;
+
+    let mut scratch = [0u64; 64];
+    let mut buf = [0u8; SEED_SIZE];
+
+    for (i, byte) in buf.iter_mut().enumerate() {
+        let mut acc = 0u64;
+        for j in 0..SAMPLES_PER_BYTE {
+            let t0 = unsafe { _rdtsc() };
+
+            // Memory accesses with data-dependent indices, to amplify cache and pipeline noise
+            let k = (acc as usize ^ j) % scratch.len();
+            scratch[k] = scratch[k].wrapping_add(t0).rotate_left((i + j) as u32);
+            let _ = unsafe { core::ptr::read_volatile(&scratch[(k * 7) % scratch.len()]) };
+
+            let delta = unsafe { _rdtsc() }.wrapping_sub(t0);
+            acc = acc.rotate_left(7) ^ delta;
+        }
+        *byte = acc.to_le_bytes().iter().fold(0, |a, b| a ^ b);
+    }
+
+    buf
+}
@@ -1,4 +1,6 @@
-use crate::app::AppDescriptor;
+use crate::app::{AppDescriptor, AppMode, RestartPolicy};
+use alloc::vec;
+use alloc::vec::Vec;
 use applib::{Color, Framebuffer, OwnedPixels, Rect};
 use applib::{StyleSheet, StyleSheetColors, StyleSheetText, TextSizes};
 use lazy_static::lazy_static;
@@ -27,12 +29,16 @@ lazy_static! {
         Framebuffer::from_png(include_bytes!("../../icons/png/reload.png"));
     pub static ref MOVE_ICON: Framebuffer<OwnedPixels> =
         Framebuffer::from_png(include_bytes!("../../icons/png/move.png"));
+    pub static ref HOME_ICON: Framebuffer<OwnedPixels> =
+        Framebuffer::from_png(include_bytes!("../../icons/png/home.png"));
     pub static ref PLAY_ICON: Framebuffer<OwnedPixels> =
         Framebuffer::from_png(include_bytes!("../../icons/png/play.png"));
     pub static ref PAUSE_ICON: Framebuffer<OwnedPixels> =
         Framebuffer::from_png(include_bytes!("../../icons/png/pause.png"));
     pub static ref INSPECT_ICON: Framebuffer<OwnedPixels> =
         Framebuffer::from_png(include_bytes!("../../icons/png/inspect.png"));
+    pub static ref STOP_ICON: Framebuffer<OwnedPixels> =
+        Framebuffer::from_png(include_bytes!("../../icons/png/stop.png"));
     pub static ref SPEEDOMETER_ICON: Framebuffer<OwnedPixels> =
         Framebuffer::from_png(include_bytes!("../../icons/png/speedometer.png"));
     pub static ref CHIP_ICON: Framebuffer<OwnedPixels> =
@@ -81,7 +87,7 @@ lazy_static! {
     //
     // WASM apps
 
-    pub static ref APPLICATIONS: [AppDescriptor; 5] = [
+    pub static ref APPLICATIONS: Vec<AppDescriptor> = vec![
         AppDescriptor {
             data: include_bytes!("../wasm/cube_3d.wasm"),
             name: "3D Demo",
@@ -93,6 +99,10 @@ lazy_static! {
             },
             min_size: (200, 200),
             icon: &CUBE_ICON,
+            restart_policy: RestartPolicy::Always,
+            mode: AppMode::Windowed,
+            args: &[],
+            env: &[],
         },
         AppDescriptor {
             data: include_bytes!("../wasm/chronometer.wasm"),
@@ -105,6 +115,10 @@ lazy_static! {
             },
             min_size: (200, 200),
             icon: &CHRONO_ICON,
+            restart_policy: RestartPolicy::OnFailure { max_retries: 3 },
+            mode: AppMode::Windowed,
+            args: &[],
+            env: &[],
         },
         AppDescriptor {
             data: include_bytes!("../wasm/terminal.wasm"),
@@ -117,6 +131,10 @@ lazy_static! {
             },
             min_size: (200, 200),
             icon: &PYTHON_ICON,
+            restart_policy: RestartPolicy::OnFailure { max_retries: 3 },
+            mode: AppMode::Windowed,
+            args: &[],
+            env: &[],
         },
         AppDescriptor {
             data: include_bytes!("../wasm/web_browser.wasm"),
@@ -129,6 +147,10 @@ lazy_static! {
             },
             min_size: (200, 200),
             icon: &WEB_ICON,
+            restart_policy: RestartPolicy::OnFailure { max_retries: 3 },
+            mode: AppMode::Windowed,
+            args: &[],
+            env: &[],
         },
         AppDescriptor {
             data: include_bytes!("../wasm/text_editor.wasm"),
@@ -141,6 +163,29 @@ lazy_static! {
             },
             min_size: (200, 400),
             icon: &UI_ICON,
+            restart_policy: RestartPolicy::Never,
+            mode: AppMode::Windowed,
+            args: &[],
+            env: &[],
+        },
+        // Used by the test scenarios, crashes on purpose. Only built into test images
+        // (`./make.py test`), as it makes TCP connections to the test harness.
+        #[cfg(feature = "test-apps")]
+        AppDescriptor {
+            data: include_bytes!("../wasm/host_fuzz.wasm"),
+            name: "Host API fuzzer",
+            init_win_rect: Rect {
+                x0: 0,
+                y0: 0,
+                w: 200,
+                h: 200
+            },
+            min_size: (200, 200),
+            icon: &CHIP_ICON,
+            restart_policy: RestartPolicy::Always,
+            mode: AppMode::Background { step_rate: 30.0 },
+            args: &[],
+            env: &[],
         },
     ];
 }
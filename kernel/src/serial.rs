@@ -1,10 +1,30 @@
 use lazy_static::lazy_static;
 use spin::Mutex;
 use uart_16550::SerialPort;
+use x86_64::instructions::port::Port;
+
+const COM1_BASE: u16 = 0x3F8;
+const COM2_BASE: u16 = 0x2F8;
+const COM3_BASE: u16 = 0x3E8;
 
 lazy_static! {
+    // Kernel logs
     pub static ref SERIAL1: Mutex<SerialPort> = {
-        let mut port = unsafe { SerialPort::new(0x3F8) };
+        let mut port = unsafe { SerialPort::new(COM1_BASE) };
+        port.init();
+        Mutex::new(port)
+    };
+
+    // Kernel command console (see console.rs)
+    pub static ref SERIAL2: Mutex<SerialPort> = {
+        let mut port = unsafe { SerialPort::new(COM2_BASE) };
+        port.init();
+        Mutex::new(port)
+    };
+
+    // GDB remote stub (see gdb.rs)
+    pub static ref SERIAL3: Mutex<SerialPort> = {
+        let mut port = unsafe { SerialPort::new(COM3_BASE) };
         port.init();
         Mutex::new(port)
     };
@@ -23,6 +43,65 @@ pub fn _print(args: core::fmt::Arguments) {
     });
 }
 
+#[doc(hidden)]
+pub fn _print_console(args: core::fmt::Arguments) {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts::without_interrupts;
+
+    without_interrupts(|| {
+        SERIAL2
+            .lock()
+            .write_fmt(args)
+            .expect("Printing to serial failed");
+    });
+}
+
+// Non-blocking read on COM2
+// (uart_16550 only provides a blocking receive(), so we check the line status register ourselves)
+pub fn try_receive_console() -> Option<u8> {
+    let _port = SERIAL2.lock();
+    try_receive(COM2_BASE)
+}
+
+pub fn try_receive_gdb() -> Option<u8> {
+    let _port = SERIAL3.lock();
+    try_receive(COM3_BASE)
+}
+
+// Whether there is data waiting on COM3, without reading it
+pub fn gdb_data_ready() -> bool {
+    let _port = SERIAL3.lock();
+    let mut line_status: Port<u8> = Port::new(COM3_BASE + 5);
+    unsafe { line_status.read() & 0x1 != 0 }
+}
+
+// Reads as all ones if there is no UART at that address
+pub fn gdb_port_present() -> bool {
+    let _port = SERIAL3.lock();
+    let mut line_status: Port<u8> = Port::new(COM3_BASE + 5);
+    unsafe { line_status.read() != 0xff }
+}
+
+pub fn send_gdb(bytes: &[u8]) {
+    let mut port = SERIAL3.lock();
+    for &byte in bytes {
+        port.send_raw(byte);
+    }
+}
+
+fn try_receive(base: u16) -> Option<u8> {
+    let mut line_status: Port<u8> = Port::new(base + 5);
+    let mut data: Port<u8> = Port::new(base);
+
+    unsafe {
+        let data_ready = line_status.read() & 0x1 != 0;
+        match data_ready {
+            true => Some(data.read()),
+            false => None,
+        }
+    }
+}
+
 #[macro_export]
 macro_rules! serial_print {
     ($($arg:tt)*) => {
@@ -37,3 +116,18 @@ macro_rules! serial_println {
     ($fmt:expr, $($arg:tt)*) => ($crate::serial_print!(
         concat!($fmt, "\n"), $($arg)*));
 }
+
+#[macro_export]
+macro_rules! console_print {
+    ($($arg:tt)*) => {
+        $crate::serial::_print_console(format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! console_println {
+    () => ($crate::console_print!("\n"));
+    ($fmt:expr) => ($crate::console_print!(concat!($fmt, "\n")));
+    ($fmt:expr, $($arg:tt)*) => ($crate::console_print!(
+        concat!($fmt, "\n"), $($arg)*));
+}
@@ -10,8 +10,10 @@ use core::f32;
 use core::f32::consts::PI;
 use num_traits::Float;
 
-pub enum PieMenuEntry {
+// Buttons carry the action they trigger, so that selections do not depend on the displayed text
+pub enum PieMenuEntry<A> {
     Button {
+        action: A,
         icon: &'static Framebuffer<OwnedPixels>,
         color: Color,
         text: String,
@@ -23,7 +25,7 @@ pub enum PieMenuEntry {
     },
 }
 
-impl PieMenuEntry {
+impl<A> PieMenuEntry<A> {
     fn weight(&self) -> f32 {
         match self {
             PieMenuEntry::Button { weight, .. } => *weight,
@@ -44,11 +46,12 @@ pub struct PieDrawCalls {
     calls: Vec<DrawCall>,
 }
 
-pub fn pie_menu<'a, F: FbViewMut>(
+// Returns the actions of the selected and hovered entries
+pub fn pie_menu<F: FbViewMut, A: Copy>(
     uitk_context: &mut uitk::UiContext<F>,
-    entries: &'a [PieMenuEntry],
+    entries: &[PieMenuEntry<A>],
     center: Point2D<i64>,
-) -> (Option<&'a str>, PieDrawCalls) {
+) -> (Option<A>, Option<A>, PieDrawCalls) {
     const INNER_RADIUS: f32 = 50.0;
     const OUTER_RADIUS: f32 = 100.0;
     const DEADZONE_INNER_RADIUS: f32 = 25.0;
@@ -75,6 +78,7 @@ pub fn pie_menu<'a, F: FbViewMut>(
     assert!(!entries.is_empty());
 
     let mut selected_entry = None;
+    let mut hovered_entry = None;
     //let mut a0 = 0.0;
     let mut a0 = -PI * entries[0].weight() / total_weight;
     let mut draw_calls = PieDrawCalls { calls: Vec::new() };
@@ -104,14 +108,17 @@ pub fn pie_menu<'a, F: FbViewMut>(
 
         let is_hovered = match entry {
             PieMenuEntry::Spacer { .. } => false,
-            PieMenuEntry::Button { text, .. } => {
+            PieMenuEntry::Button { action, .. } => {
                 let is_hovered = v_cursor.cross(v0) < 0.0
                     && v_cursor.cross(v1) > 0.0
                     && center_dist > DEADZONE_INNER_RADIUS
                     && center_dist < DEADZONE_OUTER_RADIUS;
 
-                if is_hovered && uitk_context.input_state.pointer.left_click_trigger {
-                    selected_entry = Some(text.as_str());
+                if is_hovered {
+                    hovered_entry = Some(*action);
+                    if uitk_context.input_state.pointer.left_click_trigger {
+                        selected_entry = Some(*action);
+                    }
                 }
 
                 is_hovered
@@ -216,7 +223,7 @@ pub fn pie_menu<'a, F: FbViewMut>(
         a0 = a1;
     }
 
-    (selected_entry, draw_calls)
+    (selected_entry, hovered_entry, draw_calls)
 }
 
 impl PieDrawCalls {
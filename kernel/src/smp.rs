@@ -0,0 +1,564 @@
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+use alloc::vec;
+use lazy_static::lazy_static;
+use spin::Mutex;
+use uefi::table::boot::{MemoryMap, MemoryType};
+use x86_64::instructions::segmentation::{Segment, CS, DS, ES, SS};
+use x86_64::instructions::tables::{lgdt, lidt, sgdt, sidt};
+use x86_64::registers::control::{Cr3, Cr4, Cr4Flags};
+use x86_64::registers::model_specific::Efer;
+use x86_64::structures::gdt::SegmentSelector;
+use x86_64::structures::DescriptorTablePointer;
+
+use crate::acpi::Madt;
+use crate::time::SystemClock;
+
+pub const MAX_CORES: usize = 16;
+
+const AP_STACK_SIZE: usize = 1 << 20;
+
+// In milliseconds
+const INIT_DELAY: f64 = 10.0;
+const SIPI_TIMEOUT: f64 = 1.0;
+const AP_START_TIMEOUT: f64 = 100.0;
+
+// Local APIC registers (xAPIC, memory-mapped)
+const LAPIC_ID: usize = 0x20;
+const LAPIC_ICR_LOW: usize = 0x300;
+const LAPIC_ICR_HIGH: usize = 0x310;
+
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+const ICR_INIT: u32 = 0x4500; // INIT, level assert
+const ICR_STARTUP: u32 = 0x4600; // Start-up IPI, level assert
+
+// The trampoline is loaded below 1MB, at a page-aligned address
+const TRAMPOLINE_MAX_ADDR: u64 = 0xa0000;
+const PAGE_SIZE: u64 = 4096;
+
+//
+// Symmetric multiprocessing
+//
+// Application processors (APs) are started with INIT-SIPI-SIPI and then spin on a
+// shared work queue. The main loop stays on the bootstrap processor (BSP), which
+// dispatches a batch of jobs with for_each_parallel() and waits for all of them to
+// complete before moving on, so nothing else in the kernel runs concurrently.
+
+//
+// AP trampoline
+//
+// Copied to a free page below 1MB. APs start there in real mode, load a temporary GDT,
+// switch straight to long mode using the BSP's page tables, and call the entry point
+// on their own stack. Everything is addressed relative to the start of the page, the
+// 32-bit far jump target and GDT base being patched in by the BSP.
+
+core::arch::global_asm!(
+    r#"
+.global ap_trampoline_start
+.global ap_trampoline_end
+.global ap_trampoline_gdt
+.global ap_trampoline_gdt_ptr
+.global ap_trampoline_jump
+.global ap_trampoline_long_mode
+.global ap_trampoline_data
+
+.code16
+ap_trampoline_start:
+    cli
+    cld
+    movw %cs, %ax
+    movw %ax, %ds
+
+    lgdtl ap_trampoline_gdt_ptr - ap_trampoline_start
+
+    # CR4, copied from the BSP (PAE, OSFXSR, ...)
+    movl ap_trampoline_data - ap_trampoline_start + 40, %eax
+    movl %eax, %cr4
+
+    movl ap_trampoline_data - ap_trampoline_start, %eax
+    movl %eax, %cr3
+
+    # EFER, copied from the BSP (long mode, NX)
+    movl $0xc0000080, %ecx
+    movl ap_trampoline_data - ap_trampoline_start + 8, %eax
+    movl ap_trampoline_data - ap_trampoline_start + 12, %edx
+    wrmsr
+
+    # PG, WP, NE, ET, MP, PE
+    movl $0x80010033, %eax
+    movl %eax, %cr0
+
+    # Far return into the 64-bit code segment
+    pushl $0x8
+    pushl ap_trampoline_jump - ap_trampoline_start
+    lretl
+
+.code64
+ap_trampoline_long_mode:
+    movw $0x10, %ax
+    movw %ax, %ds
+    movw %ax, %es
+    movw %ax, %ss
+    xorw %ax, %ax
+    movw %ax, %fs
+    movw %ax, %gs
+
+    movq .Lap_data+16(%rip), %rsp
+    movq .Lap_data+32(%rip), %rdi
+    movq .Lap_data+24(%rip), %rax
+    callq *%rax
+1:
+    hlt
+    jmp 1b
+
+.p2align 3
+ap_trampoline_gdt:
+    .quad 0
+    .quad 0x00af9a000000ffff
+    .quad 0x00cf92000000ffff
+ap_trampoline_gdt_ptr:
+    .word 23
+    .long 0
+ap_trampoline_jump:
+    .long 0
+
+.p2align 3
+ap_trampoline_data:
+.Lap_data:
+    .quad 0
+    .quad 0
+    .quad 0
+    .quad 0
+    .quad 0
+    .quad 0
+ap_trampoline_end:
+"#,
+    options(att_syntax)
+);
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_end: u8;
+    static ap_trampoline_gdt: u8;
+    static ap_trampoline_gdt_ptr: u8;
+    static ap_trampoline_jump: u8;
+    static ap_trampoline_long_mode: u8;
+    static ap_trampoline_data: u8;
+}
+
+// Layout of the data block at the end of the trampoline
+#[repr(C)]
+struct TrampolineData {
+    cr3: u64,
+    efer: u64,
+    stack_top: u64,
+    entry: u64,
+    core_index: u64,
+    cr4: u64,
+}
+
+struct Trampoline {
+    base: u64,
+}
+
+impl Trampoline {
+    fn install(base: u64) -> Self {
+        let offset_of =
+            |sym: *const u8| sym as u64 - unsafe { &ap_trampoline_start as *const u8 as u64 };
+
+        let (len, gdt_off, gdt_ptr_off, jump_off, long_mode_off) = unsafe {
+            (
+                offset_of(&ap_trampoline_end),
+                offset_of(&ap_trampoline_gdt),
+                offset_of(&ap_trampoline_gdt_ptr),
+                offset_of(&ap_trampoline_jump),
+                offset_of(&ap_trampoline_long_mode),
+            )
+        };
+
+        assert!(len <= PAGE_SIZE, "AP trampoline does not fit in a page");
+
+        unsafe {
+            let dst = base as *mut u8;
+            core::ptr::copy_nonoverlapping(&ap_trampoline_start as *const u8, dst, len as usize);
+
+            // GDT base (in the GDT pointer, after the 16-bit limit) and far jump target
+            let gdt_base = dst.add(gdt_ptr_off as usize + 2) as *mut u32;
+            gdt_base.write_unaligned((base + gdt_off) as u32);
+            let jump = dst.add(jump_off as usize) as *mut u32;
+            jump.write_unaligned((base + long_mode_off) as u32);
+        }
+
+        Trampoline { base }
+    }
+
+    fn sipi_vector(&self) -> u32 {
+        (self.base / PAGE_SIZE) as u32
+    }
+
+    fn set_data(&self, data: TrampolineData) {
+        let data_off = unsafe {
+            &ap_trampoline_data as *const u8 as u64 - &ap_trampoline_start as *const u8 as u64
+        };
+        let ptr = (self.base + data_off) as *mut TrampolineData;
+        unsafe { ptr.write_volatile(data) };
+    }
+}
+
+//
+// Local APIC
+
+struct LocalApic {
+    base: u64,
+}
+
+impl LocalApic {
+    fn read(&self, reg: usize) -> u32 {
+        unsafe { ((self.base as usize + reg) as *const u32).read_volatile() }
+    }
+
+    fn write(&self, reg: usize, val: u32) {
+        unsafe { ((self.base as usize + reg) as *mut u32).write_volatile(val) }
+    }
+
+    fn id(&self) -> u32 {
+        self.read(LAPIC_ID) >> 24
+    }
+
+    fn send_ipi(&self, apic_id: u32, icr_low: u32) {
+        self.write(LAPIC_ICR_HIGH, apic_id << 24);
+        self.write(LAPIC_ICR_LOW, icr_low);
+        while self.read(LAPIC_ICR_LOW) & ICR_DELIVERY_PENDING != 0 {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+//
+// AP startup
+
+static N_CORES: AtomicUsize = AtomicUsize::new(1);
+static AP_STARTED: AtomicBool = AtomicBool::new(false);
+
+// Local APIC ID of each core, by core index (see current_core())
+static CORE_APIC_IDS: [AtomicU32; MAX_CORES] = [const { AtomicU32::new(u32::MAX) }; MAX_CORES];
+static LAPIC_BASE: AtomicU64 = AtomicU64::new(0);
+
+// APs share the GDT and IDT set up for the BSP, so that interrupt gates refer to valid selectors
+#[derive(Clone, Copy)]
+struct BspTables {
+    gdt: DescriptorTablePointer,
+    idt: DescriptorTablePointer,
+    code_selector: SegmentSelector,
+    data_selector: SegmentSelector,
+}
+
+lazy_static! {
+    static ref BSP_TABLES: Mutex<Option<BspTables>> = Mutex::new(None);
+}
+
+pub fn init(madt: Option<&Madt>, memory_map: &MemoryMap, clock: &SystemClock) {
+    let Some(madt) = madt else {
+        log::warn!("No MADT, running on the bootstrap processor only");
+        return;
+    };
+
+    let Some(trampoline_addr) = find_trampoline_page(memory_map) else {
+        log::warn!(
+            "No free page below 1MB for the AP trampoline, running on the bootstrap processor only"
+        );
+        return;
+    };
+
+    let (l4_frame, _) = Cr3::read();
+    let cr3 = l4_frame.start_address().as_u64();
+    if cr3 > u32::MAX as u64 {
+        log::warn!(
+            "Page tables above 4GB ({:#x}), running on the bootstrap processor only",
+            cr3
+        );
+        return;
+    }
+
+    // PCID can only be enabled in long mode, and CET requires CR0.WP, which is set afterwards.
+    // The kernel does not use either of them.
+    let cr4 = Cr4::read_raw() & !(Cr4Flags::PCID | Cr4Flags::CONTROL_FLOW_ENFORCEMENT).bits();
+
+    let lapic = LocalApic {
+        base: madt.local_apic_addr,
+    };
+    let bsp_apic_id = lapic.id();
+
+    LAPIC_BASE.store(madt.local_apic_addr, Ordering::SeqCst);
+    CORE_APIC_IDS[0].store(bsp_apic_id, Ordering::SeqCst);
+
+    *BSP_TABLES.lock() = Some(BspTables {
+        gdt: sgdt(),
+        idt: sidt(),
+        code_selector: CS::get_reg(),
+        data_selector: SS::get_reg(),
+    });
+
+    log::debug!("Installing AP trampoline at {:#x}", trampoline_addr);
+    let trampoline = Trampoline::install(trampoline_addr);
+
+    let mut n_cores = 1;
+
+    for processor in madt.processors.iter() {
+        if !processor.enabled || processor.apic_id == bsp_apic_id {
+            continue;
+        }
+
+        if n_cores >= MAX_CORES {
+            log::warn!("More than {} processors, ignoring the rest", MAX_CORES);
+            break;
+        }
+
+        if processor.apic_id > 0xff {
+            log::warn!(
+                "Cannot start processor with APIC ID {} (x2APIC only)",
+                processor.apic_id
+            );
+            continue;
+        }
+
+        // Stacks are never freed, even if the AP fails to start (it might still do so later)
+        let stack = vec![0u8; AP_STACK_SIZE].leak();
+        let stack_top = (stack.as_ptr() as u64 + AP_STACK_SIZE as u64) & !0xf;
+
+        trampoline.set_data(TrampolineData {
+            cr3,
+            efer: Efer::read_raw() & !(1 << 10), // LMA is set by the CPU
+            stack_top,
+            entry: ap_main as *const () as u64,
+            core_index: n_cores as u64,
+            cr4,
+        });
+
+        AP_STARTED.store(false, Ordering::SeqCst);
+        CORE_APIC_IDS[n_cores].store(processor.apic_id, Ordering::SeqCst);
+
+        lapic.send_ipi(processor.apic_id, ICR_INIT);
+        clock.spin_delay(INIT_DELAY);
+
+        // The second SIPI is only needed if the first one was missed
+        for _ in 0..2 {
+            lapic.send_ipi(processor.apic_id, ICR_STARTUP | trampoline.sipi_vector());
+            if wait_for_ap(clock, SIPI_TIMEOUT) {
+                break;
+            }
+        }
+
+        if !wait_for_ap(clock, AP_START_TIMEOUT) {
+            log::warn!("Processor with APIC ID {} did not start", processor.apic_id);
+            continue;
+        }
+
+        log::debug!("Started core {} (APIC ID {})", n_cores, processor.apic_id);
+        n_cores += 1;
+    }
+
+    N_CORES.store(n_cores, Ordering::SeqCst);
+
+    log::info!("{} cores online", n_cores);
+}
+
+pub fn core_count() -> usize {
+    N_CORES.load(Ordering::Relaxed)
+}
+
+// Index of the core this is running on (0 being the bootstrap processor)
+pub fn current_core() -> usize {
+    let base = LAPIC_BASE.load(Ordering::Relaxed);
+    if base == 0 {
+        return 0;
+    }
+
+    let apic_id = LocalApic { base }.id();
+    CORE_APIC_IDS
+        .iter()
+        .position(|id| id.load(Ordering::Relaxed) == apic_id)
+        .unwrap_or(0)
+}
+
+fn wait_for_ap(clock: &SystemClock, timeout: f64) -> bool {
+    let t0 = clock.time();
+    while clock.time() - t0 < timeout {
+        if AP_STARTED.load(Ordering::SeqCst) {
+            return true;
+        }
+        core::hint::spin_loop();
+    }
+    AP_STARTED.load(Ordering::SeqCst)
+}
+
+fn find_trampoline_page(memory_map: &MemoryMap) -> Option<u64> {
+    memory_map
+        .entries()
+        .filter(|desc| {
+            matches!(
+                desc.ty,
+                MemoryType::CONVENTIONAL
+                    | MemoryType::BOOT_SERVICES_CODE
+                    | MemoryType::BOOT_SERVICES_DATA
+            )
+        })
+        .find_map(|desc| {
+            // Skipping page 0, which holds the real mode IVT
+            let start = u64::max(desc.phys_start, PAGE_SIZE);
+            let end = desc.phys_start + desc.page_count * PAGE_SIZE;
+            match start + PAGE_SIZE <= u64::min(end, TRAMPOLINE_MAX_ADDR) {
+                true => Some(start),
+                false => None,
+            }
+        })
+}
+
+extern "sysv64" fn ap_main(core_index: u64) -> ! {
+    let tables = BSP_TABLES.lock().expect("BSP tables not saved");
+    unsafe {
+        lgdt(&tables.gdt);
+        CS::set_reg(tables.code_selector);
+        SS::set_reg(tables.data_selector);
+        DS::set_reg(tables.data_selector);
+        ES::set_reg(tables.data_selector);
+        lidt(&tables.idt);
+    }
+
+    // From here on the trampoline data can be reused for the next AP
+    AP_STARTED.store(true, Ordering::SeqCst);
+
+    let core_index = core_index as usize;
+
+    loop {
+        if !JOB_PENDING.load(Ordering::Acquire) {
+            core::hint::spin_loop();
+            continue;
+        }
+
+        match take_job() {
+            Some((job, index)) => run_job(core_index, job, index),
+            None => core::hint::spin_loop(),
+        }
+    }
+}
+
+//
+// Work queue
+
+#[derive(Clone, Copy)]
+struct JobRef(*const (dyn Fn(usize) + Sync));
+
+// Safety: the closure is Sync, and for_each_parallel() waits for all jobs to complete
+// before the closure goes out of scope
+unsafe impl Send for JobRef {}
+
+struct WorkQueue {
+    job: Option<JobRef>,
+    len: usize,
+    next: usize,
+    done: usize,
+}
+
+static JOB_PENDING: AtomicBool = AtomicBool::new(false);
+
+// Time spent running jobs on each core, in TSC ticks
+static CORE_BUSY_TICKS: [AtomicU64; MAX_CORES] = [const { AtomicU64::new(0) }; MAX_CORES];
+
+lazy_static! {
+    static ref WORK_QUEUE: Mutex<WorkQueue> = Mutex::new(WorkQueue {
+        job: None,
+        len: 0,
+        next: 0,
+        done: 0,
+    });
+}
+
+struct SendPtr<T>(*mut T);
+
+unsafe impl<T: Send> Send for SendPtr<T> {}
+unsafe impl<T: Send> Sync for SendPtr<T> {}
+
+impl<T> SendPtr<T> {
+    // Accessing the pointer through a method, so that closures capture the whole wrapper
+    fn get(&self) -> *mut T {
+        self.0
+    }
+}
+
+// Calls func on every item, spreading the work across all cores (the calling core included).
+// Returns once all items have been processed.
+pub fn for_each_parallel<T, F>(items: &mut [T], func: F)
+where
+    T: Send,
+    F: Fn(&mut T) + Sync,
+{
+    let len = items.len();
+
+    if core_count() == 1 || len <= 1 {
+        items.iter_mut().for_each(func);
+        return;
+    }
+
+    let items_ptr = SendPtr(items.as_mut_ptr());
+    let run = |index: usize| {
+        // Safety: each index is handed out exactly once by the work queue
+        let item = unsafe { &mut *items_ptr.get().add(index) };
+        func(item)
+    };
+
+    let run_ref: &(dyn Fn(usize) + Sync) = &run;
+    let job = JobRef(unsafe {
+        core::mem::transmute::<
+            *const (dyn Fn(usize) + Sync + '_),
+            *const (dyn Fn(usize) + Sync + 'static),
+        >(run_ref)
+    });
+
+    *WORK_QUEUE.lock() = WorkQueue {
+        job: Some(job),
+        len,
+        next: 0,
+        done: 0,
+    };
+    JOB_PENDING.store(true, Ordering::Release);
+
+    while let Some((job, index)) = take_job() {
+        run_job(0, job, index);
+    }
+
+    while WORK_QUEUE.lock().done < len {
+        core::hint::spin_loop();
+    }
+
+    JOB_PENDING.store(false, Ordering::Release);
+    WORK_QUEUE.lock().job = None;
+}
+
+fn take_job() -> Option<(JobRef, usize)> {
+    let mut queue = WORK_QUEUE.lock();
+    let job = queue.job?;
+    if queue.next >= queue.len {
+        return None;
+    }
+    let index = queue.next;
+    queue.next += 1;
+    Some((job, index))
+}
+
+fn run_job(core_index: usize, job: JobRef, index: usize) {
+    let t0 = unsafe { core::arch::x86_64::_rdtsc() };
+    unsafe { (*job.0)(index) };
+    let t1 = unsafe { core::arch::x86_64::_rdtsc() };
+
+    CORE_BUSY_TICKS[core_index].fetch_add(t1 - t0, Ordering::Relaxed);
+    WORK_QUEUE.lock().done += 1;
+}
+
+// Time spent running jobs on each core since the last call, in milliseconds
+pub fn take_busy_times(clock: &SystemClock) -> [f64; MAX_CORES] {
+    core::array::from_fn(|i| {
+        let ticks = CORE_BUSY_TICKS[i].swap(0, Ordering::Relaxed);
+        clock.tsc_to_ms(ticks)
+    })
+}
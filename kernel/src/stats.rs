@@ -1,14 +1,20 @@
 use crate::allocator::AllocStats;
+use crate::smp::MAX_CORES;
 use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
 
 const HISTORY_SIZE: usize = 256; // In number of frames
 
 pub struct SystemStats {
     pub heap_total: usize,
 
-    by_app: BTreeMap<&'static str, [AppDataPoint; HISTORY_SIZE]>,
+    // By app instance name
+    by_app: BTreeMap<String, [AppDataPoint; HISTORY_SIZE]>,
     system: [SystemDataPoint; HISTORY_SIZE],
 
+    // Since boot
+    crash_counts: BTreeMap<String, u32>,
+
     ring_index: usize,
 }
 
@@ -18,6 +24,8 @@ pub struct SystemDataPoint {
     pub net_sent: usize,
     pub alloc: AllocStats,
     pub frametime_used: f64,
+    // Time each core spent busy during the frame, in milliseconds
+    pub core_busy: [f64; MAX_CORES],
 }
 
 #[derive(Debug, Clone)]
@@ -29,36 +37,46 @@ pub struct AppDataPoint {
 }
 
 impl SystemStats {
-    pub fn new(alloc_stats: &AllocStats, app_names: &[&'static str]) -> Self {
-        let by_app = app_names
-            .iter()
-            .map(|app_name| {
-                let app_history: [AppDataPoint; HISTORY_SIZE] =
-                    core::array::from_fn(|_| AppDataPoint {
-                        net_recv: 0,
-                        net_sent: 0,
-                        mem_used: 0,
-                        frametime_used: 0.0,
-                    });
-
-                (*app_name, app_history)
-            })
-            .collect();
-
+    pub fn new(alloc_stats: &AllocStats, app_names: &[&str]) -> Self {
         let system_history: [SystemDataPoint; HISTORY_SIZE] =
             core::array::from_fn(|_| SystemDataPoint {
                 net_recv: 0,
                 net_sent: 0,
                 alloc: alloc_stats.clone(),
                 frametime_used: 0.0,
+                core_busy: [0.0; MAX_CORES],
             });
 
-        SystemStats {
+        let mut stats = SystemStats {
             heap_total: alloc_stats.total,
-            by_app,
+            by_app: BTreeMap::new(),
             system: system_history,
+            crash_counts: BTreeMap::new(),
             ring_index: 0,
+        };
+
+        for app_name in app_names {
+            stats.add_app(app_name);
         }
+
+        stats
+    }
+
+    pub fn add_app(&mut self, app_name: &str) {
+        let app_history: [AppDataPoint; HISTORY_SIZE] = core::array::from_fn(|_| AppDataPoint {
+            net_recv: 0,
+            net_sent: 0,
+            mem_used: 0,
+            frametime_used: 0.0,
+        });
+
+        self.by_app.insert(app_name.to_string(), app_history);
+        self.crash_counts.insert(app_name.to_string(), 0);
+    }
+
+    pub fn remove_app(&mut self, app_name: &str) {
+        self.by_app.remove(app_name);
+        self.crash_counts.remove(app_name);
     }
 
     pub fn next_frame(&mut self) {
@@ -74,6 +92,19 @@ impl SystemStats {
         app_history.get_mut(self.ring_index).unwrap()
     }
 
+    // For apps which were not stepped during this frame (see THROTTLED_STEP_RATE)
+    pub fn record_idle_frame(&mut self, app_name: &str) {
+        let prev_index = (self.ring_index + HISTORY_SIZE - 1) % HISTORY_SIZE;
+        let app_history = self.by_app.get_mut(app_name).expect("Unknown app");
+
+        app_history[self.ring_index] = AppDataPoint {
+            net_recv: 0,
+            net_sent: 0,
+            mem_used: app_history[prev_index].mem_used,
+            frametime_used: 0.0,
+        };
+    }
+
     pub fn get_system_history<T, F>(&self, selector: F) -> [T; HISTORY_SIZE]
     where
         F: Fn(&SystemDataPoint) -> T,
@@ -84,6 +115,18 @@ impl SystemStats {
         })
     }
 
+    pub fn record_crash(&mut self, app_name: &str) {
+        *self.crash_counts.get_mut(app_name).expect("Unknown app") += 1;
+    }
+
+    pub fn crash_count(&self, app_name: &str) -> u32 {
+        *self.crash_counts.get(app_name).expect("Unknown app")
+    }
+
+    pub fn app_names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.by_app.keys().map(|app_name| app_name.as_str())
+    }
+
     pub fn get_app_history<T, F>(&self, app_name: &str, selector: F) -> [T; HISTORY_SIZE]
     where
         F: Fn(&AppDataPoint) -> T,
@@ -1,12 +1,25 @@
+use alloc::string::String;
+
+use crate::acpi::Acpi;
+use crate::capture::ScreenCapture;
+use crate::pci::PciBus;
+use crate::random::SystemRng;
 use crate::stats::SystemStats;
 use crate::{network::TcpStack, time::SystemClock};
+use applib::input::KeyboardLayout;
 use applib::StyleSheet;
-use rand::rngs::SmallRng;
 
 pub struct System {
     pub clock: SystemClock,
     pub tcp_stack: TcpStack,
-    pub rng: SmallRng,
+    pub rng: SystemRng,
+    pub acpi: Acpi,
+    pub pci_bus: PciBus,
     pub stylesheet: &'static StyleSheet,
     pub stats: SystemStats,
+    pub capture: ScreenCapture,
+    // Applied to the input state at the start of each frame, can be changed from the console
+    pub keyboard_layout: KeyboardLayout,
+    // Only written by the kernel for now (e.g. app crash reports), read from the console
+    pub clipboard: String,
 }
@@ -5,6 +5,8 @@ use chrono::{DateTime, NaiveDate, TimeZone, Utc};
 pub struct SystemClock {
     period_s: f64,
     epoch_offset: f64,
+    // Added to the monotonic clock to get the time since the UNIX epoch
+    realtime_offset_ns: u64,
 }
 
 impl SystemClock {
@@ -15,9 +17,13 @@ impl SystemClock {
         let n = unsafe { core::arch::x86_64::_rdtsc() };
         let epoch_offset: f64 = secs_since_epoch - (n as f64 * period_s);
 
+        let monotonic_ns = (n as f64 * period_s * 1e9) as u64;
+        let realtime_offset_ns = (secs_since_epoch as u64 * 1_000_000_000) - monotonic_ns;
+
         SystemClock {
             period_s,
             epoch_offset,
+            realtime_offset_ns,
         }
     }
 
@@ -27,6 +33,25 @@ impl SystemClock {
         1000f64 * (n as f64) * self.period_s + self.epoch_offset
     }
 
+    // Nanoseconds since the CPU was reset
+    pub fn monotonic_ns(&self) -> u64 {
+        let n = unsafe { core::arch::x86_64::_rdtsc() };
+        (n as f64 * self.period_s * 1e9) as u64
+    }
+
+    // Nanoseconds since the UNIX epoch (only as accurate as the RTC at boot)
+    pub fn realtime_ns(&self) -> u64 {
+        self.monotonic_ns() + self.realtime_offset_ns
+    }
+
+    pub fn resolution_ns(&self) -> u64 {
+        u64::max(1, (self.period_s * 1e9) as u64)
+    }
+
+    pub fn tsc_to_ms(&self, ticks: u64) -> f64 {
+        1000f64 * (ticks as f64) * self.period_s
+    }
+
     pub fn spin_delay(&self, duration: f64) {
         let t0 = self.time();
         while self.time() - t0 < duration {}
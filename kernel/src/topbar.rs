@@ -11,6 +11,7 @@ use applib::{FbView, Framebuffer, OwnedPixels};
 use chrono::{DateTime, Datelike, Month, Timelike, Utc};
 
 use crate::resources;
+use crate::smp;
 use crate::stats::SystemStats;
 use crate::TOPBAR_H;
 
@@ -255,6 +256,49 @@ pub fn topbar<'a, F: FbViewMut>(
         },
     );
 
+    //
+    // Per-core usage
+
+    const CORE_BAR_W: u32 = 20;
+    const CORE_BAR_MARGIN_W: u32 = 4;
+
+    for core in 0..smp::core_count() {
+        let busy_data = system_stats.get_system_history(|dp| dp.core_busy[core] as f32);
+        let agg_busy = busy_data
+            .iter()
+            .take(FRAMETIME_WINDOW_LEN)
+            .fold(0.0, |acc, v| acc + v / FRAMETIME_WINDOW_LEN as f32);
+        let usage = f32::min(agg_busy / target_frametime, 1.0);
+
+        let bar_rect = Rect {
+            x0: x,
+            y0: 0,
+            w: CORE_BAR_W,
+            h: RESOURCES_BAR_H,
+        }
+        .align_to_rect_vert(&topbar_rect);
+
+        uitk_context.tooltip(
+            &bar_rect,
+            (0, tooltip_dy),
+            &format!("Core {}: {:.0}%", core, 100.0 * usage),
+        );
+        uitk_context.horiz_bar(
+            &HorizBarConfig {
+                max_val: 1.0,
+                rect: bar_rect,
+            },
+            &[BarValue {
+                color: Color::GREEN,
+                val: usage,
+            }],
+        );
+
+        x += (CORE_BAR_W + CORE_BAR_MARGIN_W) as i64;
+    }
+
+    x += (SEP_MARGIN_W - CORE_BAR_MARGIN_W) as i64;
+
     //
     // OS version
 
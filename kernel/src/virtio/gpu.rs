@@ -1,7 +1,7 @@
-use alloc::{boxed::Box, vec, vec::Vec};
+use alloc::{boxed::Box, vec};
 
 use crate::memory;
-use crate::pci::PciDevice;
+use crate::pci::PciBus;
 use core::mem::MaybeUninit;
 
 use super::{QueueMessage, VirtioDevice, VirtioQueue, VirtqSerializable};
@@ -41,14 +41,13 @@ impl Default for GpuVirtioMsg {
 impl VirtqSerializable for GpuVirtioMsg {}
 
 impl VirtioGPU {
-    pub fn new(pci_devices: &mut Vec<PciDevice>) -> Self {
-        let i = (0..pci_devices.len())
-            .find(|&i| {
-                pci_devices[i].vendor_id == 0x1af4 && pci_devices[i].device_id == 0x1040 + 16
+    pub fn new(pci_bus: &mut PciBus) -> Self {
+        let pci_dev = pci_bus
+            .claim("virtio-gpu", |dev| {
+                dev.vendor_id == 0x1af4 && dev.device_id == 0x1040 + 16
             })
             .expect("Cannot find VirtIO GPU device");
 
-        let pci_dev = pci_devices.swap_remove(i);
         let mut virtio_dev = VirtioDevice::new(pci_dev, 0x0);
 
         let controlq = virtio_dev.initialize_queue(0); // queue 0 (controlq)
@@ -1,6 +1,7 @@
 use super::{QueueMessage, VirtioDevice, VirtioQueue, VirtqSerializable};
-use crate::pci::PciDevice;
+use crate::pci::PciBus;
 use alloc::vec::Vec;
+use applib::input::keymap::EventType;
 
 const Q_SIZE: usize = 64;
 const BUF_SIZE: usize = core::mem::size_of::<VirtioInputEvent>();
@@ -8,27 +9,40 @@ const BUF_SIZE: usize = core::mem::size_of::<VirtioInputEvent>();
 pub struct VirtioInput {
     pub virtio_dev: VirtioDevice,
     eventq: VirtioQueue<Q_SIZE, BUF_SIZE>,
+    statusq: VirtioQueue<Q_SIZE, BUF_SIZE>,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(u16)]
+#[allow(non_camel_case_types)]
+pub enum LedCode {
+    LED_NUML = 0x0,
+    LED_CAPSL = 0x1,
 }
 
 impl VirtioInput {
-    pub fn new(pci_devices: &mut Vec<PciDevice>) -> Self {
-        let i = (0..pci_devices.len())
-            .find(|&i| {
-                pci_devices[i].vendor_id == 0x1af4 && pci_devices[i].device_id == 0x1040 + 18
+    pub fn new(pci_bus: &mut PciBus) -> Self {
+        let pci_dev = pci_bus
+            .claim("virtio-input", |dev| {
+                dev.vendor_id == 0x1af4 && dev.device_id == 0x1040 + 18
             })
             .expect("Cannot find VirtIO input device");
 
-        let pci_dev = pci_devices.swap_remove(i);
         let mut virtio_dev = VirtioDevice::new(pci_dev, 0x0);
 
         let mut eventq = virtio_dev.initialize_queue(0); // queue 0 (eventq)
                                                          //log::debug!("out of initialize_queue(): {:?}", eventq.descriptor_area.as_ptr());
+        let statusq = virtio_dev.initialize_queue(1); // queue 1 (statusq)
         virtio_dev.write_status(0x04); // DRIVER_OK
 
         let msg = [QueueMessage::<VirtioInputEvent>::DevWriteOnly];
         unsafe { while eventq.try_push(&msg).is_some() {} };
 
-        VirtioInput { virtio_dev, eventq }
+        VirtioInput {
+            virtio_dev,
+            eventq,
+            statusq,
+        }
     }
 
     pub fn poll(&mut self) -> Vec<VirtioInputEvent> {
@@ -46,8 +60,38 @@ impl VirtioInput {
             }
         }
 
+        // Reclaiming descriptors of status messages consumed by the device
+        while unsafe { self.statusq.try_pop::<VirtioInputEvent, 1>() }.is_some() {}
+
         out
     }
+
+    // Devices which do not have the LED ignore those messages (e.g the mouse)
+    pub fn set_led(&mut self, led: LedCode, on: bool) {
+        let led_event = VirtioInputEvent {
+            _type: EventType::EV_LED as u16,
+            code: led as u16,
+            value: on.into(),
+        };
+        let syn_event = VirtioInputEvent {
+            _type: EventType::EV_SYN as u16,
+            code: 0,
+            value: 0,
+        };
+
+        for event in [led_event, syn_event] {
+            let msg = [QueueMessage::DevReadOnly {
+                data: event,
+                len: None,
+            }];
+            if unsafe { self.statusq.try_push(&msg) }.is_none() {
+                log::warn!("VirtIO input status queue full, dropping LED update");
+                return;
+            }
+        }
+
+        unsafe { self.statusq.notify_device() };
+    }
 }
 
 #[repr(C)]
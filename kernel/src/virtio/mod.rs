@@ -16,6 +16,7 @@ const VIRTIO_PCI_VENDOR: u8 = 0x09;
 pub mod gpu;
 pub mod input;
 pub mod network;
+pub mod rng;
 
 #[repr(u32)]
 #[allow(non_camel_case_types)]
@@ -248,6 +249,13 @@ impl<const Q_SIZE: usize, const BUF_SIZE: usize> VirtioQueue<Q_SIZE, BUF_SIZE> {
     }
 
     pub unsafe fn try_pop<T: VirtqSerializable, const N: usize>(&mut self) -> Option<[T; N]> {
+        self.try_pop_with_len().map(|(out, _)| out)
+    }
+
+    // Also returns the number of bytes the device wrote into the buffers
+    pub unsafe fn try_pop_with_len<T: VirtqSerializable, const N: usize>(
+        &mut self,
+    ) -> Option<([T; N], usize)> {
         let mapper = memory::get_mapper();
 
         let new_index = read_volatile(&self.storage.device_area.idx) as usize;
@@ -288,7 +296,7 @@ impl<const Q_SIZE: usize, const BUF_SIZE: usize> VirtioQueue<Q_SIZE, BUF_SIZE> {
 
         self.pop_index += 1;
 
-        Some(out.into_inner())
+        Some((out.into_inner(), it.len as usize))
     }
 }
 
@@ -334,7 +342,7 @@ impl VirtioDevice {
                 .filter_map(|pci_cap| {
                     let virtio_cap = unsafe {
                         pci_config_space
-                            .read_struct::<VirtioPciCap>(&pci_device.addr, pci_cap.offset)
+                            .read_struct::<VirtioPciCap>(&pci_device.addr, pci_cap.offset.into())
                     };
 
                     if virtio_cap.cfg_type != cfg_type {
@@ -473,7 +481,7 @@ impl VirtioDevice {
         };
 
         let notify_off_multiplier: u64 = unsafe {
-            let offset = self.notification_cap.config_space_offset + 4;
+            let offset = self.notification_cap.config_space_offset as u16 + 4;
             pci_config_space.read(&self.pci_device.addr, offset)
         }
         .into();
@@ -1,8 +1,7 @@
 use core::mem::MaybeUninit;
 
 use super::{QueueMessage, VirtioDevice, VirtioQueue, VirtqSerializable};
-use crate::pci::PciDevice;
-use alloc::vec::Vec;
+use crate::pci::PciBus;
 use tinyvec::ArrayVec;
 
 const Q_SIZE: usize = 256;
@@ -36,12 +35,13 @@ struct VirtioNetConfig {
 }
 
 impl VirtioNetwork {
-    pub fn new(pci_devices: &mut Vec<PciDevice>) -> Self {
-        let i = (0..pci_devices.len())
-            .find(|&i| pci_devices[i].vendor_id == 0x1af4 && pci_devices[i].device_id == 0x1000)
+    pub fn new(pci_bus: &mut PciBus) -> Self {
+        let pci_dev = pci_bus
+            .claim("virtio-net", |dev| {
+                dev.vendor_id == 0x1af4 && dev.device_id == 0x1000
+            })
             .expect("Cannot find VirtIO network device");
 
-        let pci_dev = pci_devices.swap_remove(i);
         let feature_bits = NetworkFeatureBits::VIRTIO_NET_F_MAC as u32;
         let mut virtio_dev = VirtioDevice::new(pci_dev, feature_bits);
 
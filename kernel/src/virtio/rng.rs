@@ -0,0 +1,90 @@
+use super::{QueueMessage, VirtioDevice, VirtioQueue, VirtqSerializable};
+use crate::pci::PciBus;
+use crate::time::SystemClock;
+
+const Q_SIZE: usize = 8;
+pub const ENTROPY_BUF_SIZE: usize = 32;
+
+const BUF_SIZE: usize = core::mem::size_of::<EntropyBuffer>();
+
+// Max time to wait for the device to fill a buffer, in milliseconds
+const READ_TIMEOUT: f64 = 100.0;
+
+// The VirtioDevice is only needed for the setup: the request queue is all that is used afterwards
+pub struct VirtioRng {
+    requestq: VirtioQueue<Q_SIZE, BUF_SIZE>,
+    // Set while a request is pending, including one which timed out: its completion has to be
+    // discarded before the next request, so that it is not taken for the response to that one
+    in_flight: bool,
+}
+
+impl VirtioRng {
+    // The entropy device is optional, the kernel falls back to other sources without it
+    pub fn new(pci_bus: &mut PciBus) -> Option<Self> {
+        let pci_dev = pci_bus.claim("virtio-rng", |dev| {
+            dev.vendor_id == 0x1af4 && (dev.device_id == 0x1005 || dev.device_id == 0x1040 + 4)
+        })?;
+
+        let mut virtio_dev = VirtioDevice::new(pci_dev, 0x0);
+
+        let requestq = virtio_dev.initialize_queue(0); // queue 0 (requestq)
+        virtio_dev.write_status(0x04); // DRIVER_OK
+
+        Some(VirtioRng {
+            requestq,
+            in_flight: false,
+        })
+    }
+
+    // None if the device did not fill the whole buffer in time
+    pub fn read_entropy(&mut self, clock: &SystemClock) -> Option<[u8; ENTROPY_BUF_SIZE]> {
+        if self.in_flight {
+            self.wait_for_completion(clock)?;
+            self.in_flight = false;
+        }
+
+        let msg = [QueueMessage::<EntropyBuffer>::DevWriteOnly];
+
+        unsafe {
+            self.requestq.try_push(&msg)?;
+            self.requestq.notify_device();
+        }
+        self.in_flight = true;
+
+        let (buf, used_len) = self.wait_for_completion(clock)?;
+        self.in_flight = false;
+
+        if used_len < ENTROPY_BUF_SIZE {
+            log::warn!(
+                "VirtIO entropy device only provided {} of {} bytes",
+                used_len,
+                ENTROPY_BUF_SIZE
+            );
+            return None;
+        }
+
+        Some(buf.data)
+    }
+
+    fn wait_for_completion(&mut self, clock: &SystemClock) -> Option<(EntropyBuffer, usize)> {
+        let t0 = clock.time();
+        loop {
+            if let Some(([buf], used_len)) = unsafe { self.requestq.try_pop_with_len() } {
+                return Some((buf, used_len));
+            }
+
+            if clock.time() - t0 > READ_TIMEOUT {
+                log::warn!("Timed out waiting for VirtIO entropy device");
+                return None;
+            }
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Default)]
+struct EntropyBuffer {
+    data: [u8; ENTROPY_BUF_SIZE],
+}
+
+impl VirtqSerializable for EntropyBuffer {}
@@ -0,0 +1,302 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use anyhow::format_err;
+use core::fmt::Write;
+use wasmi::{Instance, Store};
+
+use super::binary::{
+    append_to_vec_section, count_imports, find_section, read_func_param_counts, read_sections,
+    read_u32_vec, section_insert_index, write_module, write_name, write_sleb, write_uleb, Reader,
+    SECTION_CODE, SECTION_EXPORT, SECTION_FUNCTION, SECTION_GLOBAL, SECTION_IMPORT, SECTION_TYPE,
+};
+use super::symbols::GuestSymbols;
+
+// Number of innermost frames kept
+const SHADOW_STACK_LEN: u32 = 32;
+
+const DEPTH_EXPORT: &str = "__munal_shadow_depth";
+const SLOT_EXPORT_PREFIX: &str = "__munal_shadow_";
+
+//
+// Guest backtraces
+//
+// wasmi does not expose the guest call stack when a trap happens, so apps are instrumented
+// when loaded to maintain their own "shadow" call stack, which the kernel reads after a crash.
+// Each function body is moved to a new function, and replaced with a wrapper which pushes the
+// function index to the shadow stack, calls the original body, and pops it. Since the wrapper
+// keeps the index of the original function, calls, tables and exports all go through it
+// without having to be rewritten. A trap skips the pops, leaving the stack as it was at the
+// time of the trap.
+// The shadow stack is a ring buffer of mutable globals (exported so that the kernel can read
+// them), indexed by a depth counter.
+
+pub fn instrument(module: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let sections = read_sections(module)?;
+    let section_data = |id: u8| find_section(&sections, id).map(|section| section.data);
+
+    let param_counts = match section_data(SECTION_TYPE) {
+        Some(data) => read_func_param_counts(data)?,
+        None => Vec::new(),
+    };
+    let (n_imported_funcs, n_imported_globals) = match section_data(SECTION_IMPORT) {
+        Some(data) => count_imports(data)?,
+        None => (0, 0),
+    };
+    let func_types = match section_data(SECTION_FUNCTION) {
+        Some(data) => read_u32_vec(data)?,
+        None => Vec::new(),
+    };
+    let n_defined_globals = match section_data(SECTION_GLOBAL) {
+        Some(data) => Reader::new(data).uleb32()?,
+        None => 0,
+    };
+
+    let code = section_data(SECTION_CODE).unwrap_or(&[]);
+    let mut code_reader = Reader::new(code);
+    let n_bodies = match code.is_empty() {
+        true => 0,
+        false => code_reader.uleb32()?,
+    };
+    if n_bodies as usize != func_types.len() {
+        return Err(format_err!(
+            "Function and code sections do not match ({} vs {})",
+            func_types.len(),
+            n_bodies
+        ));
+    }
+
+    let n_funcs = func_types.len() as u32;
+
+    // Function indices
+    let first_func = n_imported_funcs;
+    let first_body = first_func + n_funcs;
+    let enter_func = first_body + n_funcs;
+    let leave_func = enter_func + 1;
+
+    // Type indices
+    let enter_type = param_counts.len() as u32;
+    let leave_type = enter_type + 1;
+
+    // Global indices (the shadow stack slots follow the depth)
+    let depth_global = n_imported_globals + n_defined_globals;
+    let first_slot_global = depth_global + 1;
+
+    //
+    // Types: (i32) -> () and () -> ()
+
+    let type_section = append_to_vec_section(
+        section_data(SECTION_TYPE),
+        2,
+        &[0x60, 1, 0x7f, 0, 0x60, 0, 0],
+    )?;
+
+    //
+    // Functions: wrappers (in place of the original functions), original bodies, enter and leave
+
+    let mut new_funcs = Vec::new();
+    for &type_index in func_types.iter() {
+        write_uleb(&mut new_funcs, type_index as u64);
+    }
+    write_uleb(&mut new_funcs, enter_type as u64);
+    write_uleb(&mut new_funcs, leave_type as u64);
+    let function_section =
+        append_to_vec_section(section_data(SECTION_FUNCTION), n_funcs + 2, &new_funcs)?;
+
+    let mut code_section = Vec::with_capacity(code.len() + 32 * n_funcs as usize);
+    write_uleb(&mut code_section, (2 * n_funcs + 2) as u64);
+
+    for (i, &type_index) in func_types.iter().enumerate() {
+        let n_params = *param_counts
+            .get(type_index as usize)
+            .ok_or_else(|| format_err!("Invalid type index {}", type_index))?;
+
+        let func_index = first_func + i as u32;
+
+        let mut body = vec![0x00];
+        body.push(0x41); // i32.const
+        write_sleb(&mut body, func_index as i64);
+        body.push(0x10); // call
+        write_uleb(&mut body, enter_func as u64);
+        for param in 0..n_params {
+            body.push(0x20); // local.get
+            write_uleb(&mut body, param as u64);
+        }
+        body.push(0x10);
+        write_uleb(&mut body, (first_body + i as u32) as u64);
+        body.push(0x10);
+        write_uleb(&mut body, leave_func as u64);
+        body.push(0x0b); // end
+
+        write_uleb(&mut code_section, body.len() as u64);
+        code_section.extend_from_slice(&body);
+    }
+
+    // Original bodies are kept as-is (they already include their size)
+    code_section.extend_from_slice(code_reader.remaining());
+
+    let enter_body = enter_body(depth_global, first_slot_global);
+    write_uleb(&mut code_section, enter_body.len() as u64);
+    code_section.extend_from_slice(&enter_body);
+
+    let leave_body = leave_body(depth_global);
+    write_uleb(&mut code_section, leave_body.len() as u64);
+    code_section.extend_from_slice(&leave_body);
+
+    //
+    // Globals and their exports
+
+    let mut new_globals = Vec::new();
+    let mut new_exports = Vec::new();
+    for i in 0..SHADOW_STACK_LEN + 1 {
+        // Mutable i32, initialized to 0
+        new_globals.extend_from_slice(&[0x7f, 0x01, 0x41, 0x00, 0x0b]);
+
+        let name = match i {
+            0 => String::from(DEPTH_EXPORT),
+            _ => format!("{}{}", SLOT_EXPORT_PREFIX, i - 1),
+        };
+        write_name(&mut new_exports, &name);
+        new_exports.push(0x03);
+        write_uleb(&mut new_exports, (depth_global + i) as u64);
+    }
+    let global_section = append_to_vec_section(
+        section_data(SECTION_GLOBAL),
+        SHADOW_STACK_LEN + 1,
+        &new_globals,
+    )?;
+    let export_section = append_to_vec_section(
+        section_data(SECTION_EXPORT),
+        SHADOW_STACK_LEN + 1,
+        &new_exports,
+    )?;
+
+    //
+    // Putting the module back together
+
+    let mut new_sections: Vec<(u8, &[u8])> = sections
+        .iter()
+        .map(|section| (section.id, section.data))
+        .collect();
+
+    for (id, data) in [
+        (SECTION_TYPE, type_section.as_slice()),
+        (SECTION_FUNCTION, function_section.as_slice()),
+        (SECTION_GLOBAL, global_section.as_slice()),
+        (SECTION_EXPORT, export_section.as_slice()),
+        (SECTION_CODE, code_section.as_slice()),
+    ] {
+        match new_sections
+            .iter_mut()
+            .find(|(other_id, _)| *other_id == id)
+        {
+            Some(section) => section.1 = data,
+            None => {
+                let index = section_insert_index(&new_sections, id);
+                new_sections.insert(index, (id, data));
+            }
+        }
+    }
+
+    Ok(write_module(&new_sections))
+}
+
+// Stores the function index (parameter 0) at depth % SHADOW_STACK_LEN, then increments the depth.
+// Globals cannot be indexed dynamically, hence the br_table over one block per slot.
+fn enter_body(depth_global: u32, first_slot_global: u32) -> Vec<u8> {
+    let mut body = vec![0x00];
+
+    // Outer block, exited once the slot is written
+    body.extend_from_slice(&[0x02, 0x40]);
+    for _ in 0..SHADOW_STACK_LEN {
+        body.extend_from_slice(&[0x02, 0x40]);
+    }
+
+    body.push(0x23); // global.get
+    write_uleb(&mut body, depth_global as u64);
+    body.extend_from_slice(&[0x41, (SHADOW_STACK_LEN - 1) as u8, 0x71]); // i32.const, i32.and
+    body.push(0x0e); // br_table
+    write_uleb(&mut body, SHADOW_STACK_LEN as u64);
+    for slot in 0..SHADOW_STACK_LEN {
+        write_uleb(&mut body, slot as u64);
+    }
+    write_uleb(&mut body, (SHADOW_STACK_LEN - 1) as u64);
+
+    for slot in 0..SHADOW_STACK_LEN {
+        body.push(0x0b);
+        body.extend_from_slice(&[0x20, 0x00]); // local.get 0
+        body.push(0x24); // global.set
+        write_uleb(&mut body, (first_slot_global + slot) as u64);
+        body.push(0x0c); // br (to the outer block)
+        write_uleb(&mut body, (SHADOW_STACK_LEN - 1 - slot) as u64);
+    }
+    body.push(0x0b);
+
+    body.push(0x23);
+    write_uleb(&mut body, depth_global as u64);
+    body.extend_from_slice(&[0x41, 0x01, 0x6a]); // i32.const 1, i32.add
+    body.push(0x24);
+    write_uleb(&mut body, depth_global as u64);
+    body.push(0x0b);
+
+    body
+}
+
+fn leave_body(depth_global: u32) -> Vec<u8> {
+    let mut body = vec![0x00];
+
+    body.push(0x23);
+    write_uleb(&mut body, depth_global as u64);
+    body.extend_from_slice(&[0x41, 0x01, 0x6b]); // i32.const 1, i32.sub
+    body.push(0x24);
+    write_uleb(&mut body, depth_global as u64);
+    body.push(0x0b);
+
+    body
+}
+
+pub struct GuestBacktrace {
+    // Function indices, innermost first
+    frames: Vec<u32>,
+    // Outer frames which did not fit in the shadow stack
+    n_omitted: u32,
+}
+
+impl GuestBacktrace {
+    // Returns None if the app was not instrumented
+    pub fn capture<T>(store: &Store<T>, instance: &Instance) -> Option<Self> {
+        let read_global = |name: &str| instance.get_global(store, name)?.get(store).i32();
+
+        let depth = read_global(DEPTH_EXPORT)?.max(0) as u32;
+        let n_frames = u32::min(depth, SHADOW_STACK_LEN);
+
+        let frames = (0..n_frames)
+            .map(|i| {
+                let slot = (depth - 1 - i) % SHADOW_STACK_LEN;
+                let name = format!("{}{}", SLOT_EXPORT_PREFIX, slot);
+                read_global(&name).map(|func_index| func_index as u32)
+            })
+            .collect::<Option<Vec<u32>>>()?;
+
+        Some(GuestBacktrace {
+            frames,
+            n_omitted: depth - n_frames,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn format(&self, symbols: &GuestSymbols) -> String {
+        let mut s = String::new();
+        for (i, &func_index) in self.frames.iter().enumerate() {
+            writeln!(s, "  #{} {}", i, symbols.describe(func_index)).unwrap();
+        }
+        if self.n_omitted > 0 {
+            writeln!(s, "  ({} more frames)", self.n_omitted).unwrap();
+        }
+        s
+    }
+}
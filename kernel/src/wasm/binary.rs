@@ -0,0 +1,395 @@
+use alloc::vec::Vec;
+use anyhow::format_err;
+
+//
+// Minimal WebAssembly binary format support
+//
+// Just enough to locate sections, read the few ones the kernel cares about (see backtrace.rs
+// and symbols.rs) and write new ones. Also used for the DWARF sections, which share the
+// LEB128 encoding.
+
+pub const SECTION_CUSTOM: u8 = 0;
+pub const SECTION_TYPE: u8 = 1;
+pub const SECTION_IMPORT: u8 = 2;
+pub const SECTION_FUNCTION: u8 = 3;
+pub const SECTION_GLOBAL: u8 = 6;
+pub const SECTION_EXPORT: u8 = 7;
+pub const SECTION_CODE: u8 = 10;
+
+const WASM_HEADER: [u8; 8] = *b"\0asm\x01\0\0\0";
+
+// Order in which non-custom sections must appear in a module
+const SECTION_ORDER: [u8; 13] = [1, 2, 3, 4, 5, 13, 6, 7, 8, 9, 12, 10, 11];
+
+pub struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.data[self.pos..]
+    }
+
+    pub fn bytes(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.data.len())
+            .ok_or_else(|| format_err!("Unexpected end of data at offset {}", self.pos))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    pub fn skip(&mut self, len: usize) -> anyhow::Result<()> {
+        self.bytes(len).map(|_| ())
+    }
+
+    pub fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> anyhow::Result<u16> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> anyhow::Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    pub fn uleb(&mut self) -> anyhow::Result<u64> {
+        let mut val: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                val |= ((byte & 0x7f) as u64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(val);
+            }
+        }
+    }
+
+    pub fn uleb32(&mut self) -> anyhow::Result<u32> {
+        let val = self.uleb()?;
+        u32::try_from(val).map_err(|_| format_err!("Value too large: {}", val))
+    }
+
+    pub fn sleb(&mut self) -> anyhow::Result<i64> {
+        let mut val: i64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                val |= ((byte & 0x7f) as i64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    val |= -1 << shift;
+                }
+                return Ok(val);
+            }
+        }
+    }
+
+    // Length-prefixed string, as used for WASM names
+    pub fn name(&mut self) -> anyhow::Result<&'a [u8]> {
+        let len = self.uleb()? as usize;
+        self.bytes(len)
+    }
+
+    // Null-terminated string, as used in DWARF
+    pub fn cstr(&mut self) -> anyhow::Result<&'a [u8]> {
+        let len = self
+            .remaining()
+            .iter()
+            .position(|&byte| byte == 0)
+            .ok_or_else(|| format_err!("Unterminated string at offset {}", self.pos))?;
+        let bytes = self.bytes(len)?;
+        self.skip(1)?;
+        Ok(bytes)
+    }
+}
+
+pub fn write_uleb(out: &mut Vec<u8>, mut val: u64) {
+    loop {
+        let byte = (val & 0x7f) as u8;
+        val >>= 7;
+        match val {
+            0 => {
+                out.push(byte);
+                return;
+            }
+            _ => out.push(byte | 0x80),
+        }
+    }
+}
+
+pub fn write_sleb(out: &mut Vec<u8>, mut val: i64) {
+    loop {
+        let byte = (val & 0x7f) as u8;
+        val >>= 7;
+        let done = (val == 0 && byte & 0x40 == 0) || (val == -1 && byte & 0x40 != 0);
+        match done {
+            true => {
+                out.push(byte);
+                return;
+            }
+            false => out.push(byte | 0x80),
+        }
+    }
+}
+
+pub fn write_name(out: &mut Vec<u8>, name: &str) {
+    write_uleb(out, name.len() as u64);
+    out.extend_from_slice(name.as_bytes());
+}
+
+//
+// Sections
+
+pub struct Section<'a> {
+    pub id: u8,
+    pub data: &'a [u8],
+}
+
+impl<'a> Section<'a> {
+    // Name and content of a custom section
+    pub fn custom(&self) -> Option<(&'a [u8], &'a [u8])> {
+        if self.id != SECTION_CUSTOM {
+            return None;
+        }
+        let mut reader = Reader::new(self.data);
+        let name = reader.name().ok()?;
+        Some((name, reader.remaining()))
+    }
+}
+
+pub fn read_sections(module: &[u8]) -> anyhow::Result<Vec<Section<'_>>> {
+    let mut reader = Reader::new(module);
+
+    if reader.bytes(WASM_HEADER.len())? != WASM_HEADER {
+        return Err(format_err!("Not a WASM module"));
+    }
+
+    let mut sections = Vec::new();
+    while !reader.is_empty() {
+        let id = reader.u8()?;
+        let len = reader.uleb()? as usize;
+        let data = reader.bytes(len)?;
+        sections.push(Section { id, data });
+    }
+
+    Ok(sections)
+}
+
+pub fn find_section<'a, 'b>(sections: &'b [Section<'a>], id: u8) -> Option<&'b Section<'a>> {
+    sections.iter().find(|section| section.id == id)
+}
+
+pub fn find_custom_section<'a>(sections: &[Section<'a>], name: &str) -> Option<&'a [u8]> {
+    sections
+        .iter()
+        .filter_map(|section| section.custom())
+        .find(|(section_name, _)| *section_name == name.as_bytes())
+        .map(|(_, data)| data)
+}
+
+// Rebuilds a module from its sections
+pub fn write_module(sections: &[(u8, &[u8])]) -> Vec<u8> {
+    let total_len: usize = sections.iter().map(|(_, data)| data.len() + 6).sum();
+
+    let mut module = Vec::with_capacity(WASM_HEADER.len() + total_len);
+    module.extend_from_slice(&WASM_HEADER);
+    for &(id, data) in sections {
+        module.push(id);
+        write_uleb(&mut module, data.len() as u64);
+        module.extend_from_slice(data);
+    }
+
+    module
+}
+
+// Index at which a new section with this ID has to be inserted
+pub fn section_insert_index(sections: &[(u8, &[u8])], id: u8) -> usize {
+    let rank = |id: u8| SECTION_ORDER.iter().position(|&other| other == id);
+    let new_rank = rank(id);
+
+    sections
+        .iter()
+        .position(|&(other_id, _)| rank(other_id).is_some() && rank(other_id) > new_rank)
+        .unwrap_or(sections.len())
+}
+
+//
+// Common section contents
+
+// Number of function and global imports
+pub fn count_imports(section: &[u8]) -> anyhow::Result<(u32, u32)> {
+    let mut reader = Reader::new(section);
+
+    let skip_limits = |reader: &mut Reader| -> anyhow::Result<()> {
+        let flags = reader.u8()?;
+        reader.uleb()?;
+        if flags & 0x1 != 0 {
+            reader.uleb()?;
+        }
+        Ok(())
+    };
+
+    let (mut n_funcs, mut n_globals) = (0, 0);
+
+    let count = reader.uleb32()?;
+    for _ in 0..count {
+        reader.name()?;
+        reader.name()?;
+        match reader.u8()? {
+            // Function
+            0x00 => {
+                reader.uleb()?;
+                n_funcs += 1;
+            }
+            // Table
+            0x01 => {
+                reader.u8()?;
+                skip_limits(&mut reader)?;
+            }
+            // Memory
+            0x02 => skip_limits(&mut reader)?,
+            // Global
+            0x03 => {
+                reader.u8()?;
+                reader.u8()?;
+                n_globals += 1;
+            }
+            // Tag
+            0x04 => {
+                reader.u8()?;
+                reader.uleb()?;
+            }
+            kind => return Err(format_err!("Unknown import kind {:#x}", kind)),
+        }
+    }
+
+    Ok((n_funcs, n_globals))
+}
+
+// Number of parameters of each function type
+pub fn read_func_param_counts(section: &[u8]) -> anyhow::Result<Vec<u32>> {
+    let mut reader = Reader::new(section);
+
+    let read_valtypes = |reader: &mut Reader| -> anyhow::Result<u32> {
+        let count = reader.uleb32()?;
+        for _ in 0..count {
+            match reader.u8()? {
+                0x7f | 0x7e | 0x7d | 0x7c | 0x7b | 0x70 | 0x6f => (),
+                valtype => return Err(format_err!("Unsupported value type {:#x}", valtype)),
+            }
+        }
+        Ok(count)
+    };
+
+    let count = reader.uleb32()?;
+    let mut param_counts = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        match reader.u8()? {
+            0x60 => (),
+            form => return Err(format_err!("Unsupported type form {:#x}", form)),
+        }
+        param_counts.push(read_valtypes(&mut reader)?);
+        read_valtypes(&mut reader)?;
+    }
+
+    Ok(param_counts)
+}
+
+pub fn read_u32_vec(section: &[u8]) -> anyhow::Result<Vec<u32>> {
+    let mut reader = Reader::new(section);
+    let count = reader.uleb32()?;
+    (0..count).map(|_| reader.uleb32()).collect()
+}
+
+// Appends items to a section made of a single vector (most of them)
+pub fn append_to_vec_section(
+    section: Option<&[u8]>,
+    n_items: u32,
+    items: &[u8],
+) -> anyhow::Result<Vec<u8>> {
+    let (count, existing) = match section {
+        Some(section) => {
+            let mut reader = Reader::new(section);
+            (reader.uleb32()?, reader.remaining())
+        }
+        None => (0, &[][..]),
+    };
+
+    let mut out = Vec::with_capacity(existing.len() + items.len() + 5);
+    write_uleb(&mut out, (count + n_items) as u64);
+    out.extend_from_slice(existing);
+    out.extend_from_slice(items);
+
+    Ok(out)
+}
+
+// Mutability of each global defined by the module
+pub fn read_globals_mutability(section: &[u8]) -> anyhow::Result<Vec<bool>> {
+    let mut reader = Reader::new(section);
+
+    let count = reader.uleb32()?;
+    let mut mutability = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        reader.u8()?;
+        mutability.push(reader.u8()? != 0);
+        skip_const_expr(&mut reader)?;
+    }
+
+    Ok(mutability)
+}
+
+// Initializer expressions (including the "extended constant expressions" arithmetic)
+fn skip_const_expr(reader: &mut Reader) -> anyhow::Result<()> {
+    loop {
+        match reader.u8()? {
+            // end
+            0x0b => return Ok(()),
+            // i32.const, i64.const
+            0x41 | 0x42 => {
+                reader.sleb()?;
+            }
+            // f32.const, f64.const
+            0x43 => reader.skip(4)?,
+            0x44 => reader.skip(8)?,
+            // global.get, ref.func
+            0x23 | 0xd2 => {
+                reader.uleb()?;
+            }
+            // ref.null
+            0xd0 => {
+                reader.u8()?;
+            }
+            // i32/i64 add, sub, mul
+            0x6a | 0x6b | 0x6c | 0x7c | 0x7d | 0x7e => (),
+            opcode => return Err(format_err!("Unsupported constant opcode {:#x}", opcode)),
+        }
+    }
+}
@@ -1,31 +1,133 @@
-use alloc::collections::BTreeMap;
+use alloc::borrow::Cow;
+use alloc::collections::{BTreeMap, VecDeque};
 use alloc::string::ToString;
 use alloc::vec;
+use alloc::vec::Vec;
 use alloc::{borrow::ToOwned, string::String};
+use applib::abi;
 use applib::content::TrackedContent;
 use applib::content::UuidProvider;
 use applib::geometry::Point2D;
 use applib::BorrowedPixels;
 use core::fmt::Write;
 use core::mem::size_of;
+use core::ops::Range;
 use smoltcp::iface::SocketHandle;
 
 use rand::RngCore;
 use smoltcp::wire::Ipv4Address;
 use wasmi::{
     AsContext, AsContextMut, Caller, Config, Engine, Func, Instance, Linker, Memory, Module, Store,
-    TypedFunc,
+    TypedFunc, TypedResumableCall, TypedResumableInvocation, Val,
 };
 
 use applib::{input::InputState, FbViewMut, Framebuffer, Rect};
 
+use crate::panic::{catch_panic, set_active_app};
 use crate::stats::AppDataPoint;
 use crate::system::System;
 
+mod backtrace;
+mod binary;
+mod poll;
+mod snapshot;
+mod stdio;
+mod symbols;
+
+use backtrace::GuestBacktrace;
+use poll::{ClockId, PollRequest, Suspended, Suspension};
+use symbols::GuestSymbols;
+
+pub use stdio::{console_rich_text, ConsoleOutput};
+
 pub struct WasmEngine;
 
+// Kernel state reachable from host functions. Apps may be stepped in parallel on
+// several cores (see smp.rs), so it is only ever accessed through a lock.
+pub struct HostState<'a> {
+    pub system: &'a mut System,
+    pub uuid_provider: &'a mut UuidProvider,
+}
+
+// Safety: the references are only used by whichever core holds the SharedHostState lock
+unsafe impl Send for HostState<'_> {}
+
+pub type SharedHostState<'a> = spin::Mutex<HostState<'a>>;
+
 const STEP_FUEL: u64 = u64::MAX;
 
+// Environment of all apps, which LaunchArgs can override
+// (this works but is sadly not enough to display a backtrace, not sure why)
+const DEFAULT_ENV: [&str; 1] = ["RUST_BACKTRACE=full"];
+
+// Command-line arguments and environment variables of an app (WASI args_get() and environ_get())
+#[derive(Debug, Clone, Default)]
+pub struct LaunchArgs {
+    // Without the program name: argv[0] is the app instance name
+    pub args: Vec<String>,
+    // KEY=VALUE strings, a variable defined several times takes the last value
+    pub env: Vec<String>,
+}
+
+impl LaunchArgs {
+    // Shell-like, without quoting: words are separated by spaces, and the leading KEY=VALUE
+    // words are environment variables
+    pub fn parse(command_line: &str) -> Self {
+        let mut words = command_line.split_whitespace().peekable();
+
+        let mut env = Vec::new();
+        while let Some(var) = words.next_if(|word| is_env_var(word)) {
+            env.push(var.to_string());
+        }
+        let args = words.map(|word| word.to_string()).collect();
+
+        LaunchArgs { args, env }
+    }
+
+    pub fn to_command_line(&self) -> String {
+        let words: Vec<&str> = self
+            .env
+            .iter()
+            .chain(self.args.iter())
+            .map(|word| word.as_str())
+            .collect();
+        words.join(" ")
+    }
+
+    // The environment of both, and the arguments of the other one
+    pub fn overridden_by(&self, other: &LaunchArgs) -> Self {
+        LaunchArgs {
+            args: other.args.clone(),
+            env: self.env.iter().chain(other.env.iter()).cloned().collect(),
+        }
+    }
+}
+
+// Like in a shell, the key must be an identifier: "a=b" is a variable but "--opt=b" or "1=b" are not
+fn is_env_var(word: &str) -> bool {
+    let Some((key, _)) = word.split_once('=') else {
+        return false;
+    };
+    let mut chars = key.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+// Variables defined several times only keep their last value
+fn merge_env<'a>(vars: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let mut merged: Vec<String> = Vec::new();
+    for var in vars.filter(|var| is_env_var(var)) {
+        let key = var.split('=').next().unwrap();
+        merged.retain(|existing| existing.split('=').next() != Some(key));
+        merged.push(var.to_string());
+    }
+    merged
+}
+
 impl WasmEngine {
     pub fn new() -> Self {
         WasmEngine
@@ -38,77 +140,352 @@ impl WasmEngine {
         input_state: &InputState,
         wasm_code: &[u8],
         app_name: &str,
+        launch_args: &LaunchArgs,
         init_rect: &Rect,
-    ) -> WasmApp {
+    ) -> anyhow::Result<WasmApp> {
+        let (mut wasm_app, wasm_init) =
+            self.load_app(uuid_provider, wasm_code, app_name, launch_args)?;
+
+        let shared = SharedHostState::new(HostState {
+            system,
+            uuid_provider,
+        });
+
+        log::info!("Initializing {}", app_name);
+        let mut suspended_call = None;
+        wasm_app
+            .call_guest(&shared, input_state, init_rect, |store| {
+                let call = wasm_init.call_resumable(store, ())?;
+                keep_suspended_call(call, &mut suspended_call)
+            })
+            .map_err(|error| anyhow::format_err!("Initialization failed: {}", error))?;
+
+        // An app which waits in init() finishes it on its first steps
+        wasm_app.suspended_call = suspended_call;
+
+        Ok(wasm_app)
+    }
+
+    // Same as instantiate_app(), but the app state comes from a snapshot instead of init()
+    pub fn restore_app(
+        &self,
+        uuid_provider: &mut UuidProvider,
+        wasm_code: &[u8],
+        app_name: &str,
+        launch_args: &LaunchArgs,
+        snapshot: &[u8],
+    ) -> anyhow::Result<WasmApp> {
+        let (mut wasm_app, _) = self.load_app(uuid_provider, wasm_code, app_name, launch_args)?;
+
+        let n_globals = wasm_app
+            .n_snapshot_globals
+            .ok_or_else(|| anyhow::format_err!("Snapshots are not supported for this app"))?;
+
+        log::info!("Restoring {} from a snapshot", app_name);
+        snapshot::restore(
+            &mut wasm_app.store_wrapper.store,
+            &wasm_app.instance,
+            uuid_provider,
+            n_globals,
+            wasm_app.module_hash,
+            snapshot,
+        )
+        .map_err(|error| anyhow::format_err!("Cannot restore snapshot: {}", error))?;
+
+        Ok(wasm_app)
+    }
+
+    // Compiles and instantiates the app, without calling its init function
+    fn load_app(
+        &self,
+        uuid_provider: &mut UuidProvider,
+        wasm_code: &[u8],
+        app_name: &str,
+        launch_args: &LaunchArgs,
+    ) -> anyhow::Result<(WasmApp, TypedFunc<(), ()>)> {
         let engine = Engine::new(&Config::default().consume_fuel(true));
 
-        let module = Module::new(&engine, wasm_code).unwrap();
-        let store_data = StoreData::new(uuid_provider, app_name);
+        let module_hash = snapshot::module_hash(wasm_code);
+        let symbols = GuestSymbols::parse(wasm_code);
+
+        let wasm_code = match backtrace::instrument(wasm_code) {
+            Ok(instrumented) => Cow::Owned(instrumented),
+            Err(error) => {
+                log::warn!("Cannot instrument {} for backtraces: {}", app_name, error);
+                Cow::Borrowed(wasm_code)
+            }
+        };
+        let (wasm_code, n_snapshot_globals) = match snapshot::export_globals(&wasm_code) {
+            Ok((exported, n_globals)) => (Cow::Owned(exported), Some(n_globals)),
+            Err(error) => {
+                log::warn!("Cannot instrument {} for snapshots: {}", app_name, error);
+                (wasm_code, None)
+            }
+        };
+
+        let module = Module::new(&engine, &wasm_code[..])
+            .map_err(|error| anyhow::format_err!("Invalid WASM module: {}", error))?;
+
+        // Host APIs access the guest through its exported memory, including from the start function
+        if !module
+            .get_export("memory")
+            .is_some_and(|export| export.memory().is_some())
+        {
+            return Err(anyhow::format_err!("Missing export \"memory\""));
+        }
+
+        let store_data = StoreData::new(uuid_provider, app_name, launch_args);
         let mut store: Store<StoreData> = Store::new(&engine, store_data);
         let mut linker = <Linker<StoreData>>::new(&engine);
 
-        add_host_apis(&mut store, &mut linker);
+        // Apps built before the ABI was versioned call host_get_input_state() without a buffer length
+        let legacy_input_api = module
+            .imports()
+            .find(|import| import.module() == "env" && import.name() == "host_get_input_state")
+            .and_then(|import| import.ty().func().map(|ty| ty.params().len() == 1));
+
+        add_host_apis(&mut store, &mut linker, legacy_input_api == Some(true));
 
+        // Unknown imports are reported by wasmi with their module and name
         let instance = linker
             .instantiate(&mut store, &module)
-            .unwrap()
+            .map_err(|error| anyhow::format_err!("Cannot link app: {}", error))?
             .start(&mut store)
-            .unwrap();
-
-        let wasm_init = instance.get_typed_func::<(), ()>(&store, "init").unwrap();
-        let wasm_step = instance.get_typed_func::<(), ()>(&store, "step").unwrap();
-
-        let mut store_wrapper = StoreWrapper { store };
+            .map_err(|error| anyhow::format_err!("Start function failed: {}", error))?;
+
+        let get_export = |name: &str| -> anyhow::Result<TypedFunc<(), ()>> {
+            instance
+                .get_func(&store, name)
+                .ok_or_else(|| anyhow::format_err!("Missing export \"{}\"", name))?
+                .typed::<(), ()>(&store)
+                .map_err(|error| anyhow::format_err!("Invalid export \"{}\": {}", name, error))
+        };
 
-        store_wrapper.with_context(system, uuid_provider, input_state, init_rect, |store| {
-            log::info!("Initializing {}", app_name);
-            wasm_init
-                .call(store, ())
-                .expect("Failed to initialize WASM app");
-        });
+        let wasm_init = get_export("init")?;
+        let wasm_step = get_export("step")?;
+
+        let abi_version = read_abi_version(&mut store, &instance)?;
+        if !(abi::MIN_ABI_VERSION..=abi::ABI_VERSION).contains(&abi_version) {
+            return Err(anyhow::format_err!(
+                "Unsupported ABI version {} (supported: {} to {})",
+                abi_version,
+                abi::MIN_ABI_VERSION,
+                abi::ABI_VERSION
+            ));
+        }
+        let is_legacy = abi_version == abi::LEGACY_ABI_VERSION;
+        if legacy_input_api.is_some_and(|legacy| legacy != is_legacy) {
+            return Err(anyhow::format_err!(
+                "host_get_input_state() import does not match ABI version {}",
+                abi_version
+            ));
+        }
+        if abi_version < abi::ABI_VERSION {
+            log::warn!("{} uses the older ABI version {}", app_name, abi_version);
+        }
+        store.data_mut().abi_version = abi_version;
 
-        WasmApp {
-            store_wrapper,
+        let wasm_app = WasmApp {
+            store_wrapper: StoreWrapper { store },
             instance,
             wasm_step,
+            symbols,
+            module_hash,
+            n_snapshot_globals,
+            suspended_call: None,
+        };
+
+        Ok((wasm_app, wasm_init))
+    }
+}
+
+// Apps built before the ABI was versioned do not export their version
+fn read_abi_version(store: &mut Store<StoreData>, instance: &Instance) -> anyhow::Result<u32> {
+    let Some(func) = instance.get_func(&*store, abi::ABI_VERSION_EXPORT) else {
+        return Ok(abi::LEGACY_ABI_VERSION);
+    };
+
+    let func = func.typed::<(), i32>(&*store).map_err(|error| {
+        anyhow::format_err!("Invalid export \"{}\": {}", abi::ABI_VERSION_EXPORT, error)
+    })?;
+
+    store.set_fuel(STEP_FUEL).unwrap();
+    let version = func
+        .call(&mut *store, ())
+        .map_err(|error| anyhow::format_err!("Cannot get the ABI version: {}", error))?;
+
+    Ok(version as u32)
+}
+
+// A host function may suspend the app (see poll.rs): the interrupted guest call is kept, to be
+// resumed on a later step. Other host errors are traps.
+fn keep_suspended_call(
+    call: TypedResumableCall<()>,
+    suspended_call: &mut Option<TypedResumableInvocation<()>>,
+) -> Result<(), wasmi::Error> {
+    match call {
+        TypedResumableCall::Finished(()) => Ok(()),
+        TypedResumableCall::Resumable(invocation) => {
+            let suspended = invocation
+                .host_error()
+                .downcast_ref::<Suspended>()
+                .is_some();
+            match suspended {
+                true => {
+                    *suspended_call = Some(invocation);
+                    Ok(())
+                }
+                false => Err(wasmi::Error::host(HostTrap(invocation))),
+            }
+        }
+    }
+}
+
+// Any other host error stops the app. wasmi only lends the error of an interrupted call, so the
+// call is kept along with it, instead of copying the error.
+struct HostTrap(TypedResumableInvocation<()>);
+
+impl core::fmt::Display for HostTrap {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        core::fmt::Display::fmt(self.0.host_error(), f)
+    }
+}
+
+impl core::fmt::Debug for HostTrap {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        core::fmt::Debug::fmt(self.0.host_error(), f)
+    }
+}
+
+impl wasmi::core::HostError for HostTrap {}
+
+// Return value of the host function which suspended the app, or None if the app should keep
+// waiting
+fn resume_errno(store: &mut Store<StoreData>, instance: &Instance) -> Option<i32> {
+    let memory = instance.get_memory(&*store, "memory").unwrap();
+    let (mem_data, store_data) = memory.data_and_store_mut(store);
+
+    let ret = match store_data.suspension.take() {
+        Some(Suspension::Poll(request)) => {
+            let stdin_len = store_data.stdin.len();
+            let ready = store_data.with_step_context(|step_context| {
+                request.try_complete(mem_data, &step_context.system.clock, stdin_len)
+            });
+
+            match ready {
+                Ok(true) => Ok(()),
+                Ok(false) => {
+                    store_data.suspension = Some(Suspension::Poll(request));
+                    return None;
+                }
+                Err(error) => Err(error),
+            }
         }
+        Some(Suspension::Yield) | None => Ok(()),
+    };
+
+    Some(wasi_errno("poll_oneoff", ret))
+}
+
+//
+// Guest memory access
+//
+// Addresses and lengths passed to host functions come from the app, so every access to its
+// linear memory is bounds-checked. An invalid access is an error: host functions which have an
+// error return value (WASI errno, -1 for TCP) report it, the others trap, which only crashes
+// the app.
+
+#[derive(Debug)]
+struct GuestMemError {
+    addr: u32,
+    len: u64,
+    mem_size: usize,
+}
+
+impl core::fmt::Display for GuestMemError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "Invalid memory access ({} bytes at {:#x}, memory size is {:#x})",
+            self.len, self.addr, self.mem_size
+        )
     }
 }
 
-fn get_wasm_mem_slice<'a>(caller: &'a Caller<StoreData>, addr: i32, len: i32) -> &'a [u8] {
-    let mem = get_linear_memory(caller);
+impl From<GuestMemError> for wasmi::Error {
+    fn from(error: GuestMemError) -> Self {
+        wasmi::Error::new(error.to_string())
+    }
+}
 
-    let mem_data = mem.data(caller);
-    let len = len as usize;
-    let addr = addr as usize;
+// Guest addresses are unsigned 32-bit values, passed as i32
+fn guest_mem_range(mem_size: usize, addr: i32, len: u64) -> Result<Range<usize>, GuestMemError> {
+    let start = addr as u32 as usize;
+    let end = usize::try_from(len)
+        .ok()
+        .and_then(|len| start.checked_add(len));
+
+    match end {
+        Some(end) if end <= mem_size => Ok(start..end),
+        _ => Err(GuestMemError {
+            addr: addr as u32,
+            len,
+            mem_size,
+        }),
+    }
+}
 
-    &mem_data[addr..addr + len]
+// Lengths are unsigned 32-bit values too
+fn get_wasm_mem_slice<'a>(
+    caller: &'a Caller<StoreData>,
+    addr: i32,
+    len: i32,
+) -> Result<&'a [u8], GuestMemError> {
+    let mem_data = get_linear_memory(caller).data(caller);
+    let range = guest_mem_range(mem_data.len(), addr, len as u32 as u64)?;
+    Ok(&mem_data[range])
 }
 
 fn get_wasm_mem_slice_mut<'a>(
     caller: &'a mut Caller<StoreData>,
     addr: i32,
     len: i32,
-) -> &'a mut [u8] {
-    let mem = get_linear_memory(caller);
-
-    let mem_data = mem.data_mut(caller);
-    let len = len as usize;
-    let addr = addr as usize;
-
-    &mut mem_data[addr..addr + len]
+) -> Result<&'a mut [u8], GuestMemError> {
+    let mem_data = get_linear_memory(caller).data_mut(caller);
+    let range = guest_mem_range(mem_data.len(), addr, len as u32 as u64)?;
+    Ok(&mut mem_data[range])
 }
 
-fn write_to_wasm_mem<'a, T: Sized>(caller: &'a mut Caller<StoreData>, addr: i32, data: &T) {
-    let mem = get_linear_memory(caller);
+fn write_to_wasm_mem(
+    caller: &mut Caller<StoreData>,
+    addr: i32,
+    data: &[u8],
+) -> Result<(), GuestMemError> {
+    let mem_data = get_linear_memory(caller).data_mut(caller);
+    let range = guest_mem_range(mem_data.len(), addr, data.len() as u64)?;
+    mem_data[range].copy_from_slice(data);
+    Ok(())
+}
 
-    unsafe {
-        let len = size_of::<T>();
-        let ptr = data as *const T as *const u8;
-        let mem_slice = core::slice::from_raw_parts(ptr, len);
-        mem.write(caller, addr as usize, mem_slice)
-            .expect("Failed to write to WASM memory");
-    }
+// WASI scatter/gather buffers: an array of (address, length) pairs of u32
+fn read_iovecs(
+    caller: &Caller<StoreData>,
+    iovs: i32,
+    iovs_len: i32,
+) -> Result<Vec<(i32, i32)>, GuestMemError> {
+    let mem_data = get_linear_memory(caller).data(caller);
+    let range = guest_mem_range(mem_data.len(), iovs, iovs_len as u32 as u64 * 8)?;
+
+    let iovecs = mem_data[range]
+        .chunks_exact(8)
+        .map(|iovec| {
+            let buf = i32::from_le_bytes(iovec[..4].try_into().unwrap());
+            let buf_len = i32::from_le_bytes(iovec[4..].try_into().unwrap());
+            (buf, buf_len)
+        })
+        .collect();
+
+    Ok(iovecs)
 }
 
 fn get_linear_memory(caller: &Caller<StoreData>) -> Memory {
@@ -129,6 +506,8 @@ struct WasmFramebufferDef {
 struct SocketsStore {
     sockets: BTreeMap<i32, SocketHandle>,
     next_id: i32,
+    // IDs given out before the app was restored from a snapshot, whose sockets are gone
+    stale_below: i32,
 }
 
 impl SocketsStore {
@@ -136,6 +515,15 @@ impl SocketsStore {
         Self {
             sockets: BTreeMap::new(),
             next_id: 0,
+            stale_below: 0,
+        }
+    }
+
+    fn restored(next_id: i32) -> Self {
+        Self {
+            sockets: BTreeMap::new(),
+            next_id,
+            stale_below: next_id,
         }
     }
 
@@ -146,8 +534,17 @@ impl SocketsStore {
         new_id
     }
 
+    // None for stale, closed or made-up IDs, which the app should see as closed connections
     fn get_handle(&self, handle_id: i32) -> Option<SocketHandle> {
-        self.sockets.get(&handle_id).cloned()
+        let handle = self.sockets.get(&handle_id).copied();
+        if handle.is_none() && handle_id >= self.stale_below {
+            log::warn!("Unknown TCP connection {}", handle_id);
+        }
+        handle
+    }
+
+    fn remove_handle(&mut self, handle_id: i32) -> Option<SocketHandle> {
+        self.sockets.remove(&handle_id)
     }
 }
 
@@ -158,8 +555,7 @@ struct StoreWrapper {
 impl StoreWrapper {
     fn with_context<F, T>(
         &mut self,
-        system: &mut System,
-        uuid_provider: &mut UuidProvider,
+        shared: &SharedHostState,
         input_state: &InputState,
         win_rect: &Rect,
         mut func: F,
@@ -169,23 +565,39 @@ impl StoreWrapper {
     {
         self.store.set_fuel(STEP_FUEL).unwrap();
 
+        set_active_app(Some(&self.store.data().app_name));
+
         self.store.as_context_mut().data_mut().step_context = Some(StepContext {
             // reference -> raw pointer conversions here
-            system,
-            uuid_provider,
+            shared: shared as *const SharedHostState as *const SharedHostState<'static>,
             input_state,
 
             win_rect: win_rect.clone(),
             timings: BTreeMap::new(),
+            holds_host_lock: false,
         });
 
         let res = func(&mut self.store);
 
         self.store.as_context_mut().data_mut().step_context = None;
 
+        set_active_app(None);
+
         res
     }
 
+    // Cleans up after a panic caught in the middle of with_context()
+    fn recover_from_panic(&mut self, shared: &SharedHostState) {
+        let step_context = self.store.as_context_mut().data_mut().step_context.take();
+
+        if step_context.is_some_and(|step_context| step_context.holds_host_lock) {
+            // Safety: the lock was taken on this core, by the host call which panicked
+            unsafe { shared.force_unlock() };
+        }
+
+        set_active_app(None);
+    }
+
     fn get_framebuffer(&self, instance: &Instance) -> Option<Framebuffer<BorrowedPixels>> {
         let wasm_fb_def = self.store.as_context().data().framebuffer.clone()?;
 
@@ -193,8 +605,10 @@ impl StoreWrapper {
         let ctx = self.store.as_context();
         let mem_data = mem.data(ctx);
 
+        // Checked by host_set_framebuffer, but it may also come from a snapshot
         let WasmFramebufferDef { addr, w, h } = wasm_fb_def;
-        let wasm_fb_bytes = &mem_data[addr..addr + (w * h * 4) as usize];
+        let len = (w as usize).checked_mul(h as usize)?.checked_mul(4)?;
+        let wasm_fb_bytes = mem_data.get(addr..addr.checked_add(len)?)?;
 
         let wasm_fb = Framebuffer::<BorrowedPixels>::from_bytes(wasm_fb_bytes, w, h);
 
@@ -204,20 +618,33 @@ impl StoreWrapper {
 
 struct StoreData {
     app_name: String,
+    // Host ABI version the app was built against, which decides how data is encoded for it
+    abi_version: u32,
     framebuffer: Option<WasmFramebufferDef>,
     sockets_store: SocketsStore,
     step_context: Option<StepContext>,
     net_recv: usize,
     net_sent: usize,
-    console_output: TrackedContent<String>,
+    console_output: TrackedContent<ConsoleOutput>,
+    // Including the program name
+    args: Vec<String>,
+    env: Vec<String>,
+    stdin: VecDeque<u8>,
+    // Stdout written since it was last taken, only kept while the app is piped
+    piped_stdout: Option<Vec<u8>>,
+    // Set by the host function which suspended the app, if any
+    suspension: Option<Suspension>,
+    // Copy of guest memory from the last host_qemu_dump() call, for pmemsave in the QEMU monitor
+    qemu_dump: Vec<u8>,
 }
 
 struct StepContext {
-    system: *mut System,
-    uuid_provider: *mut UuidProvider,
+    shared: *const SharedHostState<'static>,
     input_state: *const InputState,
     win_rect: Rect,
     timings: BTreeMap<String, u64>,
+    // Whether a host call is holding the SharedHostState lock
+    holds_host_lock: bool,
 }
 
 struct StepContextView<'a> {
@@ -227,19 +654,33 @@ struct StepContextView<'a> {
     win_rect: &'a Rect,
     timings: &'a mut BTreeMap<String, u64>,
 
-    console_output: &'a mut TrackedContent<String>,
+    console_output: &'a mut TrackedContent<ConsoleOutput>,
 }
 
 impl StoreData {
-    fn new(uuid_provider: &mut UuidProvider, app_name: &str) -> Self {
+    fn new(uuid_provider: &mut UuidProvider, app_name: &str, launch_args: &LaunchArgs) -> Self {
+        let args = core::iter::once(app_name.to_owned())
+            .chain(launch_args.args.iter().cloned())
+            .collect();
+        let env = DEFAULT_ENV
+            .into_iter()
+            .chain(launch_args.env.iter().map(|var| var.as_str()));
+
         StoreData {
             app_name: app_name.to_owned(),
+            abi_version: abi::LEGACY_ABI_VERSION,
             framebuffer: None,
             sockets_store: SocketsStore::new(),
             step_context: None,
             net_recv: 0,
             net_sent: 0,
-            console_output: TrackedContent::new(String::new(), uuid_provider),
+            console_output: TrackedContent::new(ConsoleOutput::new(), uuid_provider),
+            args,
+            env: merge_env(env),
+            stdin: VecDeque::new(),
+            piped_stdout: None,
+            suspension: None,
+            qemu_dump: Vec::new(),
         }
     }
 
@@ -255,10 +696,18 @@ impl StoreData {
 
         let step_context = step_context.as_mut().expect("No StepContext set");
 
+        // Safety: thanks to the StoreDataWrapper scope, those pointers should always be valid
+        let shared = unsafe { step_context.shared.as_ref().unwrap() };
+        let mut host_state = shared.lock();
+        step_context.holds_host_lock = true;
+        let HostState {
+            system,
+            uuid_provider,
+        } = &mut *host_state;
+
         let step_context_view = StepContextView {
-            // Safety: thanks to the StoreDataWrapper scope, those pointers should always be valid
-            system: unsafe { step_context.system.as_mut().unwrap() },
-            uuid_provider: unsafe { step_context.uuid_provider.as_mut().unwrap() },
+            system,
+            uuid_provider,
             input_state: unsafe { step_context.input_state.as_ref().unwrap() },
 
             win_rect: &step_context.win_rect,
@@ -267,7 +716,10 @@ impl StoreData {
             console_output,
         };
 
-        func(step_context_view)
+        let res = func(step_context_view);
+        step_context.holds_host_lock = false;
+
+        res
     }
 }
 
@@ -275,13 +727,19 @@ pub struct WasmApp {
     store_wrapper: StoreWrapper,
     instance: Instance,
     wasm_step: TypedFunc<(), ()>,
+    symbols: GuestSymbols,
+    // Of the original module, checked when restoring snapshots
+    module_hash: u64,
+    // None if the module could not be instrumented for snapshots
+    n_snapshot_globals: Option<u32>,
+    // Step call interrupted by a host function which suspended the app (see poll.rs)
+    suspended_call: Option<TypedResumableInvocation<()>>,
 }
 
 impl WasmApp {
     pub fn step(
         &mut self,
-        system: &mut System,
-        uuid_provider: &mut UuidProvider,
+        shared: &SharedHostState,
         input_state: &InputState,
         win_rect: &Rect,
         is_foreground: bool,
@@ -303,34 +761,43 @@ impl WasmApp {
         //
         // Stepping WASM app
 
-        let t0 = system.clock.time();
-
-        let step_ret = self
-            .store_wrapper
-            .with_context(
-                system,
-                uuid_provider,
-                &relative_input_state,
-                win_rect,
-                |mut store| {
-                    store.data_mut().net_recv = 0;
-                    store.data_mut().net_sent = 0;
-
-                    match is_paused {
-                        false => self.wasm_step.call(&mut store, ()),
-                        true => Ok(()),
+        let t0 = shared.lock().system.clock.time();
+
+        let wasm_step = self.wasm_step.clone();
+        let instance = self.instance;
+        let mut suspended_call = self.suspended_call.take();
+        let step_ret = self.call_guest(shared, &relative_input_state, win_rect, |mut store| {
+            store.data_mut().net_recv = 0;
+            store.data_mut().net_sent = 0;
+
+            if is_paused {
+                return Ok(());
+            }
+
+            // A suspended app only steps again once its call is resumed and completed
+            let call = match suspended_call.take() {
+                None => wasm_step.call_resumable(&mut store, ())?,
+                Some(invocation) => match resume_errno(store, &instance) {
+                    Some(errno) => invocation.resume(&mut store, &[Val::I32(errno)])?,
+                    None => {
+                        suspended_call = Some(invocation);
+                        return Ok(());
                     }
                 },
-            )
-            .map_err(|wasm_err| anyhow::format_err!(wasm_err));
+            };
 
-        let t1 = system.clock.time();
+            keep_suspended_call(call, &mut suspended_call)
+        });
+        self.suspended_call = suspended_call;
+
+        let t1 = shared.lock().system.clock.time();
 
         //
         // Filling app stats
 
         let app_name = self.store_wrapper.store.data().app_name.as_str();
-        let app_stats = system.stats.get_app_point_mut(app_name);
+        let mut host_state = shared.lock();
+        let app_stats = host_state.system.stats.get_app_point_mut(app_name);
 
         let store = &self.store_wrapper.store;
         let mem = self.instance.get_memory(store, "memory").unwrap();
@@ -349,13 +816,119 @@ impl WasmApp {
         step_ret
     }
 
+    // Calls into the guest. Traps and panics in host calls (which only crash the app) are
+    // turned into errors, reported with a guest backtrace.
+    fn call_guest<F>(
+        &mut self,
+        shared: &SharedHostState,
+        input_state: &InputState,
+        win_rect: &Rect,
+        func: F,
+    ) -> anyhow::Result<()>
+    where
+        F: FnMut(&mut Store<StoreData>) -> Result<(), wasmi::Error>,
+    {
+        let ret = catch_panic(|| {
+            self.store_wrapper
+                .with_context(shared, input_state, win_rect, func)
+        });
+
+        let ret = match ret {
+            Ok(wasm_ret) => wasm_ret.map_err(|wasm_err| anyhow::format_err!(wasm_err)),
+            Err(report) => {
+                self.store_wrapper.recover_from_panic(shared);
+                Err(anyhow::format_err!("{}", report))
+            }
+        };
+
+        ret.map_err(|error| self.report_crash(shared, error))
+    }
+
     pub fn get_framebuffer(&self) -> Option<Framebuffer<BorrowedPixels>> {
         self.store_wrapper.get_framebuffer(&self.instance)
     }
 
-    pub fn get_console_output(&self) -> &TrackedContent<String> {
+    // Adds the guest backtrace to the error, and writes it all to the kernel log and the app
+    // console (shown in the audit window)
+    fn report_crash(&mut self, shared: &SharedHostState, error: anyhow::Error) -> anyhow::Error {
+        let store = &mut self.store_wrapper.store;
+
+        let error = match GuestBacktrace::capture(store, &self.instance) {
+            Some(backtrace) if !backtrace.is_empty() => anyhow::format_err!(
+                "{}\n\nBacktrace:\n{}",
+                error,
+                backtrace.format(&self.symbols)
+            ),
+            _ => error,
+        };
+
+        let app_name = store.data().app_name.clone();
+        log::error!("{} crashed: {}", app_name, error);
+
+        let mut host_state = shared.lock();
+        let console_output = store
+            .data_mut()
+            .console_output
+            .mutate(&mut *host_state.uuid_provider);
+        writeln!(console_output, "CRASH: {}", error).unwrap();
+
+        error
+    }
+
+    // Waiting in poll_oneoff() or sched_yield()
+    pub fn is_suspended(&self) -> bool {
+        self.suspended_call.is_some()
+    }
+
+    pub fn snapshot(&self) -> anyhow::Result<Vec<u8>> {
+        let n_globals = self
+            .n_snapshot_globals
+            .ok_or_else(|| anyhow::format_err!("Snapshots are not supported for this app"))?;
+
+        // The host call stack of a suspended app is not part of the snapshot
+        if self.is_suspended() {
+            return Err(anyhow::format_err!(
+                "The app is waiting in a host call, try again later"
+            ));
+        }
+
+        snapshot::capture(
+            &self.store_wrapper.store,
+            &self.instance,
+            n_globals,
+            self.module_hash,
+        )
+    }
+
+    pub fn get_console_output(&self) -> &TrackedContent<ConsoleOutput> {
         &self.store_wrapper.store.data().console_output
     }
+
+    pub fn write_stdin(&mut self, data: &[u8]) {
+        let store_data = self.store_wrapper.store.data_mut();
+        let stdin = &mut store_data.stdin;
+
+        let n = usize::min(data.len(), stdio::MAX_STDIN_LEN - stdin.len());
+        if n < data.len() {
+            log::warn!(
+                "{}: stdin full, dropping {} bytes",
+                store_data.app_name,
+                data.len() - n
+            );
+        }
+        stdin.extend(&data[..n]);
+    }
+
+    // Stdout written since the last call, to be fed to another app. Stdout is only kept while
+    // the app is piped.
+    pub fn take_piped_stdout(&mut self, piped: bool) -> Vec<u8> {
+        let piped_stdout = &mut self.store_wrapper.store.data_mut().piped_stdout;
+        let new = match piped {
+            true => Some(Vec::new()),
+            false => None,
+        };
+        core::mem::replace(piped_stdout, new).unwrap_or_default()
+    }
 }
 
 // fn debug_stall(t0: f64, t1: f64, fu0: u64, fu1: u64, store_data: &StoreData) {
@@ -390,10 +963,11 @@ impl WasmApp {
 //     }
 // }
 
-fn add_host_apis(mut store: &mut Store<StoreData>, linker: &mut Linker<StoreData>) {
-    // This works but is sadly not enough to display a backtrace, not sure why
-    const ENV_VARS: [&str; 1] = ["RUST_BACKTRACE=full"];
-
+fn add_host_apis(
+    mut store: &mut Store<StoreData>,
+    linker: &mut Linker<StoreData>,
+    legacy_input_api: bool,
+) {
     macro_rules! linker_impl {
         ($module:expr, $name:expr, $func:expr) => {
             linker
@@ -407,8 +981,11 @@ fn add_host_apis(mut store: &mut Store<StoreData>, linker: &mut Linker<StoreData
         ($module:expr, $name:expr, [$($x:ty),*], $y:ty) => {
             linker_impl!(
                 $module, $name,
-                |_: Caller<StoreData>, $(_: $x),*| -> $y {
-                    panic!("WASM function {}() is not implemented (stub)", $name);
+                |_: Caller<StoreData>, $(_: $x),*| -> Result<$y, wasmi::Error> {
+                    Err(wasmi::Error::new(alloc::format!(
+                        "WASM function {}() is not implemented (stub)",
+                        $name
+                    )))
                 }
             )
         };
@@ -435,7 +1012,6 @@ fn add_host_apis(mut store: &mut Store<StoreData>, linker: &mut Linker<StoreData
     let m = "wasi_snapshot_preview1";
 
     linker_stub!(m, "fd_filestat_set_size", [i32, i64], i32);
-    linker_stub!(m, "fd_read", [i32, i32, i32, i32], i32);
     linker_stub!(m, "fd_readdir", [i32, i32, i32, i64, i32], i32);
     linker_stub!(m, "path_create_directory", [i32, i32, i32], i32);
     linker_stub!(m, "path_filestat_get", [i32, i32, i32, i32, i32], i32);
@@ -450,8 +1026,6 @@ fn add_host_apis(mut store: &mut Store<StoreData>, linker: &mut Linker<StoreData
     linker_stub!(m, "path_remove_directory", [i32, i32, i32], i32);
     linker_stub!(m, "path_rename", [i32, i32, i32, i32, i32, i32], i32);
     linker_stub!(m, "path_unlink_file", [i32, i32, i32], i32);
-    linker_stub!(m, "poll_oneoff", [i32, i32, i32, i32], i32);
-    linker_stub!(m, "sched_yield", [], i32);
     linker_stub!(m, "fd_close", [i32], i32);
     linker_stub!(m, "fd_filestat_get", [i32, i32], i32);
     linker_stub!(m, "fd_prestat_dir_name", [i32, i32, i32], i32);
@@ -467,7 +1041,6 @@ fn add_host_apis(mut store: &mut Store<StoreData>, linker: &mut Linker<StoreData
     //
     // WASMI stubs (with return value)
 
-    linker_stub!(m, "args_get", [i32, i32], i32, Errno::SUCCESS as i32);
     linker_stub!(m, "proc_exit", [i32], (), ());
     linker_stub!(m, "fd_fdstat_get", [i32, i32], i32, Errno::EBADFS as i32);
     linker_stub!(
@@ -487,49 +1060,122 @@ fn add_host_apis(mut store: &mut Store<StoreData>, linker: &mut Linker<StoreData
                                        precision: i64,
                                        time: i32|
      -> i32 {
-        let buf = time as usize;
-
         log::debug!(
             "Function clock_time_get() called (dest buffer {:#x} clock_id {:#x} precision {})",
-            buf,
+            time,
             clock_id,
             precision
         );
 
-        let t = caller.data_mut().with_step_context(|step_context| {
-            (step_context.system.clock.time() * 1e9) as u64 // Not sure about the 1e9
-        });
+        // The precision is only a hint
+        let Some(clock) = ClockId::from_wasi(clock_id) else {
+            return Errno::EINVAL as i32;
+        };
+
+        let t = caller
+            .data_mut()
+            .with_step_context(|step_context| clock.now_ns(&step_context.system.clock));
+
+        let ret = write_to_wasm_mem(&mut caller, time, &t.to_le_bytes());
+        wasi_errno("clock_time_get", ret)
+    });
+
+    linker_impl!(m, "clock_res_get", |mut caller: Caller<StoreData>,
+                                      clock_id: i32,
+                                      resolution: i32|
+     -> i32 {
+        log::debug!(
+            "Function clock_res_get() called (dest buffer {:#x} clock_id {:#x})",
+            resolution,
+            clock_id
+        );
+
+        if ClockId::from_wasi(clock_id).is_none() {
+            return Errno::EINVAL as i32;
+        }
+
+        let res = caller
+            .data_mut()
+            .with_step_context(|step_context| step_context.system.clock.resolution_ns());
+
+        let ret = write_to_wasm_mem(&mut caller, resolution, &res.to_le_bytes());
+        wasi_errno("clock_res_get", ret)
+    });
 
-        let mem = get_linear_memory(&caller);
-        let mem_data = mem.data_mut(&mut caller);
+    // Both suspend the app, see poll.rs
 
-        let data = t.to_le_bytes();
-        mem_data[buf..buf + 8].copy_from_slice(&data);
+    linker_impl!(m, "poll_oneoff", |mut caller: Caller<StoreData>,
+                                    subscriptions: i32,
+                                    out: i32,
+                                    nsubscriptions: i32,
+                                    nevents: i32|
+     -> Result<i32, wasmi::Error> {
+        log::debug!(
+            "Function poll_oneoff() called ({} subscriptions at {:#x})",
+            nsubscriptions,
+            subscriptions
+        );
+
+        let memory = get_linear_memory(&caller);
+        let (mem_data, store_data) = memory.data_and_store_mut(&mut caller);
+        let stdin_len = store_data.stdin.len();
+
+        let res = store_data.with_step_context(
+            |step_context| -> Result<(PollRequest, bool), GuestMemError> {
+                let clock = &step_context.system.clock;
+                let request = PollRequest::parse(
+                    mem_data,
+                    clock,
+                    subscriptions,
+                    out,
+                    nsubscriptions,
+                    nevents,
+                )?;
+                let ready = request.try_complete(mem_data, clock, stdin_len)?;
+                Ok((request, ready))
+            },
+        );
 
-        0
+        match res {
+            Err(error) => Ok(wasi_errno("poll_oneoff", Err(error))),
+            Ok((request, _)) if request.is_empty() => Ok(Errno::EINVAL as i32),
+            Ok((_, true)) => Ok(Errno::SUCCESS as i32),
+            Ok((request, false)) => {
+                store_data.suspension = Some(Suspension::Poll(request));
+                Err(wasmi::Error::host(Suspended))
+            }
+        }
     });
 
+    linker_impl!(
+        m,
+        "sched_yield",
+        |mut caller: Caller<StoreData>| -> Result<i32, wasmi::Error> {
+            log::debug!("Function sched_yield() called");
+            caller.data_mut().suspension = Some(Suspension::Yield);
+            Err(wasmi::Error::host(Suspended))
+        }
+    );
+
     linker_impl!(m, "random_get", |mut caller: Caller<StoreData>,
                                    buf: i32,
                                    buf_len: i32|
      -> i32 {
         log::debug!("Function random_get() called (dest buffer {:#x})", buf);
 
-        let buf = buf as usize;
-        let buf_len = buf_len as usize;
+        let mut fill_random = || -> Result<(), GuestMemError> {
+            // Checked before allocating, since the length comes from the app
+            let buf_len = get_wasm_mem_slice(&caller, buf, buf_len)?.len();
+            let mut rand_bytes = vec![0u8; buf_len];
 
-        let mut rand_bytes = vec![0u8; buf_len];
-
-        caller.data_mut().with_step_context(|step_context| {
-            step_context.system.rng.fill_bytes(&mut rand_bytes);
-        });
-
-        let mem = get_linear_memory(&caller);
-        let mem_data = mem.data_mut(&mut caller);
+            caller.data_mut().with_step_context(|step_context| {
+                step_context.system.rng.fill_bytes(&mut rand_bytes);
+            });
 
-        mem_data[buf..buf + buf_len].copy_from_slice(&rand_bytes);
+            write_to_wasm_mem(&mut caller, buf, &rand_bytes)
+        };
 
-        0
+        wasi_errno("random_get", fill_random())
     });
 
     linker_impl!(m, "environ_sizes_get", |mut caller: Caller<StoreData>,
@@ -542,20 +1188,9 @@ fn add_host_apis(mut store: &mut Store<StoreData>, linker: &mut Linker<StoreData
             environ_buf_size
         );
 
-        let mem = get_linear_memory(&caller);
-        let mem_data = mem.data_mut(&mut caller);
-
-        let environ_count = environ_count as usize;
-        let environ_buf_size = environ_buf_size as usize;
-
-        let n_env_vars = ENV_VARS.len() as u32;
-        let string_data_size: u32 = ENV_VARS.iter().map(|s| s.len() as u32 + 1).sum();
-
-        mem_data[environ_count..environ_count + 4].copy_from_slice(&u32::to_le_bytes(n_env_vars));
-        mem_data[environ_buf_size..environ_buf_size + 4]
-            .copy_from_slice(&u32::to_le_bytes(string_data_size));
-
-        0
+        let env = caller.data().env.clone();
+        let ret = write_string_list_sizes(&mut caller, &env, environ_count, environ_buf_size);
+        wasi_errno("environ_sizes_get", ret)
     });
 
     linker_impl!(m, "environ_get", |mut caller: Caller<StoreData>,
@@ -568,25 +1203,9 @@ fn add_host_apis(mut store: &mut Store<StoreData>, linker: &mut Linker<StoreData
             environ_buf
         );
 
-        let mem = get_linear_memory(&caller);
-        let mem_data = mem.data_mut(&mut caller);
-
-        let mut p_addr = environ as usize;
-        let mut str_addr = environ_buf as usize;
-
-        for env_str in ENV_VARS.iter() {
-            let p_bytes = &u32::to_le_bytes(str_addr as u32);
-            mem_data[p_addr..p_addr + p_bytes.len()].copy_from_slice(p_bytes);
-            p_addr += p_bytes.len();
-
-            let s_bytes = env_str.as_bytes();
-            mem_data[str_addr..str_addr + s_bytes.len()].copy_from_slice(s_bytes);
-            str_addr += s_bytes.len();
-            mem_data[str_addr] = 0;
-            str_addr += 1;
-        }
-
-        0
+        let env = caller.data().env.clone();
+        let ret = write_string_list(&mut caller, &env, environ, environ_buf);
+        wasi_errno("environ_get", ret)
     });
 
     linker_impl!(m, "args_sizes_get", |mut caller: Caller<StoreData>,
@@ -594,47 +1213,113 @@ fn add_host_apis(mut store: &mut Store<StoreData>, linker: &mut Linker<StoreData
                                        argv_buf_size: i32|
      -> i32 {
         log::debug!(
-            "Function environ_sizes_get() called (dest buffers {:#x} {:#x})",
+            "Function args_sizes_get() called (dest buffers {:#x} {:#x})",
             argc,
             argv_buf_size
         );
 
-        let mem = get_linear_memory(&caller);
-        let mem_data = mem.data_mut(&mut caller);
-
-        let argc = argc as usize;
-        let argv_buf_size = argv_buf_size as usize;
+        let args = caller.data().args.clone();
+        let ret = write_string_list_sizes(&mut caller, &args, argc, argv_buf_size);
+        wasi_errno("args_sizes_get", ret)
+    });
 
-        mem_data[argc..argc + 4].fill(0x00);
-        mem_data[argv_buf_size..argv_buf_size + 4].fill(0x00);
+    linker_impl!(m, "args_get", |mut caller: Caller<StoreData>,
+                                 argv: i32,
+                                 argv_buf: i32|
+     -> i32 {
+        log::debug!(
+            "Function args_get() called (dest buffers {:#x} {:#x})",
+            argv,
+            argv_buf
+        );
 
-        0
+        let args = caller.data().args.clone();
+        let ret = write_string_list(&mut caller, &args, argv, argv_buf);
+        wasi_errno("args_get", ret)
     });
 
     linker_impl!(m, "fd_write", |mut caller: Caller<StoreData>,
-                                 _fd: i32,
+                                 fd: i32,
                                  iovs: i32,
-                                 _iovs_len: i32,
+                                 iovs_len: i32,
                                  nwritten: i32|
      -> i32 {
-        //log::debug!("Function fd_write() called (fd {} iovs_len {})", fd, iovs_len);
+        let mut write = || -> Result<(), WasiError> {
+            if fd != 1 && fd != 2 {
+                return Err(WasiError::Errno(Errno::EBADFS));
+            }
 
-        let mem = get_linear_memory(&caller);
-        let mem_data = mem.data_mut(&mut caller);
+            let mut data = Vec::new();
+            for (buf, buf_len) in read_iovecs(&caller, iovs, iovs_len)? {
+                let buf = get_wasm_mem_slice(&caller, buf, buf_len)?;
+                let n = usize::min(buf.len(), stdio::MAX_WRITE_LEN - data.len());
+                data.extend_from_slice(&buf[..n]);
+            }
 
-        let iovs = iovs as usize;
-        let nwritten = nwritten as usize;
+            write_to_wasm_mem(&mut caller, nwritten, &(data.len() as u32).to_le_bytes())?;
 
-        let buf_ptr = u32::from_le_bytes(mem_data[iovs..iovs + 4].try_into().unwrap()) as usize;
-        let buf_len = u32::from_le_bytes(mem_data[iovs + 4..iovs + 8].try_into().unwrap()) as usize;
+            let store_data = caller.data_mut();
+            if let (1, Some(piped_stdout)) = (fd, &mut store_data.piped_stdout) {
+                piped_stdout.extend_from_slice(&data);
+            }
 
-        let s = core::str::from_utf8(&mem_data[buf_ptr..buf_ptr + buf_len]).unwrap();
+            let text = String::from_utf8_lossy(&data);
+            store_data.with_step_context(|step_context| {
+                let StepContextView {
+                    uuid_provider,
+                    console_output,
+                    ..
+                } = step_context;
+                let console_output = console_output.mutate(uuid_provider);
+                match fd {
+                    1 => console_output.write_str(&text).unwrap(),
+                    _ => console_output.write_stderr(&text),
+                }
+            });
 
-        log::debug!("{}", s);
+            Ok(())
+        };
 
-        mem_data[nwritten..nwritten + 4].copy_from_slice((buf_len as u32).to_le_bytes().as_slice());
+        wasi_errno("fd_write", write())
+    });
+
+    linker_impl!(m, "fd_read", |mut caller: Caller<StoreData>,
+                                fd: i32,
+                                iovs: i32,
+                                iovs_len: i32,
+                                nread: i32|
+     -> i32 {
+        let mut read = || -> Result<(), WasiError> {
+            if fd != 0 {
+                return Err(WasiError::Errno(Errno::EBADFS));
+            }
+
+            // Checking all buffers before consuming any input
+            let iovecs = read_iovecs(&caller, iovs, iovs_len)?;
+            for &(buf, buf_len) in iovecs.iter() {
+                get_wasm_mem_slice(&caller, buf, buf_len)?;
+            }
+            get_wasm_mem_slice(&caller, nread, 4)?;
 
-        0
+            if caller.data().stdin.is_empty() {
+                return Err(WasiError::Errno(Errno::EAGAIN));
+            }
+
+            let mut n_read: u32 = 0;
+            for (buf, buf_len) in iovecs {
+                let stdin = &mut caller.data_mut().stdin;
+                let n = usize::min(buf_len as u32 as usize, stdin.len());
+                let bytes: Vec<u8> = stdin.drain(..n).collect();
+                write_to_wasm_mem(&mut caller, buf, &bytes)?;
+                n_read += n as u32;
+            }
+
+            write_to_wasm_mem(&mut caller, nread, &n_read.to_le_bytes())?;
+
+            Ok(())
+        };
+
+        wasi_errno("fd_read", read())
     });
 
     //
@@ -645,51 +1330,86 @@ fn add_host_apis(mut store: &mut Store<StoreData>, linker: &mut Linker<StoreData
     linker_impl!(m, "host_log", |mut caller: Caller<StoreData>,
                                  addr: i32,
                                  len: i32,
-                                 level| {
-        let mem_slice = get_wasm_mem_slice(&caller, addr, len);
+                                 level|
+     -> Result<(), wasmi::Error> {
+        let mem_slice = get_wasm_mem_slice(&caller, addr, len)?;
 
-        let msg = core::str::from_utf8(mem_slice)
-            .expect("Not UTF-8")
-            .trim_end()
-            .to_owned();
+        let msg = String::from_utf8_lossy(mem_slice).trim_end().to_owned();
 
         caller.data_mut().with_step_context(|mut step_context| {
             log_message(&msg, level, &mut step_context);
         });
+
+        Ok(())
     });
 
-    linker_impl!(
-        m,
-        "host_get_input_state",
-        |mut caller: Caller<StoreData>, addr: i32| {
-            let system_state = caller
-                .data_mut()
-                .with_step_context(|step_context| step_context.input_state.clone());
+    if legacy_input_api {
+        // Version 1: the guest buffer is exactly as large as its InputState
+        linker_impl!(
+            m,
+            "host_get_input_state",
+            |mut caller: Caller<StoreData>, addr: i32| -> Result<(), wasmi::Error> {
+                let payload = caller.data_mut().with_step_context(|step_context| {
+                    abi::encode_input_state(step_context.input_state, abi::LEGACY_ABI_VERSION)
+                });
+                write_to_wasm_mem(&mut caller, addr, &payload)?;
+                Ok(())
+            }
+        );
+    } else {
+        linker_impl!(
+            m,
+            "host_get_input_state",
+            |mut caller: Caller<StoreData>, addr: i32, max_len: i32| -> Result<i32, wasmi::Error> {
+                let abi_version = caller.data().abi_version;
+                let payload = caller.data_mut().with_step_context(|step_context| {
+                    abi::encode_input_state(step_context.input_state, abi_version)
+                });
+
+                // Buffer layout: [u32 payload length][payload]
+                // If the guest buffer is too small, nothing is written and the guest
+                // is expected to retry with at least the returned size.
+                let required_len = size_of::<u32>() + payload.len();
+
+                if required_len <= max_len as u32 as usize {
+                    let len_prefix = (payload.len() as u32).to_le_bytes();
+                    let mem_slice = get_wasm_mem_slice_mut(&mut caller, addr, required_len as i32)?;
+                    mem_slice[..size_of::<u32>()].copy_from_slice(&len_prefix);
+                    mem_slice[size_of::<u32>()..].copy_from_slice(&payload);
+                }
 
-            write_to_wasm_mem(&mut caller, addr, &system_state);
-        }
-    );
+                Ok(required_len as i32)
+            }
+        );
+    }
 
-    linker_impl!(
-        m,
-        "host_get_win_rect",
-        |mut caller: Caller<StoreData>, addr: i32| {
-            let win_rect = caller
-                .data_mut()
-                .with_step_context(|step_context| step_context.win_rect.clone());
-            write_to_wasm_mem(&mut caller, addr, &win_rect);
-        }
-    );
+    linker_impl!(m, "host_get_win_rect", |mut caller: Caller<StoreData>,
+                                          addr: i32|
+     -> Result<(), wasmi::Error> {
+        let win_rect = caller
+            .data_mut()
+            .with_step_context(|step_context| abi::encode_rect(step_context.win_rect));
+        write_to_wasm_mem(&mut caller, addr, &win_rect)?;
+        Ok(())
+    });
 
     linker_impl!(
         m,
         "host_set_framebuffer",
-        |mut caller: Caller<StoreData>, addr: i32, w: i32, h: i32| {
+        |mut caller: Caller<StoreData>, addr: i32, w: i32, h: i32| -> Result<(), wasmi::Error> {
+            let (w, h) = (w as u32, h as u32);
+
+            // The framebuffer is read by the kernel later on, so it must fit in memory
+            let mem_size = get_linear_memory(&caller).data(&caller).len();
+            let range = guest_mem_range(mem_size, addr, w as u64 * h as u64 * 4)?;
+
             caller.data_mut().framebuffer = Some(WasmFramebufferDef {
-                addr: addr as usize,
-                w: w as u32,
-                h: h as u32,
+                addr: range.start,
+                w,
+                h,
             });
+
+            Ok(())
         }
     );
 
@@ -699,7 +1419,8 @@ fn add_host_apis(mut store: &mut Store<StoreData>, linker: &mut Linker<StoreData
      -> i32 {
         let mut try_connect = || -> anyhow::Result<i32> {
             let ip_bytes = ip_addr.to_le_bytes();
-            let port: u16 = port.try_into().expect("Invalid port value");
+            let port = u16::try_from(port)
+                .map_err(|_| anyhow::format_err!("Invalid TCP port {}", port))?;
 
             let socket_handle = caller.data_mut().with_step_context(|step_context| {
                 step_context
@@ -724,11 +1445,9 @@ fn add_host_apis(mut store: &mut Store<StoreData>, linker: &mut Linker<StoreData
     linker_impl!(m, "host_tcp_may_send", |mut caller: Caller<StoreData>,
                                           handle_id: i32|
      -> i32 {
-        let socket_handle = caller
-            .data_mut()
-            .sockets_store
-            .get_handle(handle_id)
-            .expect("No TCP connection");
+        let Some(socket_handle) = caller.data().sockets_store.get_handle(handle_id) else {
+            return 0;
+        };
 
         let ret: bool = caller.data_mut().with_step_context(|step_context| {
             step_context.system.tcp_stack.may_send(socket_handle).into()
@@ -740,11 +1459,9 @@ fn add_host_apis(mut store: &mut Store<StoreData>, linker: &mut Linker<StoreData
     linker_impl!(m, "host_tcp_may_recv", |mut caller: Caller<StoreData>,
                                           handle_id: i32|
      -> i32 {
-        let socket_handle = caller
-            .data_mut()
-            .sockets_store
-            .get_handle(handle_id)
-            .expect("No TCP connection");
+        let Some(socket_handle) = caller.data().sockets_store.get_handle(handle_id) else {
+            return 0;
+        };
 
         let ret: bool = caller.data_mut().with_step_context(|step_context| {
             step_context.system.tcp_stack.may_recv(socket_handle).into()
@@ -759,13 +1476,15 @@ fn add_host_apis(mut store: &mut Store<StoreData>, linker: &mut Linker<StoreData
                                        handle_id: i32|
      -> i32 {
         let mut try_write = || -> anyhow::Result<usize> {
-            let buf = get_wasm_mem_slice(&mut caller, addr, len).to_vec();
+            let buf = get_wasm_mem_slice(&caller, addr, len)
+                .map_err(|error| anyhow::format_err!("{}", error))?
+                .to_vec();
 
             let socket_handle = caller
                 .data_mut()
                 .sockets_store
                 .get_handle(handle_id)
-                .expect("No TCP connection");
+                .ok_or_else(|| anyhow::format_err!("Stale TCP connection"))?;
 
             let written_len = caller.data_mut().with_step_context(|step_context| {
                 step_context.system.tcp_stack.write(socket_handle, &buf)
@@ -792,8 +1511,10 @@ fn add_host_apis(mut store: &mut Store<StoreData>, linker: &mut Linker<StoreData
                                       handle_id: i32|
      -> i32 {
         let mut try_read = || -> anyhow::Result<i32> {
-            let len = len as usize;
-            let addr = addr as usize;
+            // Checked before reading, so that no data is lost
+            let len = get_wasm_mem_slice(&caller, addr, len)
+                .map_err(|error| anyhow::format_err!("{}", error))?
+                .len();
 
             let mut buf = vec![0u8; len];
 
@@ -802,16 +1523,14 @@ fn add_host_apis(mut store: &mut Store<StoreData>, linker: &mut Linker<StoreData
                     .data_mut()
                     .sockets_store
                     .get_handle(handle_id)
-                    .expect("No TCP connection");
+                    .ok_or_else(|| anyhow::format_err!("Stale TCP connection"))?;
                 caller.data_mut().with_step_context(|step_context| {
                     step_context.system.tcp_stack.read(socket_handle, &mut buf)
                 })?
             };
 
-            let mem = get_linear_memory(&caller);
-            let mem_data = mem.data_mut(&mut caller);
-
-            mem_data[addr..addr + read_len].copy_from_slice(&buf[..read_len]);
+            write_to_wasm_mem(&mut caller, addr, &buf[..read_len])
+                .map_err(|error| anyhow::format_err!("{}", error))?;
 
             caller.data_mut().net_recv += read_len;
 
@@ -831,11 +1550,11 @@ fn add_host_apis(mut store: &mut Store<StoreData>, linker: &mut Linker<StoreData
         m,
         "host_tcp_close",
         |mut caller: Caller<StoreData>, handle_id: i32| {
-            let socket_handle = caller
-                .data_mut()
-                .sockets_store
-                .get_handle(handle_id)
-                .expect("No TCP connection");
+            // Removed first, so that the app cannot use the freed socket anymore
+            let Some(socket_handle) = caller.data_mut().sockets_store.remove_handle(handle_id)
+            else {
+                return;
+            };
 
             caller.data_mut().with_step_context(|step_context| {
                 step_context.system.tcp_stack.close(socket_handle)
@@ -843,82 +1562,141 @@ fn add_host_apis(mut store: &mut Store<StoreData>, linker: &mut Linker<StoreData
         }
     );
 
-    linker_impl!(
-        m,
-        "host_get_time",
-        |mut caller: Caller<StoreData>, buf: i32| {
-            let buf = buf as usize;
-
-            let t = caller
-                .data_mut()
-                .with_step_context(|step_context| step_context.system.clock.time());
-
-            let mem = get_linear_memory(&caller);
-            let mem_data = mem.data_mut(&mut caller);
+    linker_impl!(m, "host_get_time", |mut caller: Caller<StoreData>,
+                                      buf: i32|
+     -> Result<(), wasmi::Error> {
+        let t = caller
+            .data_mut()
+            .with_step_context(|step_context| step_context.system.clock.time());
 
-            let data = t.to_le_bytes();
-            mem_data[buf..buf + 8].copy_from_slice(&data);
-        }
-    );
+        write_to_wasm_mem(&mut caller, buf, &t.to_le_bytes())?;
+        Ok(())
+    });
 
-    linker_impl!(
-        m,
-        "host_get_stylesheet",
-        |mut caller: Caller<StoreData>, addr: i32| {
-            let stylesheet = caller
-                .data_mut()
-                .with_step_context(|step_context| step_context.system.stylesheet.clone());
+    linker_impl!(m, "host_get_stylesheet", |mut caller: Caller<StoreData>,
+                                            addr: i32|
+     -> Result<(), wasmi::Error> {
+        let stylesheet = caller.data_mut().with_step_context(|step_context| {
+            abi::encode_stylesheet(step_context.system.stylesheet)
+        });
 
-            write_to_wasm_mem(&mut caller, addr, &stylesheet);
-        }
-    );
+        write_to_wasm_mem(&mut caller, addr, &stylesheet)?;
+        Ok(())
+    });
 
     linker_impl!(
         m,
         "host_get_consumed_fuel",
-        |mut caller: Caller<StoreData>, consumed_addr: i32| {
+        |mut caller: Caller<StoreData>, consumed_addr: i32| -> Result<(), wasmi::Error> {
             let remaining = caller.get_fuel().expect("Fuel metering disabled");
             let consumed = STEP_FUEL - remaining;
-            write_to_wasm_mem(&mut caller, consumed_addr, &consumed.to_le_bytes());
+            write_to_wasm_mem(&mut caller, consumed_addr, &consumed.to_le_bytes())?;
+            Ok(())
         }
     );
 
+    linker_impl!(m, "host_save_timing", |mut caller: Caller<StoreData>,
+                                         key_addr: i32,
+                                         key_len: i32,
+                                         consumed_addr: i32|
+     -> Result<(), wasmi::Error> {
+        let key_buf = get_wasm_mem_slice(&caller, key_addr, key_len)?;
+        let key = String::from_utf8_lossy(key_buf).to_string();
+
+        let consumed_buf: [u8; 8] = get_wasm_mem_slice(&caller, consumed_addr, 8)?
+            .try_into()
+            .unwrap();
+        let consumed: u64 = u64::from_le_bytes(consumed_buf);
+
+        caller
+            .data_mut()
+            .with_step_context(|step_context| step_context.timings.insert(key.clone(), consumed));
+
+        Ok(())
+    });
+
     linker_impl!(
         m,
-        "host_save_timing",
-        |mut caller: Caller<StoreData>, key_addr: i32, key_len: i32, consumed_addr: i32| {
-            let key_buf = get_wasm_mem_slice(&mut caller, key_addr, key_len);
-            let key = core::str::from_utf8(key_buf)
-                .expect("Invalid key")
-                .to_string();
-
-            let consumed_buf: [u8; 8] = get_wasm_mem_slice(&mut caller, consumed_addr, 8)
-                .try_into()
-                .unwrap();
-            let consumed: u64 = u64::from_le_bytes(consumed_buf);
-
+        "host_take_screenshot",
+        |mut caller: Caller<StoreData>| {
             caller.data_mut().with_step_context(|step_context| {
-                step_context.timings.insert(key.clone(), consumed)
-            });
+                step_context.system.capture.request_screenshot()
+            })
         }
     );
 
     linker_impl!(
         m,
-        "host_qemu_dump",
-        |caller: Caller<StoreData>, addr: i32, len: i32| {
-            let mem_slice = get_wasm_mem_slice(&caller, addr, len);
-            let buf = mem_slice.to_vec();
-
-            let phys_addr = buf.leak().as_mut_ptr() as u64;
-
-            log::debug!(
-                "QEMU DUMP: pmemsave 0x{:x} {} pmem_dump.bin",
-                phys_addr,
-                len
-            );
+        "host_take_window_screenshot",
+        |mut caller: Caller<StoreData>| {
+            let app_name = caller.data().app_name.clone();
+            caller.data_mut().with_step_context(|step_context| {
+                step_context
+                    .system
+                    .capture
+                    .request_window_screenshot(&app_name)
+            })
         }
     );
+
+    linker_impl!(m, "host_qemu_dump", |mut caller: Caller<StoreData>,
+                                       addr: i32,
+                                       len: i32|
+     -> Result<(), wasmi::Error> {
+        let mem_slice = get_wasm_mem_slice(&caller, addr, len)?;
+        let buf = mem_slice.to_vec();
+
+        // Only the last dump is kept, until the next one or until the app is dropped
+        let phys_addr = buf.as_ptr() as u64;
+        caller.data_mut().qemu_dump = buf;
+
+        log::debug!(
+            "QEMU DUMP: pmemsave 0x{:x} {} pmem_dump.bin",
+            phys_addr,
+            len
+        );
+
+        Ok(())
+    });
+}
+
+// For args_sizes_get() and environ_sizes_get(): number of strings, and size of the buffer
+// holding all of them (null-terminated)
+fn write_string_list_sizes(
+    caller: &mut Caller<StoreData>,
+    strings: &[String],
+    count_addr: i32,
+    buf_size_addr: i32,
+) -> Result<(), GuestMemError> {
+    let count = strings.len() as u32;
+    let buf_size: u32 = strings.iter().map(|s| s.len() as u32 + 1).sum();
+
+    write_to_wasm_mem(caller, count_addr, &count.to_le_bytes())?;
+    write_to_wasm_mem(caller, buf_size_addr, &buf_size.to_le_bytes())
+}
+
+// For args_get() and environ_get(): an array of pointers to the strings, which are written
+// one after the other in the buffer
+fn write_string_list(
+    caller: &mut Caller<StoreData>,
+    strings: &[String],
+    ptrs_addr: i32,
+    buf_addr: i32,
+) -> Result<(), GuestMemError> {
+    let mut p_addr = ptrs_addr;
+    let mut str_addr = buf_addr;
+
+    for s in strings.iter() {
+        write_to_wasm_mem(caller, p_addr, &str_addr.to_le_bytes())?;
+        p_addr = p_addr.wrapping_add(4);
+
+        let mut s_bytes = s.as_bytes().to_vec();
+        s_bytes.push(0);
+        write_to_wasm_mem(caller, str_addr, &s_bytes)?;
+        str_addr = str_addr.wrapping_add(s_bytes.len() as i32);
+    }
+
+    Ok(())
 }
 
 fn log_message(msg: &str, level: i32, step_context: &mut StepContextView) {
@@ -941,8 +1719,34 @@ fn log_message(msg: &str, level: i32, step_context: &mut StepContextView) {
 }
 
 #[repr(i32)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 enum Errno {
     SUCCESS = 0,
+    EAGAIN = 6,
     EBADFS = 8,
+    EFAULT = 21,
+    EINVAL = 28,
+}
+
+enum WasiError {
+    Mem(GuestMemError),
+    Errno(Errno),
+}
+
+impl From<GuestMemError> for WasiError {
+    fn from(error: GuestMemError) -> Self {
+        WasiError::Mem(error)
+    }
+}
+
+// WASI functions report invalid guest memory accesses with EFAULT
+fn wasi_errno<E: Into<WasiError>>(func_name: &str, ret: Result<(), E>) -> i32 {
+    match ret.map_err(Into::into) {
+        Ok(()) => Errno::SUCCESS as i32,
+        Err(WasiError::Mem(error)) => {
+            log::warn!("{}(): {}", func_name, error);
+            Errno::EFAULT as i32
+        }
+        Err(WasiError::Errno(errno)) => errno as i32,
+    }
 }
@@ -0,0 +1,230 @@
+use alloc::vec::Vec;
+
+use super::stdio::MAX_WRITE_LEN;
+use super::{guest_mem_range, Errno, GuestMemError};
+use crate::time::SystemClock;
+
+//
+// Waiting in apps
+//
+// Apps are stepped, so a host call cannot block the core until some event happens. Instead,
+// poll_oneoff() (which std::thread::sleep() relies on) and sched_yield() suspend the app: the
+// host function fails with a Suspended error, which stops the guest call in a resumable state,
+// and the call is resumed on a later step of the app (see WasmApp::step()), once one of the
+// subscriptions is ready.
+// Only the standard streams are file descriptors for now: subscriptions to other ones are
+// reported as failed events (EBADF), the same way fd_read() and fd_write() reject them.
+
+const SUBSCRIPTION_SIZE: u64 = 48;
+const EVENT_SIZE: u64 = 32;
+
+// WASI eventtype
+const EVENTTYPE_CLOCK: u8 = 0;
+const EVENTTYPE_FD_READ: u8 = 1;
+const EVENTTYPE_FD_WRITE: u8 = 2;
+
+// WASI subclockflags: the timeout is an absolute time rather than a duration
+const SUBSCRIPTION_CLOCK_ABSTIME: u16 = 1;
+
+// Returned by host functions to suspend the guest call (the reason is in StoreData)
+#[derive(Debug)]
+pub struct Suspended;
+
+impl core::fmt::Display for Suspended {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "App suspended")
+    }
+}
+
+impl wasmi::core::HostError for Suspended {}
+
+// Why an app is suspended
+pub enum Suspension {
+    // Resumed on the next step
+    Yield,
+    Poll(PollRequest),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ClockId {
+    Realtime,
+    Monotonic,
+}
+
+impl ClockId {
+    // CPU time clocks are not supported
+    pub fn from_wasi(clock_id: i32) -> Option<Self> {
+        match clock_id {
+            0 => Some(ClockId::Realtime),
+            1 => Some(ClockId::Monotonic),
+            _ => None,
+        }
+    }
+
+    pub fn now_ns(&self, clock: &SystemClock) -> u64 {
+        match self {
+            ClockId::Realtime => clock.realtime_ns(),
+            ClockId::Monotonic => clock.monotonic_ns(),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Subscription {
+    Clock { clock_id: ClockId, deadline_ns: u64 },
+    FdRead { fd: u32 },
+    FdWrite { fd: u32 },
+    // Ready right away, with an error
+    Invalid { event_type: u8, errno: Errno },
+}
+
+pub struct PollRequest {
+    // With their userdata
+    subscriptions: Vec<(u64, Subscription)>,
+    out: i32,
+    nevents: i32,
+}
+
+struct Event {
+    userdata: u64,
+    errno: Errno,
+    event_type: u8,
+    nbytes: u64,
+}
+
+impl PollRequest {
+    // Relative timeouts are turned into deadlines right away. The output buffers are checked
+    // upfront as well, so that an invalid call fails before suspending the app.
+    pub fn parse(
+        mem: &[u8],
+        clock: &SystemClock,
+        subscriptions: i32,
+        out: i32,
+        nsubscriptions: i32,
+        nevents: i32,
+    ) -> Result<Self, GuestMemError> {
+        let n = nsubscriptions as u32 as u64;
+        guest_mem_range(mem.len(), out, n * EVENT_SIZE)?;
+        guest_mem_range(mem.len(), nevents, 4)?;
+        let range = guest_mem_range(mem.len(), subscriptions, n * SUBSCRIPTION_SIZE)?;
+
+        let subscriptions = mem[range]
+            .chunks_exact(SUBSCRIPTION_SIZE as usize)
+            .map(|sub| {
+                let read_u16 = |i: usize| u16::from_le_bytes(sub[i..i + 2].try_into().unwrap());
+                let read_u32 = |i: usize| u32::from_le_bytes(sub[i..i + 4].try_into().unwrap());
+                let read_u64 = |i: usize| u64::from_le_bytes(sub[i..i + 8].try_into().unwrap());
+
+                let userdata = read_u64(0);
+                let event_type = sub[8];
+
+                let subscription = match event_type {
+                    EVENTTYPE_CLOCK => match ClockId::from_wasi(read_u32(16) as i32) {
+                        Some(clock_id) => {
+                            let timeout = read_u64(24);
+                            let deadline_ns = match read_u16(40) & SUBSCRIPTION_CLOCK_ABSTIME {
+                                0 => clock_id.now_ns(clock).saturating_add(timeout),
+                                _ => timeout,
+                            };
+                            Subscription::Clock {
+                                clock_id,
+                                deadline_ns,
+                            }
+                        }
+                        None => Subscription::Invalid {
+                            event_type,
+                            errno: Errno::EINVAL,
+                        },
+                    },
+                    EVENTTYPE_FD_READ => Subscription::FdRead { fd: read_u32(16) },
+                    EVENTTYPE_FD_WRITE => Subscription::FdWrite { fd: read_u32(16) },
+                    _ => Subscription::Invalid {
+                        event_type,
+                        errno: Errno::EINVAL,
+                    },
+                };
+
+                (userdata, subscription)
+            })
+            .collect();
+
+        Ok(PollRequest {
+            subscriptions,
+            out,
+            nevents,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscriptions.is_empty()
+    }
+
+    // Writes the events of the subscriptions which are ready, if any.
+    // Returns false if the app should keep waiting.
+    pub fn try_complete(
+        &self,
+        mem: &mut [u8],
+        clock: &SystemClock,
+        stdin_len: usize,
+    ) -> Result<bool, GuestMemError> {
+        let events: Vec<Event> = self
+            .subscriptions
+            .iter()
+            .filter_map(|(userdata, subscription)| {
+                let (errno, event_type, nbytes) = match *subscription {
+                    Subscription::Clock {
+                        clock_id,
+                        deadline_ns,
+                    } => match clock_id.now_ns(clock) >= deadline_ns {
+                        true => (Errno::SUCCESS, EVENTTYPE_CLOCK, 0),
+                        false => return None,
+                    },
+                    Subscription::FdRead { fd: 0 } => match stdin_len {
+                        0 => return None,
+                        n => (Errno::SUCCESS, EVENTTYPE_FD_READ, n as u64),
+                    },
+                    Subscription::FdWrite { fd: 1 | 2 } => {
+                        (Errno::SUCCESS, EVENTTYPE_FD_WRITE, MAX_WRITE_LEN as u64)
+                    }
+                    Subscription::FdRead { .. } => (Errno::EBADFS, EVENTTYPE_FD_READ, 0),
+                    Subscription::FdWrite { .. } => (Errno::EBADFS, EVENTTYPE_FD_WRITE, 0),
+                    Subscription::Invalid { event_type, errno } => (errno, event_type, 0),
+                };
+
+                Some(Event {
+                    userdata: *userdata,
+                    errno,
+                    event_type,
+                    nbytes,
+                })
+            })
+            .collect();
+
+        if events.is_empty() {
+            return Ok(false);
+        }
+
+        for (i, event) in events.iter().enumerate() {
+            let addr = self.out.wrapping_add((i as u64 * EVENT_SIZE) as i32);
+            let range = guest_mem_range(mem.len(), addr, EVENT_SIZE)?;
+            mem[range].copy_from_slice(&event.to_bytes());
+        }
+
+        let range = guest_mem_range(mem.len(), self.nevents, 4)?;
+        mem[range].copy_from_slice(&(events.len() as u32).to_le_bytes());
+
+        Ok(true)
+    }
+}
+
+impl Event {
+    // WASI event layout (fd_readwrite is left empty for clock events)
+    fn to_bytes(&self) -> [u8; EVENT_SIZE as usize] {
+        let mut bytes = [0u8; EVENT_SIZE as usize];
+        bytes[0..8].copy_from_slice(&self.userdata.to_le_bytes());
+        bytes[8..10].copy_from_slice(&(self.errno as u16).to_le_bytes());
+        bytes[10] = self.event_type;
+        bytes[16..24].copy_from_slice(&self.nbytes.to_le_bytes());
+        bytes
+    }
+}
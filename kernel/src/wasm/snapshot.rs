@@ -0,0 +1,306 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use anyhow::format_err;
+use applib::content::UuidProvider;
+use wasmi::core::{F32, F64};
+use wasmi::{Instance, Store, Val};
+
+use super::binary::{
+    append_to_vec_section, count_imports, find_section, read_globals_mutability, read_sections,
+    section_insert_index, write_module, write_name, write_uleb, Reader, SECTION_EXPORT,
+    SECTION_GLOBAL, SECTION_IMPORT,
+};
+use super::{ConsoleOutput, SocketsStore, StoreData, WasmFramebufferDef};
+
+const SNAPSHOT_MAGIC: &[u8; 8] = b"MUNALSNP";
+const SNAPSHOT_VERSION: u8 = 2;
+
+const GLOBAL_EXPORT_PREFIX: &str = "__munal_global_";
+
+const PAGE_SIZE: usize = 65_536;
+
+//
+// App snapshots
+//
+// The whole state of an app is in its wasmi store: linear memory, globals and tables, plus what
+// the kernel keeps on its side (framebuffer definition, console output, sockets). A snapshot
+// saves the memory and the mutable globals; wasmi only gives access to exported globals, so all
+// of them are exported when the app is loaded. Restoring instantiates the module again without
+// calling init(), then overwrites its state.
+// Tables are not saved: they are expected to stay as initialized by the module, which is the
+// case for apps built from Rust. Sockets cannot be saved either, their IDs refer to closed
+// connections after a restore, and pending stdin input is dropped.
+//
+// The format is self-contained, so that snapshots can outlive the kernel which took them:
+//   magic, version, module hash (u64)
+//   framebuffer (flag, then address, width and height)
+//   next socket ID, console output (text, then the byte ranges written to stderr)
+//   globals (count, then value type and bits as u64 for each one)
+//   memory (page count, then for each page either 0 if it is all zeroes, or 1 and its content)
+
+// Exports the mutable globals defined by the module. Returns the new module and the number of
+// globals exported.
+pub fn export_globals(module: &[u8]) -> anyhow::Result<(Vec<u8>, u32)> {
+    let sections = read_sections(module)?;
+    let section_data = |id: u8| find_section(&sections, id).map(|section| section.data);
+
+    let (_, n_imported_globals) = match section_data(SECTION_IMPORT) {
+        Some(data) => count_imports(data)?,
+        None => (0, 0),
+    };
+    let mutability = match section_data(SECTION_GLOBAL) {
+        Some(data) => read_globals_mutability(data)?,
+        None => Vec::new(),
+    };
+
+    let mut new_exports = Vec::new();
+    let mut n_exported = 0;
+    for (i, _) in mutability
+        .iter()
+        .enumerate()
+        .filter(|(_, mutable)| **mutable)
+    {
+        write_name(&mut new_exports, &global_export_name(n_exported));
+        new_exports.push(0x03);
+        write_uleb(&mut new_exports, (n_imported_globals + i as u32) as u64);
+        n_exported += 1;
+    }
+
+    let export_section =
+        append_to_vec_section(section_data(SECTION_EXPORT), n_exported, &new_exports)?;
+
+    let mut new_sections: Vec<(u8, &[u8])> = sections
+        .iter()
+        .map(|section| (section.id, section.data))
+        .collect();
+
+    match new_sections
+        .iter_mut()
+        .find(|(id, _)| *id == SECTION_EXPORT)
+    {
+        Some(section) => section.1 = export_section.as_slice(),
+        None => {
+            let index = section_insert_index(&new_sections, SECTION_EXPORT);
+            new_sections.insert(index, (SECTION_EXPORT, export_section.as_slice()));
+        }
+    }
+
+    Ok((write_module(&new_sections), n_exported))
+}
+
+fn global_export_name(index: u32) -> String {
+    format!("{}{}", GLOBAL_EXPORT_PREFIX, index)
+}
+
+// FNV-1a, to check that a snapshot is restored with the same module
+pub fn module_hash(module: &[u8]) -> u64 {
+    module.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+pub fn capture(
+    store: &Store<StoreData>,
+    instance: &Instance,
+    n_globals: u32,
+    module_hash: u64,
+) -> anyhow::Result<Vec<u8>> {
+    let mut out = Vec::new();
+
+    out.extend_from_slice(SNAPSHOT_MAGIC);
+    out.push(SNAPSHOT_VERSION);
+    out.extend_from_slice(&module_hash.to_le_bytes());
+
+    //
+    // Kernel-side state
+
+    let data = store.data();
+
+    match &data.framebuffer {
+        Some(WasmFramebufferDef { addr, w, h }) => {
+            out.push(1);
+            write_uleb(&mut out, *addr as u64);
+            write_uleb(&mut out, *w as u64);
+            write_uleb(&mut out, *h as u64);
+        }
+        None => out.push(0),
+    }
+
+    write_uleb(&mut out, data.sockets_store.next_id as u64);
+    let console_output = data.console_output.as_ref();
+    write_name(&mut out, console_output.as_str());
+    write_uleb(&mut out, console_output.stderr_ranges().len() as u64);
+    for range in console_output.stderr_ranges() {
+        write_uleb(&mut out, range.start as u64);
+        write_uleb(&mut out, range.end as u64);
+    }
+
+    //
+    // Globals
+
+    write_uleb(&mut out, n_globals as u64);
+    for i in 0..n_globals {
+        let name = global_export_name(i);
+        let global = instance
+            .get_global(store, &name)
+            .ok_or_else(|| format_err!("Missing global export {}", name))?;
+
+        let (valtype, bits): (u8, u64) = match global.get(store) {
+            Val::I32(val) => (0x7f, val as u32 as u64),
+            Val::I64(val) => (0x7e, val as u64),
+            Val::F32(val) => (0x7d, val.to_bits() as u64),
+            Val::F64(val) => (0x7c, val.to_bits()),
+            _ => return Err(format_err!("Unsupported type for global {}", name)),
+        };
+
+        out.push(valtype);
+        out.extend_from_slice(&bits.to_le_bytes());
+    }
+
+    //
+    // Linear memory
+
+    let memory = instance
+        .get_memory(store, "memory")
+        .ok_or_else(|| format_err!("No memory export"))?;
+    let mem_data = memory.data(store);
+
+    write_uleb(&mut out, (mem_data.len() / PAGE_SIZE) as u64);
+    for page in mem_data.chunks(PAGE_SIZE) {
+        match page.iter().all(|&byte| byte == 0) {
+            true => out.push(0),
+            false => {
+                out.push(1);
+                out.extend_from_slice(page);
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+// The store must come from a fresh instantiation of the same module, on which init() was not
+// called
+pub fn restore(
+    store: &mut Store<StoreData>,
+    instance: &Instance,
+    uuid_provider: &mut UuidProvider,
+    n_globals: u32,
+    module_hash: u64,
+    snapshot: &[u8],
+) -> anyhow::Result<()> {
+    let mut reader = Reader::new(snapshot);
+
+    if reader.bytes(SNAPSHOT_MAGIC.len())? != SNAPSHOT_MAGIC {
+        return Err(format_err!("Not an app snapshot"));
+    }
+    let version = reader.u8()?;
+    if version != SNAPSHOT_VERSION {
+        return Err(format_err!("Unsupported snapshot version {}", version));
+    }
+    if reader.u64()? != module_hash {
+        return Err(format_err!(
+            "Snapshot taken with a different build of the app"
+        ));
+    }
+
+    //
+    // Kernel-side state (only applied once everything else was restored)
+
+    let framebuffer = match reader.u8()? {
+        0 => None,
+        _ => Some(WasmFramebufferDef {
+            addr: reader.uleb()? as usize,
+            w: reader.uleb32()?,
+            h: reader.uleb32()?,
+        }),
+    };
+
+    let next_socket_id = reader.uleb32()? as i32;
+    let console_text = core::str::from_utf8(reader.name()?)
+        .map_err(|_| format_err!("Invalid console output in snapshot"))?;
+
+    let mut stderr_ranges = Vec::new();
+    let mut prev_end = 0;
+    for _ in 0..reader.uleb32()? {
+        let start = reader.uleb()? as usize;
+        let end = reader.uleb()? as usize;
+        let valid = prev_end <= start && start < end && console_text.get(start..end).is_some();
+        if !valid {
+            return Err(format_err!("Invalid console output in snapshot"));
+        }
+        stderr_ranges.push(start..end);
+        prev_end = end;
+    }
+    let console_output = ConsoleOutput::restored(console_text.to_string(), stderr_ranges);
+
+    //
+    // Globals
+
+    let n_saved_globals = reader.uleb32()?;
+    if n_saved_globals != n_globals {
+        return Err(format_err!(
+            "Snapshot has {} globals, the app has {}",
+            n_saved_globals,
+            n_globals
+        ));
+    }
+
+    for i in 0..n_globals {
+        let name = global_export_name(i);
+        let valtype = reader.u8()?;
+        let bits = reader.u64()?;
+
+        let val = match valtype {
+            0x7f => Val::I32(bits as u32 as i32),
+            0x7e => Val::I64(bits as i64),
+            0x7d => Val::F32(F32::from_bits(bits as u32)),
+            0x7c => Val::F64(F64::from_bits(bits)),
+            _ => return Err(format_err!("Invalid value type {:#x} in snapshot", valtype)),
+        };
+
+        let global = instance
+            .get_global(&*store, &name)
+            .ok_or_else(|| format_err!("Missing global export {}", name))?;
+        global
+            .set(&mut *store, val)
+            .map_err(|error| format_err!("Cannot restore global {}: {}", name, error))?;
+    }
+
+    //
+    // Linear memory
+
+    let memory = instance
+        .get_memory(&*store, "memory")
+        .ok_or_else(|| format_err!("No memory export"))?;
+
+    let n_pages = reader.uleb()? as usize;
+    let current_pages = memory.data(&*store).len() / PAGE_SIZE;
+    if n_pages < current_pages {
+        return Err(format_err!(
+            "Snapshot memory is smaller than the initial memory ({} vs {} pages)",
+            n_pages,
+            current_pages
+        ));
+    }
+    if n_pages > current_pages {
+        memory
+            .grow(&mut *store, (n_pages - current_pages) as _)
+            .map_err(|error| format_err!("Cannot grow memory: {}", error))?;
+    }
+
+    for page in memory.data_mut(&mut *store).chunks_mut(PAGE_SIZE) {
+        match reader.u8()? {
+            0 => page.fill(0),
+            _ => page.copy_from_slice(reader.bytes(PAGE_SIZE)?),
+        }
+    }
+
+    let data = store.data_mut();
+    data.framebuffer = framebuffer;
+    data.sockets_store = SocketsStore::restored(next_socket_id);
+    *data.console_output.mutate(uuid_provider) = console_output;
+
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use applib::content::{ContentId, TrackedContent};
+use applib::drawing::text::{Font, RichText};
+use applib::Color;
+use core::ops::Range;
+
+//
+// Standard streams of apps
+//
+// WASI programs write to stdout and stderr (file descriptors 1 and 2), which end up in the app
+// console along with its log messages and crash reports. Stdin (file descriptor 0) is fed from
+// the input line of the audit window, or from the stdout of another app (see the "pipe" console
+// command).
+// Apps are stepped, so reading stdin cannot block: without pending input, fd_read() fails with
+// EAGAIN, and the app is expected to try again on its next step.
+
+// Pending input is dropped past this size, if the app does not read its stdin
+pub const MAX_STDIN_LEN: usize = 64 * 1024;
+
+// Writes larger than that are cut short (WASI programs retry with the rest)
+pub const MAX_WRITE_LEN: usize = 64 * 1024;
+
+// Console of an app, shown in the audit window and the crash panel
+#[derive(Debug, Clone)]
+pub struct ConsoleOutput {
+    text: String,
+    // Byte ranges of the text which was written to stderr, in order
+    stderr: Vec<Range<usize>>,
+}
+
+impl ConsoleOutput {
+    pub fn new() -> Self {
+        Self {
+            text: String::new(),
+            stderr: Vec::new(),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    pub fn stderr_ranges(&self) -> &[Range<usize>] {
+        &self.stderr
+    }
+
+    pub fn write_stderr(&mut self, s: &str) {
+        let start = self.text.len();
+        self.text.push_str(s);
+        let end = self.text.len();
+
+        match self.stderr.last_mut() {
+            Some(range) if range.end == start => range.end = end,
+            _ if start < end => self.stderr.push(start..end),
+            _ => (),
+        }
+    }
+
+    // From a snapshot, ranges are checked by the caller
+    pub fn restored(text: String, stderr: Vec<Range<usize>>) -> Self {
+        Self { text, stderr }
+    }
+}
+
+impl From<String> for ConsoleOutput {
+    fn from(text: String) -> Self {
+        Self {
+            text,
+            stderr: Vec::new(),
+        }
+    }
+}
+
+// Anything which is not stderr: log messages, stdout, crash reports
+impl core::fmt::Write for ConsoleOutput {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.text.push_str(s);
+        Ok(())
+    }
+}
+
+// Stderr is drawn in its own color
+pub fn console_rich_text(
+    console: &TrackedContent<ConsoleOutput>,
+    color: Color,
+    stderr_color: Color,
+    font: &'static Font,
+) -> TrackedContent<RichText> {
+    let ConsoleOutput { text, stderr } = console.as_ref();
+
+    let mut rich_text = RichText::new();
+    let mut pos = 0;
+    for range in stderr {
+        rich_text.add_part(&text[pos..range.start], color, font, None);
+        rich_text.add_part(&text[range.clone()], stderr_color, font, None);
+        pos = range.end;
+    }
+    rich_text.add_part(&text[pos..], color, font, None);
+
+    let content_id =
+        ContentId::from_hash(&(console.get_id(), color, stderr_color, font.name.as_str()));
+    TrackedContent::new_with_id(rich_text, content_id)
+}
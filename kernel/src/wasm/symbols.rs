@@ -0,0 +1,555 @@
+use alloc::borrow::Cow;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use anyhow::format_err;
+
+use super::binary::{
+    count_imports, find_custom_section, find_section, read_sections, Reader, SECTION_CODE,
+    SECTION_IMPORT,
+};
+
+//
+// Guest symbols
+//
+// Function names come from the "name" custom section (kept in release builds unless the app is
+// fully stripped), and source locations from the DWARF line table when the app is built with
+// debug info. Since guest backtraces only record which functions were called (see
+// backtrace.rs), the location shown is where each function starts.
+
+pub struct GuestSymbols {
+    names: BTreeMap<u32, String>,
+    locations: BTreeMap<u32, SourceLocation>,
+}
+
+struct SourceLocation {
+    file: String,
+    line: u64,
+}
+
+impl GuestSymbols {
+    // Never fails: missing or invalid sections only make backtraces less readable
+    pub fn parse(module: &[u8]) -> Self {
+        let mut symbols = GuestSymbols {
+            names: BTreeMap::new(),
+            locations: BTreeMap::new(),
+        };
+
+        let sections = match read_sections(module) {
+            Ok(sections) => sections,
+            Err(error) => {
+                log::warn!("Cannot read WASM sections: {}", error);
+                return symbols;
+            }
+        };
+
+        if let Some(data) = find_custom_section(&sections, "name") {
+            match parse_function_names(data) {
+                Ok(names) => symbols.names = names,
+                Err(error) => log::warn!("Invalid WASM name section: {}", error),
+            }
+        }
+
+        if let Some(debug_line) = find_custom_section(&sections, ".debug_line") {
+            let debug_line_str = find_custom_section(&sections, ".debug_line_str").unwrap_or(&[]);
+            let debug_str = find_custom_section(&sections, ".debug_str").unwrap_or(&[]);
+
+            let n_imported_funcs = match find_section(&sections, SECTION_IMPORT) {
+                Some(section) => count_imports(section.data).map(|(n_funcs, _)| n_funcs),
+                None => Ok(0),
+            };
+            let code = find_section(&sections, SECTION_CODE).map(|section| section.data);
+
+            let res = n_imported_funcs.and_then(|n_imported_funcs| {
+                let func_ranges = read_func_ranges(code.unwrap_or(&[]), n_imported_funcs)?;
+                parse_line_table(debug_line, debug_line_str, debug_str, &func_ranges)
+            });
+
+            match res {
+                Ok(locations) => symbols.locations = locations,
+                Err(error) => log::warn!("Invalid DWARF line table: {}", error),
+            }
+        }
+
+        symbols
+    }
+
+    pub fn describe(&self, func_index: u32) -> String {
+        let name = match self.names.get(&func_index) {
+            Some(name) => demangle(name),
+            None => Cow::Owned(format!("func[{}]", func_index)),
+        };
+
+        match self.locations.get(&func_index) {
+            Some(location) => format!("{} at {}:{}", name, location.file, location.line),
+            None => name.into_owned(),
+        }
+    }
+}
+
+fn parse_function_names(data: &[u8]) -> anyhow::Result<BTreeMap<u32, String>> {
+    const SUBSECTION_FUNCTION_NAMES: u8 = 1;
+
+    let mut reader = Reader::new(data);
+    let mut names = BTreeMap::new();
+
+    while !reader.is_empty() {
+        let subsection_id = reader.u8()?;
+        let len = reader.uleb()? as usize;
+        let subsection = reader.bytes(len)?;
+
+        if subsection_id != SUBSECTION_FUNCTION_NAMES {
+            continue;
+        }
+
+        let mut reader = Reader::new(subsection);
+        let count = reader.uleb32()?;
+        for _ in 0..count {
+            let func_index = reader.uleb32()?;
+            let name = String::from_utf8_lossy(reader.name()?).into_owned();
+            names.insert(func_index, name);
+        }
+    }
+
+    Ok(names)
+}
+
+//
+// DWARF line table
+//
+// Addresses in WASM DWARF are offsets within the code section content.
+
+struct FuncRange {
+    start: u64,
+    end: u64,
+    func_index: u32,
+}
+
+fn read_func_ranges(code: &[u8], n_imported_funcs: u32) -> anyhow::Result<Vec<FuncRange>> {
+    let mut reader = Reader::new(code);
+    let mut ranges = Vec::new();
+
+    if code.is_empty() {
+        return Ok(ranges);
+    }
+
+    let count = reader.uleb32()?;
+    for i in 0..count {
+        let start = reader.pos() as u64;
+        let len = reader.uleb()? as usize;
+        reader.skip(len)?;
+        ranges.push(FuncRange {
+            start,
+            end: reader.pos() as u64,
+            func_index: n_imported_funcs + i,
+        });
+    }
+
+    Ok(ranges)
+}
+
+// Location of the lowest address in each function
+fn parse_line_table(
+    debug_line: &[u8],
+    debug_line_str: &[u8],
+    debug_str: &[u8],
+    func_ranges: &[FuncRange],
+) -> anyhow::Result<BTreeMap<u32, SourceLocation>> {
+    let mut first_rows: BTreeMap<u32, (u64, String, u64)> = BTreeMap::new();
+
+    let mut reader = Reader::new(debug_line);
+    while !reader.is_empty() {
+        let mut unit_len = reader.u32()? as u64;
+        let is_dwarf64 = unit_len == 0xffff_ffff;
+        if is_dwarf64 {
+            unit_len = reader.u64()?;
+        }
+        let unit = reader.bytes(unit_len as usize)?;
+
+        let strings = DwarfStrings {
+            debug_line_str,
+            debug_str,
+            is_dwarf64,
+        };
+        parse_line_program(unit, &strings, func_ranges, &mut first_rows)?;
+    }
+
+    let locations = first_rows
+        .into_iter()
+        .map(|(func_index, (_, file, line))| (func_index, SourceLocation { file, line }))
+        .collect();
+
+    Ok(locations)
+}
+
+struct DwarfStrings<'a> {
+    debug_line_str: &'a [u8],
+    debug_str: &'a [u8],
+    is_dwarf64: bool,
+}
+
+impl DwarfStrings<'_> {
+    fn read_offset(&self, reader: &mut Reader) -> anyhow::Result<u64> {
+        match self.is_dwarf64 {
+            true => reader.u64(),
+            false => reader.u32().map(|offset| offset as u64),
+        }
+    }
+}
+
+const DW_FORM_BLOCK: u64 = 0x09;
+const DW_FORM_DATA1: u64 = 0x0b;
+const DW_FORM_DATA2: u64 = 0x05;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_DATA8: u64 = 0x07;
+const DW_FORM_DATA16: u64 = 0x1e;
+const DW_FORM_STRING: u64 = 0x08;
+const DW_FORM_STRP: u64 = 0x0e;
+const DW_FORM_UDATA: u64 = 0x0f;
+const DW_FORM_LINE_STRP: u64 = 0x1f;
+
+const DW_LNCT_PATH: u64 = 0x1;
+const DW_LNCT_DIRECTORY_INDEX: u64 = 0x2;
+
+enum FormValue<'a> {
+    Str(&'a [u8]),
+    Int(u64),
+    Other,
+}
+
+fn read_form<'a>(
+    reader: &mut Reader<'a>,
+    form: u64,
+    strings: &DwarfStrings<'a>,
+) -> anyhow::Result<FormValue<'a>> {
+    let string_at = |section: &'a [u8], offset: u64| -> anyhow::Result<&'a [u8]> {
+        let mut reader = Reader::new(section);
+        reader.skip(offset as usize)?;
+        reader.cstr()
+    };
+
+    let value = match form {
+        DW_FORM_STRING => FormValue::Str(reader.cstr()?),
+        DW_FORM_LINE_STRP => {
+            let offset = strings.read_offset(reader)?;
+            FormValue::Str(string_at(strings.debug_line_str, offset)?)
+        }
+        DW_FORM_STRP => {
+            let offset = strings.read_offset(reader)?;
+            FormValue::Str(string_at(strings.debug_str, offset)?)
+        }
+        DW_FORM_UDATA => FormValue::Int(reader.uleb()?),
+        DW_FORM_DATA1 => FormValue::Int(reader.u8()? as u64),
+        DW_FORM_DATA2 => FormValue::Int(reader.u16()? as u64),
+        DW_FORM_DATA4 => FormValue::Int(reader.u32()? as u64),
+        DW_FORM_DATA8 => FormValue::Int(reader.u64()?),
+        DW_FORM_DATA16 => {
+            reader.skip(16)?;
+            FormValue::Other
+        }
+        DW_FORM_BLOCK => {
+            let len = reader.uleb()? as usize;
+            reader.skip(len)?;
+            FormValue::Other
+        }
+        _ => return Err(format_err!("Unsupported DWARF form {:#x}", form)),
+    };
+
+    Ok(value)
+}
+
+// (path, directory index) entries of a DWARF 5 directory or file name table
+fn read_v5_entries<'a>(
+    reader: &mut Reader<'a>,
+    strings: &DwarfStrings<'a>,
+) -> anyhow::Result<Vec<(&'a [u8], u64)>> {
+    let n_formats = reader.u8()?;
+    let formats = (0..n_formats)
+        .map(|_| Ok((reader.uleb()?, reader.uleb()?)))
+        .collect::<anyhow::Result<Vec<(u64, u64)>>>()?;
+
+    let count = reader.uleb()?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let (mut path, mut dir_index): (&[u8], u64) = (&[], 0);
+        for &(content_type, form) in formats.iter() {
+            match (content_type, read_form(reader, form, strings)?) {
+                (DW_LNCT_PATH, FormValue::Str(s)) => path = s,
+                (DW_LNCT_DIRECTORY_INDEX, FormValue::Int(i)) => dir_index = i,
+                _ => (),
+            }
+        }
+        entries.push((path, dir_index));
+    }
+
+    Ok(entries)
+}
+
+fn parse_line_program(
+    unit: &[u8],
+    strings: &DwarfStrings,
+    func_ranges: &[FuncRange],
+    first_rows: &mut BTreeMap<u32, (u64, String, u64)>,
+) -> anyhow::Result<()> {
+    const DW_LNS_COPY: u8 = 1;
+    const DW_LNS_ADVANCE_PC: u8 = 2;
+    const DW_LNS_ADVANCE_LINE: u8 = 3;
+    const DW_LNS_SET_FILE: u8 = 4;
+    const DW_LNS_CONST_ADD_PC: u8 = 8;
+    const DW_LNS_FIXED_ADVANCE_PC: u8 = 9;
+    const DW_LNE_END_SEQUENCE: u8 = 1;
+    const DW_LNE_SET_ADDRESS: u8 = 2;
+
+    let mut reader = Reader::new(unit);
+
+    //
+    // Header
+
+    let version = reader.u16()?;
+    if !(2..=5).contains(&version) {
+        return Err(format_err!("Unsupported DWARF version {}", version));
+    }
+    if version >= 5 {
+        // Address size, segment selector size
+        reader.skip(2)?;
+    }
+    let header_len = strings.read_offset(&mut reader)? as usize;
+    let program_start = reader.pos() + header_len;
+
+    let min_inst_len = reader.u8()? as u64;
+    if version >= 4 {
+        // Max ops per instruction
+        reader.u8()?;
+    }
+    let _default_is_stmt = reader.u8()?;
+    let line_base = reader.u8()? as i8 as i64;
+    let line_range = reader.u8()? as u64;
+    let opcode_base = reader.u8()?;
+    let std_opcode_lengths = reader.bytes(opcode_base.saturating_sub(1) as usize)?;
+
+    if line_range == 0 {
+        return Err(format_err!("Invalid DWARF line range"));
+    }
+
+    // Full path of each file, by file index
+    let files: Vec<String> = match version {
+        5 => {
+            let dirs = read_v5_entries(&mut reader, strings)?;
+            read_v5_entries(&mut reader, strings)?
+                .into_iter()
+                .map(|(path, dir_index)| {
+                    let dir = dirs.get(dir_index as usize).map(|(dir, _)| *dir);
+                    join_path(dir, path)
+                })
+                .collect()
+        }
+        _ => {
+            let mut dirs = Vec::new();
+            loop {
+                let dir = reader.cstr()?;
+                if dir.is_empty() {
+                    break;
+                }
+                dirs.push(dir);
+            }
+
+            // File indices start at 1 before DWARF 5
+            let mut files = vec![String::new()];
+            loop {
+                let path = reader.cstr()?;
+                if path.is_empty() {
+                    break;
+                }
+                let dir_index = reader.uleb()? as usize;
+                reader.uleb()?;
+                reader.uleb()?;
+                let dir = dir_index.checked_sub(1).and_then(|i| dirs.get(i)).copied();
+                files.push(join_path(dir, path));
+            }
+            files
+        }
+    };
+
+    //
+    // Line number program
+
+    let mut reader = Reader::new(unit);
+    reader.skip(program_start)?;
+
+    let (mut address, mut file, mut line) = (0u64, 1u64, 1i64);
+
+    let mut emit_row = |address: u64, file: u64, line: i64| {
+        let Ok(index) = func_ranges.binary_search_by(|range| match address {
+            a if a < range.start => core::cmp::Ordering::Greater,
+            a if a >= range.end => core::cmp::Ordering::Less,
+            _ => core::cmp::Ordering::Equal,
+        }) else {
+            return;
+        };
+        let func_index = func_ranges[index].func_index;
+
+        let is_first = first_rows
+            .get(&func_index)
+            .map_or(true, |(first_address, _, _)| address < *first_address);
+        if is_first {
+            let file = files.get(file as usize).cloned().unwrap_or_default();
+            first_rows.insert(func_index, (address, file, line.max(0) as u64));
+        }
+    };
+
+    while !reader.is_empty() {
+        let opcode = reader.u8()?;
+
+        if opcode >= opcode_base {
+            let adjusted = (opcode - opcode_base) as u64;
+            address += (adjusted / line_range) * min_inst_len;
+            line += line_base + (adjusted % line_range) as i64;
+            emit_row(address, file, line);
+            continue;
+        }
+
+        match opcode {
+            // Extended opcodes
+            0 => {
+                let len = reader.uleb()? as usize;
+                let mut ext_reader = Reader::new(reader.bytes(len)?);
+                match ext_reader.u8()? {
+                    DW_LNE_END_SEQUENCE => {
+                        (address, file, line) = (0, 1, 1);
+                    }
+                    DW_LNE_SET_ADDRESS => {
+                        address = match len - 1 {
+                            4 => ext_reader.u32()? as u64,
+                            8 => ext_reader.u64()?,
+                            size => return Err(format_err!("Unsupported address size {}", size)),
+                        };
+                    }
+                    _ => (),
+                }
+            }
+            DW_LNS_COPY => emit_row(address, file, line),
+            DW_LNS_ADVANCE_PC => address += reader.uleb()? * min_inst_len,
+            DW_LNS_ADVANCE_LINE => line += reader.sleb()?,
+            DW_LNS_SET_FILE => file = reader.uleb()?,
+            DW_LNS_CONST_ADD_PC => {
+                address += ((255 - opcode_base) as u64 / line_range) * min_inst_len
+            }
+            DW_LNS_FIXED_ADVANCE_PC => address += reader.u16()? as u64,
+            // Other standard opcodes only have ULEB operands
+            _ => {
+                let n_operands = std_opcode_lengths[opcode as usize - 1];
+                for _ in 0..n_operands {
+                    reader.uleb()?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn join_path(dir: Option<&[u8]>, path: &[u8]) -> String {
+    let path = String::from_utf8_lossy(path);
+    match dir {
+        Some(dir) if !dir.is_empty() && !path.starts_with('/') => {
+            format!("{}/{}", String::from_utf8_lossy(dir), path)
+        }
+        _ => path.into_owned(),
+    }
+}
+
+//
+// Rust symbol demangling
+//
+// Only the legacy scheme (_ZN<len><ident>...17h<hash>E) is supported, other names are left as-is.
+
+fn demangle(name: &str) -> Cow<'_, str> {
+    let Some(inner) = name
+        .strip_prefix("_ZN")
+        .or_else(|| name.strip_prefix("__ZN"))
+        .and_then(|inner| inner.strip_suffix('E'))
+    else {
+        return Cow::Borrowed(name);
+    };
+
+    let mut parts = Vec::new();
+    let mut rest = inner;
+    while !rest.is_empty() {
+        let n_digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let Some(len) = rest[..n_digits].parse::<usize>().ok() else {
+            return Cow::Borrowed(name);
+        };
+        let Some(end) = n_digits.checked_add(len) else {
+            return Cow::Borrowed(name);
+        };
+        let Some(part) = rest.get(n_digits..end) else {
+            return Cow::Borrowed(name);
+        };
+        parts.push(part);
+        rest = &rest[end..];
+    }
+
+    let is_hash = |part: &str| {
+        part.len() == 17
+            && part.starts_with('h')
+            && part[1..].chars().all(|c| c.is_ascii_hexdigit())
+    };
+    if parts.last().is_some_and(|part| is_hash(part)) {
+        parts.pop();
+    }
+
+    let parts: Vec<String> = parts.into_iter().map(unescape_ident).collect();
+    Cow::Owned(parts.join("::"))
+}
+
+fn unescape_ident(part: &str) -> String {
+    // Identifiers starting with '$' get an extra underscore
+    let part = match part.starts_with("_$") {
+        true => &part[1..],
+        false => part,
+    };
+    let part = part.replace("..", "::");
+
+    let mut out = String::with_capacity(part.len());
+    let mut rest = part.as_str();
+    while let Some(start) = rest.find('$') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+
+        let unescaped = after.find('$').and_then(|end| {
+            let c = match &after[..end] {
+                "SP" => '@',
+                "BP" => '*',
+                "RF" => '&',
+                "LT" => '<',
+                "GT" => '>',
+                "LP" => '(',
+                "RP" => ')',
+                "C" => ',',
+                code => {
+                    let hex = code.strip_prefix('u')?;
+                    char::from_u32(u32::from_str_radix(hex, 16).ok()?)?
+                }
+            };
+            Some((c, end))
+        });
+
+        match unescaped {
+            Some((c, end)) => {
+                out.push(c);
+                rest = &after[end + 1..];
+            }
+            None => {
+                out.push('$');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+
+    out
+}
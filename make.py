@@ -1,7 +1,9 @@
 #!/usr/bin/env python3
 
 import os
+import re
 import sys
+import struct
 import shutil
 import argparse
 from pathlib import Path
@@ -16,15 +18,24 @@ WASM_APPS = [
     "text_editor",
 ]
 
+# Only built into test images, with the kernel's test-apps feature
+TEST_WASM_APPS = [
+    "host_fuzz",
+]
+
 CRATE_PATHS = [
     "kernel/",
     "applib/",
     "guestlib/",
-    *[f"wasm_apps/{app}" for app in WASM_APPS]
+    *[f"wasm_apps/{app}" for app in WASM_APPS + TEST_WASM_APPS]
 ]
 
 TOOLCHAIN_VERSION = "nightly-2025-06-01-x86_64-unknown-linux-gnu"
 
+# Must match kernel/src/backtrace.rs
+SYMBOL_TABLE_MAGIC = b"KSYMTAB!"
+SYMBOL_TABLE_SIZE = 2 << 20
+
 
 def main():
 
@@ -36,6 +47,14 @@ def main():
     subparsers.add_parser("fix")
     subparsers.add_parser("clean")
     subparsers.add_parser("setup-toolchain")
+    capture_parser = subparsers.add_parser("capture")
+    capture_parser.add_argument("mode", choices=["screenshot", "record"])
+    capture_parser.add_argument("--out-dir", default="captures/")
+    capture_parser.add_argument("--window", default=None, help="Only capture this app's window (screenshot mode)")
+    test_parser = subparsers.add_parser("test")
+    test_parser.add_argument("--update-golden", action="store_true")
+    test_parser.add_argument("--kvm", action="store_true")
+    test_parser.add_argument("-k", "--filter", default=None, help="Only run scenarios containing this string")
     args = parser.parse_args()
 
     if args.cmd == "build":
@@ -51,14 +70,19 @@ def main():
         _clean()
     elif args.cmd == "setup-toolchain":
         _setup_toolchain()
+    elif args.cmd == "capture":
+        _capture(args.mode, Path(args.out_dir), args.window)
+    elif args.cmd == "test":
+        _build(test=True)
+        _test(args.update_golden, args.kvm, args.filter)
 
 
-def _build():
+def _build(test=False):
 
     #
     # Building WASM apps
 
-    for app in WASM_APPS:
+    for app in WASM_APPS + (TEST_WASM_APPS if test else []):
 
         wasm_bin_path = _build_crate(
             crate_path=f"wasm_apps/{app}/",
@@ -77,9 +101,105 @@ def _build():
         binary_name="kernel.efi",
         target="x86_64-unknown-uefi",
         dep_paths=["applib/"],
+        features=["test-apps"] if test else [],
     )
 
-    _copy_if_new(kernel_bin_path, Path("esp/efi/boot/") / "bootx64.efi")
+    efi_path = Path("esp/efi/boot/") / "bootx64.efi"
+    if _copy_if_new(kernel_bin_path, efi_path):
+        _embed_symbols(efi_path, Path("kernel/target/kernel.map"))
+
+
+def _embed_symbols(efi_path, map_path):
+
+    # Fills in the symbol table reserved in the kernel binary, used for backtraces
+    # (see kernel/src/backtrace.rs), from the function symbols in the linker map
+
+    if not map_path.exists():
+        print(f"No linker map at {map_path}, backtraces will not be symbolized")
+        return
+
+    map_text = map_path.read_text(errors="replace")
+
+    base_match = re.search(r"Preferred load address is ([0-9a-fA-F]+)", map_text)
+    image_base = int(base_match.group(1), 16) if base_match else 0
+
+    symbols = {}
+    for match in re.finditer(r"^\s*[0-9a-fA-F]{4}:[0-9a-fA-F]{8}\s+(\S+)\s+([0-9a-fA-F]{16})\s+f\s", map_text, re.MULTILINE):
+        name, addr = match.group(1), int(match.group(2), 16)
+        symbols.setdefault(addr - image_base, _demangle(name))
+
+    def build_table(max_name_len):
+        entries = sorted(symbols.items())
+        names = b""
+        header = SYMBOL_TABLE_MAGIC + struct.pack("<I", len(entries))
+        packed_entries = b""
+        for rva, name in entries:
+            name = name.encode()[:max_name_len]
+            packed_entries += struct.pack("<III", rva, len(names), len(name))
+            names += name
+        return header + packed_entries + names
+
+    # Shortening names until the table fits
+    max_name_len = 256
+    table = build_table(max_name_len)
+    while len(table) > SYMBOL_TABLE_SIZE and max_name_len > 16:
+        max_name_len //= 2
+        table = build_table(max_name_len)
+
+    if len(table) > SYMBOL_TABLE_SIZE:
+        print(f"Too many symbols ({len(symbols)}) to fit the kernel symbol table")
+        return
+
+    efi_bytes = bytearray(efi_path.read_bytes())
+    offset = efi_bytes.find(SYMBOL_TABLE_MAGIC + bytes(4))
+    if offset < 0:
+        print(f"No symbol table placeholder found in {efi_path}")
+        return
+
+    efi_bytes[offset:offset + len(table)] = table
+    efi_path.write_bytes(efi_bytes)
+
+    print(f"Embedded {len(symbols)} symbols in {efi_path}")
+
+
+def _demangle(name):
+
+    # Legacy Rust mangling (_ZN<len><ident>...17h<hash>E), other names are left as-is
+
+    match = re.fullmatch(r"_?_ZN(.*)E", name)
+    if match is None:
+        return name
+
+    rest = match.group(1)
+    parts = []
+    while rest:
+        len_match = re.match(r"\d+", rest)
+        if len_match is None:
+            return name
+        n = int(len_match.group(0))
+        start = len_match.end()
+        parts.append(rest[start:start + n])
+        rest = rest[start + n:]
+
+    if parts and re.fullmatch(r"h[0-9a-f]{16}", parts[-1]):
+        parts.pop()
+
+    escapes = {"SP": "@", "BP": "*", "RF": "&", "LT": "<", "GT": ">", "LP": "(", "RP": ")", "C": ","}
+
+    def unescape_char(match):
+        code = match.group(1)
+        if code.startswith("u"):
+            return chr(int(code[1:], 16))
+        return escapes.get(code, match.group(0))
+
+    def unescape(part):
+        # Identifiers starting with '$' get an extra underscore
+        if part.startswith("_$"):
+            part = part[1:]
+        part = part.replace("..", "::")
+        return re.sub(r"\$([A-Z]+|u[0-9a-f]+)\$", unescape_char, part)
+
+    return "::".join(unescape(part) for part in parts)
 
 
 def _run():
@@ -90,6 +210,8 @@ def _run():
     qemu_args = " ".join(
         [
             "-enable-kvm",
+            "-machine q35",  # PCIe, with ECAM configuration access
+            "-smp 4",
             "-m 1G",
             "-rtc base=utc",
             "-display sdl",
@@ -103,11 +225,14 @@ def _run():
             "-device virtio-keyboard",
             "-device virtio-mouse",
             "-device virtio-net-pci,netdev=network0 -netdev user,id=network0",
+            "-device virtio-rng-pci",
             "-vga virtio",
 
             # Debugging
             "-monitor stdio",
             "-serial file:log.txt",
+            "-serial tcp::4444,server,nowait",  # Kernel console (COM2)
+            "-serial tcp::4445,server,nowait",  # GDB stub (COM3)
             #"--trace \"virt*\"",
             # "-object filter-dump,id=f1,netdev=network0,file=dump.dat",
         ]
@@ -119,6 +244,80 @@ def _run():
         sys.exit(1)
 
 
+def _test(update_golden, kvm, name_filter):
+
+    from tests.harness import QemuHarness, TestFailure
+    from tests.scenarios import SCENARIOS, Context
+
+    scenarios = [s for s in SCENARIOS if name_filter is None or name_filter in s.__name__]
+    ctx = Context(golden_dir=Path("tests/golden/"), update_golden=update_golden)
+
+    failures = []
+    with QemuHarness(esp_path="esp", output_dir=Path("tests/output/"), kvm=kvm) as harness:
+        for scenario in scenarios:
+            print(f"Running {scenario.__name__}... ", end="", flush=True)
+            try:
+                scenario(harness, ctx)
+                print("ok")
+            except TestFailure as e:
+                print(f"FAILED\n    {e}")
+                failures.append(scenario.__name__)
+
+    print(f"{len(scenarios) - len(failures)}/{len(scenarios)} scenarios passed")
+    if failures:
+        sys.exit(1)
+
+
+def _capture(mode, out_dir, window=None):
+
+    # Connects to the kernel console of a running instance (see the -serial args in _run())
+    import base64
+    import socket
+
+    out_dir.mkdir(parents=True, exist_ok=True)
+
+    sock = socket.create_connection(("localhost", 4444))
+    sock_file = sock.makefile("rb")
+
+    if mode == "screenshot":
+        command = f"screenshot {window}\n" if window else "screenshot\n"
+    else:
+        command = "record\n"
+    sock.sendall(command.encode())
+
+    if mode == "record":
+        print("Recording, press Ctrl+C to stop")
+
+    label = None
+    data = []
+    try:
+        for line in sock_file:
+            line = line.decode(errors="replace").strip()
+            if line.startswith("ERR"):
+                print(f"Capture failed: {line}")
+                break
+            elif line.startswith("BEGIN PNG "):
+                label = line.removeprefix("BEGIN PNG ")
+                data = []
+            elif line.startswith("| ") and label is not None:
+                data.append(line.removeprefix("| "))
+            elif line == "END PNG" and label is not None:
+                path = out_dir / f"{label}.png"
+                path.write_bytes(base64.b64decode("".join(data)))
+                print(f"Saved {path}")
+                label = None
+                if mode == "screenshot":
+                    break
+    except KeyboardInterrupt:
+        pass
+
+    if mode == "record":
+        sock.sendall(b"record\n")
+        print(f"To make a video: ffmpeg -framerate 10 -pattern_type glob -i '{out_dir}/frame_*.png' demo.mp4")
+
+    sock.close()
+
+
 def _fmt():
     for crate_path in CRATE_PATHS:
         _shell_exec("cargo fmt", workdir=crate_path)
@@ -148,12 +347,20 @@ def _build_crate(
     target,
     mode="release",
     dep_paths=None,
+    features=(),
 ):
 
     crate_path = Path(crate_path)
 
     binary_path = crate_path / "target" / target / mode / binary_name
-    if binary_path.exists():
+
+    # Features the binary was last built with, so that switching between normal and test
+    # builds triggers a rebuild
+    features_path = binary_path.with_name(binary_name + ".features")
+    features_str = ",".join(features)
+    same_features = features_path.exists() and features_path.read_text() == features_str
+
+    if binary_path.exists() and same_features:
 
         binary_mtime = binary_path.lstat().st_mtime
         needs_build = _check_source_changed(crate_path, binary_mtime)
@@ -169,14 +376,17 @@ def _build_crate(
             return binary_path
 
     mode_arg = "" if mode == "debug" else "--release"
+    features_arg = f"--features {features_str}" if features_str else ""
 
     print(f"Building {binary_path}")
     try:
-        _shell_exec(f"cargo build {mode_arg}", workdir=crate_path)
+        _shell_exec(f"cargo build {mode_arg} {features_arg}", workdir=crate_path)
     except (KeyboardInterrupt, subprocess.CalledProcessError):
         print("Build failed.")
         sys.exit(1)
 
+    features_path.write_text(features_str)
+
     return binary_path
 
 
@@ -206,6 +416,8 @@ def _copy_if_new(src, dst):
     if not dst.exists() or dst.lstat().st_mtime < src.lstat().st_mtime:
         dst.parent.mkdir(parents=True, exist_ok=True)
         shutil.copy2(src, dst)
+        return True
+    return False
 
 def _shell_exec(cmd, workdir=None):
     subprocess.check_call(cmd, cwd=workdir, shell=True)
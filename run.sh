@@ -27,6 +27,10 @@ cd text_editor/
 cargo build --release
 cd ../
 
+cd host_fuzz/
+cargo build --release
+cd ../
+
 cd ../
 
 
@@ -39,6 +43,7 @@ cp wasm_apps/chronometer/target/wasm32-wasip1/release/chronometer.wasm embedded_
 cp wasm_apps/terminal/target/wasm32-wasip1/release/terminal.wasm embedded_data/terminal.wasm
 cp wasm_apps/web_browser/target/wasm32-wasip1/release/web_browser.wasm embedded_data/web_browser.wasm
 cp wasm_apps/text_editor/target/wasm32-wasip1/release/text_editor.wasm embedded_data/text_editor.wasm
+cp wasm_apps/host_fuzz/target/wasm32-wasip1/release/host_fuzz.wasm embedded_data/host_fuzz.wasm
 
 
 #
@@ -57,6 +62,8 @@ cp kernel/target/x86_64-unknown-uefi/release/kernel.efi esp/efi/boot/bootx64.efi
 
 qemu-system-x86_64 \
     -enable-kvm \
+    -machine q35 \
+    -smp 4 \
     -m 1G \
     -rtc base=utc \
     -display sdl \
@@ -66,5 +73,8 @@ qemu-system-x86_64 \
     -device virtio-keyboard \
     -device virtio-mouse \
     -device virtio-net-pci,netdev=network0 -netdev user,id=network0 \
+    -device virtio-rng-pci \
     -vga virtio \
-    -serial stdio
+    -serial stdio \
+    -serial tcp::4444,server,nowait \
+    -serial tcp::4445,server,nowait
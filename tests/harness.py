@@ -0,0 +1,356 @@
+"""
+Headless test harness: boots the kernel under QEMU without a display, drives it
+through QMP (synthetic input, framebuffer dumps) and the kernel console (COM2),
+and checks the results against golden PNGs and the kernel log.
+"""
+
+import json
+import time
+import socket
+import tempfile
+import subprocess
+from pathlib import Path
+
+import numpy as np
+from PIL import Image
+
+
+BOOT_TIMEOUT = 120.0  # TCG is slow, especially for the UEFI firmware
+CONSOLE_TIMEOUT = 30.0
+QMP_TIMEOUT = 30.0
+
+# Max fraction of (non-masked) pixels allowed to differ from the golden image
+DEFAULT_PIXEL_TOLERANCE = 0.001
+# Per-channel difference under which two pixels are considered identical
+CHANNEL_THRESHOLD = 8
+
+# Position the pointer is reset to before absolute moves (the kernel clamps it to the screen)
+POINTER_RESET_DELTA = -10000
+
+
+class TestFailure(Exception):
+    pass
+
+
+class QemuHarness:
+
+    def __init__(self, esp_path, output_dir, kvm=False):
+        self.esp_path = Path(esp_path)
+        self.output_dir = Path(output_dir)
+        self.kvm = kvm
+
+        self._tmp_dir = Path(tempfile.mkdtemp(prefix="munal-test-"))
+        self.qmp_path = self._tmp_dir / "qmp.sock"
+        self.console_path = self._tmp_dir / "console.sock"
+        self.log_path = self.output_dir / "kernel_log.txt"
+
+        self._process = None
+        self._qmp = None
+        self._qmp_buf = b""
+        self._console = None
+        self._console_buf = b""
+        self._log_offset = 0
+
+    #
+    # Lifecycle
+
+    def start(self):
+
+        self.output_dir.mkdir(parents=True, exist_ok=True)
+
+        qemu_args = [
+            "qemu-system-x86_64",
+            "-accel", "kvm" if self.kvm else "tcg",
+            "-machine", "q35",
+            "-smp", "4",
+            "-m", "1G",
+            "-rtc", "base=utc",
+            "-display", "none",
+
+            # UEFI boot
+            "-drive", "if=pflash,format=raw,readonly=on,file=uefi_firmware/code.fd",
+            "-drive", "if=pflash,format=raw,readonly=on,file=uefi_firmware/vars.fd",
+            "-drive", f"format=raw,file=fat:rw:{self.esp_path}",
+
+            # VirtIO peripherals
+            "-device", "virtio-keyboard",
+            "-device", "virtio-mouse",
+            # Connections from the Host API fuzzer to 10.0.2.100:9 each get their own `cat`
+            # process, rather than reaching whatever listens on the host
+            "-device", "virtio-net-pci,netdev=network0",
+            "-netdev", "user,id=network0,guestfwd=tcp:10.0.2.100:9-cmd:cat",
+            "-device", "virtio-rng-pci",
+            "-vga", "virtio",
+
+            # Host-controlled channels
+            "-qmp", f"unix:{self.qmp_path},server=on,wait=off",
+            "-serial", f"file:{self.log_path}",
+            "-serial", f"unix:{self.console_path},server=on,wait=off",
+        ]
+
+        self._process = subprocess.Popen(qemu_args)
+
+        self._qmp = _connect_unix(self.qmp_path)
+        self._qmp_recv()  # Greeting
+        self.qmp("qmp_capabilities")
+
+        self._console = _connect_unix(self.console_path)
+
+        self.wait_log("Entering main loop", timeout=BOOT_TIMEOUT)
+        self.sync()
+
+    def stop(self):
+        if self._process is None:
+            return
+        try:
+            self.qmp("quit")
+        except (OSError, TestFailure):
+            pass
+        try:
+            self._process.wait(timeout=10)
+        except subprocess.TimeoutExpired:
+            self._process.kill()
+        self._process = None
+
+    def __enter__(self):
+        self.start()
+        return self
+
+    def __exit__(self, *_):
+        self.stop()
+
+    #
+    # QMP
+
+    def qmp(self, command, **arguments):
+        msg = {"execute": command}
+        if arguments:
+            msg["arguments"] = arguments
+        self._qmp.sendall(json.dumps(msg).encode() + b"\n")
+
+        # Skipping asynchronous events
+        while True:
+            resp = self._qmp_recv()
+            if "return" in resp:
+                return resp["return"]
+            if "error" in resp:
+                raise TestFailure(f"QMP {command} failed: {resp['error']}")
+
+    def _qmp_recv(self):
+        """Returns the next QMP message (one per line), keeping the ones received along with it"""
+        deadline = time.monotonic() + QMP_TIMEOUT
+        while b"\n" not in self._qmp_buf:
+            remaining = deadline - time.monotonic()
+            if remaining <= 0:
+                raise TestFailure("Timed out waiting for QMP")
+            self._qmp.settimeout(remaining)
+            try:
+                chunk = self._qmp.recv(4096)
+            except socket.timeout:
+                continue
+            if not chunk:
+                raise TestFailure("QMP connection closed")
+            self._qmp_buf += chunk
+        line, _, self._qmp_buf = self._qmp_buf.partition(b"\n")
+        return json.loads(line)
+
+    #
+    # Kernel console
+
+    def console(self, command):
+        """Runs a kernel console command, returns its output lines (without the status line)"""
+        self._console.sendall(command.encode() + b"\n")
+
+        lines = []
+        deadline = time.monotonic() + CONSOLE_TIMEOUT
+        while True:
+            line = self._read_console_line(deadline)
+            # Prompts are not followed by a newline, so they end up at the start of lines
+            while line.startswith("> "):
+                line = line[2:]
+            if line.startswith("OK"):
+                return lines
+            if line.startswith("ERR"):
+                raise TestFailure(f"Console command '{command}' failed: {line}")
+            lines.append(line)
+
+    def sync(self, n_frames=2):
+        """Waits for the kernel to go through a few frames (the console is polled once per frame)"""
+        for _ in range(n_frames):
+            self.console("mem")
+
+    def _read_console_line(self, deadline):
+        self._console.settimeout(0.5)
+        while b"\n" not in self._console_buf:
+            if time.monotonic() > deadline:
+                raise TestFailure("Timed out waiting for the kernel console")
+            try:
+                chunk = self._console.recv(4096)
+            except socket.timeout:
+                continue
+            if not chunk:
+                raise TestFailure("Kernel console connection closed")
+            self._console_buf += chunk
+        line, _, self._console_buf = self._console_buf.partition(b"\n")
+        return line.decode(errors="replace").rstrip("\r")
+
+    #
+    # Synthetic input
+
+    def move_pointer(self, x, y):
+        """Absolute move, done by first pushing the pointer against the top-left corner"""
+        self._send_events([
+            _rel_event("x", POINTER_RESET_DELTA),
+            _rel_event("y", POINTER_RESET_DELTA),
+        ])
+        self._send_events([_rel_event("x", x), _rel_event("y", y)])
+        self.sync()
+
+    def click(self, button="left"):
+        self._send_events([_btn_event(button, True)])
+        self.sync()
+        self._send_events([_btn_event(button, False)])
+        self.sync()
+
+    def scroll(self, delta):
+        button = "wheel-up" if delta > 0 else "wheel-down"
+        for _ in range(abs(delta)):
+            self._send_events([_btn_event(button, True)])
+            self._send_events([_btn_event(button, False)])
+        self.sync()
+
+    def press_key(self, qcode):
+        self._send_events([_key_event(qcode, True)])
+        self._send_events([_key_event(qcode, False)])
+        self.sync()
+
+    def type_text(self, text):
+        for c in text:
+            qcode, shift = _char_to_qcode(c)
+            events = [_key_event(qcode, True), _key_event(qcode, False)]
+            if shift:
+                events = [_key_event("shift", True), *events, _key_event("shift", False)]
+            self._send_events(events)
+        self.sync()
+
+    def _send_events(self, events):
+        self.qmp("input-send-event", events=events)
+
+    #
+    # Framebuffer
+
+    def screenshot(self, name):
+        path = (self.output_dir / f"{name}.png").absolute()
+        self.qmp("screendump", filename=str(path), format="png")
+        return path
+
+    def check_screenshot(self, name, golden_dir, update_golden=False, masks=(),
+                         tolerance=DEFAULT_PIXEL_TOLERANCE):
+        """
+        Compares a framebuffer dump with tests/golden/<name>.png.
+        masks: (x0, y0, w, h) regions to ignore, e.g. the clock in the top bar.
+        """
+
+        path = self.screenshot(name)
+        golden_path = Path(golden_dir) / f"{name}.png"
+
+        if update_golden:
+            golden_path.parent.mkdir(parents=True, exist_ok=True)
+            Image.open(path).save(golden_path)
+            return
+
+        if not golden_path.exists():
+            raise TestFailure(f"No golden image {golden_path} (run with --update-golden)")
+
+        actual = np.asarray(Image.open(path).convert("RGB"), dtype=np.int16)
+        expected = np.asarray(Image.open(golden_path).convert("RGB"), dtype=np.int16)
+
+        if actual.shape != expected.shape:
+            raise TestFailure(f"{name}: shape {actual.shape} != golden {expected.shape}")
+
+        mask = np.ones(actual.shape[:2], dtype=bool)
+        for (x0, y0, w, h) in masks:
+            mask[y0:y0 + h, x0:x0 + w] = False
+
+        diff = np.abs(actual - expected).max(axis=2) > CHANNEL_THRESHOLD
+        diff &= mask
+        frac = diff.sum() / max(mask.sum(), 1)
+
+        if frac > tolerance:
+            diff_img = np.where(diff[..., None], [255, 0, 0], actual // 3).astype(np.uint8)
+            diff_path = self.output_dir / f"{name}.diff.png"
+            Image.fromarray(diff_img).save(diff_path)
+            raise TestFailure(
+                f"{name}: {frac:.4%} of pixels differ from golden image (see {diff_path})"
+            )
+
+    #
+    # Kernel log
+
+    def wait_log(self, pattern, timeout=CONSOLE_TIMEOUT):
+        """Waits for a log line containing pattern, only looking at lines logged since the last call"""
+        deadline = time.monotonic() + timeout
+        while time.monotonic() < deadline:
+            if self._process.poll() is not None:
+                raise TestFailure(f"QEMU exited with code {self._process.returncode}")
+            if self.log_path.exists():
+                with open(self.log_path, "rb") as f:
+                    f.seek(self._log_offset)
+                    data = f.read()
+                # Only consuming complete lines
+                for line in data.splitlines(keepends=True):
+                    if not line.endswith(b"\n"):
+                        break
+                    self._log_offset += len(line)
+                    if pattern.encode() in line:
+                        return line.decode(errors="replace")
+            time.sleep(0.2)
+        raise TestFailure(f"Timed out waiting for log line '{pattern}'")
+
+    def assert_no_log(self, pattern):
+        with open(self.log_path, "r", errors="replace") as f:
+            for line in f:
+                if pattern in line:
+                    raise TestFailure(f"Unexpected log line: {line.strip()}")
+
+
+def _connect_unix(path, timeout=10.0):
+    deadline = time.monotonic() + timeout
+    while True:
+        try:
+            sock = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+            sock.connect(str(path))
+            return sock
+        except (FileNotFoundError, ConnectionRefusedError):
+            if time.monotonic() > deadline:
+                raise TestFailure(f"Cannot connect to {path}")
+            time.sleep(0.1)
+
+
+def _rel_event(axis, value):
+    return {"type": "rel", "data": {"axis": axis, "value": value}}
+
+
+def _btn_event(button, down):
+    return {"type": "btn", "data": {"down": down, "button": button}}
+
+
+def _key_event(qcode, down):
+    return {"type": "key", "data": {"down": down, "key": {"type": "qcode", "data": qcode}}}
+
+
+_QCODE_SYMBOLS = {
+    " ": ("spc", False), "\n": ("ret", False), ".": ("dot", False), ",": ("comma", False),
+    "-": ("minus", False), "/": ("slash", False), ":": ("semicolon", True), "!": ("1", True),
+}
+
+
+def _char_to_qcode(c):
+    """Assumes the US layout on the kernel side"""
+    if c in _QCODE_SYMBOLS:
+        return _QCODE_SYMBOLS[c]
+    if c.isascii() and c.isalpha():
+        return c.lower(), c.isupper()
+    if c.isascii() and c.isdigit():
+        return c, False
+    raise ValueError(f"Cannot type {c!r}")
@@ -0,0 +1,100 @@
+"""
+Test scenarios, run in order against a single QEMU instance.
+Each scenario should leave the desktop as it found it (all windows closed, no menu open).
+"""
+
+from .harness import TestFailure
+
+
+TOPBAR_H = 40
+
+# The top bar shows the clock and live resource graphs
+TOPBAR_MASK = (0, 0, 10000, TOPBAR_H)
+
+SCENARIOS = []
+
+
+def scenario(func):
+    SCENARIOS.append(func)
+    return func
+
+
+class Context:
+    def __init__(self, golden_dir, update_golden):
+        self.golden_dir = golden_dir
+        self.update_golden = update_golden
+
+    def check_screenshot(self, h, name, masks=(TOPBAR_MASK,)):
+        h.check_screenshot(name, self.golden_dir, self.update_golden, masks=masks)
+
+
+@scenario
+def boot_desktop(h, ctx):
+    ctx.check_screenshot(h, "desktop")
+    h.assert_no_log("ERROR")
+    h.assert_no_log("panicked")
+
+
+@scenario
+def console_apps(h, ctx):
+    lines = h.console("apps")
+    names = [line.split("  ")[0].strip() for line in lines if line.strip()]
+    for expected in ["3D Demo", "Chronometer", "Python terminal", "Web Browser", "Text Editor"]:
+        if expected not in names:
+            raise TestFailure(f"App '{expected}' missing from console listing: {lines}")
+
+
+@scenario
+def pie_desktop_menu(h, ctx):
+    h.move_pointer(300, 500)
+    h.click("right")
+    ctx.check_screenshot(h, "pie_desktop_menu")
+
+    # Clicking outside of the menu closes it
+    h.move_pointer(50, 100)
+    h.click("left")
+    ctx.check_screenshot(h, "desktop")
+
+
+@scenario
+def text_editor_typing(h, ctx):
+    h.console("launch Text Editor")
+    h.wait_log("Initializing app Text Editor")
+    h.sync(n_frames=10)
+
+    h.type_text("Hello from the test harness")
+    ctx.check_screenshot(h, "text_editor_typing")
+
+    h.console("close Text Editor")
+    h.sync()
+
+
+@scenario
+def app_pie_menu(h, ctx):
+    h.console("launch Text Editor")
+    h.sync(n_frames=10)
+
+    # Right-clicking on the title bar, which sits above the window at its initial position
+    h.move_pointer(600, 270)
+    h.click("right")
+    ctx.check_screenshot(h, "app_pie_menu")
+
+    h.move_pointer(50, 100)
+    h.click("left")
+    h.console("close Text Editor")
+    h.sync()
+
+
+@scenario
+def host_api_fuzz(h, ctx):
+    h.console("launch Host API fuzzer")
+
+    # The fuzzer crashes on purpose from time to time, and is restarted by the kernel
+    for _ in range(3):
+        h.wait_log("fuzz: seed")
+    h.sync(n_frames=10)
+
+    h.console("close Host API fuzzer")
+    h.sync()
+    h.assert_no_log("FUZZ FAILURE")
+    h.assert_no_log("panicked")
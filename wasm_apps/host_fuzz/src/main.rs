@@ -0,0 +1,479 @@
+use applib::abi::{RECT_SIZE, STYLESHEET_SIZE};
+use core::cell::OnceCell;
+use guestlib::WasmLogger;
+
+static LOGGER: WasmLogger = WasmLogger;
+const LOGGING_LEVEL: log::LevelFilter = log::LevelFilter::Info;
+
+//
+// Host API fuzzer
+//
+// Calls host functions with random arguments, to check that whatever an app passes to the
+// kernel, only the app itself can be affected. Invalid addresses point outside of the linear
+// memory (or straddle its end), and valid ones into a scratch buffer, so that the fuzzer does not
+// overwrite its own state.
+//
+// Each step makes a batch of calls to functions which have an error return value, and checks
+// that they report invalid arguments. Some steps also call a function which traps on invalid
+// arguments: the app then crashes, and is restarted shortly after by the kernel.
+// Unexpected results are logged as "FUZZ FAILURE", which the host_api_fuzz test scenario
+// looks for.
+
+const CALLS_PER_STEP: u32 = 20;
+
+// On average, one step in TRAP_PERIOD calls a function which may trap
+const TRAP_PERIOD: u64 = 4;
+
+const SCRATCH_SIZE: u32 = 4096;
+
+const WASI_SUCCESS: i32 = 0;
+const WASI_EAGAIN: i32 = 6;
+const WASI_EFAULT: i32 = 21;
+
+const WASI_CLOCK_MONOTONIC: u32 = 1;
+
+// Connections are only ever made to this address, which the test harness forwards to a
+// throwaway process on the host (guestfwd in tests/harness.py). Other ports are out of range,
+// and must be rejected without connecting anywhere.
+const TCP_FUZZ_IP: [u8; 4] = [10, 0, 2, 100];
+const TCP_FUZZ_PORT: u16 = 9;
+
+// Beyond that, the oldest connection is closed instead of opening a new one
+const MAX_OPEN_CONNECTIONS: usize = 4;
+
+#[link(wasm_import_module = "wasi_snapshot_preview1")]
+extern "C" {
+    #[link_name = "clock_time_get"]
+    fn wasi_clock_time_get(clock_id: i32, precision: i64, time: i32) -> i32;
+    #[link_name = "random_get"]
+    fn wasi_random_get(buf: i32, buf_len: i32) -> i32;
+    #[link_name = "environ_sizes_get"]
+    fn wasi_environ_sizes_get(environ_count: i32, environ_buf_size: i32) -> i32;
+    #[link_name = "environ_get"]
+    fn wasi_environ_get(environ: i32, environ_buf: i32) -> i32;
+    #[link_name = "args_sizes_get"]
+    fn wasi_args_sizes_get(argc: i32, argv_buf_size: i32) -> i32;
+    #[link_name = "args_get"]
+    fn wasi_args_get(argv: i32, argv_buf: i32) -> i32;
+    #[link_name = "fd_write"]
+    fn wasi_fd_write(fd: i32, iovs: i32, iovs_len: i32, nwritten: i32) -> i32;
+    #[link_name = "fd_read"]
+    fn wasi_fd_read(fd: i32, iovs: i32, iovs_len: i32, nread: i32) -> i32;
+    #[link_name = "poll_oneoff"]
+    fn wasi_poll_oneoff(subscriptions: i32, out: i32, nsubscriptions: i32, nevents: i32) -> i32;
+}
+
+// environ_sizes_get() / environ_get(), and args_sizes_get() / args_get()
+type WasiListFn = unsafe extern "C" fn(i32, i32) -> i32;
+
+// Same as in guestlib, which only exposes safe wrappers
+extern "C" {
+    fn host_log(addr: i32, len: i32, level: i32);
+    fn host_get_input_state(addr: i32, max_len: i32) -> i32;
+    fn host_get_win_rect(addr: i32);
+    fn host_set_framebuffer(addr: i32, w: i32, h: i32);
+    fn host_tcp_connect(ip_addr: i32, port: i32) -> i32;
+    fn host_tcp_may_send(handle_id: i32) -> i32;
+    fn host_tcp_may_recv(handle_id: i32) -> i32;
+    fn host_tcp_write(addr: i32, len: i32, handle_id: i32) -> i32;
+    fn host_tcp_read(addr: i32, len: i32, handle_id: i32) -> i32;
+    fn host_tcp_close(handle_id: i32);
+    fn host_get_time(buf: i32);
+    fn host_get_stylesheet(buf: i32);
+    fn host_get_consumed_fuel(addr: i32);
+    fn host_save_timing(key_addr: i32, key_len: i32, consumed_addr: i32);
+}
+
+// Guest memory region passed to a host function
+#[derive(Debug, Clone, Copy)]
+struct Region {
+    addr: i32,
+    len: i32,
+    valid: bool,
+}
+
+struct AppState {
+    rng: Rng,
+    scratch: Vec<u8>,
+    n_steps: u64,
+    n_calls: u64,
+    // Returned by host_tcp_connect(), including the connections closed since
+    tcp_handles: Vec<i32>,
+    open_tcp_handles: Vec<i32>,
+}
+
+static mut APP_STATE: OnceCell<AppState> = OnceCell::new();
+
+fn main() {}
+
+#[no_mangle]
+pub fn init() -> () {
+    log::set_max_level(LOGGING_LEVEL);
+    log::set_logger(&LOGGER).unwrap();
+
+    let seed = guestlib::get_time().to_bits() | 1;
+    log::info!("fuzz: seed {:#x}", seed);
+
+    let state = AppState {
+        rng: Rng(seed),
+        scratch: vec![0u8; SCRATCH_SIZE as usize],
+        n_steps: 0,
+        n_calls: 0,
+        tcp_handles: Vec::new(),
+        open_tcp_handles: Vec::new(),
+    };
+    unsafe {
+        APP_STATE
+            .set(state)
+            .unwrap_or_else(|_| panic!("App already initialized"));
+    }
+}
+
+#[no_mangle]
+pub fn step() {
+    let state = unsafe { APP_STATE.get_mut().expect("App not initialized") };
+
+    for _ in 0..CALLS_PER_STEP {
+        state.fuzz_call();
+    }
+
+    state.n_steps += 1;
+    if state.n_steps % 100 == 0 {
+        log::info!("fuzz: {} steps, {} calls", state.n_steps, state.n_calls);
+    }
+
+    if state.rng.below(TRAP_PERIOD) == 0 {
+        state.fuzz_trapping_call();
+    }
+}
+
+impl AppState {
+    fn scratch_addr(&self) -> u32 {
+        self.scratch.as_ptr() as u32
+    }
+
+    // Valid or invalid region of the given length (at most SCRATCH_SIZE)
+    fn region(&mut self, len: u32) -> Region {
+        let mem_size = memory_size();
+
+        let addr = match self.rng.below(8) {
+            0..=3 => {
+                let offset = self.rng.below((SCRATCH_SIZE - len) as u64 + 1) as u32;
+                return Region {
+                    addr: (self.scratch_addr() + offset) as i32,
+                    len: len as i32,
+                    valid: true,
+                };
+            }
+
+            // Straddling the end of memory
+            4 if len > 1 => mem_size - 1 - self.rng.below(len as u64 - 1) as u32,
+
+            // Past the end of memory
+            5 => mem_size + 1 + self.rng.below(1 << 20) as u32,
+
+            // Wrapping around the address space
+            6 => u32::MAX - self.rng.below(len as u64 + 16) as u32,
+
+            _ => [0x7fff_ffff, 0x8000_0000, u32::MAX][self.rng.below(3) as usize],
+        };
+
+        Region {
+            addr: addr as i32,
+            len: len as i32,
+            valid: false,
+        }
+    }
+
+    // Region of a random length, possibly huge or "negative"
+    fn sized_region(&mut self, max_len: u32) -> Region {
+        match self.rng.below(4) {
+            0 => {
+                let lengths = [u32::MAX, 0x8000_0000, 0x7fff_ffff, memory_size()];
+                Region {
+                    addr: self.scratch_addr() as i32,
+                    len: lengths[self.rng.below(4) as usize] as i32,
+                    valid: false,
+                }
+            }
+            _ => {
+                let len = self.rng.below(max_len as u64 + 1) as u32;
+                self.region(len)
+            }
+        }
+    }
+
+    fn fuzz_call(&mut self) {
+        match self.rng.below(9) {
+            0 => {
+                let time = self.region(8);
+                let ret = unsafe { wasi_clock_time_get(0, 0, time.addr) };
+                check_errno("clock_time_get", ret, &[time]);
+            }
+            1 => {
+                let buf = self.sized_region(256);
+                let ret = unsafe { wasi_random_get(buf.addr, buf.len) };
+                check_errno("random_get", ret, &[buf]);
+            }
+            2 => {
+                let count = self.region(4);
+                let buf_size = self.region(4);
+                let ret = unsafe { wasi_environ_sizes_get(count.addr, buf_size.addr) };
+                check_errno("environ_sizes_get", ret, &[count, buf_size]);
+            }
+            3 => {
+                let (func_name, sizes_get, get): (&str, WasiListFn, WasiListFn) =
+                    match self.rng.below(2) {
+                        0 => ("environ_get", wasi_environ_sizes_get, wasi_environ_get),
+                        _ => ("args_get", wasi_args_sizes_get, wasi_args_get),
+                    };
+                let (n_strings, buf_size) = self.string_list_sizes(sizes_get);
+                if 4 * n_strings > SCRATCH_SIZE || buf_size > SCRATCH_SIZE {
+                    return;
+                }
+                let ptrs = self.region(4 * n_strings);
+                let buf = self.region(buf_size);
+                let ret = unsafe { get(ptrs.addr, buf.addr) };
+                check_errno(func_name, ret, &[ptrs, buf]);
+            }
+            4 => {
+                let argc = self.region(4);
+                let argv_buf_size = self.region(4);
+                let ret = unsafe { wasi_args_sizes_get(argc.addr, argv_buf_size.addr) };
+                check_errno("args_sizes_get", ret, &[argc, argv_buf_size]);
+            }
+            5 => {
+                let (iovs, buf) = self.iovec(64);
+                let nwritten = self.region(4);
+                let ret = unsafe { wasi_fd_write(1, iovs.addr, 1, nwritten.addr) };
+                check_errno("fd_write", ret, &[iovs, buf, nwritten]);
+            }
+            6 => {
+                // Nothing is written to the stdin of the fuzzer
+                let (iovs, buf) = self.iovec(64);
+                let nread = self.region(4);
+                let ret = unsafe { wasi_fd_read(0, iovs.addr, 1, nread.addr) };
+                check_errno_or("fd_read", ret, WASI_EAGAIN, &[iovs, buf, nread]);
+            }
+            7 => {
+                // An expired timeout, so that a valid call does not suspend the fuzzer
+                let subscription = self.region(48);
+                if subscription.valid {
+                    let offset = (subscription.addr as u32 - self.scratch_addr()) as usize;
+                    let clock_sub = &mut self.scratch[offset..offset + 48];
+                    clock_sub.fill(0);
+                    clock_sub[16..20].copy_from_slice(&WASI_CLOCK_MONOTONIC.to_le_bytes());
+                }
+                let events = self.region(32);
+                let nevents = self.region(4);
+                let ret =
+                    unsafe { wasi_poll_oneoff(subscription.addr, events.addr, 1, nevents.addr) };
+                check_errno("poll_oneoff", ret, &[subscription, events, nevents]);
+            }
+            _ => self.fuzz_tcp_call(),
+        }
+
+        self.n_calls += 1;
+    }
+
+    // Handle IDs are either made up, or were returned by host_tcp_connect() (and may have been
+    // closed since). Only the results of calls on unknown handles or invalid buffers are checked,
+    // since the connections themselves may or may not succeed.
+    fn fuzz_tcp_call(&mut self) {
+        let handle_id = match self.rng.below(3) {
+            0 if !self.tcp_handles.is_empty() => {
+                let i = self.rng.below(self.tcp_handles.len() as u64) as usize;
+                self.tcp_handles[i]
+            }
+            1 => self.rng.below(16) as i32,
+            _ => self.rng.next() as i32,
+        };
+        let is_open = self.open_tcp_handles.contains(&handle_id);
+
+        match self.rng.below(5) {
+            0 => {
+                if self.open_tcp_handles.len() >= MAX_OPEN_CONNECTIONS {
+                    let oldest = self.open_tcp_handles.remove(0);
+                    unsafe { host_tcp_close(oldest) };
+                    return;
+                }
+                // Mostly out of range ports, which must be rejected
+                let port = match self.rng.below(4) {
+                    0 => TCP_FUZZ_PORT as i32,
+                    1 => -1 - self.rng.below(1 << 31) as i32,
+                    _ => (1 << 16) + self.rng.below((1 << 31) - (1 << 16)) as i32,
+                };
+                let ret = unsafe { host_tcp_connect(i32::from_le_bytes(TCP_FUZZ_IP), port) };
+                if u16::try_from(port).is_err() && ret != -1 {
+                    log::error!("FUZZ FAILURE: tcp_connect returned {} (port {})", ret, port);
+                }
+                if ret >= 0 {
+                    self.tcp_handles.push(ret);
+                    self.open_tcp_handles.push(ret);
+                }
+            }
+            1 => {
+                unsafe { host_tcp_close(handle_id) };
+                self.open_tcp_handles
+                    .retain(|&open_id| open_id != handle_id);
+            }
+            2 => {
+                let (func_name, ret) = match self.rng.below(2) {
+                    0 => ("tcp_may_send", unsafe { host_tcp_may_send(handle_id) }),
+                    _ => ("tcp_may_recv", unsafe { host_tcp_may_recv(handle_id) }),
+                };
+                if !is_open && ret != 0 {
+                    log::error!(
+                        "FUZZ FAILURE: {} returned {} (handle {})",
+                        func_name,
+                        ret,
+                        handle_id
+                    );
+                }
+            }
+            _ => {
+                let buf = self.sized_region(64);
+                let (func_name, ret) = match self.rng.below(2) {
+                    0 => ("tcp_write", unsafe {
+                        host_tcp_write(buf.addr, buf.len, handle_id)
+                    }),
+                    _ => ("tcp_read", unsafe {
+                        host_tcp_read(buf.addr, buf.len, handle_id)
+                    }),
+                };
+                if (!is_open || !buf.valid) && ret != -1 {
+                    log::error!(
+                        "FUZZ FAILURE: {} returned {} (handle {}, {:?})",
+                        func_name,
+                        ret,
+                        handle_id,
+                        buf
+                    );
+                }
+            }
+        }
+    }
+
+    // Only returns if the arguments were valid, otherwise the app traps
+    fn fuzz_trapping_call(&mut self) {
+        let (func_name, regions) = match self.rng.below(8) {
+            0 => {
+                // Trace level, which the kernel does not log
+                let msg = self.sized_region(64);
+                unsafe { host_log(msg.addr, msg.len, 5) };
+                ("host_log", [msg, msg])
+            }
+            1 => {
+                let rect = self.region(RECT_SIZE as u32);
+                unsafe { host_get_win_rect(rect.addr) };
+                ("host_get_win_rect", [rect, rect])
+            }
+            2 => {
+                let stylesheet = self.region(STYLESHEET_SIZE as u32);
+                unsafe { host_get_stylesheet(stylesheet.addr) };
+                ("host_get_stylesheet", [stylesheet, stylesheet])
+            }
+            3 => {
+                let time = self.region(8);
+                unsafe { host_get_time(time.addr) };
+                ("host_get_time", [time, time])
+            }
+            4 => {
+                let consumed = self.region(8);
+                unsafe { host_get_consumed_fuel(consumed.addr) };
+                ("host_get_consumed_fuel", [consumed, consumed])
+            }
+            5 => {
+                let key = self.sized_region(32);
+                let consumed = self.region(8);
+                unsafe { host_save_timing(key.addr, key.len, consumed.addr) };
+                ("host_save_timing", [key, consumed])
+            }
+            6 => {
+                let required_len = unsafe { host_get_input_state(0, 0) } as u32;
+                if required_len > SCRATCH_SIZE {
+                    return;
+                }
+                let buf = self.region(required_len);
+                unsafe { host_get_input_state(buf.addr, i32::MAX) };
+                ("host_get_input_state", [buf, buf])
+            }
+            _ => {
+                let w = 1 + self.rng.below(32) as u32;
+                let h = 1 + self.rng.below(32) as u32;
+                let framebuffer = self.region(w * h * 4);
+                unsafe { host_set_framebuffer(framebuffer.addr, w as i32, h as i32) };
+                ("host_set_framebuffer", [framebuffer, framebuffer])
+            }
+        };
+
+        if !regions.iter().all(|region| region.valid) {
+            log::error!("FUZZ FAILURE: {}() did not trap ({:?})", func_name, regions);
+        }
+    }
+
+    // WASI iovec pointing to a buffer. The buffer is only written if the iovec is valid.
+    fn iovec(&mut self, max_len: u32) -> (Region, Region) {
+        let iovs = self.region(8);
+        let buf = self.sized_region(max_len);
+        if iovs.valid {
+            let offset = (iovs.addr as u32 - self.scratch_addr()) as usize;
+            self.scratch[offset..offset + 4].copy_from_slice(&buf.addr.to_le_bytes());
+            self.scratch[offset + 4..offset + 8].copy_from_slice(&buf.len.to_le_bytes());
+        }
+        (iovs, buf)
+    }
+
+    fn string_list_sizes(&mut self, sizes_get: WasiListFn) -> (u32, u32) {
+        let addr = self.scratch_addr() as i32;
+        unsafe { sizes_get(addr, addr + 4) };
+        let read_u32 = |offset: usize| {
+            u32::from_le_bytes(self.scratch[offset..offset + 4].try_into().unwrap())
+        };
+        (read_u32(0), read_u32(4))
+    }
+}
+
+fn check_errno(func_name: &str, ret: i32, regions: &[Region]) {
+    check_errno_or(func_name, ret, WASI_SUCCESS, regions)
+}
+
+// When all regions are valid, the call is expected to return valid_ret
+fn check_errno_or(func_name: &str, ret: i32, valid_ret: i32, regions: &[Region]) {
+    let expected = match regions.iter().all(|region| region.valid) {
+        true => valid_ret,
+        false => WASI_EFAULT,
+    };
+
+    if ret != expected {
+        log::error!(
+            "FUZZ FAILURE: {}() returned {} instead of {} ({:?})",
+            func_name,
+            ret,
+            expected,
+            regions
+        );
+    }
+}
+
+fn memory_size() -> u32 {
+    (core::arch::wasm32::memory_size(0) * 65536) as u32
+}
+
+// xorshift64*
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
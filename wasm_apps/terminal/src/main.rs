@@ -22,6 +22,9 @@ struct EvalResult {
     pyres: python::EvalResult,
 }
 
+// There is no filesystem, so scripts passed as the first argument are bundled with the app
+const SCRIPTS: [(&str, &str); 1] = [("scripts/hello.py", include_str!("scripts/hello.py"))];
+
 static LOGGER: WasmLogger = WasmLogger;
 const LOGGING_LEVEL: log::LevelFilter = log::LevelFilter::Debug;
 
@@ -49,7 +52,7 @@ pub fn init() -> () {
 
     let mut uuid_provider = uitk::UuidProvider::new();
 
-    let state = AppState {
+    let mut state = AppState {
         pixel_data: PixelData::new(),
         input_buffer: TrackedContent::new(RichText::new(), &mut uuid_provider),
         history: TrackedContent::new(Vec::new(), &mut uuid_provider),
@@ -58,6 +61,24 @@ pub fn init() -> () {
         textbox_state: TextBoxState::new(),
         python: python::Python::new(),
     };
+
+    if let Some(script_path) = std::env::args().nth(1) {
+        let pyres = match SCRIPTS.iter().find(|(path, _)| *path == script_path) {
+            Some((_, source)) => state.python.run_code(source),
+            None => {
+                log::error!("Unknown script {}", script_path);
+                python::EvalResult::Failure(format!("\nNo such script: {}", script_path))
+            }
+        };
+        state
+            .history
+            .mutate(&mut state.uuid_provider)
+            .push(EvalResult {
+                cmd: format!("# {}", script_path),
+                pyres,
+            });
+    }
+
     unsafe {
         APP_STATE
             .set(state)
@@ -0,0 +1,9 @@
+print("Hello from a bundled script")
+
+def fib(n):
+    a, b = 0, 1
+    for _ in range(n):
+        a, b = b, a + b
+    return a
+
+print("First Fibonacci numbers:", [fib(i) for i in range(15)])
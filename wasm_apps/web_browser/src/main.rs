@@ -188,7 +188,7 @@ pub fn init() -> () {
 
     let mut uuid_provider = uitk::UuidProvider::new();
 
-    let state = AppState {
+    let mut state = AppState {
         pixel_data: PixelData::new(),
         url_text: TrackedContent::new(url_text, &mut uuid_provider),
         url_textbox_state: TextBoxState::new(),
@@ -200,6 +200,17 @@ pub fn init() -> () {
         webview_scroll_dragging: (false, false),
         request_state: RequestState::Home,
     };
+
+    // The first argument is a page to open instead of the home screen
+    if let Some(url) = std::env::args().nth(1) {
+        log::info!("Opening {}", url);
+        let res =
+            parse_url(&url).and_then(|http_target| initiate_redirect(&mut state, http_target));
+        if let Err(error) = res {
+            log::error!("Cannot open {}: {}", url, error);
+        }
+    }
+
     unsafe {
         APP_STATE
             .set(state)
@@ -338,10 +349,10 @@ fn compute_ui_layout(stylesheet: &StyleSheet, win_rect: &Rect) -> UiLayout {
 }
 
 fn check_enter_pressed(input_state: &InputState) -> bool {
-    input_state.events.iter().any(|event| {
-        if let Some(InputEvent::KeyPress {
+    input_state.iter_events().any(|event| {
+        if let InputEvent::KeyPress {
             keycode: Keycode::KEY_ENTER,
-        }) = event
+        } = event
         {
             true
         } else {